@@ -13,6 +13,8 @@ use std::time::Instant;
 const AAD_LEN: usize = 64;
 // Length of plaintext and ciphertext for all seal/open benchmarks
 const MSG_LEN: usize = 64;
+// Length of plaintext for the seal_large benchmark, and the seal benchmark it's compared against
+const LARGE_MSG_LEN: usize = 4 * 1024 * 1024;
 // Length of PSK. Since we're only testing the 128-bit security level, make it 128 bits
 const PSK_LEN: usize = 16;
 
@@ -122,6 +124,28 @@ where
         b.iter(|| encryption_ctx.seal(&mut plaintext, &aad).unwrap())
     });
 
+    // Bench seal() and seal_large() on a LARGE_MSG_LEN-byte plaintext
+    let mut large_plaintext = vec![0u8; LARGE_MSG_LEN];
+    csprng.fill_bytes(&mut large_plaintext);
+    let bench_name = format!("seal[msglen={}]", LARGE_MSG_LEN);
+    group.bench_function(bench_name, |b| {
+        b.iter(|| encryption_ctx.seal(&mut large_plaintext, b"").unwrap())
+    });
+    let bench_name = format!("seal_large[msglen={}]", LARGE_MSG_LEN);
+    group.bench_function(bench_name, |b| {
+        b.iter(|| {
+            encryption_ctx
+                .seal_large(&mut large_plaintext, b"")
+                .unwrap()
+        })
+    });
+
+    // Bench export() of a 32-byte secret, as done for per-request key derivation
+    group.bench_function("export[len=32]", |b| {
+        let mut secret = [0u8; 32];
+        b.iter(|| encryption_ctx.export(b"bench export", &mut secret).unwrap())
+    });
+
     // Bench open() on MSG_LEN-bytes ciphertexts with AAD_LEN-byte AADs. This is more complicated
     // than the other benchmarks because we need to first construct and store a ton of ciphertexts
     // that we can open() in sequence.
@@ -195,14 +219,14 @@ pub fn benches() {
     let mut c = Criterion::default().configure_from_args();
 
     // NIST ciphersuite at the 128-bit security level is AES-GCM-128, HKDF-SHA256, and ECDH-P256
-    #[cfg(feature = "p256")]
+    #[cfg(all(feature = "p256", feature = "aes-gcm"))]
     bench_ciphersuite::<hpke::aead::AesGcm128, hpke::kdf::HkdfSha256, hpke::kem::DhP256HkdfSha256>(
         "NIST[seclevel=128]",
         &mut c,
     );
 
     // Non-NIST ciphersuite at the 128-bit security level is ChaCha20Poly1305, HKDF-SHA256, and X25519
-    #[cfg(feature = "x25519")]
+    #[cfg(all(feature = "x25519", feature = "chacha20poly1305"))]
     bench_ciphersuite::<
         hpke::aead::ChaCha20Poly1305,
         hpke::kdf::HkdfSha256,
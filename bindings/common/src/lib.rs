@@ -0,0 +1,561 @@
+//! # hpke-bindings-common
+//! The `hpke` crate picks its ciphersuite at the type level. Foreign languages can't do that, so
+//! every language binding needs to take the ciphersuite at runtime and dispatch to the
+//! appropriate monomorphized function. This crate is that dispatch layer, shared by all the
+//! bindings in this directory. It is not meant to be used directly.
+//!
+//! Ciphersuites are given by their IANA identifiers. Keys, encapsulated keys, and ciphertexts are
+//! plain byte strings. Ciphertexts are of the form `ciphertext || tag`.
+//!
+//! Side channels
+//! =============
+//! Dispatching at runtime must not leak anything the static API wouldn't. Every branch this crate
+//! takes is on a value that's public anyway:
+//!
+//! * the KEM, KDF, and AEAD identifiers, which are sent in the clear or fixed by the protocol
+//! * which [`SenderMode`] or [`ReceiverMode`] is used, which the receiver has to know up front
+//! * the lengths of keys, info strings, PSK IDs, plaintexts, and ciphertexts
+//! * whether a key or encapsulated key parses, and whether a ciphertext authenticates, since the
+//!   caller sees the error either way
+//!
+//! The suite is picked before any key is parsed, so an unknown identifier is reported the same way
+//! no matter what the keys are. Private keys, PSKs, and plaintexts are only handled by the
+//! monomorphized functions of the `hpke` crate, so they get the same constant-time treatment as
+//! they do in the static API.
+
+// This crate is plain Rust. The unsafe FFI glue lives in the per-language binding crates.
+#![forbid(unsafe_code)]
+
+use hpke::{
+    aead::{
+        Aead as AeadTrait, AeadCtxR, AeadCtxS, AeadTag, AesGcm128, AesGcm256, ChaCha20Poly1305,
+    },
+    kdf::{HkdfSha256, HkdfSha384, HkdfSha512, Kdf as KdfTrait},
+    kem::{DhP256HkdfSha256, Kem as KemTrait, X25519HkdfSha256},
+    kex::KeyExchange,
+    Deserializable, EncappedKey, HpkeError, OpModeR, OpModeS, PskBundle, Serializable,
+};
+use rand::rngs::OsRng;
+
+/// Describes things that can go wrong in the bindings
+#[derive(Clone, Copy, Debug)]
+pub enum BindingError {
+    /// Represents an error in the `hpke` crate
+    Hpke(HpkeError),
+    /// When you get an algorithm identifier you don't recognize. Error is of the form
+    /// `(alg, given_id)`.
+    UnknownAlgorithm(&'static str, u16),
+}
+
+// This just wraps the HpkeError
+impl From<HpkeError> for BindingError {
+    fn from(e: HpkeError) -> BindingError {
+        BindingError::Hpke(e)
+    }
+}
+
+impl core::fmt::Display for BindingError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            BindingError::Hpke(e) => e.fmt(f),
+            BindingError::UnknownAlgorithm(alg, id) => {
+                write!(f, "Unknown {} identifier: {:#06x}", alg, id)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BindingError {}
+
+/// A full HPKE ciphersuite, given by its algorithm identifiers
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Suite {
+    pub kem_id: u16,
+    pub kdf_id: u16,
+    pub aead_id: u16,
+}
+
+/// The operation mode of the HPKE session (sender's view). This is `hpke::OpModeS` with all the
+/// keys still serialized. Keypairs are of the form `(private_key, public_key)`.
+#[derive(Clone, Copy)]
+pub enum SenderMode<'a> {
+    Base,
+    Psk(PskBundle<'a>),
+    Auth((&'a [u8], &'a [u8])),
+    AuthPsk((&'a [u8], &'a [u8]), PskBundle<'a>),
+}
+
+/// The operation mode of the HPKE session (receiver's view). This is `hpke::OpModeR` with all the
+/// keys still serialized.
+#[derive(Clone, Copy)]
+pub enum ReceiverMode<'a> {
+    Base,
+    Psk(PskBundle<'a>),
+    Auth(&'a [u8]),
+    AuthPsk(&'a [u8], PskBundle<'a>),
+}
+
+/// The object-safe subset of `AeadCtxS`. The tag is appended to the ciphertext.
+pub trait SenderContext: Send {
+    /// Encrypts the given plaintext and returns `ciphertext || tag`
+    fn seal(&mut self, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, HpkeError>;
+
+    /// Returns `out_len` many secret bytes derived from this encryption context
+    fn export(&self, exporter_ctx: &[u8], out_len: usize) -> Result<Vec<u8>, HpkeError>;
+}
+
+/// The object-safe subset of `AeadCtxR`. The tag is expected to be appended to the ciphertext.
+pub trait ReceiverContext: Send {
+    /// Decrypts the given `ciphertext || tag` and returns the plaintext
+    fn open(&mut self, ciphertext: &[u8], aad: &[u8]) -> Result<Vec<u8>, HpkeError>;
+
+    /// Returns `out_len` many secret bytes derived from this encryption context
+    fn export(&self, exporter_ctx: &[u8], out_len: usize) -> Result<Vec<u8>, HpkeError>;
+}
+
+impl<A, Kdf, Kem> SenderContext for AeadCtxS<A, Kdf, Kem>
+where
+    A: AeadTrait,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    AeadCtxS<A, Kdf, Kem>: Send,
+{
+    fn seal(&mut self, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, HpkeError> {
+        let mut buf = plaintext.to_vec();
+        let tag = AeadCtxS::seal(self, &mut buf, aad)?;
+        buf.extend_from_slice(&tag.to_bytes());
+        Ok(buf)
+    }
+
+    fn export(&self, exporter_ctx: &[u8], out_len: usize) -> Result<Vec<u8>, HpkeError> {
+        let mut buf = vec![0u8; out_len];
+        AeadCtxS::export(self, exporter_ctx, &mut buf)?;
+        Ok(buf)
+    }
+}
+
+impl<A, Kdf, Kem> ReceiverContext for AeadCtxR<A, Kdf, Kem>
+where
+    A: AeadTrait,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    AeadCtxR<A, Kdf, Kem>: Send,
+{
+    fn open(&mut self, ciphertext: &[u8], aad: &[u8]) -> Result<Vec<u8>, HpkeError> {
+        let (mut buf, tag) = split_ciphertext::<A>(ciphertext)?;
+        AeadCtxR::open(self, &mut buf, aad, &tag)?;
+        Ok(buf)
+    }
+
+    fn export(&self, exporter_ctx: &[u8], out_len: usize) -> Result<Vec<u8>, HpkeError> {
+        let mut buf = vec![0u8; out_len];
+        AeadCtxR::export(self, exporter_ctx, &mut buf)?;
+        Ok(buf)
+    }
+}
+
+// Calls $f::<Kem>($args) where Kem is the type corresponding to the given runtime KEM ID. This
+// only branches on the KEM ID, which is public.
+macro_rules! dispatch_kem {
+    ($kem_id:expr, $f:ident($($arg:expr),*)) => {
+        match $kem_id {
+            X25519HkdfSha256::KEM_ID => Ok($f::<X25519HkdfSha256>($($arg),*)),
+            DhP256HkdfSha256::KEM_ID => Ok($f::<DhP256HkdfSha256>($($arg),*)),
+            id => Err(BindingError::UnknownAlgorithm("KEM", id)),
+        }
+    };
+}
+
+// Calls $f::<A, Kdf, Kem>($args) where A, Kdf, Kem are the types corresponding to the given
+// runtime suite. This unrolls into 18 match arms. It only branches on the suite IDs, which are
+// public, and it runs before $f sees any secret input.
+macro_rules! dispatch_suite {
+    ($suite:expr, $f:ident($($arg:expr),*)) => {
+        match $suite.aead_id {
+            AesGcm128::AEAD_ID => dispatch_suite!(@kdf $suite, AesGcm128, $f($($arg),*)),
+            AesGcm256::AEAD_ID => dispatch_suite!(@kdf $suite, AesGcm256, $f($($arg),*)),
+            ChaCha20Poly1305::AEAD_ID => {
+                dispatch_suite!(@kdf $suite, ChaCha20Poly1305, $f($($arg),*))
+            }
+            id => Err(BindingError::UnknownAlgorithm("AEAD", id)),
+        }
+    };
+    (@kdf $suite:expr, $aead_ty:ty, $f:ident($($arg:expr),*)) => {
+        match $suite.kdf_id {
+            HkdfSha256::KDF_ID => dispatch_suite!(@kem $suite, $aead_ty, HkdfSha256, $f($($arg),*)),
+            HkdfSha384::KDF_ID => dispatch_suite!(@kem $suite, $aead_ty, HkdfSha384, $f($($arg),*)),
+            HkdfSha512::KDF_ID => dispatch_suite!(@kem $suite, $aead_ty, HkdfSha512, $f($($arg),*)),
+            id => Err(BindingError::UnknownAlgorithm("KDF", id)),
+        }
+    };
+    (@kem $suite:expr, $aead_ty:ty, $kdf_ty:ty, $f:ident($($arg:expr),*)) => {
+        match $suite.kem_id {
+            X25519HkdfSha256::KEM_ID => {
+                $f::<$aead_ty, $kdf_ty, X25519HkdfSha256>($($arg),*).map_err(BindingError::from)
+            }
+            DhP256HkdfSha256::KEM_ID => {
+                $f::<$aead_ty, $kdf_ty, DhP256HkdfSha256>($($arg),*).map_err(BindingError::from)
+            }
+            id => Err(BindingError::UnknownAlgorithm("KEM", id)),
+        }
+    };
+}
+
+type KemPubkey<Kem> = <<Kem as KemTrait>::Kex as KeyExchange>::PublicKey;
+type KemPrivkey<Kem> = <<Kem as KemTrait>::Kex as KeyExchange>::PrivateKey;
+
+// Splits `ciphertext || tag` into its two components
+fn split_ciphertext<A: AeadTrait>(
+    ciphertext_and_tag: &[u8],
+) -> Result<(Vec<u8>, AeadTag<A>), HpkeError> {
+    let tag_size = AeadTag::<A>::size();
+    if ciphertext_and_tag.len() < tag_size {
+        return Err(HpkeError::InvalidEncoding);
+    }
+
+    let (ciphertext, tag_bytes) = ciphertext_and_tag.split_at(ciphertext_and_tag.len() - tag_size);
+    Ok((ciphertext.to_vec(), AeadTag::from_bytes(tag_bytes)?))
+}
+
+// Deserializes the keys in a SenderMode
+fn parse_sender_mode<'a, Kem: KemTrait>(
+    mode: &SenderMode<'a>,
+) -> Result<OpModeS<'a, Kem::Kex>, HpkeError> {
+    let parse_keypair = |(sk, pk): (&[u8], &[u8])| -> Result<_, HpkeError> {
+        Ok((
+            KemPrivkey::<Kem>::from_bytes(sk)?,
+            KemPubkey::<Kem>::from_bytes(pk)?,
+        ))
+    };
+
+    Ok(match *mode {
+        SenderMode::Base => OpModeS::Base,
+        SenderMode::Psk(bundle) => OpModeS::Psk(bundle),
+        SenderMode::Auth(keypair) => OpModeS::Auth(parse_keypair(keypair)?),
+        SenderMode::AuthPsk(keypair, bundle) => OpModeS::AuthPsk(parse_keypair(keypair)?, bundle),
+    })
+}
+
+// Deserializes the keys in a ReceiverMode
+fn parse_receiver_mode<'a, Kem: KemTrait>(
+    mode: &ReceiverMode<'a>,
+) -> Result<OpModeR<'a, Kem::Kex>, HpkeError> {
+    Ok(match *mode {
+        ReceiverMode::Base => OpModeR::Base,
+        ReceiverMode::Psk(bundle) => OpModeR::Psk(bundle),
+        ReceiverMode::Auth(pk) => OpModeR::Auth(KemPubkey::<Kem>::from_bytes(pk)?),
+        ReceiverMode::AuthPsk(pk, bundle) => {
+            OpModeR::AuthPsk(KemPubkey::<Kem>::from_bytes(pk)?, bundle)
+        }
+    })
+}
+
+fn gen_keypair_impl<Kem: KemTrait>() -> (Vec<u8>, Vec<u8>) {
+    let (sk, pk) = Kem::gen_keypair(&mut OsRng);
+    (sk.to_bytes().to_vec(), pk.to_bytes().to_vec())
+}
+
+fn derive_keypair_impl<Kem: KemTrait>(ikm: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let (sk, pk) = Kem::derive_keypair(ikm);
+    (sk.to_bytes().to_vec(), pk.to_bytes().to_vec())
+}
+
+fn setup_sender_impl<A, Kdf, Kem>(
+    mode: &SenderMode,
+    pk_recip: &[u8],
+    info: &[u8],
+) -> Result<(Vec<u8>, Box<dyn SenderContext>), HpkeError>
+where
+    A: AeadTrait + 'static,
+    Kdf: KdfTrait + 'static,
+    Kem: KemTrait + 'static,
+    AeadCtxS<A, Kdf, Kem>: Send,
+{
+    let mode = parse_sender_mode::<Kem>(mode)?;
+    let pk_recip = KemPubkey::<Kem>::from_bytes(pk_recip)?;
+    let (encapped_key, ctx) =
+        hpke::setup_sender::<A, Kdf, Kem, _>(&mode, &pk_recip, info, &mut OsRng)?;
+
+    Ok((encapped_key.to_bytes().to_vec(), Box::new(ctx)))
+}
+
+fn setup_receiver_impl<A, Kdf, Kem>(
+    mode: &ReceiverMode,
+    sk_recip: &[u8],
+    encapped_key: &[u8],
+    info: &[u8],
+) -> Result<Box<dyn ReceiverContext>, HpkeError>
+where
+    A: AeadTrait + 'static,
+    Kdf: KdfTrait + 'static,
+    Kem: KemTrait + 'static,
+    AeadCtxR<A, Kdf, Kem>: Send,
+{
+    let mode = parse_receiver_mode::<Kem>(mode)?;
+    let sk_recip = KemPrivkey::<Kem>::from_bytes(sk_recip)?;
+    let encapped_key = EncappedKey::<Kem>::from_bytes(encapped_key)?;
+    let ctx = hpke::setup_receiver::<A, Kdf, Kem>(&mode, &sk_recip, &encapped_key, info)?;
+
+    Ok(Box::new(ctx))
+}
+
+fn single_shot_seal_impl<A, Kdf, Kem>(
+    mode: &SenderMode,
+    pk_recip: &[u8],
+    info: &[u8],
+    plaintext: &[u8],
+    aad: &[u8],
+) -> Result<(Vec<u8>, Vec<u8>), HpkeError>
+where
+    A: AeadTrait,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    let mode = parse_sender_mode::<Kem>(mode)?;
+    let pk_recip = KemPubkey::<Kem>::from_bytes(pk_recip)?;
+
+    let mut buf = plaintext.to_vec();
+    let (encapped_key, tag) = hpke::single_shot_seal::<A, Kdf, Kem, _>(
+        &mode, &pk_recip, info, &mut buf, aad, &mut OsRng,
+    )?;
+    buf.extend_from_slice(&tag.to_bytes());
+
+    Ok((encapped_key.to_bytes().to_vec(), buf))
+}
+
+fn single_shot_open_impl<A, Kdf, Kem>(
+    mode: &ReceiverMode,
+    sk_recip: &[u8],
+    encapped_key: &[u8],
+    info: &[u8],
+    ciphertext: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, HpkeError>
+where
+    A: AeadTrait,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    let mode = parse_receiver_mode::<Kem>(mode)?;
+    let sk_recip = KemPrivkey::<Kem>::from_bytes(sk_recip)?;
+    let encapped_key = EncappedKey::<Kem>::from_bytes(encapped_key)?;
+
+    let (mut buf, tag) = split_ciphertext::<A>(ciphertext)?;
+    hpke::single_shot_open::<A, Kdf, Kem>(
+        &mode,
+        &sk_recip,
+        &encapped_key,
+        info,
+        &mut buf,
+        aad,
+        &tag,
+    )?;
+
+    Ok(buf)
+}
+
+/// Generates a random keypair for the given KEM. Returns `(private_key, public_key)`.
+pub fn gen_keypair(kem_id: u16) -> Result<(Vec<u8>, Vec<u8>), BindingError> {
+    dispatch_kem!(kem_id, gen_keypair_impl())
+}
+
+/// Deterministically derives a keypair for the given KEM from the given input keying material.
+/// Returns `(private_key, public_key)`.
+pub fn derive_keypair(kem_id: u16, ikm: &[u8]) -> Result<(Vec<u8>, Vec<u8>), BindingError> {
+    dispatch_kem!(kem_id, derive_keypair_impl(ikm))
+}
+
+/// Initiates an encryption context to the given recipient public key. Returns the encapsulated
+/// key (to be sent to the recipient) and the encryption context.
+pub fn setup_sender(
+    suite: &Suite,
+    mode: &SenderMode,
+    pk_recip: &[u8],
+    info: &[u8],
+) -> Result<(Vec<u8>, Box<dyn SenderContext>), BindingError> {
+    dispatch_suite!(suite, setup_sender_impl(mode, pk_recip, info))
+}
+
+/// Initiates a decryption context given the recipient's private key and an encapsulated key
+pub fn setup_receiver(
+    suite: &Suite,
+    mode: &ReceiverMode,
+    sk_recip: &[u8],
+    encapped_key: &[u8],
+    info: &[u8],
+) -> Result<Box<dyn ReceiverContext>, BindingError> {
+    dispatch_suite!(
+        suite,
+        setup_receiver_impl(mode, sk_recip, encapped_key, info)
+    )
+}
+
+/// Does a `setup_sender` and `seal` in one shot. Returns `(encapped_key, ciphertext || tag)`.
+pub fn single_shot_seal(
+    suite: &Suite,
+    mode: &SenderMode,
+    pk_recip: &[u8],
+    info: &[u8],
+    plaintext: &[u8],
+    aad: &[u8],
+) -> Result<(Vec<u8>, Vec<u8>), BindingError> {
+    dispatch_suite!(
+        suite,
+        single_shot_seal_impl(mode, pk_recip, info, plaintext, aad)
+    )
+}
+
+/// Does a `setup_receiver` and `open` in one shot. `ciphertext` is of the form
+/// `ciphertext || tag`.
+pub fn single_shot_open(
+    suite: &Suite,
+    mode: &ReceiverMode,
+    sk_recip: &[u8],
+    encapped_key: &[u8],
+    info: &[u8],
+    ciphertext: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, BindingError> {
+    dispatch_suite!(
+        suite,
+        single_shot_open_impl(mode, sk_recip, encapped_key, info, ciphertext, aad)
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SUITES: &[Suite] = &[
+        Suite {
+            kem_id: X25519HkdfSha256::KEM_ID,
+            kdf_id: HkdfSha256::KDF_ID,
+            aead_id: ChaCha20Poly1305::AEAD_ID,
+        },
+        Suite {
+            kem_id: DhP256HkdfSha256::KEM_ID,
+            kdf_id: HkdfSha384::KDF_ID,
+            aead_id: AesGcm128::AEAD_ID,
+        },
+    ];
+
+    /// Tests that the context API round-trips through the runtime dispatch layer
+    #[test]
+    fn test_ctx_correctness() {
+        for suite in SUITES {
+            let (sk_recip, pk_recip) = gen_keypair(suite.kem_id).unwrap();
+            let (sk_sender, pk_sender) = gen_keypair(suite.kem_id).unwrap();
+            let info = b"hpke-bindings-common test";
+            let psk_bundle = PskBundle {
+                psk: b"a very secret psk",
+                psk_id: b"psk1",
+            };
+
+            let sender_mode = SenderMode::AuthPsk((&sk_sender, &pk_sender), psk_bundle);
+            let receiver_mode = ReceiverMode::AuthPsk(&pk_sender, psk_bundle);
+
+            let (encapped_key, mut sender_ctx) =
+                setup_sender(suite, &sender_mode, &pk_recip, info).unwrap();
+            let mut receiver_ctx =
+                setup_receiver(suite, &receiver_mode, &sk_recip, &encapped_key, info).unwrap();
+
+            for msg in &[&b"first"[..], &b"second"[..]] {
+                let ciphertext = sender_ctx.seal(msg, b"aad").unwrap();
+                let plaintext = receiver_ctx.open(&ciphertext, b"aad").unwrap();
+                assert_eq!(&plaintext, msg);
+            }
+
+            assert_eq!(
+                sender_ctx.export(b"label", 32).unwrap(),
+                receiver_ctx.export(b"label", 32).unwrap()
+            );
+        }
+    }
+
+    /// Tests that single-shot open undoes single-shot seal, and fails on a modified ciphertext
+    #[test]
+    fn test_single_shot_correctness() {
+        for suite in SUITES {
+            let (sk_recip, pk_recip) =
+                derive_keypair(suite.kem_id, b"fixed ikm for this test").unwrap();
+            let msg = b"the quick brown fox";
+
+            let (encapped_key, ciphertext) =
+                single_shot_seal(suite, &SenderMode::Base, &pk_recip, b"info", msg, b"aad")
+                    .unwrap();
+
+            let plaintext = single_shot_open(
+                suite,
+                &ReceiverMode::Base,
+                &sk_recip,
+                &encapped_key,
+                b"info",
+                &ciphertext,
+                b"aad",
+            )
+            .unwrap();
+            assert_eq!(plaintext, msg);
+
+            let mut bad_ciphertext = ciphertext;
+            bad_ciphertext[0] ^= 1;
+            let res = single_shot_open(
+                suite,
+                &ReceiverMode::Base,
+                &sk_recip,
+                &encapped_key,
+                b"info",
+                &bad_ciphertext,
+                b"aad",
+            );
+            assert!(matches!(
+                res,
+                Err(BindingError::Hpke(HpkeError::InvalidTag))
+            ));
+        }
+    }
+
+    /// Tests that unknown algorithm identifiers are rejected
+    #[test]
+    fn test_unknown_alg() {
+        let suite = Suite {
+            kem_id: X25519HkdfSha256::KEM_ID,
+            kdf_id: 0x1337,
+            aead_id: ChaCha20Poly1305::AEAD_ID,
+        };
+        let res = single_shot_seal(&suite, &SenderMode::Base, &[0u8; 32], b"", b"", b"");
+        assert!(matches!(
+            res,
+            Err(BindingError::UnknownAlgorithm("KDF", 0x1337))
+        ));
+        assert!(gen_keypair(0x1337).is_err());
+    }
+
+    /// Tests that the suite is dispatched on before any key is looked at, so an unknown suite gives
+    /// the same error whether or not the keys parse
+    #[test]
+    fn test_dispatch_before_keys() {
+        let suite = Suite {
+            kem_id: 0x1337,
+            kdf_id: HkdfSha256::KDF_ID,
+            aead_id: ChaCha20Poly1305::AEAD_ID,
+        };
+        let (sk, pk) = gen_keypair(X25519HkdfSha256::KEM_ID).unwrap();
+        let bad_sk = [0xffu8; 7];
+
+        for sk_recip in [&sk[..], &bad_sk[..]] {
+            let res = single_shot_open(
+                &suite,
+                &ReceiverMode::Auth(&pk),
+                sk_recip,
+                &pk,
+                b"",
+                &[0u8; 16],
+                b"",
+            );
+            assert!(matches!(
+                res,
+                Err(BindingError::UnknownAlgorithm("KEM", 0x1337))
+            ));
+        }
+    }
+}
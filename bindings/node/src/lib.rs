@@ -0,0 +1,340 @@
+//! # hpke-node
+//! [napi-rs](https://napi.rs) bindings for the `hpke` crate. This exposes keypair generation, the
+//! single-shot API, and the context API to Node.js, so that Node services talking to Rust
+//! producers use the exact same implementation.
+//!
+//! A `CipherSuite` is made from a `KemId`, a `KdfId`, and an `AeadId`, and all operations are
+//! methods on the suite. Keys, encapsulated keys, and ciphertexts are `Buffer`s. Ciphertexts are
+//! of the form `ciphertext || tag`.
+//!
+//! ```js
+//! const { AeadId, CipherSuite, KdfId, KemId } = require('rust-hpke')
+//!
+//! const suite = new CipherSuite(KemId.DhkemX25519HkdfSha256, KdfId.HkdfSha256, AeadId.ChaCha20Poly1305)
+//! const { privateKey, publicKey } = suite.generateKeypair()
+//! const { encappedKey, ciphertext } = suite.seal(publicKey, info, plaintext, aad)
+//! const decrypted = suite.open(privateKey, encappedKey, info, ciphertext, aad)
+//! ```
+//!
+//! To build a loadable module, run `npm run build` in this directory.
+
+// The FFI glue that the napi macros generate is this crate's only unsafe code. Hand-written code
+// here doesn't get to add more.
+#![deny(unsafe_code)]
+
+use std::sync::Mutex;
+
+use hpke::PskBundle;
+use hpke_bindings_common::{self as common, BindingError};
+use napi::{bindgen_prelude::Buffer, Error, Result, Status};
+use napi_derive::napi;
+
+// Converts a dispatch error into a JS exception. HPKE failures are thrown as generic failures, and
+// bad algorithm IDs are thrown as invalid arguments.
+fn to_js_err(e: BindingError) -> Error {
+    let status = match e {
+        BindingError::Hpke(_) => Status::GenericFailure,
+        BindingError::UnknownAlgorithm(..) => Status::InvalidArg,
+    };
+    Error::new(status, e.to_string())
+}
+
+/// The supported KEM identifiers
+#[napi]
+pub enum KemId {
+    DhkemP256HkdfSha256 = 0x0010,
+    DhkemX25519HkdfSha256 = 0x0020,
+}
+
+/// The supported KDF identifiers
+#[napi]
+pub enum KdfId {
+    HkdfSha256 = 0x0001,
+    HkdfSha384 = 0x0002,
+    HkdfSha512 = 0x0003,
+}
+
+/// The supported AEAD identifiers
+#[napi]
+pub enum AeadId {
+    Aes128Gcm = 0x0001,
+    Aes256Gcm = 0x0002,
+    ChaCha20Poly1305 = 0x0003,
+}
+
+/// A serialized keypair
+#[napi(object)]
+pub struct Keypair {
+    pub private_key: Buffer,
+    pub public_key: Buffer,
+}
+
+/// The output of a single-shot seal
+#[napi(object)]
+pub struct SealedMessage {
+    pub encapped_key: Buffer,
+    pub ciphertext: Buffer,
+}
+
+/// The sender's optional mode parameters. `psk` and `pskId` must be given together, as must
+/// `senderPrivateKey` and `senderPublicKey`.
+#[napi(object)]
+#[derive(Default)]
+pub struct SenderOptions {
+    pub psk: Option<Buffer>,
+    pub psk_id: Option<Buffer>,
+    pub sender_private_key: Option<Buffer>,
+    pub sender_public_key: Option<Buffer>,
+}
+
+/// The receiver's optional mode parameters. `psk` and `pskId` must be given together.
+#[napi(object)]
+#[derive(Default)]
+pub struct ReceiverOptions {
+    pub psk: Option<Buffer>,
+    pub psk_id: Option<Buffer>,
+    pub sender_public_key: Option<Buffer>,
+}
+
+// Makes a PSK bundle out of the optional psk and psk_id fields. They must be given together.
+fn psk_bundle<'a>(
+    psk: &'a Option<Buffer>,
+    psk_id: &'a Option<Buffer>,
+) -> Result<Option<PskBundle<'a>>> {
+    match (psk, psk_id) {
+        (Some(psk), Some(psk_id)) => Ok(Some(PskBundle { psk, psk_id })),
+        (None, None) => Ok(None),
+        _ => Err(Error::new(
+            Status::InvalidArg,
+            "psk and pskId must be given together",
+        )),
+    }
+}
+
+impl SenderOptions {
+    // Picks the sender mode from whichever of the fields are set
+    fn mode(&self) -> Result<common::SenderMode<'_>> {
+        let keypair = match (&self.sender_private_key, &self.sender_public_key) {
+            (Some(sk), Some(pk)) => Some((&sk[..], &pk[..])),
+            (None, None) => None,
+            _ => {
+                return Err(Error::new(
+                    Status::InvalidArg,
+                    "senderPrivateKey and senderPublicKey must be given together",
+                ))
+            }
+        };
+        Ok(match (keypair, psk_bundle(&self.psk, &self.psk_id)?) {
+            (None, None) => common::SenderMode::Base,
+            (None, Some(bundle)) => common::SenderMode::Psk(bundle),
+            (Some(keypair), None) => common::SenderMode::Auth(keypair),
+            (Some(keypair), Some(bundle)) => common::SenderMode::AuthPsk(keypair, bundle),
+        })
+    }
+}
+
+impl ReceiverOptions {
+    // Picks the receiver mode from whichever of the fields are set
+    fn mode(&self) -> Result<common::ReceiverMode<'_>> {
+        let pk_sender = self.sender_public_key.as_deref();
+        Ok(match (pk_sender, psk_bundle(&self.psk, &self.psk_id)?) {
+            (None, None) => common::ReceiverMode::Base,
+            (None, Some(bundle)) => common::ReceiverMode::Psk(bundle),
+            (Some(pk), None) => common::ReceiverMode::Auth(pk),
+            (Some(pk), Some(bundle)) => common::ReceiverMode::AuthPsk(pk, bundle),
+        })
+    }
+}
+
+/// The HPKE sender's context. This is what you use to `seal` plaintexts.
+#[napi]
+pub struct SenderContext {
+    encapped_key: Vec<u8>,
+    ctx: Mutex<Box<dyn common::SenderContext>>,
+}
+
+#[napi]
+impl SenderContext {
+    /// The encapsulated key that the receiver needs to set up their context
+    #[napi(getter)]
+    pub fn encapped_key(&self) -> Buffer {
+        self.encapped_key.clone().into()
+    }
+
+    /// Encrypts the given plaintext and returns `ciphertext || tag`
+    #[napi]
+    pub fn seal(&self, plaintext: Buffer, aad: Buffer) -> Result<Buffer> {
+        let ciphertext = self
+            .ctx
+            .lock()
+            .unwrap()
+            .seal(&plaintext, &aad)
+            .map_err(|e| to_js_err(e.into()))?;
+        Ok(ciphertext.into())
+    }
+
+    /// Returns `length` many secret bytes derived from this encryption context
+    #[napi]
+    pub fn export(&self, exporter_context: Buffer, length: u32) -> Result<Buffer> {
+        let secret = self
+            .ctx
+            .lock()
+            .unwrap()
+            .export(&exporter_context, length as usize)
+            .map_err(|e| to_js_err(e.into()))?;
+        Ok(secret.into())
+    }
+}
+
+/// The HPKE receiver's context. This is what you use to `open` ciphertexts.
+#[napi]
+pub struct ReceiverContext(Mutex<Box<dyn common::ReceiverContext>>);
+
+#[napi]
+impl ReceiverContext {
+    /// Decrypts the given `ciphertext || tag` and returns the plaintext
+    #[napi]
+    pub fn open(&self, ciphertext: Buffer, aad: Buffer) -> Result<Buffer> {
+        let plaintext = self
+            .0
+            .lock()
+            .unwrap()
+            .open(&ciphertext, &aad)
+            .map_err(|e| to_js_err(e.into()))?;
+        Ok(plaintext.into())
+    }
+
+    /// Returns `length` many secret bytes derived from this encryption context
+    #[napi]
+    pub fn export(&self, exporter_context: Buffer, length: u32) -> Result<Buffer> {
+        let secret = self
+            .0
+            .lock()
+            .unwrap()
+            .export(&exporter_context, length as usize)
+            .map_err(|e| to_js_err(e.into()))?;
+        Ok(secret.into())
+    }
+}
+
+/// A full HPKE ciphersuite. The operation mode of every method is determined by which of the
+/// optional `psk`/`pskId` and sender key fields are given.
+#[napi]
+pub struct CipherSuite(common::Suite);
+
+#[napi]
+impl CipherSuite {
+    #[napi(constructor)]
+    pub fn new(kem: KemId, kdf: KdfId, aead: AeadId) -> Self {
+        CipherSuite(common::Suite {
+            kem_id: kem as u16,
+            kdf_id: kdf as u16,
+            aead_id: aead as u16,
+        })
+    }
+
+    /// Generates a random keypair
+    #[napi]
+    pub fn generate_keypair(&self) -> Result<Keypair> {
+        let (sk, pk) = common::gen_keypair(self.0.kem_id).map_err(to_js_err)?;
+        Ok(Keypair {
+            private_key: sk.into(),
+            public_key: pk.into(),
+        })
+    }
+
+    /// Deterministically derives a keypair from the given input keying material
+    #[napi]
+    pub fn derive_keypair(&self, ikm: Buffer) -> Result<Keypair> {
+        let (sk, pk) = common::derive_keypair(self.0.kem_id, &ikm).map_err(to_js_err)?;
+        Ok(Keypair {
+            private_key: sk.into(),
+            public_key: pk.into(),
+        })
+    }
+
+    /// Initiates an encryption context to the given recipient public key. The encapsulated key
+    /// is available as `encappedKey` on the returned context.
+    #[napi]
+    pub fn setup_sender(
+        &self,
+        pk_recip: Buffer,
+        info: Buffer,
+        options: Option<SenderOptions>,
+    ) -> Result<SenderContext> {
+        let options = options.unwrap_or_default();
+        let (encapped_key, ctx) =
+            common::setup_sender(&self.0, &options.mode()?, &pk_recip, &info).map_err(to_js_err)?;
+        Ok(SenderContext {
+            encapped_key,
+            ctx: Mutex::new(ctx),
+        })
+    }
+
+    /// Initiates a decryption context given the recipient's private key and an encapsulated key
+    #[napi]
+    pub fn setup_receiver(
+        &self,
+        sk_recip: Buffer,
+        encapped_key: Buffer,
+        info: Buffer,
+        options: Option<ReceiverOptions>,
+    ) -> Result<ReceiverContext> {
+        let options = options.unwrap_or_default();
+        let ctx =
+            common::setup_receiver(&self.0, &options.mode()?, &sk_recip, &encapped_key, &info)
+                .map_err(to_js_err)?;
+        Ok(ReceiverContext(Mutex::new(ctx)))
+    }
+
+    /// Does a `setupSender` and `seal` in one shot
+    #[napi]
+    pub fn seal(
+        &self,
+        pk_recip: Buffer,
+        info: Buffer,
+        plaintext: Buffer,
+        aad: Buffer,
+        options: Option<SenderOptions>,
+    ) -> Result<SealedMessage> {
+        let options = options.unwrap_or_default();
+        let (encapped_key, ciphertext) = common::single_shot_seal(
+            &self.0,
+            &options.mode()?,
+            &pk_recip,
+            &info,
+            &plaintext,
+            &aad,
+        )
+        .map_err(to_js_err)?;
+        Ok(SealedMessage {
+            encapped_key: encapped_key.into(),
+            ciphertext: ciphertext.into(),
+        })
+    }
+
+    /// Does a `setupReceiver` and `open` in one shot. Returns the plaintext.
+    #[napi]
+    pub fn open(
+        &self,
+        sk_recip: Buffer,
+        encapped_key: Buffer,
+        info: Buffer,
+        ciphertext: Buffer,
+        aad: Buffer,
+        options: Option<ReceiverOptions>,
+    ) -> Result<Buffer> {
+        let options = options.unwrap_or_default();
+        let plaintext = common::single_shot_open(
+            &self.0,
+            &options.mode()?,
+            &sk_recip,
+            &encapped_key,
+            &info,
+            &ciphertext,
+            &aad,
+        )
+        .map_err(to_js_err)?;
+        Ok(plaintext.into())
+    }
+}
@@ -0,0 +1,338 @@
+//! # hpke-python
+//! [PyO3](https://pyo3.rs) bindings for the `hpke` crate. This exposes keypair generation, the
+//! single-shot API, and the context API to Python as the `rust_hpke` module.
+//!
+//! The naming follows other Python HPKE libraries: a `CipherSuite` is made from a `KemId`, a
+//! `KdfId`, and an `AeadId`, and all operations are methods on the suite. Keys, encapsulated
+//! keys, and ciphertexts are `bytes`. Ciphertexts are of the form `ciphertext || tag`.
+//!
+//! ```python
+//! from rust_hpke import AeadId, CipherSuite, KdfId, KemId
+//!
+//! suite = CipherSuite(KemId.DHKEM_X25519_HKDF_SHA256, KdfId.HKDF_SHA256, AeadId.CHACHA20_POLY1305)
+//! sk, pk = suite.generate_keypair()
+//! enc, ct = suite.seal(pk, b"info", b"hello", b"aad")
+//! assert suite.open(sk, enc, b"info", ct, b"aad") == b"hello"
+//! ```
+//!
+//! To build an importable module, run `maturin build --release` in this directory.
+
+// The FFI glue that the pyo3 macros generate is this crate's only unsafe code. Hand-written code
+// here doesn't get to add more.
+#![deny(unsafe_code)]
+
+use std::sync::Mutex;
+
+use hpke::PskBundle;
+use hpke_bindings_common::{self as common, BindingError};
+use pyo3::{
+    create_exception,
+    exceptions::{PyException, PyValueError},
+    prelude::*,
+    types::PyBytes,
+};
+
+create_exception!(
+    rust_hpke,
+    HpkeError,
+    PyException,
+    "Raised when an HPKE operation fails"
+);
+
+// Converts a dispatch error into the appropriate Python exception
+fn to_py_err(e: BindingError) -> PyErr {
+    match e {
+        BindingError::Hpke(_) => HpkeError::new_err(e.to_string()),
+        BindingError::UnknownAlgorithm(..) => PyValueError::new_err(e.to_string()),
+    }
+}
+
+/// The supported KEM identifiers
+#[pyclass(eq, eq_int, frozen, from_py_object)]
+#[derive(Clone, Copy, PartialEq)]
+pub enum KemId {
+    #[pyo3(name = "DHKEM_P256_HKDF_SHA256")]
+    DhkemP256HkdfSha256 = 0x0010,
+    #[pyo3(name = "DHKEM_X25519_HKDF_SHA256")]
+    DhkemX25519HkdfSha256 = 0x0020,
+}
+
+/// The supported KDF identifiers
+#[pyclass(eq, eq_int, frozen, from_py_object)]
+#[derive(Clone, Copy, PartialEq)]
+pub enum KdfId {
+    #[pyo3(name = "HKDF_SHA256")]
+    HkdfSha256 = 0x0001,
+    #[pyo3(name = "HKDF_SHA384")]
+    HkdfSha384 = 0x0002,
+    #[pyo3(name = "HKDF_SHA512")]
+    HkdfSha512 = 0x0003,
+}
+
+/// The supported AEAD identifiers
+#[pyclass(eq, eq_int, frozen, from_py_object)]
+#[derive(Clone, Copy, PartialEq)]
+pub enum AeadId {
+    #[pyo3(name = "AES128_GCM")]
+    Aes128Gcm = 0x0001,
+    #[pyo3(name = "AES256_GCM")]
+    Aes256Gcm = 0x0002,
+    #[pyo3(name = "CHACHA20_POLY1305")]
+    ChaCha20Poly1305 = 0x0003,
+}
+
+// Makes a PSK bundle out of the optional psk and psk_id arguments. They must be given together.
+fn psk_bundle<'a>(
+    psk: Option<&'a [u8]>,
+    psk_id: Option<&'a [u8]>,
+) -> PyResult<Option<PskBundle<'a>>> {
+    match (psk, psk_id) {
+        (Some(psk), Some(psk_id)) => Ok(Some(PskBundle { psk, psk_id })),
+        (None, None) => Ok(None),
+        _ => Err(PyValueError::new_err(
+            "psk and psk_id must be given together",
+        )),
+    }
+}
+
+// Picks the sender mode from whichever of the optional arguments are set
+fn sender_mode<'a>(
+    psk: Option<&'a [u8]>,
+    psk_id: Option<&'a [u8]>,
+    sender_keypair: Option<&'a (Vec<u8>, Vec<u8>)>,
+) -> PyResult<common::SenderMode<'a>> {
+    let keypair = sender_keypair.map(|(sk, pk)| (sk.as_slice(), pk.as_slice()));
+    Ok(match (keypair, psk_bundle(psk, psk_id)?) {
+        (None, None) => common::SenderMode::Base,
+        (None, Some(bundle)) => common::SenderMode::Psk(bundle),
+        (Some(keypair), None) => common::SenderMode::Auth(keypair),
+        (Some(keypair), Some(bundle)) => common::SenderMode::AuthPsk(keypair, bundle),
+    })
+}
+
+// Picks the receiver mode from whichever of the optional arguments are set
+fn receiver_mode<'a>(
+    psk: Option<&'a [u8]>,
+    psk_id: Option<&'a [u8]>,
+    pk_sender: Option<&'a [u8]>,
+) -> PyResult<common::ReceiverMode<'a>> {
+    Ok(match (pk_sender, psk_bundle(psk, psk_id)?) {
+        (None, None) => common::ReceiverMode::Base,
+        (None, Some(bundle)) => common::ReceiverMode::Psk(bundle),
+        (Some(pk), None) => common::ReceiverMode::Auth(pk),
+        (Some(pk), Some(bundle)) => common::ReceiverMode::AuthPsk(pk, bundle),
+    })
+}
+
+/// The HPKE sender's context. This is what you use to `seal` plaintexts.
+#[pyclass(frozen)]
+pub struct SenderContext(Mutex<Box<dyn common::SenderContext>>);
+
+#[pymethods]
+impl SenderContext {
+    /// Encrypts the given plaintext and returns `ciphertext || tag`
+    fn seal<'py>(
+        &self,
+        py: Python<'py>,
+        plaintext: &[u8],
+        aad: &[u8],
+    ) -> PyResult<Bound<'py, PyBytes>> {
+        let ciphertext = self
+            .0
+            .lock()
+            .unwrap()
+            .seal(plaintext, aad)
+            .map_err(|e| to_py_err(e.into()))?;
+        Ok(PyBytes::new(py, &ciphertext))
+    }
+
+    /// Returns `length` many secret bytes derived from this encryption context
+    fn export<'py>(
+        &self,
+        py: Python<'py>,
+        exporter_context: &[u8],
+        length: usize,
+    ) -> PyResult<Bound<'py, PyBytes>> {
+        let secret = self
+            .0
+            .lock()
+            .unwrap()
+            .export(exporter_context, length)
+            .map_err(|e| to_py_err(e.into()))?;
+        Ok(PyBytes::new(py, &secret))
+    }
+}
+
+/// The HPKE receiver's context. This is what you use to `open` ciphertexts.
+#[pyclass(frozen)]
+pub struct ReceiverContext(Mutex<Box<dyn common::ReceiverContext>>);
+
+#[pymethods]
+impl ReceiverContext {
+    /// Decrypts the given `ciphertext || tag` and returns the plaintext
+    fn open<'py>(
+        &self,
+        py: Python<'py>,
+        ciphertext: &[u8],
+        aad: &[u8],
+    ) -> PyResult<Bound<'py, PyBytes>> {
+        let plaintext = self
+            .0
+            .lock()
+            .unwrap()
+            .open(ciphertext, aad)
+            .map_err(|e| to_py_err(e.into()))?;
+        Ok(PyBytes::new(py, &plaintext))
+    }
+
+    /// Returns `length` many secret bytes derived from this encryption context
+    fn export<'py>(
+        &self,
+        py: Python<'py>,
+        exporter_context: &[u8],
+        length: usize,
+    ) -> PyResult<Bound<'py, PyBytes>> {
+        let secret = self
+            .0
+            .lock()
+            .unwrap()
+            .export(exporter_context, length)
+            .map_err(|e| to_py_err(e.into()))?;
+        Ok(PyBytes::new(py, &secret))
+    }
+}
+
+/// A full HPKE ciphersuite. The operation mode of every method is determined by which of the
+/// keyword arguments `psk`/`psk_id` and `sender_keypair`/`pk_sender` are given.
+#[pyclass(frozen)]
+pub struct CipherSuite(common::Suite);
+
+type KeypairBytes<'py> = (Bound<'py, PyBytes>, Bound<'py, PyBytes>);
+
+#[pymethods]
+impl CipherSuite {
+    #[new]
+    fn new(kem: KemId, kdf: KdfId, aead: AeadId) -> Self {
+        CipherSuite(common::Suite {
+            kem_id: kem as u16,
+            kdf_id: kdf as u16,
+            aead_id: aead as u16,
+        })
+    }
+
+    /// Generates a random keypair. Returns `(private_key, public_key)`.
+    fn generate_keypair<'py>(&self, py: Python<'py>) -> PyResult<KeypairBytes<'py>> {
+        let (sk, pk) = common::gen_keypair(self.0.kem_id).map_err(to_py_err)?;
+        Ok((PyBytes::new(py, &sk), PyBytes::new(py, &pk)))
+    }
+
+    /// Deterministically derives a keypair from the given input keying material. Returns
+    /// `(private_key, public_key)`.
+    fn derive_keypair<'py>(&self, py: Python<'py>, ikm: &[u8]) -> PyResult<KeypairBytes<'py>> {
+        let (sk, pk) = common::derive_keypair(self.0.kem_id, ikm).map_err(to_py_err)?;
+        Ok((PyBytes::new(py, &sk), PyBytes::new(py, &pk)))
+    }
+
+    /// Initiates an encryption context to the given recipient public key. Returns
+    /// `(encapped_key, context)`.
+    #[pyo3(signature = (pk_recip, info, *, psk=None, psk_id=None, sender_keypair=None))]
+    fn setup_sender<'py>(
+        &self,
+        py: Python<'py>,
+        pk_recip: &[u8],
+        info: &[u8],
+        psk: Option<&[u8]>,
+        psk_id: Option<&[u8]>,
+        sender_keypair: Option<(Vec<u8>, Vec<u8>)>,
+    ) -> PyResult<(Bound<'py, PyBytes>, SenderContext)> {
+        let mode = sender_mode(psk, psk_id, sender_keypair.as_ref())?;
+        let (encapped_key, ctx) =
+            common::setup_sender(&self.0, &mode, pk_recip, info).map_err(to_py_err)?;
+        Ok((
+            PyBytes::new(py, &encapped_key),
+            SenderContext(Mutex::new(ctx)),
+        ))
+    }
+
+    /// Initiates a decryption context given the recipient's private key and an encapsulated key
+    #[pyo3(signature = (sk_recip, encapped_key, info, *, psk=None, psk_id=None, pk_sender=None))]
+    fn setup_receiver(
+        &self,
+        sk_recip: &[u8],
+        encapped_key: &[u8],
+        info: &[u8],
+        psk: Option<&[u8]>,
+        psk_id: Option<&[u8]>,
+        pk_sender: Option<&[u8]>,
+    ) -> PyResult<ReceiverContext> {
+        let mode = receiver_mode(psk, psk_id, pk_sender)?;
+        let ctx = common::setup_receiver(&self.0, &mode, sk_recip, encapped_key, info)
+            .map_err(to_py_err)?;
+        Ok(ReceiverContext(Mutex::new(ctx)))
+    }
+
+    /// Does a `setup_sender` and `seal` in one shot. Returns `(encapped_key, ciphertext)`.
+    #[pyo3(signature = (pk_recip, info, plaintext, aad, *, psk=None, psk_id=None, sender_keypair=None))]
+    #[allow(clippy::too_many_arguments)]
+    fn seal<'py>(
+        &self,
+        py: Python<'py>,
+        pk_recip: &[u8],
+        info: &[u8],
+        plaintext: &[u8],
+        aad: &[u8],
+        psk: Option<&[u8]>,
+        psk_id: Option<&[u8]>,
+        sender_keypair: Option<(Vec<u8>, Vec<u8>)>,
+    ) -> PyResult<(Bound<'py, PyBytes>, Bound<'py, PyBytes>)> {
+        let mode = sender_mode(psk, psk_id, sender_keypair.as_ref())?;
+        let (encapped_key, ciphertext) =
+            common::single_shot_seal(&self.0, &mode, pk_recip, info, plaintext, aad)
+                .map_err(to_py_err)?;
+        Ok((
+            PyBytes::new(py, &encapped_key),
+            PyBytes::new(py, &ciphertext),
+        ))
+    }
+
+    /// Does a `setup_receiver` and `open` in one shot. Returns the plaintext.
+    #[pyo3(signature = (sk_recip, encapped_key, info, ciphertext, aad, *, psk=None, psk_id=None, pk_sender=None))]
+    #[allow(clippy::too_many_arguments)]
+    fn open<'py>(
+        &self,
+        py: Python<'py>,
+        sk_recip: &[u8],
+        encapped_key: &[u8],
+        info: &[u8],
+        ciphertext: &[u8],
+        aad: &[u8],
+        psk: Option<&[u8]>,
+        psk_id: Option<&[u8]>,
+        pk_sender: Option<&[u8]>,
+    ) -> PyResult<Bound<'py, PyBytes>> {
+        let mode = receiver_mode(psk, psk_id, pk_sender)?;
+        let plaintext = common::single_shot_open(
+            &self.0,
+            &mode,
+            sk_recip,
+            encapped_key,
+            info,
+            ciphertext,
+            aad,
+        )
+        .map_err(to_py_err)?;
+        Ok(PyBytes::new(py, &plaintext))
+    }
+}
+
+#[pymodule]
+fn rust_hpke(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("HpkeError", m.py().get_type::<HpkeError>())?;
+    m.add_class::<KemId>()?;
+    m.add_class::<KdfId>()?;
+    m.add_class::<AeadId>()?;
+    m.add_class::<CipherSuite>()?;
+    m.add_class::<SenderContext>()?;
+    m.add_class::<ReceiverContext>()?;
+    Ok(())
+}
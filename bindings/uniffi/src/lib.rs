@@ -0,0 +1,564 @@
+//! # hpke-uniffi
+//! [UniFFI](https://mozilla.github.io/uniffi-rs/) bindings for the `hpke` crate. This exposes
+//! keypair generation, the single-shot API, and the context API to Kotlin and Swift (and anything
+//! else UniFFI can target), so that mobile apps use the exact same implementation as everything
+//! else.
+//!
+//! The ciphersuite is given at runtime as a `Suite` record, and every call is dispatched to the
+//! appropriate monomorphized function by `hpke-bindings-common`. Keys, encapsulated keys, and
+//! ciphertexts all cross the FFI boundary as plain byte strings. Ciphertexts are of the form
+//! `ciphertext || tag`.
+//!
+//! To generate the foreign-language sources, build this crate and run
+//! `cargo run -p hpke-uniffi --features=hpke-uniffi/cli --bin uniffi-bindgen -- generate --library
+//! path/to/libhpke_uniffi.so --language kotlin --out-dir out/`
+
+// The FFI glue that the uniffi macros generate is this crate's only unsafe code. Hand-written code
+// here doesn't get to add more.
+#![deny(unsafe_code)]
+
+use std::sync::{Arc, Mutex};
+
+use hpke::{
+    aead::{Aead as AeadTrait, AesGcm128, AesGcm256, ChaCha20Poly1305},
+    kdf::{HkdfSha256, HkdfSha384, HkdfSha512, Kdf as KdfTrait},
+    kem::{DhP256HkdfSha256, Kem as KemTrait, X25519HkdfSha256},
+    PskBundle,
+};
+use hpke_bindings_common::{self as common, BindingError};
+
+uniffi::setup_scaffolding!();
+
+/// Describes things that can go wrong in an HPKE operation. This mirrors `hpke::HpkeError`.
+#[derive(Debug, uniffi::Error)]
+pub enum HpkeError {
+    /// The nonce sequence counter has overflowed
+    SeqOverflow,
+    /// The authentication tag was invalid when opening
+    InvalidTag,
+    /// An unspecified error occured during encryption
+    Encryption,
+    /// A key exchange input or output was invalid
+    InvalidKeyExchange,
+    /// The KDF was asked to output too many bytes
+    InvalidKdfLength,
+    /// The deserializer was given a bad encoding
+    InvalidEncoding,
+    /// The recipient has no private key with the given key ID
+    UnknownKeyId,
+    /// A signature failed to verify
+    InvalidSignature,
+    /// The encapsulated key was already seen by a replay cache
+    ReplayedKey,
+    /// The peer's key confirmation tag didn't match
+    ConfirmationFailed,
+    /// The deserializer was given a well-formed encoding that isn't canonical
+    NonCanonicalEncoding,
+    /// The deserializer was given a canonical encoding of an invalid public key
+    InvalidPoint,
+    /// The output doesn't fit in its fixed-capacity buffer
+    BufferTooSmall,
+    /// The KEM can't do the requested mode
+    UnsupportedMode,
+    /// The message names a different ciphersuite than the one opening it
+    SuiteMismatch,
+    /// A key was used outside of its validity period
+    KeyExpired,
+    /// The sequence number was already used under this context
+    ReusedSeq,
+}
+
+impl From<hpke::HpkeError> for HpkeError {
+    fn from(e: hpke::HpkeError) -> HpkeError {
+        match e {
+            hpke::HpkeError::SeqOverflow => HpkeError::SeqOverflow,
+            hpke::HpkeError::InvalidTag => HpkeError::InvalidTag,
+            hpke::HpkeError::Encryption => HpkeError::Encryption,
+            hpke::HpkeError::InvalidKeyExchange => HpkeError::InvalidKeyExchange,
+            hpke::HpkeError::InvalidKdfLength => HpkeError::InvalidKdfLength,
+            hpke::HpkeError::InvalidEncoding => HpkeError::InvalidEncoding,
+            hpke::HpkeError::UnknownKeyId => HpkeError::UnknownKeyId,
+            hpke::HpkeError::InvalidSignature => HpkeError::InvalidSignature,
+            hpke::HpkeError::ReplayedKey => HpkeError::ReplayedKey,
+            hpke::HpkeError::ConfirmationFailed => HpkeError::ConfirmationFailed,
+            hpke::HpkeError::NonCanonicalEncoding => HpkeError::NonCanonicalEncoding,
+            hpke::HpkeError::InvalidPoint => HpkeError::InvalidPoint,
+            hpke::HpkeError::BufferTooSmall => HpkeError::BufferTooSmall,
+            hpke::HpkeError::UnsupportedMode => HpkeError::UnsupportedMode,
+            hpke::HpkeError::SuiteMismatch => HpkeError::SuiteMismatch,
+            hpke::HpkeError::KeyExpired => HpkeError::KeyExpired,
+            hpke::HpkeError::ReusedSeq => HpkeError::ReusedSeq,
+        }
+    }
+}
+
+impl From<BindingError> for HpkeError {
+    fn from(e: BindingError) -> HpkeError {
+        match e {
+            BindingError::Hpke(e) => e.into(),
+            // Every Kem, Kdf, and Aead value maps to a supported algorithm ID
+            BindingError::UnknownAlgorithm(..) => unreachable!("{}", e),
+        }
+    }
+}
+
+// uniffi needs errors to be Display so it can give foreign exceptions a message
+impl std::fmt::Display for HpkeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let e = match self {
+            HpkeError::SeqOverflow => hpke::HpkeError::SeqOverflow,
+            HpkeError::InvalidTag => hpke::HpkeError::InvalidTag,
+            HpkeError::Encryption => hpke::HpkeError::Encryption,
+            HpkeError::InvalidKeyExchange => hpke::HpkeError::InvalidKeyExchange,
+            HpkeError::InvalidKdfLength => hpke::HpkeError::InvalidKdfLength,
+            HpkeError::InvalidEncoding => hpke::HpkeError::InvalidEncoding,
+            HpkeError::UnknownKeyId => hpke::HpkeError::UnknownKeyId,
+            HpkeError::InvalidSignature => hpke::HpkeError::InvalidSignature,
+            HpkeError::ReplayedKey => hpke::HpkeError::ReplayedKey,
+            HpkeError::ConfirmationFailed => hpke::HpkeError::ConfirmationFailed,
+            HpkeError::NonCanonicalEncoding => hpke::HpkeError::NonCanonicalEncoding,
+            HpkeError::InvalidPoint => hpke::HpkeError::InvalidPoint,
+            HpkeError::BufferTooSmall => hpke::HpkeError::BufferTooSmall,
+            HpkeError::UnsupportedMode => hpke::HpkeError::UnsupportedMode,
+            HpkeError::SuiteMismatch => hpke::HpkeError::SuiteMismatch,
+            HpkeError::KeyExpired => hpke::HpkeError::KeyExpired,
+            HpkeError::ReusedSeq => hpke::HpkeError::ReusedSeq,
+        };
+        e.fmt(f)
+    }
+}
+
+impl std::error::Error for HpkeError {}
+
+/// The supported KEMs
+#[derive(Clone, Copy, Debug, uniffi::Enum)]
+pub enum Kem {
+    /// DHKEM(X25519, HKDF-SHA256)
+    X25519HkdfSha256,
+    /// DHKEM(P-256, HKDF-SHA256)
+    DhP256HkdfSha256,
+}
+
+impl Kem {
+    fn id(self) -> u16 {
+        match self {
+            Kem::X25519HkdfSha256 => X25519HkdfSha256::KEM_ID,
+            Kem::DhP256HkdfSha256 => DhP256HkdfSha256::KEM_ID,
+        }
+    }
+}
+
+/// The supported KDFs
+#[derive(Clone, Copy, Debug, uniffi::Enum)]
+pub enum Kdf {
+    /// HKDF-SHA256
+    HkdfSha256,
+    /// HKDF-SHA384
+    HkdfSha384,
+    /// HKDF-SHA512
+    HkdfSha512,
+}
+
+impl Kdf {
+    fn id(self) -> u16 {
+        match self {
+            Kdf::HkdfSha256 => HkdfSha256::KDF_ID,
+            Kdf::HkdfSha384 => HkdfSha384::KDF_ID,
+            Kdf::HkdfSha512 => HkdfSha512::KDF_ID,
+        }
+    }
+}
+
+/// The supported AEADs
+#[derive(Clone, Copy, Debug, uniffi::Enum)]
+pub enum Aead {
+    /// AES-GCM-128
+    AesGcm128,
+    /// AES-GCM-256
+    AesGcm256,
+    /// ChaCha20Poly1305
+    ChaCha20Poly1305,
+}
+
+impl Aead {
+    fn id(self) -> u16 {
+        match self {
+            Aead::AesGcm128 => AesGcm128::AEAD_ID,
+            Aead::AesGcm256 => AesGcm256::AEAD_ID,
+            Aead::ChaCha20Poly1305 => ChaCha20Poly1305::AEAD_ID,
+        }
+    }
+}
+
+/// A full HPKE ciphersuite
+#[derive(Clone, Copy, Debug, uniffi::Record)]
+pub struct Suite {
+    pub kem: Kem,
+    pub kdf: Kdf,
+    pub aead: Aead,
+}
+
+impl From<Suite> for common::Suite {
+    fn from(suite: Suite) -> common::Suite {
+        common::Suite {
+            kem_id: suite.kem.id(),
+            kdf_id: suite.kdf.id(),
+            aead_id: suite.aead.id(),
+        }
+    }
+}
+
+/// A serialized keypair
+#[derive(Clone, uniffi::Record)]
+pub struct Keypair {
+    pub private_key: Vec<u8>,
+    pub public_key: Vec<u8>,
+}
+
+/// The operation mode of the HPKE session (sender's view). See `hpke::OpModeS`.
+#[derive(Clone, uniffi::Enum)]
+pub enum SenderMode {
+    /// No extra information included
+    Base,
+    /// A preshared key known to the sender and receiver
+    Psk { psk: Vec<u8>, psk_id: Vec<u8> },
+    /// The identity keypair of the sender
+    Auth {
+        sender_private_key: Vec<u8>,
+        sender_public_key: Vec<u8>,
+    },
+    /// Both of the above
+    AuthPsk {
+        sender_private_key: Vec<u8>,
+        sender_public_key: Vec<u8>,
+        psk: Vec<u8>,
+        psk_id: Vec<u8>,
+    },
+}
+
+impl SenderMode {
+    // Borrows this as the mode type the dispatch layer expects
+    fn as_common(&self) -> common::SenderMode<'_> {
+        match self {
+            SenderMode::Base => common::SenderMode::Base,
+            SenderMode::Psk { psk, psk_id } => common::SenderMode::Psk(PskBundle { psk, psk_id }),
+            SenderMode::Auth {
+                sender_private_key,
+                sender_public_key,
+            } => common::SenderMode::Auth((sender_private_key, sender_public_key)),
+            SenderMode::AuthPsk {
+                sender_private_key,
+                sender_public_key,
+                psk,
+                psk_id,
+            } => common::SenderMode::AuthPsk(
+                (sender_private_key, sender_public_key),
+                PskBundle { psk, psk_id },
+            ),
+        }
+    }
+}
+
+/// The operation mode of the HPKE session (receiver's view). See `hpke::OpModeR`.
+#[derive(Clone, uniffi::Enum)]
+pub enum ReceiverMode {
+    /// No extra information included
+    Base,
+    /// A preshared key known to the sender and receiver
+    Psk { psk: Vec<u8>, psk_id: Vec<u8> },
+    /// The identity public key of the sender
+    Auth { sender_public_key: Vec<u8> },
+    /// Both of the above
+    AuthPsk {
+        sender_public_key: Vec<u8>,
+        psk: Vec<u8>,
+        psk_id: Vec<u8>,
+    },
+}
+
+impl ReceiverMode {
+    // Borrows this as the mode type the dispatch layer expects
+    fn as_common(&self) -> common::ReceiverMode<'_> {
+        match self {
+            ReceiverMode::Base => common::ReceiverMode::Base,
+            ReceiverMode::Psk { psk, psk_id } => {
+                common::ReceiverMode::Psk(PskBundle { psk, psk_id })
+            }
+            ReceiverMode::Auth { sender_public_key } => {
+                common::ReceiverMode::Auth(sender_public_key)
+            }
+            ReceiverMode::AuthPsk {
+                sender_public_key,
+                psk,
+                psk_id,
+            } => common::ReceiverMode::AuthPsk(sender_public_key, PskBundle { psk, psk_id }),
+        }
+    }
+}
+
+/// The output of a single-shot seal
+#[derive(Clone, uniffi::Record)]
+pub struct SealedMessage {
+    pub encapped_key: Vec<u8>,
+    /// The ciphertext with the authentication tag appended
+    pub ciphertext: Vec<u8>,
+}
+
+/// The output of `setup_sender`
+#[derive(uniffi::Record)]
+pub struct SenderSetup {
+    pub encapped_key: Vec<u8>,
+    pub context: Arc<SenderContext>,
+}
+
+/// The HPKE sender's context. This is what you use to `seal` plaintexts.
+#[derive(uniffi::Object)]
+pub struct SenderContext(Mutex<Box<dyn common::SenderContext>>);
+
+#[uniffi::export]
+impl SenderContext {
+    /// Encrypts the given plaintext and returns `ciphertext || tag`
+    pub fn seal(&self, plaintext: Vec<u8>, aad: Vec<u8>) -> Result<Vec<u8>, HpkeError> {
+        let mut ctx = self.0.lock().unwrap();
+        Ok(ctx.seal(&plaintext, &aad)?)
+    }
+
+    /// Returns `out_len` many secret bytes derived from this encryption context
+    pub fn export(&self, exporter_ctx: Vec<u8>, out_len: u32) -> Result<Vec<u8>, HpkeError> {
+        let ctx = self.0.lock().unwrap();
+        Ok(ctx.export(&exporter_ctx, out_len as usize)?)
+    }
+}
+
+/// The HPKE receiver's context. This is what you use to `open` ciphertexts.
+#[derive(uniffi::Object)]
+pub struct ReceiverContext(Mutex<Box<dyn common::ReceiverContext>>);
+
+#[uniffi::export]
+impl ReceiverContext {
+    /// Decrypts the given `ciphertext || tag` and returns the plaintext
+    pub fn open(&self, ciphertext: Vec<u8>, aad: Vec<u8>) -> Result<Vec<u8>, HpkeError> {
+        let mut ctx = self.0.lock().unwrap();
+        Ok(ctx.open(&ciphertext, &aad)?)
+    }
+
+    /// Returns `out_len` many secret bytes derived from this encryption context
+    pub fn export(&self, exporter_ctx: Vec<u8>, out_len: u32) -> Result<Vec<u8>, HpkeError> {
+        let ctx = self.0.lock().unwrap();
+        Ok(ctx.export(&exporter_ctx, out_len as usize)?)
+    }
+}
+
+/// Generates a random keypair for the given KEM
+#[uniffi::export]
+pub fn generate_keypair(kem: Kem) -> Keypair {
+    let (private_key, public_key) = common::gen_keypair(kem.id()).expect("KEM is supported");
+    Keypair {
+        private_key,
+        public_key,
+    }
+}
+
+/// Deterministically derives a keypair for the given KEM from the given input keying material
+#[uniffi::export]
+pub fn derive_keypair(kem: Kem, ikm: Vec<u8>) -> Keypair {
+    let (private_key, public_key) =
+        common::derive_keypair(kem.id(), &ikm).expect("KEM is supported");
+    Keypair {
+        private_key,
+        public_key,
+    }
+}
+
+/// Initiates an encryption context to the given recipient public key. Returns the encapsulated
+/// key (to be sent to the recipient) and the encryption context.
+#[uniffi::export]
+pub fn setup_sender(
+    suite: Suite,
+    mode: SenderMode,
+    recipient_public_key: Vec<u8>,
+    info: Vec<u8>,
+) -> Result<SenderSetup, HpkeError> {
+    let (encapped_key, ctx) = common::setup_sender(
+        &suite.into(),
+        &mode.as_common(),
+        &recipient_public_key,
+        &info,
+    )?;
+
+    Ok(SenderSetup {
+        encapped_key,
+        context: Arc::new(SenderContext(Mutex::new(ctx))),
+    })
+}
+
+/// Initiates a decryption context given the recipient's private key and an encapsulated key
+#[uniffi::export]
+pub fn setup_receiver(
+    suite: Suite,
+    mode: ReceiverMode,
+    recipient_private_key: Vec<u8>,
+    encapped_key: Vec<u8>,
+    info: Vec<u8>,
+) -> Result<Arc<ReceiverContext>, HpkeError> {
+    let ctx = common::setup_receiver(
+        &suite.into(),
+        &mode.as_common(),
+        &recipient_private_key,
+        &encapped_key,
+        &info,
+    )?;
+
+    Ok(Arc::new(ReceiverContext(Mutex::new(ctx))))
+}
+
+/// Does a `setup_sender` and `seal` in one shot
+#[uniffi::export]
+pub fn single_shot_seal(
+    suite: Suite,
+    mode: SenderMode,
+    recipient_public_key: Vec<u8>,
+    info: Vec<u8>,
+    plaintext: Vec<u8>,
+    aad: Vec<u8>,
+) -> Result<SealedMessage, HpkeError> {
+    let (encapped_key, ciphertext) = common::single_shot_seal(
+        &suite.into(),
+        &mode.as_common(),
+        &recipient_public_key,
+        &info,
+        &plaintext,
+        &aad,
+    )?;
+
+    Ok(SealedMessage {
+        encapped_key,
+        ciphertext,
+    })
+}
+
+/// Does a `setup_receiver` and `open` in one shot. `ciphertext` is of the form
+/// `ciphertext || tag`.
+#[uniffi::export]
+pub fn single_shot_open(
+    suite: Suite,
+    mode: ReceiverMode,
+    recipient_private_key: Vec<u8>,
+    encapped_key: Vec<u8>,
+    info: Vec<u8>,
+    ciphertext: Vec<u8>,
+    aad: Vec<u8>,
+) -> Result<Vec<u8>, HpkeError> {
+    Ok(common::single_shot_open(
+        &suite.into(),
+        &mode.as_common(),
+        &recipient_private_key,
+        &encapped_key,
+        &info,
+        &ciphertext,
+        &aad,
+    )?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Tests that the exported API round-trips for every mode
+    #[test]
+    fn test_ctx_correctness() {
+        let suite = Suite {
+            kem: Kem::DhP256HkdfSha256,
+            kdf: Kdf::HkdfSha384,
+            aead: Aead::AesGcm256,
+        };
+        let recip = generate_keypair(suite.kem);
+        let sender = generate_keypair(suite.kem);
+        let info = b"hpke-uniffi test".to_vec();
+
+        let sender_mode = SenderMode::AuthPsk {
+            sender_private_key: sender.private_key,
+            sender_public_key: sender.public_key.clone(),
+            psk: b"a very secret psk".to_vec(),
+            psk_id: b"psk1".to_vec(),
+        };
+        let receiver_mode = ReceiverMode::AuthPsk {
+            sender_public_key: sender.public_key,
+            psk: b"a very secret psk".to_vec(),
+            psk_id: b"psk1".to_vec(),
+        };
+
+        let setup = setup_sender(suite, sender_mode, recip.public_key, info.clone()).unwrap();
+        let receiver_ctx = setup_receiver(
+            suite,
+            receiver_mode,
+            recip.private_key,
+            setup.encapped_key,
+            info,
+        )
+        .unwrap();
+
+        let ciphertext = setup
+            .context
+            .seal(b"hello".to_vec(), b"aad".to_vec())
+            .unwrap();
+        let plaintext = receiver_ctx.open(ciphertext, b"aad".to_vec()).unwrap();
+        assert_eq!(plaintext, b"hello");
+
+        // A second open of the same ciphertext must fail, since the sequence number moved on
+        let ciphertext = setup
+            .context
+            .seal(b"hello".to_vec(), b"aad".to_vec())
+            .unwrap();
+        receiver_ctx
+            .open(ciphertext.clone(), b"aad".to_vec())
+            .unwrap();
+        assert!(matches!(
+            receiver_ctx.open(ciphertext, b"aad".to_vec()),
+            Err(HpkeError::InvalidTag)
+        ));
+    }
+
+    /// Tests the lifecycle of context handles the way a foreign runtime drives it: handles are
+    /// cloned, used from another thread, and freed in an arbitrary order. This is plain Rust, so
+    /// it runs under Miri (`cargo +nightly miri test -p hpke-uniffi`).
+    #[test]
+    fn test_handle_lifecycle() {
+        let suite = Suite {
+            kem: Kem::X25519HkdfSha256,
+            kdf: Kdf::HkdfSha256,
+            aead: Aead::ChaCha20Poly1305,
+        };
+        let recip = derive_keypair(suite.kem, b"handle lifecycle".to_vec());
+        let info = b"hpke-uniffi handles".to_vec();
+
+        let setup = setup_sender(suite, SenderMode::Base, recip.public_key, info.clone()).unwrap();
+        let receiver_ctx = setup_receiver(
+            suite,
+            ReceiverMode::Base,
+            recip.private_key,
+            setup.encapped_key,
+            info,
+        )
+        .unwrap();
+
+        // The foreign side holds its own reference to the sender, and seals on another thread
+        let sender_handle = Arc::clone(&setup.context);
+        let ciphertext = std::thread::spawn(move || {
+            sender_handle
+                .seal(b"across threads".to_vec(), b"".to_vec())
+                .unwrap()
+        })
+        .join()
+        .unwrap();
+        assert_eq!(Arc::strong_count(&setup.context), 1);
+
+        // Free the sender before the receiver is done
+        drop(setup.context);
+        let receiver_handle = Arc::clone(&receiver_ctx);
+        drop(receiver_ctx);
+        let plaintext = receiver_handle.open(ciphertext, b"".to_vec()).unwrap();
+        assert_eq!(plaintext, b"across threads");
+        assert_eq!(Arc::strong_count(&receiver_handle), 1);
+    }
+}
@@ -269,8 +269,8 @@ struct AgileEncappedKey {
 }
 
 impl AgileEncappedKey {
-    fn try_lift<Kex: KeyExchange>(&self) -> Result<EncappedKey<Kex>, AgileHpkeError> {
-        EncappedKey::<Kex>::from_bytes(&self.encapped_key_bytes).map_err(|e| e.into())
+    fn try_lift<Kem: KemTrait>(&self) -> Result<EncappedKey<Kem>, AgileHpkeError> {
+        EncappedKey::<Kem>::from_bytes(&self.encapped_key_bytes).map_err(|e| e.into())
     }
 }
 
@@ -634,7 +634,7 @@ where
 {
     let mode = mode.clone().try_lift::<Kem::Kex, Kdf>()?;
     let (sk_recip, _) = recip_keypair.try_lift::<Kem::Kex>()?;
-    let encapped_key = encapped_key.try_lift::<Kem::Kex>()?;
+    let encapped_key = encapped_key.try_lift::<Kem>()?;
 
     let aead_ctx = setup_receiver::<A, Kdf, Kem>(&mode, &sk_recip, &encapped_key, info)?;
     Ok(Box::new(aead_ctx))
@@ -50,7 +50,7 @@ fn client_encrypt_msg(
     msg: &[u8],
     associated_data: &[u8],
     server_pk: &<Kex as KeyExchange>::PublicKey,
-) -> (EncappedKey<Kex>, Vec<u8>, AeadTag<Aead>) {
+) -> (EncappedKey<Kem>, Vec<u8>, AeadTag<Aead>) {
     let mut csprng = StdRng::from_entropy();
 
     // Encapsulate a key and use the resulting shared secret to encrypt a message. The AEAD context
@@ -84,7 +84,7 @@ fn server_decrypt_msg(
     let server_sk = <Kex as KeyExchange>::PrivateKey::from_bytes(server_sk_bytes)
         .expect("could not deserialize server privkey!");
     let tag = AeadTag::<Aead>::from_bytes(tag_bytes).expect("could not deserialize AEAD tag!");
-    let encapped_key = EncappedKey::<Kex>::from_bytes(encapped_key_bytes)
+    let encapped_key = EncappedKey::<Kem>::from_bytes(encapped_key_bytes)
         .expect("could not deserialize the encapsulated pubkey!");
 
     // Decapsulate and derive the shared secret. This creates a shared AEAD context.
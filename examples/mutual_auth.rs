@@ -0,0 +1,247 @@
+// This file is a template for a two-message, mutually authenticated handshake between two parties
+// who know each other's public keys and share a PSK. Here's the flow:
+//  1. The initiator opens an HpkeChannel to the responder in AuthPsk mode, and sends the
+//     encapsulated key along with a confirmation message sent over the channel.
+//  2. The responder accepts the channel, using the initiator's public key and the PSK. If the
+//     confirmation message decrypts, the initiator holds its private key and the PSK. The responder
+//     then opens its own AuthPsk channel back to the initiator. Its info string includes the
+//     initiator's encapsulated key, so the reply is bound to this handshake and can't be replayed
+//     into another. It sends that encapsulated key along with a confirmation message of its own.
+//  3. The initiator accepts the responder's channel, using the responder's public key, the PSK,
+//     and the same info string. If the confirmation message decrypts, the responder holds its
+//     private key and the PSK, and answered this very handshake.
+//
+// Afterwards, each party sends over the channel it opened and receives over the channel it
+// accepted, so each direction is keyed by its sender's AuthPsk setup.
+//
+// Things to know before using this:
+//  * The responder can't tell a fresh first message from a replayed one by itself, since it
+//    contributes no randomness until the second message. That's why it keeps a ReplayCache of the
+//    initiator's encapsulated keys. The initiator's messages should also carry a timestamp, so that
+//    the responder can drop any that are older than the cache's window.
+//  * Neither side has forward secrecy with respect to its static key. Anyone who later steals the
+//    responder's private key and the PSK can decrypt what the initiator sent.
+//  * Error handling is minimal. Everything here panics when an error is encountered, except for the
+//    checks that a real implementation has to make, which return errors.
+
+use hpke::{
+    aead::ChaCha20Poly1305, channel::HpkeChannel, kdf::HkdfSha256, kem::X25519HkdfSha256,
+    kex::KeyExchange, Deserializable, EncappedKey, HpkeError, InfoBuilder, Kem as KemTrait,
+    OpModeR, OpModeS, PskBundle, ReplayCache, Serializable,
+};
+
+use rand::{rngs::StdRng, SeedableRng};
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// These are the only algorithms we're gonna use for this example
+type Kem = X25519HkdfSha256;
+type Aead = ChaCha20Poly1305;
+type Kdf = HkdfSha256;
+
+// The KEX is dependent on the choice of KEM
+type Kex = <Kem as KemTrait>::Kex;
+type PrivateKey = <Kex as KeyExchange>::PrivateKey;
+type PublicKey = <Kex as KeyExchange>::PublicKey;
+type Channel = HpkeChannel<Aead, Kdf, Kem>;
+
+// The name of the protocol, which goes at the start of both info strings
+const PROTOCOL: &str = "rust-hpke mutual auth example/v1";
+
+// What each side sends over its channel to show that it derived the same keys
+const INITIATOR_CONFIRM: &[u8] = b"initiator confirm";
+const RESPONDER_CONFIRM: &[u8] = b"responder confirm";
+
+// How long the responder remembers initiators' encapsulated keys, in seconds
+const REPLAY_WINDOW: u64 = 300;
+
+// The info string of the initiator's channel
+fn initiator_info() -> Vec<u8> {
+    InfoBuilder::for_protocol(PROTOCOL)
+        .field(b"initiator")
+        .build()
+}
+
+// The info string of the responder's channel, which binds it to the initiator's encapsulated key
+fn responder_info(initiator_enc: &[u8]) -> Vec<u8> {
+    InfoBuilder::for_protocol(PROTOCOL)
+        .field(b"responder")
+        .field(initiator_enc)
+        .build()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("clock is before 1970")
+        .as_secs()
+}
+
+// A message of the handshake: an encapsulated key and a confirmation message
+struct HandshakeMsg {
+    enc: Vec<u8>,
+    confirm: Vec<u8>,
+}
+
+// A party's long-term identity
+struct Identity {
+    sk: PrivateKey,
+    pk: PublicKey,
+}
+
+// The result of a handshake. Each side sends over the channel it opened and receives over the one
+// it accepted.
+struct Session {
+    outbound: Channel,
+    inbound: Channel,
+}
+
+impl Session {
+    fn send(&mut self, plaintext: &[u8]) -> Vec<u8> {
+        self.outbound.send(plaintext).expect("encryption failed!")
+    }
+
+    fn recv(&mut self, frame: &[u8]) -> Result<Vec<u8>, HpkeError> {
+        self.inbound.recv(frame)
+    }
+}
+
+// The initiator's state between sending the first message and receiving the second
+struct PendingInitiator {
+    outbound: Channel,
+    enc: Vec<u8>,
+}
+
+// Step 1: the initiator opens its channel and sends the first message
+fn initiator_start(
+    me: &Identity,
+    responder_pk: &PublicKey,
+    psk: &PskBundle,
+) -> (PendingInitiator, HandshakeMsg) {
+    let mut csprng = StdRng::from_entropy();
+    let mode = OpModeS::AuthPsk((me.sk.clone(), me.pk.clone()), *psk);
+    let (enc, mut outbound) =
+        Channel::initiate(&mode, responder_pk, &initiator_info(), &mut csprng)
+            .expect("invalid responder pubkey!");
+    let confirm = outbound
+        .send(INITIATOR_CONFIRM)
+        .expect("encryption failed!");
+
+    let enc = enc.to_bytes().to_vec();
+    let msg = HandshakeMsg {
+        enc: enc.clone(),
+        confirm,
+    };
+    (PendingInitiator { outbound, enc }, msg)
+}
+
+// Step 2: the responder checks the first message, opens its own channel, and sends the second
+// message. Fails if the first message was replayed, or wasn't made by the holder of the
+// initiator's private key and the PSK.
+fn responder_respond(
+    me: &Identity,
+    initiator_pk: &PublicKey,
+    psk: &PskBundle,
+    replay_cache: &mut ReplayCache,
+    msg: &HandshakeMsg,
+) -> Result<(Session, HandshakeMsg), HpkeError> {
+    let initiator_enc = EncappedKey::<Kem>::from_bytes(&msg.enc)?;
+    let mode = OpModeR::AuthPsk(initiator_pk.clone(), *psk);
+    let mut inbound = Channel::accept(&mode, &me.sk, &initiator_enc, &initiator_info())?;
+    if inbound.recv(&msg.confirm)? != INITIATOR_CONFIRM {
+        return Err(HpkeError::InvalidTag);
+    }
+    // Only remember the key once the message checks out, so that nobody can block a handshake by
+    // sending its encapsulated key first
+    replay_cache.check_and_insert(&msg.enc, now())?;
+
+    let mut csprng = StdRng::from_entropy();
+    let mode = OpModeS::AuthPsk((me.sk.clone(), me.pk.clone()), *psk);
+    let (enc, mut outbound) =
+        Channel::initiate(&mode, initiator_pk, &responder_info(&msg.enc), &mut csprng)?;
+    let confirm = outbound.send(RESPONDER_CONFIRM)?;
+
+    let reply = HandshakeMsg {
+        enc: enc.to_bytes().to_vec(),
+        confirm,
+    };
+    Ok((Session { outbound, inbound }, reply))
+}
+
+// Step 3: the initiator checks the second message. Fails if it wasn't made by the holder of the
+// responder's private key and the PSK, in answer to this initiator's first message.
+fn initiator_finish(
+    me: &Identity,
+    responder_pk: &PublicKey,
+    psk: &PskBundle,
+    pending: PendingInitiator,
+    msg: &HandshakeMsg,
+) -> Result<Session, HpkeError> {
+    let responder_enc = EncappedKey::<Kem>::from_bytes(&msg.enc)?;
+    let mode = OpModeR::AuthPsk(responder_pk.clone(), *psk);
+    let mut inbound =
+        Channel::accept(&mode, &me.sk, &responder_enc, &responder_info(&pending.enc))?;
+    if inbound.recv(&msg.confirm)? != RESPONDER_CONFIRM {
+        return Err(HpkeError::InvalidTag);
+    }
+
+    Ok(Session {
+        outbound: pending.outbound,
+        inbound,
+    })
+}
+
+fn gen_identity() -> Identity {
+    let mut csprng = StdRng::from_entropy();
+    let (sk, pk) = Kem::gen_keypair(&mut csprng);
+    Identity { sk, pk }
+}
+
+fn main() {
+    // Both parties know each other's public keys and share a PSK. In practice, the PSK should have
+    // at least 32 bytes of entropy.
+    let initiator = gen_identity();
+    let responder = gen_identity();
+    let psk = PskBundle {
+        psk: b"a shared secret with plenty of entropy",
+        psk_id: b"example psk",
+    };
+    let mut replay_cache = ReplayCache::new(REPLAY_WINDOW);
+
+    // Run the handshake
+    let (pending, msg1) = initiator_start(&initiator, &responder.pk, &psk);
+    let (mut responder_session, msg2) =
+        responder_respond(&responder, &initiator.pk, &psk, &mut replay_cache, &msg1)
+            .expect("responder rejected the handshake!");
+    let mut initiator_session = initiator_finish(&initiator, &responder.pk, &psk, pending, &msg2)
+        .expect("initiator rejected the handshake!");
+
+    // Now both sides can talk
+    let frame = initiator_session.send(b"hello from the initiator");
+    assert_eq!(
+        responder_session.recv(&frame).unwrap(),
+        b"hello from the initiator"
+    );
+    let frame = responder_session.send(b"hello from the responder");
+    assert_eq!(
+        initiator_session.recv(&frame).unwrap(),
+        b"hello from the responder"
+    );
+
+    // A replayed first message is rejected
+    let replayed = responder_respond(&responder, &initiator.pk, &psk, &mut replay_cache, &msg1);
+    assert!(matches!(replayed, Err(HpkeError::ReplayedKey)));
+
+    // So is a first message from someone who doesn't hold the initiator's key
+    let impostor = gen_identity();
+    let (_, forged) = initiator_start(&impostor, &responder.pk, &psk);
+    let forged = responder_respond(&responder, &initiator.pk, &psk, &mut replay_cache, &forged);
+    assert!(forged.is_err());
+
+    // And a second message that answers a different first message
+    let (other_pending, _) = initiator_start(&initiator, &responder.pk, &psk);
+    let mismatched = initiator_finish(&initiator, &responder.pk, &psk, other_pending, &msg2);
+    assert!(mismatched.is_err());
+
+    println!("HANDSHAKE SUCCESSFULLY COMPLETED");
+}
@@ -7,12 +7,17 @@ use crate::{
     HpkeError,
 };
 
+#[cfg(feature = "danger")]
+use crate::seq_guard::SeqGuard;
+
+use alloc::vec::Vec;
 use core::{marker::PhantomData, u8};
 
 use aead::{AeadInPlace as BaseAead, NewAead as BaseNewAead};
-use byteorder::{BigEndian, ByteOrder};
-use generic_array::GenericArray;
+use generic_array::{typenum, GenericArray};
 use hkdf::Hkdf;
+use subtle::ConstantTimeEq;
+use zeroize::Zeroize;
 
 /// Represents authenticated encryption functionality
 pub trait Aead {
@@ -21,31 +26,48 @@ pub trait Aead {
 
     /// The algorithm identifier for an AEAD implementation
     const AEAD_ID: u16;
+
+    /// Whether `AeadImpl` leaves the buffer untouched when an in-place encryption or decryption
+    /// fails. If this is `false`, the `restore-buffers` feature makes `seal` and `open` work on a
+    /// scratch copy, so the caller's buffer is still untouched on error.
+    const UNTOUCHED_ON_ERROR: bool = true;
 }
 
+#[cfg(feature = "aes-gcm")]
 /// The implementation of AES-GCM-128
 pub struct AesGcm128 {}
 
+#[cfg(feature = "aes-gcm")]
 impl Aead for AesGcm128 {
+    #[cfg(not(feature = "aes-force-soft"))]
     type AeadImpl = aes_gcm::Aes128Gcm;
+    #[cfg(feature = "aes-force-soft")]
+    type AeadImpl = aes_gcm::AesGcm<aes_soft::Aes128, generic_array::typenum::U12>;
 
     // draft02 §8.3: AES-GCM-128
     const AEAD_ID: u16 = 0x0001;
 }
 
-/// The implementation of AES-GCM-128
+#[cfg(feature = "aes-gcm")]
+/// The implementation of AES-GCM-256
 pub struct AesGcm256 {}
 
+#[cfg(feature = "aes-gcm")]
 impl Aead for AesGcm256 {
+    #[cfg(not(feature = "aes-force-soft"))]
     type AeadImpl = aes_gcm::Aes256Gcm;
+    #[cfg(feature = "aes-force-soft")]
+    type AeadImpl = aes_gcm::AesGcm<aes_soft::Aes256, generic_array::typenum::U12>;
 
     // draft02 §8.3: AES-GCM-256
     const AEAD_ID: u16 = 0x0002;
 }
 
+#[cfg(feature = "chacha20poly1305")]
 /// The implementation of ChaCha20-Poly1305
 pub struct ChaCha20Poly1305 {}
 
+#[cfg(feature = "chacha20poly1305")]
 impl Aead for ChaCha20Poly1305 {
     type AeadImpl = chacha20poly1305::ChaCha20Poly1305;
 
@@ -53,6 +75,157 @@ impl Aead for ChaCha20Poly1305 {
     const AEAD_ID: u16 = 0x0003;
 }
 
+/// The export-only AEAD. A context with this AEAD can only [`export`](AeadCtxS::export). Its
+/// `seal` fails with `HpkeError::Encryption`, and its `open` fails with `HpkeError::InvalidTag`.
+/// This is for protocols that only want a shared secret out of HPKE, like
+/// [`KeyTransport`](crate::key_transport::KeyTransport).
+pub struct ExportOnly {}
+
+impl Aead for ExportOnly {
+    type AeadImpl = ExportOnlyImpl;
+
+    // RFC 9180 §7.3: Export-only
+    const AEAD_ID: u16 = 0xFFFF;
+}
+
+/// The `aead` crate view of [`ExportOnly`]. Every operation fails. This is an implementation
+/// detail of [`ExportOnly`].
+// The key and tag are empty. The nonce is never used, but it has the usual size so the nonce
+// arithmetic doesn't need a special case. None of this affects the exporter secret.
+#[doc(hidden)]
+#[derive(Clone)]
+pub struct ExportOnlyImpl;
+
+impl BaseNewAead for ExportOnlyImpl {
+    type KeySize = typenum::U0;
+
+    fn new(_: &GenericArray<u8, typenum::U0>) -> Self {
+        ExportOnlyImpl
+    }
+}
+
+impl BaseAead for ExportOnlyImpl {
+    type NonceSize = typenum::U12;
+    type TagSize = typenum::U0;
+    type CiphertextOverhead = typenum::U0;
+
+    fn encrypt_in_place_detached(
+        &self,
+        _: &GenericArray<u8, typenum::U12>,
+        _: &[u8],
+        _: &mut [u8],
+    ) -> Result<GenericArray<u8, typenum::U0>, aead::Error> {
+        Err(aead::Error)
+    }
+
+    fn decrypt_in_place_detached(
+        &self,
+        _: &GenericArray<u8, typenum::U12>,
+        _: &[u8],
+        _: &mut [u8],
+        _: &GenericArray<u8, typenum::U0>,
+    ) -> Result<(), aead::Error> {
+        Err(aead::Error)
+    }
+}
+
+/// An AEAD that runs outside this crate, e.g., on a SoC's AES peripheral or through the kernel's
+/// `AF_ALG` interface. To use one, set up a context with [`Offload<B>`] as its AEAD. This crate
+/// still does the key schedule and keeps track of nonces and sequence numbers, and calls the
+/// backend for every `seal` and `open`.
+pub trait AeadBackend: Clone {
+    /// The algorithm this backend implements. This decides the key, nonce, and tag sizes, and the
+    /// AEAD ID in the ciphersuite.
+    type Alg: Aead;
+
+    /// Whether the backend leaves the buffer untouched when `seal_in_place` or `open_in_place`
+    /// fails. See [`Aead::UNTOUCHED_ON_ERROR`].
+    const UNTOUCHED_ON_ERROR: bool = false;
+
+    /// Loads the given key into the backend
+    fn new(key: &AeadKey<Self::Alg>) -> Self;
+
+    /// Encrypts `buf` in place and returns the tag
+    ///
+    /// Return Value
+    /// ============
+    /// Any error is reported to the caller of `seal` as `HpkeError::Encryption`.
+    fn seal_in_place(
+        &self,
+        nonce: &AeadNonce<Self::Alg>,
+        aad: &[u8],
+        buf: &mut [u8],
+    ) -> Result<AeadTag<Self::Alg>, HpkeError>;
+
+    /// Decrypts `buf` in place, after checking `tag`
+    ///
+    /// Return Value
+    /// ============
+    /// Any error is reported to the caller of `open` as `HpkeError::InvalidTag`.
+    fn open_in_place(
+        &self,
+        nonce: &AeadNonce<Self::Alg>,
+        aad: &[u8],
+        buf: &mut [u8],
+        tag: &AeadTag<Self::Alg>,
+    ) -> Result<(), HpkeError>;
+}
+
+/// The AEAD that hands `seal` and `open` off to the backend `B`. It has the same AEAD ID as
+/// `B::Alg`, so the other end can use either this or `B::Alg` itself.
+pub struct Offload<B: AeadBackend>(PhantomData<B>);
+
+impl<B: AeadBackend> Aead for Offload<B> {
+    type AeadImpl = OffloadImpl<B>;
+
+    const AEAD_ID: u16 = B::Alg::AEAD_ID;
+    const UNTOUCHED_ON_ERROR: bool = B::UNTOUCHED_ON_ERROR;
+}
+
+/// Adapts an [`AeadBackend`] to the traits of the `aead` crate. This is an implementation detail
+/// of [`Offload`].
+#[doc(hidden)]
+#[derive(Clone)]
+pub struct OffloadImpl<B: AeadBackend>(B);
+
+impl<B: AeadBackend> BaseNewAead for OffloadImpl<B> {
+    type KeySize = <<B::Alg as Aead>::AeadImpl as BaseNewAead>::KeySize;
+
+    fn new(key: &AeadKey<B::Alg>) -> Self {
+        OffloadImpl(B::new(key))
+    }
+}
+
+impl<B: AeadBackend> BaseAead for OffloadImpl<B> {
+    type NonceSize = <<B::Alg as Aead>::AeadImpl as BaseAead>::NonceSize;
+    type TagSize = <<B::Alg as Aead>::AeadImpl as BaseAead>::TagSize;
+    type CiphertextOverhead = <<B::Alg as Aead>::AeadImpl as BaseAead>::CiphertextOverhead;
+
+    fn encrypt_in_place_detached(
+        &self,
+        nonce: &AeadNonce<B::Alg>,
+        associated_data: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<GenericArray<u8, Self::TagSize>, aead::Error> {
+        self.0
+            .seal_in_place(nonce, associated_data, buffer)
+            .map(|tag| tag.0)
+            .map_err(|_| aead::Error)
+    }
+
+    fn decrypt_in_place_detached(
+        &self,
+        nonce: &AeadNonce<B::Alg>,
+        associated_data: &[u8],
+        buffer: &mut [u8],
+        tag: &GenericArray<u8, Self::TagSize>,
+    ) -> Result<(), aead::Error> {
+        self.0
+            .open_in_place(nonce, associated_data, buffer, &AeadTag(tag.clone()))
+            .map_err(|_| aead::Error)
+    }
+}
+
 // A nonce is the same thing as a sequence counter. But you never increment a nonce.
 pub(crate) type AeadNonce<A> = GenericArray<u8, <<A as Aead>::AeadImpl as BaseAead>::NonceSize>;
 pub(crate) type AeadKey<A> = GenericArray<u8, <<A as Aead>::AeadImpl as aead::NewAead>::KeySize>;
@@ -85,23 +258,40 @@ fn increment_seq(seq: &Seq) -> Option<Seq> {
 /// Derives a nonce from the given nonce and a "sequence number". The sequence number is treated as
 /// a big-endian integer with length equal to the nonce length.
 fn mix_nonce<A: Aead>(base_nonce: &AeadNonce<A>, seq: &Seq) -> AeadNonce<A> {
-    // Write `seq` in big-endian order into a byte buffer that's the size of a nonce
-    let mut seq_buf = AeadNonce::<A>::default();
-    // We just write to the last seq_size bytes. This is necessary because our AEAD nonces (>= 96
-    // bits) are always bigger than the sequence buffer (64 bits). We write to the last 64 bits
-    // because this is a big-endian number.
-    let seq_size = core::mem::size_of::<Seq>();
-    let nonce_size = base_nonce.len();
-    BigEndian::write_u64(&mut seq_buf[nonce_size - seq_size..], seq.0);
+    // XOR `seq` in big-endian order into the last seq_size bytes of the base nonce. This is
+    // equivalent to left-padding `seq` to the nonce size. Our AEAD nonces (>= 96 bits) are always
+    // bigger than the sequence number (64 bits).
+    let seq_bytes = seq.0.to_be_bytes();
+    let mut nonce = base_nonce.clone();
+    let nonce_size = nonce.len();
+    for (nonce_byte, seq_byte) in nonce[nonce_size - seq_bytes.len()..]
+        .iter_mut()
+        .zip(seq_bytes.iter())
+    {
+        *nonce_byte ^= seq_byte;
+    }
 
-    // XOR the base nonce bytes with the sequence bytes
-    let new_nonce_iter = base_nonce
-        .iter()
-        .zip(seq_buf.iter())
-        .map(|(nonce_byte, seq_byte)| nonce_byte ^ seq_byte);
+    nonce
+}
 
-    // This cannot fail, as the length of Nonce<A> is precisely the length of Seq
-    GenericArray::from_exact_iter(new_nonce_iter).unwrap()
+// Runs an in-place AEAD operation on `buf`. If the AEAD might leave `buf` modified on error, and
+// the restore-buffers feature is on, the operation runs on a scratch copy instead, which is only
+// written back on success.
+fn run_in_place<A: Aead, T>(
+    buf: &mut [u8],
+    op: impl FnOnce(&mut [u8]) -> Result<T, aead::Error>,
+) -> Result<T, aead::Error> {
+    if cfg!(feature = "restore-buffers") && !A::UNTOUCHED_ON_ERROR {
+        let mut scratch = buf.to_vec();
+        let res = op(&mut scratch);
+        if res.is_ok() {
+            buf.copy_from_slice(&scratch);
+        }
+        scratch.zeroize();
+        res
+    } else {
+        op(buf)
+    }
 }
 
 /// An authenticated encryption tag
@@ -128,22 +318,258 @@ impl<A: Aead> Deserializable for AeadTag<A> {
     }
 }
 
+// The exporter context that context probes are derived under
+const PROBE_LABEL: &[u8] = b"rust-hpke context probe";
+
+/// A short value derived from an encryption context, which both ends compute the same way. Get
+/// one with [`AeadCtxS::probe`] or [`AeadCtxR::probe`]. If the two ends' probes are equal, they
+/// derived the same context, i.e., they agree on the suite, mode, keys, PSK, and info string. Two
+/// probes are compared in constant time.
+///
+/// A probe is an export under a fixed label, so it reveals nothing about the other exports or the
+/// AEAD key. It's 16 bytes, which is plenty to catch a mismatch but isn't meant as a MAC.
+#[derive(Clone, Copy, Debug)]
+pub struct ContextProbe([u8; 16]);
+
+impl PartialEq for ContextProbe {
+    fn eq(&self, other: &ContextProbe) -> bool {
+        self.0.ct_eq(&other.0).into()
+    }
+}
+
+impl Eq for ContextProbe {}
+
+impl Serializable for ContextProbe {
+    type OutputSize = typenum::U16;
+
+    fn to_bytes(&self) -> GenericArray<u8, Self::OutputSize> {
+        GenericArray::clone_from_slice(&self.0)
+    }
+}
+
+impl Deserializable for ContextProbe {
+    fn from_bytes(encoded: &[u8]) -> Result<Self, HpkeError> {
+        if encoded.len() != Self::size() {
+            Err(HpkeError::InvalidEncoding)
+        } else {
+            let mut arr = [0u8; 16];
+            arr.copy_from_slice(encoded);
+            Ok(ContextProbe(arr))
+        }
+    }
+}
+
+// The exporter context that key confirmation tags are derived under
+const CONFIRMATION_LABEL: &[u8] = b"rust-hpke key confirmation";
+
+/// A tag that the sender sends to prove it derived the same context as the receiver. Get one with
+/// [`AeadCtxS::confirmation_tag`] and check it with [`AeadCtxR::verify_confirmation`].
+///
+/// Unlike a [`ContextProbe`], this is meant to go from sender to receiver before any data, so
+/// that a mismatched info string or PSK shows up as `HpkeError::ConfirmationFailed` rather than
+/// as an `InvalidTag` on the first message.
+#[derive(Clone, Copy, Debug)]
+pub struct ConfirmationTag([u8; 32]);
+
+impl Serializable for ConfirmationTag {
+    type OutputSize = typenum::U32;
+
+    fn to_bytes(&self) -> GenericArray<u8, Self::OutputSize> {
+        GenericArray::clone_from_slice(&self.0)
+    }
+}
+
+impl Deserializable for ConfirmationTag {
+    fn from_bytes(encoded: &[u8]) -> Result<Self, HpkeError> {
+        if encoded.len() != Self::size() {
+            Err(HpkeError::InvalidEncoding)
+        } else {
+            let mut arr = [0u8; 32];
+            arr.copy_from_slice(encoded);
+            Ok(ConfirmationTag(arr))
+        }
+    }
+}
+
+// Namespaced exporter contexts start with this, so they can't collide with the labels this crate
+// uses internally
+const NAMESPACE_DOMAIN: &[u8] = b"rust-hpke export namespace";
+
+/// A protocol-specific prefix for exporter contexts. Subsystems that export from the same
+/// context, or that open channels with the same keys, can each use their own namespace so that
+/// they never derive the same secret, whatever labels they pick. See [`AeadCtxS::export_in`],
+/// [`AeadCtxR::export_in`], and [`HpkeChannel::initiate_in`](crate::HpkeChannel::initiate_in).
+///
+/// The namespaced exporter context is `"rust-hpke export namespace" || len(prefix) || prefix ||
+/// label`, where the length is a big-endian u16. Like every export, it's further bound to the
+/// suite ID by the key schedule.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExportNamespace {
+    prefix: Vec<u8>,
+}
+
+impl ExportNamespace {
+    /// Makes a namespace with the given prefix
+    ///
+    /// Panics
+    /// ======
+    /// Panics if `prefix` is longer than 65535 bytes.
+    pub fn new(prefix: &[u8]) -> Self {
+        assert!(
+            prefix.len() <= u16::MAX as usize,
+            "namespace prefix is too long"
+        );
+        ExportNamespace {
+            prefix: prefix.to_vec(),
+        }
+    }
+
+    /// Returns the prefix of this namespace
+    pub fn prefix(&self) -> &[u8] {
+        &self.prefix
+    }
+
+    // Returns the exporter context for the given label in this namespace
+    pub(crate) fn exporter_ctx(&self, label: &[u8]) -> Vec<u8> {
+        let mut ctx =
+            Vec::with_capacity(NAMESPACE_DOMAIN.len() + 2 + self.prefix.len() + label.len());
+        ctx.extend_from_slice(NAMESPACE_DOMAIN);
+        ctx.extend_from_slice(&(self.prefix.len() as u16).to_be_bytes());
+        ctx.extend_from_slice(&self.prefix);
+        ctx.extend_from_slice(label);
+        ctx
+    }
+}
+
+/// Counters describing how much an encryption context has been used. Get these with
+/// [`AeadCtxS::stats`] or [`AeadCtxR::stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of messages successfully sealed or opened
+    pub messages: u64,
+    /// The total length of the plaintexts of those messages
+    pub bytes: u64,
+    /// The number of seals or opens that failed
+    pub failures: u64,
+}
+
+impl Stats {
+    // Records the outcome of a seal or open of a message of the given length
+    fn record<T>(&mut self, res: &Result<T, HpkeError>, msg_len: usize) {
+        if res.is_ok() {
+            self.messages = self.messages.saturating_add(1);
+            self.bytes = self.bytes.saturating_add(msg_len as u64);
+        } else {
+            self.failures = self.failures.saturating_add(1);
+        }
+    }
+}
+
+/// The size of the chunks that [`AeadCtxS::seal_large`] splits a plaintext into. Each chunk is
+/// small enough that it's still in cache when the AEAD makes its second pass over it.
+pub const LARGE_CHUNK_SIZE: usize = 64 * 1024;
+
+// Returns the number of chunks that seal_large splits a plaintext of the given length into. An
+// empty plaintext is one empty chunk.
+fn large_chunk_count(len: usize) -> usize {
+    if len == 0 {
+        1
+    } else {
+        (len - 1) / LARGE_CHUNK_SIZE + 1
+    }
+}
+
+// Returns the byte range of the i-th chunk of a large message of the given length. The end is
+// computed without adding past `len`, since on 32-bit targets a message can come within a chunk
+// of usize::MAX.
+fn large_chunk_range(i: usize, len: usize) -> core::ops::Range<usize> {
+    let start = i * LARGE_CHUNK_SIZE;
+    let end = start + core::cmp::min(LARGE_CHUNK_SIZE, len - start);
+    start..end
+}
+
+// Returns the AAD of the chunks of a large message. This is the caller's AAD followed by one byte,
+// which large_chunk_aad_set_last sets to 1 on the last chunk and 0 otherwise. This marker keeps a
+// message from being truncated at a chunk boundary.
+fn large_chunk_aad(aad: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(aad.len() + 1);
+    buf.extend_from_slice(aad);
+    buf.push(0);
+    buf
+}
+
+fn large_chunk_aad_set_last(chunk_aad: &mut [u8], is_last: bool) {
+    chunk_aad[chunk_aad.len() - 1] = is_last as u8;
+}
+
+/// The AEAD instance of a context. This isn't keyed until the first `seal` or `open`, so contexts
+/// that are only used for `export` never run the cipher's key schedule.
+enum LazyAead<A: Aead> {
+    /// The key, before the first `seal` or `open`
+    Key(AeadKey<A>),
+    /// The keyed AEAD instance
+    Cipher(A::AeadImpl),
+}
+
+impl<A: Aead> LazyAead<A> {
+    /// Returns the AEAD instance, keying it first if this hasn't been done yet
+    fn get(&mut self) -> &A::AeadImpl {
+        if let LazyAead::Key(key) = self {
+            let cipher = <A::AeadImpl as aead::NewAead>::new(key);
+            // This drops the key, which zeroes it
+            *self = LazyAead::Cipher(cipher);
+        }
+
+        match self {
+            LazyAead::Cipher(cipher) => cipher,
+            LazyAead::Key(_) => unreachable!(),
+        }
+    }
+}
+
+impl<A: Aead> Drop for LazyAead<A> {
+    fn drop(&mut self) {
+        if let LazyAead::Key(key) = self {
+            key.as_mut_slice().zeroize();
+        }
+    }
+}
+
+// Necessary for test_setup_soundness
+#[cfg(test)]
+impl<A: Aead> Clone for LazyAead<A> {
+    fn clone(&self) -> LazyAead<A> {
+        match self {
+            LazyAead::Key(key) => LazyAead::Key(key.clone()),
+            LazyAead::Cipher(cipher) => LazyAead::Cipher(cipher.clone()),
+        }
+    }
+}
+
 /// The HPKE encryption context. This is what you use to `seal` plaintexts and `open` ciphertexts.
 pub(crate) struct AeadCtx<A: Aead, Kdf: KdfTrait, Kem: KemTrait> {
     /// Records whether the nonce sequence counter has overflowed
     overflowed: bool,
     /// The underlying AEAD instance. This also does decryption.
-    encryptor: A::AeadImpl,
+    encryptor: LazyAead<A>,
     /// The base nonce which we XOR with sequence numbers
     nonce: AeadNonce<A>,
-    /// The exporter secret, used in the `export()` method
-    exporter_secret: ExporterSecret<Kdf>,
+    /// An HKDF instance whose PRK is the exporter secret, used in the `export()` method. This is
+    /// keyed once here, rather than on every call to `export()`.
+    exporter: Hkdf<Kdf::HashImpl>,
     /// The running sequence number
     seq: Seq,
-    /// This binds the `AeadCtx` to the KEM that made it. Used to generate `suite_id`.
-    src_kem: PhantomData<Kem>,
+    /// This binds the `AeadCtx` to the KEM that made it. Used to generate `suite_id`. No `Kem`
+    /// is ever held, so the `fn` keeps `Kem` from affecting whether the context is `Send` or
+    /// `Sync`.
+    src_kem: PhantomData<fn() -> Kem>,
     /// The full ID of the ciphersuite that created this `AeadCtx`. Used for context binding.
     suite_id: FullSuiteId,
+    /// Usage counters for this context
+    stats: Stats,
+    /// The non-secret inputs of the key schedule that made this context
+    #[cfg(feature = "transcript")]
+    pub(crate) setup_transcript: crate::transcript::SetupTranscript,
 }
 
 // Necessary for test_setup_soundness
@@ -154,30 +580,47 @@ impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> Clone for AeadCtx<A, Kdf, Kem> {
             overflowed: self.overflowed,
             encryptor: self.encryptor.clone(),
             nonce: self.nonce.clone(),
-            exporter_secret: self.exporter_secret.clone(),
+            exporter: self.exporter.clone(),
             seq: self.seq.clone(),
             src_kem: PhantomData,
             suite_id: self.suite_id.clone(),
+            stats: self.stats,
+            #[cfg(feature = "transcript")]
+            setup_transcript: self.setup_transcript.clone(),
         }
     }
 }
 
 impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> AeadCtx<A, Kdf, Kem> {
-    /// Makes an AeadCtx from a raw key and nonce
+    /// Makes an AeadCtx from a raw key and nonce. This takes ownership of the key and exporter
+    /// secret. The exporter secret is zeroed once it's loaded into the HKDF instance. The key is
+    /// held until the first `seal` or `open` keys the cipher, and is zeroed then, or when the
+    /// context is dropped, whichever comes first.
     pub(crate) fn new(
-        key: &AeadKey<A>,
+        key: AeadKey<A>,
         nonce: AeadNonce<A>,
-        exporter_secret: ExporterSecret<Kdf>,
+        mut exporter_secret: ExporterSecret<Kdf>,
     ) -> AeadCtx<A, Kdf, Kem> {
         let suite_id = full_suite_id::<A, Kdf, Kem>();
+        let encryptor = LazyAead::Key(key);
+
+        // Use our exporter secret as the PRK for all future HKDF-Expand ops. The only time this
+        // fails is when the length of the PRK is not the the underlying hash function's digest
+        // size. But that's guaranteed by the type system, so we can unwrap().
+        let exporter = Hkdf::<Kdf::HashImpl>::from_prk(exporter_secret.as_slice()).unwrap();
+        exporter_secret.as_mut_slice().zeroize();
+
         AeadCtx {
             overflowed: false,
-            encryptor: <A::AeadImpl as aead::NewAead>::new(key),
+            encryptor,
             nonce,
-            exporter_secret,
+            exporter,
             seq: <Seq as Default>::default(),
             src_kem: PhantomData,
             suite_id,
+            stats: Stats::default(),
+            #[cfg(feature = "transcript")]
+            setup_transcript: Default::default(),
         }
     }
 
@@ -186,20 +629,75 @@ impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> AeadCtx<A, Kdf, Kem> {
     /// Fills a given buffer with secret bytes derived from this encryption context. This value
     /// does not depend on sequence number, so it is constant for the lifetime of this context.
     pub fn export(&self, exporter_ctx: &[u8], out_buf: &mut [u8]) -> Result<(), HpkeError> {
-        // Use our exporter secret as the PRK for an HKDF-Expand op. The only time this fails is
-        // when the length of the PRK is not the the underlying hash function's digest size. But
-        // that's guaranteed by the type system, so we can unwrap().
-        let hkdf_ctx = Hkdf::<Kdf::HashImpl>::from_prk(self.exporter_secret.as_slice()).unwrap();
-
         // This call either succeeds or returns hkdf::InvalidLength (iff the buffer length is more
         // than 255x the digest size of the underlying hash function)
-        hkdf_ctx
+        self.exporter
             .labeled_expand(&self.suite_id, b"sec", exporter_ctx, out_buf)
             .map_err(|_| HpkeError::InvalidKdfLength)
     }
+
+    // Records this context's non-secret state
+    #[cfg(feature = "transcript")]
+    fn transcript(&self) -> crate::transcript::Transcript {
+        crate::transcript::Transcript::new(
+            self.suite_id,
+            &self.setup_transcript,
+            &self.nonce,
+            self.seq.0,
+        )
+    }
+
+    // Computes the context probe. This is a short export, so it can't fail.
+    fn probe(&self) -> ContextProbe {
+        let mut buf = [0u8; 16];
+        self.export(PROBE_LABEL, &mut buf)
+            .expect("probe is way too big");
+        ContextProbe(buf)
+    }
+
+    // Computes the key confirmation tag. Like the probe, this can't fail.
+    fn confirmation_tag(&self) -> ConfirmationTag {
+        let mut buf = [0u8; 32];
+        self.export(CONFIRMATION_LABEL, &mut buf)
+            .expect("confirmation tag is way too big");
+        ConfirmationTag(buf)
+    }
+
+    // Returns the AEAD key, if the cipher hasn't been keyed yet
+    #[cfg(feature = "danger")]
+    fn key_dangerous(&self) -> Option<&AeadKey<A>> {
+        match &self.encryptor {
+            LazyAead::Key(key) => Some(key),
+            LazyAead::Cipher(_) => None,
+        }
+    }
+
+    // Returns the nonce for the current sequence number and moves on to the next one, exactly as
+    // a seal or open would
+    #[cfg(feature = "danger")]
+    fn next_nonce(&mut self) -> Result<AeadNonce<A>, HpkeError> {
+        if self.overflowed {
+            return Err(HpkeError::SeqOverflow);
+        }
+
+        let nonce = mix_nonce::<A>(&self.nonce, &self.seq);
+        match increment_seq(&self.seq) {
+            Some(new_seq) => self.seq = new_seq,
+            None => self.overflowed = true,
+        }
+        Ok(nonce)
+    }
 }
 
 /// The HPKE receiver's context. This is what you use to `open` ciphertexts.
+///
+/// Thread safety
+/// =============
+/// A context is `Send` and `Sync` whenever its AEAD and KDF implementations are, which is the case
+/// for every AEAD and KDF in this crate. So a context can be moved into another thread or task.
+/// `open` takes `&mut self`, so sharing a context between tasks that open needs a lock, while
+/// `export` takes `&self` and works through a shared reference. A context with an
+/// [`Offload<B>`] AEAD is only `Send` or `Sync` if the backend `B` is.
 pub struct AeadCtxR<A: Aead, Kdf: KdfTrait, Kem: KemTrait>(AeadCtx<A, Kdf, Kem>);
 
 // AeadCtx -> AeadCtxR via wrapping
@@ -230,14 +728,47 @@ impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> AeadCtxR<A, Kdf, Kem> {
     /// Return Value
     /// ============
     /// Returns `Ok(())` on success.  If this context has been used for so many encryptions that
-    /// the sequence number overflowed, returns `Err(HpkeError::SeqOverflow)`. If this happens,
-    /// `plaintext` will be unmodified. If the tag fails to validate, returns
-    /// `Err(HpkeError::InvalidTag)`. If this happens, `plaintext` is in an undefined state.
+    /// the sequence number overflowed, returns `Err(HpkeError::SeqOverflow)`. If the tag fails to
+    /// validate, returns `Err(HpkeError::InvalidTag)`. In either case, `ciphertext` is
+    /// unmodified, so long as the AEAD is [`UNTOUCHED_ON_ERROR`](Aead::UNTOUCHED_ON_ERROR) or the
+    /// `restore-buffers` feature is on. Every AEAD in this crate is.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "trace",
+            skip_all,
+            fields(kem_id = Kem::KEM_ID, kdf_id = Kdf::KDF_ID, aead_id = A::AEAD_ID, len = ciphertext.len())
+        )
+    )]
     pub fn open(
         &mut self,
         ciphertext: &mut [u8],
         aad: &[u8],
         tag: &AeadTag<A>,
+    ) -> Result<(), HpkeError> {
+        let res = self.open_unreported(ciphertext, aad, tag);
+        self.0.stats.record(&res, ciphertext.len());
+
+        #[cfg(feature = "events")]
+        {
+            use crate::events::{emit_result, Event, Operation, Role};
+            emit_result::<A, Kdf, Kem, _, _>(&res, Role::Receiver, Operation::Open, |suite| {
+                Some(Event::Open {
+                    suite,
+                    ciphertext_len: ciphertext.len(),
+                })
+            });
+        }
+
+        res
+    }
+
+    // The body of open(), minus the event reporting
+    fn open_unreported(
+        &mut self,
+        ciphertext: &mut [u8],
+        aad: &[u8],
+        tag: &AeadTag<A>,
     ) -> Result<(), HpkeError> {
         if self.0.overflowed {
             // If the sequence counter overflowed, we've been used for far too long. Shut down.
@@ -245,10 +776,10 @@ impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> AeadCtxR<A, Kdf, Kem> {
         } else {
             // Compute the nonce and do the encryption in place
             let nonce = mix_nonce::<A>(&self.0.nonce, &self.0.seq);
-            let decrypt_res = self
-                .0
-                .encryptor
-                .decrypt_in_place_detached(&nonce, &aad, ciphertext, &tag.0);
+            let cipher = self.0.encryptor.get();
+            let decrypt_res = run_in_place::<A, _>(ciphertext, |buf| {
+                cipher.decrypt_in_place_detached(&nonce, aad, buf, &tag.0)
+            });
 
             if decrypt_res.is_err() {
                 // Opening failed due to a bad tag
@@ -267,6 +798,129 @@ impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> AeadCtxR<A, Kdf, Kem> {
         }
     }
 
+    /// Opens a batch of in-order messages, each given as `(ciphertext, aad, tag)`. This is the
+    /// same as calling [`open`](Self::open) on each in turn, stopping at the first failure.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(())` if every message opened. Otherwise, returns the index of the first message
+    /// that failed, along with the error that [`open`](Self::open) gave for it. Every message
+    /// before that index has been opened, and the sequence number has moved past them. No message
+    /// after it has been touched.
+    pub fn open_batch(
+        &mut self,
+        batch: &mut [(&mut [u8], &[u8], &AeadTag<A>)],
+    ) -> Result<(), (usize, HpkeError)> {
+        for (i, (ciphertext, aad, tag)) in batch.iter_mut().enumerate() {
+            self.open(ciphertext, aad, tag).map_err(|e| (i, e))?;
+        }
+
+        Ok(())
+    }
+
+    // Opens a message as if this context's sequence number were `seq`, and leaves its own
+    // sequence number as it was. This is for protocols that carry the sequence number in every
+    // message, like the datagram module. They have to reject repeated sequence numbers themselves.
+    pub(crate) fn open_at(
+        &mut self,
+        seq: u64,
+        ciphertext: &mut [u8],
+        aad: &[u8],
+        tag: &AeadTag<A>,
+    ) -> Result<(), HpkeError> {
+        let saved_seq = core::mem::replace(&mut self.0.seq, Seq(seq));
+        let saved_overflowed = core::mem::replace(&mut self.0.overflowed, false);
+        let res = self.open(ciphertext, aad, tag);
+        self.0.seq = saved_seq;
+        self.0.overflowed = saved_overflowed;
+        res
+    }
+
+    // Opens a message, then runs `check` on its plaintext. If `check` fails, the sequence number
+    // is put back, so the message counts as never received. This is for protocols that check the
+    // plaintext before acting on it, like the channel module's key updates.
+    pub(crate) fn open_checked<T, F>(
+        &mut self,
+        ciphertext: &mut [u8],
+        aad: &[u8],
+        tag: &AeadTag<A>,
+        check: F,
+    ) -> Result<T, HpkeError>
+    where
+        F: FnOnce(&Self, &[u8]) -> Result<T, HpkeError>,
+    {
+        let saved_seq = self.0.seq.clone();
+        let saved_overflowed = self.0.overflowed;
+        self.open(ciphertext, aad, tag)?;
+        let res = check(self, ciphertext);
+        if res.is_err() {
+            self.0.seq = saved_seq;
+            self.0.overflowed = saved_overflowed;
+        }
+        res
+    }
+
+    /// Opens a message sealed with [`AeadCtxS::seal_large`], given the tags it returned. This
+    /// opens the chunks of `ciphertext` in place, one after another, each with its own sequence
+    /// number.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(())` on success. If the number of tags doesn't match the length of
+    /// `ciphertext`, returns `Err(HpkeError::InvalidTag)` without touching anything. Otherwise,
+    /// returns the first error that [`open`](Self::open) gave for a chunk. If this happens,
+    /// `ciphertext` is in an undefined state.
+    pub fn open_large(
+        &mut self,
+        ciphertext: &mut [u8],
+        aad: &[u8],
+        tags: &[AeadTag<A>],
+    ) -> Result<(), HpkeError> {
+        let num_chunks = large_chunk_count(ciphertext.len());
+        if tags.len() != num_chunks {
+            return Err(HpkeError::InvalidTag);
+        }
+
+        let mut chunk_aad = large_chunk_aad(aad);
+        for (i, tag) in tags.iter().enumerate() {
+            large_chunk_aad_set_last(&mut chunk_aad, i == num_chunks - 1);
+            let range = large_chunk_range(i, ciphertext.len());
+            self.open(&mut ciphertext[range], &chunk_aad, tag)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of messages and bytes this context has opened, and the number of
+    /// failed opens
+    pub fn stats(&self) -> Stats {
+        self.0.stats
+    }
+
+    /// Returns this context's [`ContextProbe`]. Compare it with the sender's to check that both
+    /// ends derived the same context before exchanging any data.
+    pub fn probe(&self) -> ContextProbe {
+        self.0.probe()
+    }
+
+    /// Checks a [`ConfirmationTag`] that the sender made with [`AeadCtxS::confirmation_tag`].
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(())` if the sender derived the same context as this one. Otherwise, e.g., if
+    /// the two ends used different info strings or PSKs, returns
+    /// `Err(HpkeError::ConfirmationFailed)`.
+    pub fn verify_confirmation(&self, tag: &ConfirmationTag) -> Result<(), HpkeError> {
+        let expected = self.0.confirmation_tag();
+        if expected.0.ct_eq(&tag.0).into() {
+            Ok(())
+        } else {
+            Err(HpkeError::ConfirmationFailed)
+        }
+    }
+
+    // def Context.Export(exporter_context, L):
+    //   return LabeledExpand(self.exporter_secret, "sec", exporter_context, L)
     /// Fills a given buffer with secret bytes derived from this encryption context. This value
     /// does not depend on sequence number, so it is constant for the lifetime of this context.
     ///
@@ -278,11 +932,71 @@ impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> AeadCtxR<A, Kdf, Kem> {
     /// fill massive buffers and you'll be fine.
     pub fn export(&self, info: &[u8], out_buf: &mut [u8]) -> Result<(), HpkeError> {
         // Pass to AeadCtx
-        self.0.export(info, out_buf)
+        let res = self.0.export(info, out_buf);
+
+        #[cfg(feature = "events")]
+        {
+            use crate::events::{emit_result, Operation, Role};
+            emit_result::<A, Kdf, Kem, _, _>(&res, Role::Receiver, Operation::Export, |_| None);
+        }
+
+        res
+    }
+
+    /// Does an [`export`](Self::export) under `label` in the given namespace
+    pub fn export_in(
+        &self,
+        namespace: &ExportNamespace,
+        label: &[u8],
+        out_buf: &mut [u8],
+    ) -> Result<(), HpkeError> {
+        self.export(&namespace.exporter_ctx(label), out_buf)
+    }
+
+    /// Returns the non-secret inputs and state of this context, for comparing with the other end
+    /// when debugging. See [`Transcript`](crate::transcript::Transcript).
+    #[cfg(feature = "transcript")]
+    pub fn transcript(&self) -> crate::transcript::Transcript {
+        self.0.transcript()
+    }
+
+    /// Returns the nonce for the next message and advances the sequence number, without doing any
+    /// decryption. The caller is responsible for doing the decryption with this nonce and the key
+    /// from [`key_dangerous`](Self::key_dangerous), e.g., on a hardware AEAD engine.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(nonce)` on success. If the sequence number overflowed, returns
+    /// `Err(HpkeError::SeqOverflow)`.
+    #[cfg(feature = "danger")]
+    pub fn next_nonce_dangerous(&mut self) -> Result<AeadNonce<A>, HpkeError> {
+        self.0.next_nonce()
+    }
+
+    /// Returns the AEAD key of this context, for handing to an external AEAD engine. The key is
+    /// only kept until this context first opens a message itself, so this returns `None` after
+    /// that. Whatever receives the key has to zero it when it's done.
+    #[cfg(feature = "danger")]
+    pub fn key_dangerous(&self) -> Option<&AeadKey<A>> {
+        self.0.key_dangerous()
+    }
+
+    /// Returns the nonce that HPKE uses for the message with sequence number `seq`, i.e., the
+    /// base nonce XORed with `seq`. This doesn't touch the context's own sequence number. It's
+    /// for opening, on an external AEAD engine, messages that carry their own sequence numbers.
+    /// Only accept each sequence number once, and only after its message's tag checks out.
+    #[cfg(feature = "danger")]
+    pub fn compute_nonce(&self, seq: u64) -> AeadNonce<A> {
+        mix_nonce::<A>(&self.0.nonce, &Seq(seq))
     }
 }
 
 /// The HPKE senders's context. This is what you use to `seal` plaintexts.
+///
+/// Thread safety
+/// =============
+/// Like [`AeadCtxR`], this is `Send` and `Sync` for every AEAD and KDF in this crate. `seal`
+/// takes `&mut self`, so sharing a context between tasks that seal needs a lock.
 pub struct AeadCtxS<A: Aead, Kdf: KdfTrait, Kem: KemTrait>(AeadCtx<A, Kdf, Kem>);
 
 // AeadCtx -> AeadCtxS via wrapping
@@ -311,20 +1025,53 @@ impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> AeadCtxS<A, Kdf, Kem> {
     /// Return Value
     /// ============
     /// Returns `Ok(tag)` on success.  If this context has been used for so many encryptions that
-    /// the sequence number overflowed, returns `Err(HpkeError::SeqOverflow)`. If this happens,
-    /// `plaintext` will be unmodified. If an unspecified error happened during encryption, returns
-    /// `Err(HpkeError::Encryption)`. If this happens, the contents of `plaintext` is undefined.
+    /// the sequence number overflowed, returns `Err(HpkeError::SeqOverflow)`. If an unspecified
+    /// error happened during encryption, returns `Err(HpkeError::Encryption)`. In either case,
+    /// `plaintext` is unmodified, so long as the AEAD is
+    /// [`UNTOUCHED_ON_ERROR`](Aead::UNTOUCHED_ON_ERROR) or the `restore-buffers` feature is on.
+    /// Every AEAD in this crate is.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "trace",
+            skip_all,
+            fields(kem_id = Kem::KEM_ID, kdf_id = Kdf::KDF_ID, aead_id = A::AEAD_ID, len = plaintext.len())
+        )
+    )]
     pub fn seal(&mut self, plaintext: &mut [u8], aad: &[u8]) -> Result<AeadTag<A>, HpkeError> {
+        let res = self.seal_unreported(plaintext, aad);
+        self.0.stats.record(&res, plaintext.len());
+
+        #[cfg(feature = "events")]
+        {
+            use crate::events::{emit_result, Event, Operation, Role};
+            emit_result::<A, Kdf, Kem, _, _>(&res, Role::Sender, Operation::Seal, |suite| {
+                Some(Event::Seal {
+                    suite,
+                    plaintext_len: plaintext.len(),
+                })
+            });
+        }
+
+        res
+    }
+
+    // The body of seal(), minus the event reporting
+    fn seal_unreported(
+        &mut self,
+        plaintext: &mut [u8],
+        aad: &[u8],
+    ) -> Result<AeadTag<A>, HpkeError> {
         if self.0.overflowed {
             // If the sequence counter overflowed, we've been used for far too long. Shut down.
             Err(HpkeError::SeqOverflow)
         } else {
             // Compute the nonce and do the encryption in place
             let nonce = mix_nonce::<A>(&self.0.nonce, &self.0.seq);
-            let tag_res = self
-                .0
-                .encryptor
-                .encrypt_in_place_detached(&nonce, &aad, plaintext);
+            let cipher = self.0.encryptor.get();
+            let tag_res = run_in_place::<A, _>(plaintext, |buf| {
+                cipher.encrypt_in_place_detached(&nonce, aad, buf)
+            });
 
             // Check if an error occurred when encrypting
             let tag = match tag_res {
@@ -343,6 +1090,64 @@ impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> AeadCtxS<A, Kdf, Kem> {
         }
     }
 
+    /// Seals a large plaintext in place, in chunks of [`LARGE_CHUNK_SIZE`] bytes. Each chunk is
+    /// sealed as its own message, so this uses up one sequence number per chunk and returns one
+    /// tag per chunk. Every chunk stays in cache between the AEAD's encryption and authentication
+    /// passes, which helps on multi-megabyte plaintexts when memory bandwidth, rather than the
+    /// cipher, is the bottleneck. Unlike `seal`, this allocates, for the tags and a copy of `aad`.
+    ///
+    /// The output is not an ordinary HPKE ciphertext. It can only be opened with
+    /// [`AeadCtxR::open_large`]. Each chunk's AAD is `aad` followed by a byte saying whether it's
+    /// the last chunk, so that chunks can't be dropped off the end.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns the tags of the chunks, in order, on success. Otherwise, returns the first error
+    /// that [`seal`](Self::seal) gave for a chunk. If this happens, the contents of `plaintext`
+    /// is undefined.
+    pub fn seal_large(
+        &mut self,
+        plaintext: &mut [u8],
+        aad: &[u8],
+    ) -> Result<Vec<AeadTag<A>>, HpkeError> {
+        let num_chunks = large_chunk_count(plaintext.len());
+        let mut tags = Vec::with_capacity(num_chunks);
+
+        let mut chunk_aad = large_chunk_aad(aad);
+        for i in 0..num_chunks {
+            large_chunk_aad_set_last(&mut chunk_aad, i == num_chunks - 1);
+            let range = large_chunk_range(i, plaintext.len());
+            tags.push(self.seal(&mut plaintext[range], &chunk_aad)?);
+        }
+
+        Ok(tags)
+    }
+
+    /// Returns the number of bytes that [`seal`](Self::seal) adds to each message, i.e., the size
+    /// of the AEAD's tag. This is 0 for [`ExportOnly`].
+    pub fn overhead(&self) -> usize {
+        AeadTag::<A>::size()
+    }
+
+    /// Returns the number of messages and bytes this context has sealed, and the number of
+    /// failed seals
+    pub fn stats(&self) -> Stats {
+        self.0.stats
+    }
+
+    /// Returns this context's [`ContextProbe`]. Compare it with the receiver's to check that both
+    /// ends derived the same context before exchanging any data.
+    pub fn probe(&self) -> ContextProbe {
+        self.0.probe()
+    }
+
+    /// Returns a [`ConfirmationTag`] for the receiver to check with
+    /// [`AeadCtxR::verify_confirmation`]. It's derived from the exporter secret, so it doesn't
+    /// use up a sequence number, and it's the same every time.
+    pub fn confirmation_tag(&self) -> ConfirmationTag {
+        self.0.confirmation_tag()
+    }
+
     // def Context.Export(exporter_context, L):
     //   return LabeledExpand(self.exporter_secret, "sec", exporter_context, L)
     /// Fills a given buffer with secret bytes derived from this encryption context. This value
@@ -354,14 +1159,118 @@ impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> AeadCtxS<A, Kdf, Kem> {
     /// underlying hash function, returns an `Err(HpkeError::InvalidKdfLength)`.
     pub fn export(&self, info: &[u8], out_buf: &mut [u8]) -> Result<(), HpkeError> {
         // Pass to AeadCtx
-        self.0.export(info, out_buf)
+        let res = self.0.export(info, out_buf);
+
+        #[cfg(feature = "events")]
+        {
+            use crate::events::{emit_result, Operation, Role};
+            emit_result::<A, Kdf, Kem, _, _>(&res, Role::Sender, Operation::Export, |_| None);
+        }
+
+        res
+    }
+
+    /// Does an [`export`](Self::export) under `label` in the given namespace
+    pub fn export_in(
+        &self,
+        namespace: &ExportNamespace,
+        label: &[u8],
+        out_buf: &mut [u8],
+    ) -> Result<(), HpkeError> {
+        self.export(&namespace.exporter_ctx(label), out_buf)
+    }
+
+    // Returns the sequence number that the next seal will use
+    pub(crate) fn next_seq(&self) -> u64 {
+        self.0.seq.0
+    }
+
+    /// Returns the non-secret inputs and state of this context, for comparing with the other end
+    /// when debugging. See [`Transcript`](crate::transcript::Transcript).
+    #[cfg(feature = "transcript")]
+    pub fn transcript(&self) -> crate::transcript::Transcript {
+        self.0.transcript()
+    }
+
+    /// Returns the nonce for the next message and advances the sequence number, without doing any
+    /// encryption. The caller is responsible for doing the encryption with this nonce and the key
+    /// from [`key_dangerous`](Self::key_dangerous), e.g., on a hardware AEAD engine.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(nonce)` on success. If the sequence number overflowed, returns
+    /// `Err(HpkeError::SeqOverflow)`.
+    #[cfg(feature = "danger")]
+    pub fn next_nonce_dangerous(&mut self) -> Result<AeadNonce<A>, HpkeError> {
+        self.0.next_nonce()
+    }
+
+    /// Returns the AEAD key of this context, for handing to an external AEAD engine. The key is
+    /// only kept until this context first seals a message itself, so this returns `None` after
+    /// that. Whatever receives the key has to zero it when it's done.
+    #[cfg(feature = "danger")]
+    pub fn key_dangerous(&self) -> Option<&AeadKey<A>> {
+        self.0.key_dangerous()
+    }
+
+    /// Returns the nonce that HPKE uses for the message with sequence number `seq`, i.e., the
+    /// base nonce XORed with `seq`. This doesn't touch the context's own sequence number.
+    ///
+    /// This is for sealing on an external AEAD engine when the application picks the sequence
+    /// numbers. Never use the same sequence number twice under one context. Prefer
+    /// [`next_nonce_dangerous`](Self::next_nonce_dangerous), which keeps count for you, or
+    /// [`compute_nonce_checked`](Self::compute_nonce_checked), which catches reuse.
+    #[cfg(feature = "danger")]
+    pub fn compute_nonce(&self, seq: u64) -> AeadNonce<A> {
+        mix_nonce::<A>(&self.0.nonce, &Seq(seq))
+    }
+
+    /// Like [`compute_nonce`](Self::compute_nonce), but first records `seq` in `guard`, which
+    /// refuses to hand out the same nonce twice. `guard` must only ever be used with this context.
+    ///
+    /// [`AeadCtxR`] has no equivalent. A receiver should only record a sequence number once the
+    /// message under it has been authenticated, which happens on the external engine, after the
+    /// nonce is handed out. It can check [`SeqGuard::is_rejected`] first, and call
+    /// [`SeqGuard::check_and_insert`] once the tag checks out, like [`DatagramReceiver`] does.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::ReusedSeq)` if `guard` rejects `seq`. See
+    /// [`SeqGuard::check_and_insert`].
+    ///
+    /// [`DatagramReceiver`]: crate::datagram::DatagramReceiver
+    #[cfg(feature = "danger")]
+    pub fn compute_nonce_checked(
+        &self,
+        seq: u64,
+        guard: &mut SeqGuard,
+    ) -> Result<AeadNonce<A>, HpkeError> {
+        guard.check_and_insert(seq)?;
+        Ok(self.compute_nonce(seq))
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::{AeadTag, AesGcm128, AesGcm256, ChaCha20Poly1305, Seq};
-    use crate::{kdf::HkdfSha256, kex::Deserializable, test_util::gen_ctx_simple_pair, HpkeError};
+    use super::{
+        large_chunk_count, large_chunk_range, mix_nonce, Aead, AeadBackend, AeadCtx, AeadCtxR,
+        AeadCtxS, AeadKey, AeadNonce, AeadTag, AesGcm128, AesGcm256, ChaCha20Poly1305, LazyAead,
+        Offload, OffloadImpl, Seq, Stats, LARGE_CHUNK_SIZE,
+    };
+    use crate::{
+        kdf::HkdfSha256,
+        kem::Kem as KemTrait,
+        kex::{Deserializable, Serializable},
+        op_mode::{OpModeR, OpModeS},
+        setup::{setup_receiver, setup_sender, ExporterSecret},
+        test_util::gen_ctx_simple_pair,
+        HpkeError,
+    };
+
+    use alloc::{rc::Rc, vec::Vec};
+    use core::cell::Cell;
+
+    use rand::{rngs::StdRng, SeedableRng};
 
     /// Tests that encryption context secret export does not change behavior based on the
     /// underlying sequence number This logic is cipher-agnostic, so we don't make the test generic
@@ -385,11 +1294,15 @@ mod test {
                     .export(b"test_export_idempotence", &mut secret1)
                     .unwrap();
 
+                // Exporting alone doesn't key the cipher
+                assert!(matches!(sender_ctx.0.encryptor, LazyAead::Key(_)));
+
                 // Modify the context by encrypting something
                 let mut plaintext = *b"back hand";
                 sender_ctx
                     .seal(&mut plaintext[..], b"")
                     .expect("seal() failed");
+                assert!(matches!(sender_ctx.0.encryptor, LazyAead::Cipher(_)));
 
                 // Get a second export secret
                 let mut secret2 = [0u8; 16];
@@ -498,6 +1411,17 @@ mod test {
 
                 // Make sure seal() isn't a no-op
                 assert!(&ciphertext[..] != &msg[..]);
+                // The overhead is exactly the tag
+                assert_eq!(sender_ctx.overhead(), tag.to_bytes().len());
+
+                // A failed open leaves the ciphertext as it was
+                let mut bad_tag = AeadTag::<A>::from_bytes(&tag.to_bytes()).unwrap();
+                bad_tag.0[0] ^= 1;
+                let orig_ciphertext = ciphertext.clone();
+                assert!(receiver_ctx
+                    .open(&mut ciphertext[..], aad, &bad_tag)
+                    .is_err());
+                assert_eq!(&ciphertext[..], &orig_ciphertext[..]);
 
                 // Decrypt with the receiver context
                 receiver_ctx
@@ -510,11 +1434,579 @@ mod test {
         };
     }
 
+    /// Tests that seals, opens, and failures are counted
+    macro_rules! test_stats {
+        ($test_name:ident, $kem_ty:ty) => {
+            #[test]
+            fn $test_name() {
+                type Kem = $kem_ty;
+                type Kdf = HkdfSha256;
+                // This logic is cipher-agnostic
+                type A = ChaCha20Poly1305;
+
+                let (mut sender_ctx, mut receiver_ctx) = gen_ctx_simple_pair::<A, Kdf, Kem>();
+                assert_eq!(sender_ctx.stats(), Stats::default());
+
+                let mut msg1 = *b"one small step";
+                let mut msg2 = *b"for man";
+                let tag1 = sender_ctx.seal(&mut msg1, b"").unwrap();
+                let tag2 = sender_ctx.seal(&mut msg2, b"").unwrap();
+                assert_eq!(
+                    sender_ctx.stats(),
+                    Stats {
+                        messages: 2,
+                        bytes: 21,
+                        failures: 0,
+                    }
+                );
+
+                // Open the second message first. This fails because of the sequence number.
+                assert!(receiver_ctx.open(&mut msg2, b"", &tag2).is_err());
+                receiver_ctx.open(&mut msg1, b"", &tag1).unwrap();
+                assert_eq!(
+                    receiver_ctx.stats(),
+                    Stats {
+                        messages: 1,
+                        bytes: 14,
+                        failures: 1,
+                    }
+                );
+            }
+        };
+    }
+
+    /// Tests that open_batch opens messages in order and reports the first failure
+    macro_rules! test_open_batch {
+        ($test_name:ident, $kem_ty:ty) => {
+            #[test]
+            fn $test_name() {
+                type Kem = $kem_ty;
+                type Kdf = HkdfSha256;
+                // This logic is cipher-agnostic
+                type A = ChaCha20Poly1305;
+
+                let (mut sender_ctx, mut receiver_ctx) = gen_ctx_simple_pair::<A, Kdf, Kem>();
+
+                // Seal 4 messages
+                let plaintexts = [*b"msg 0", *b"msg 1", *b"msg 2", *b"msg 3"];
+                let mut msgs = plaintexts;
+                let tags: Vec<_> = msgs
+                    .iter_mut()
+                    .map(|msg| sender_ctx.seal(msg, b"aad").unwrap())
+                    .collect();
+
+                // Open the first two in a batch
+                {
+                    let (first, _) = msgs.split_at_mut(2);
+                    let (m0, m1) = first.split_at_mut(1);
+                    let mut batch = [
+                        (&mut m0[0][..], &b"aad"[..], &tags[0]),
+                        (&mut m1[0][..], &b"aad"[..], &tags[1]),
+                    ];
+                    receiver_ctx.open_batch(&mut batch).unwrap();
+                }
+                assert_eq!(msgs[..2], plaintexts[..2]);
+
+                // Now open the last two out of order. The first is the failure.
+                let (_, last) = msgs.split_at_mut(2);
+                let (m2, m3) = last.split_at_mut(1);
+                let mut batch = [
+                    (&mut m3[0][..], &b"aad"[..], &tags[3]),
+                    (&mut m2[0][..], &b"aad"[..], &tags[2]),
+                ];
+                assert!(matches!(
+                    receiver_ctx.open_batch(&mut batch),
+                    Err((0, HpkeError::InvalidTag))
+                ));
+
+                // An empty batch does nothing
+                receiver_ctx.open_batch(&mut []).unwrap();
+                assert_eq!(receiver_ctx.stats().messages, 2);
+                assert_eq!(receiver_ctx.stats().failures, 1);
+            }
+        };
+    }
+
+    /// Tests that a seal done outside the context, with the key and nonces it hands out, opens
+    /// under the receiver's context
+    #[cfg(feature = "danger")]
+    macro_rules! test_external_nonce {
+        ($test_name:ident, $kem_ty:ty) => {
+            #[test]
+            fn $test_name() {
+                type A = ChaCha20Poly1305;
+                type Kdf = HkdfSha256;
+                type Kem = $kem_ty;
+
+                let (mut sender_ctx, mut receiver_ctx) = gen_ctx_simple_pair::<A, Kdf, Kem>();
+
+                // Play the hardware engine: key a cipher with the context's key
+                use aead::{AeadInPlace, NewAead};
+                let cipher = {
+                    let key = sender_ctx.key_dangerous().unwrap();
+                    <A as Aead>::AeadImpl::new(key)
+                };
+
+                // The context hands out the same nonces that compute_nonce predicts
+                for seq in 0..3u64 {
+                    let nonce = sender_ctx.next_nonce_dangerous().unwrap();
+                    assert_eq!(nonce, sender_ctx.compute_nonce(seq));
+                    assert_eq!(nonce, receiver_ctx.compute_nonce(seq));
+
+                    let mut ciphertext = b"sealed elsewhere".to_vec();
+                    let tag = cipher
+                        .encrypt_in_place_detached(&nonce, b"aad", &mut ciphertext)
+                        .unwrap();
+                    receiver_ctx
+                        .open(&mut ciphertext, b"aad", &AeadTag(tag))
+                        .unwrap();
+                    assert_eq!(&ciphertext[..], b"sealed elsewhere");
+                }
+
+                // Once the context has keyed its own cipher, the key is gone
+                sender_ctx.seal(&mut [], b"").unwrap();
+                assert!(sender_ctx.key_dangerous().is_none());
+                assert!(receiver_ctx.key_dangerous().is_none());
+            }
+        };
+    }
+
+    #[cfg(all(feature = "danger", feature = "x25519-dalek"))]
+    test_external_nonce!(test_external_nonce_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(all(feature = "danger", feature = "p256"))]
+    test_external_nonce!(test_external_nonce_p256, crate::kem::DhP256HkdfSha256);
+
+    /// A backend that runs ChaCha20Poly1305 in software and counts its calls
+    #[derive(Clone)]
+    struct CountingBackend {
+        cipher: <ChaCha20Poly1305 as Aead>::AeadImpl,
+        calls: Rc<Cell<usize>>,
+    }
+
+    impl AeadBackend for CountingBackend {
+        type Alg = ChaCha20Poly1305;
+
+        fn new(key: &AeadKey<ChaCha20Poly1305>) -> Self {
+            use aead::NewAead;
+            CountingBackend {
+                cipher: NewAead::new(key),
+                calls: Rc::new(Cell::new(0)),
+            }
+        }
+
+        fn seal_in_place(
+            &self,
+            nonce: &AeadNonce<ChaCha20Poly1305>,
+            aad: &[u8],
+            buf: &mut [u8],
+        ) -> Result<AeadTag<ChaCha20Poly1305>, HpkeError> {
+            use aead::AeadInPlace;
+            self.calls.set(self.calls.get() + 1);
+            self.cipher
+                .encrypt_in_place_detached(nonce, aad, buf)
+                .map(AeadTag)
+                .map_err(|_| HpkeError::Encryption)
+        }
+
+        fn open_in_place(
+            &self,
+            nonce: &AeadNonce<ChaCha20Poly1305>,
+            aad: &[u8],
+            buf: &mut [u8],
+            tag: &AeadTag<ChaCha20Poly1305>,
+        ) -> Result<(), HpkeError> {
+            use aead::AeadInPlace;
+            self.calls.set(self.calls.get() + 1);
+            self.cipher
+                .decrypt_in_place_detached(nonce, aad, buf, &tag.0)
+                .map_err(|_| HpkeError::InvalidTag)
+        }
+    }
+
+    /// Tests that an offloaded AEAD interoperates with the in-process one, in both directions
+    macro_rules! test_offload {
+        ($test_name:ident, $kem_ty:ty) => {
+            #[test]
+            fn $test_name() {
+                type Kdf = HkdfSha256;
+                type Kem = $kem_ty;
+                type Hw = Offload<CountingBackend>;
+                let mut csprng = StdRng::from_entropy();
+
+                let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+                let info = b"offload test";
+
+                // Seal on the "hardware", open in software
+                let (encapped_key, mut sender_ctx) =
+                    setup_sender::<Hw, Kdf, Kem, _>(&OpModeS::Base, &pk_recip, info, &mut csprng)
+                        .unwrap();
+                let mut receiver_ctx = setup_receiver::<ChaCha20Poly1305, Kdf, Kem>(
+                    &OpModeR::Base,
+                    &sk_recip,
+                    &encapped_key,
+                    info,
+                )
+                .unwrap();
+                for _ in 0..2 {
+                    let mut msg = b"offloaded".to_vec();
+                    let tag = sender_ctx.seal(&mut msg, b"aad").unwrap();
+                    let tag = AeadTag::<ChaCha20Poly1305>::from_bytes(&tag.to_bytes()).unwrap();
+                    receiver_ctx.open(&mut msg, b"aad", &tag).unwrap();
+                    assert_eq!(&msg[..], b"offloaded");
+                }
+
+                // Seal in software, open on the "hardware"
+                let (encapped_key, mut sender_ctx) = setup_sender::<ChaCha20Poly1305, Kdf, Kem, _>(
+                    &OpModeS::Base,
+                    &pk_recip,
+                    info,
+                    &mut csprng,
+                )
+                .unwrap();
+                let mut receiver_ctx =
+                    setup_receiver::<Hw, Kdf, Kem>(&OpModeR::Base, &sk_recip, &encapped_key, info)
+                        .unwrap();
+                let mut msg = b"offloaded".to_vec();
+                let tag = sender_ctx.seal(&mut msg, b"aad").unwrap();
+                let mut tag = AeadTag::<Hw>::from_bytes(&tag.to_bytes()).unwrap();
+                receiver_ctx.open(&mut msg, b"aad", &tag).unwrap();
+                assert_eq!(&msg[..], b"offloaded");
+
+                // A backend failure comes out the same as it would in software
+                tag.0[0] ^= 1;
+                assert!(matches!(
+                    receiver_ctx.open(&mut msg, b"aad", &tag),
+                    Err(HpkeError::InvalidTag)
+                ));
+
+                // Both opens went through the backend
+                match &receiver_ctx.0.encryptor {
+                    LazyAead::Cipher(OffloadImpl(backend)) => assert_eq!(backend.calls.get(), 2),
+                    LazyAead::Key(_) => panic!("backend was never keyed"),
+                }
+            }
+        };
+    }
+
+    /// A backend that garbles the buffer whenever it fails
+    #[cfg(feature = "restore-buffers")]
+    #[derive(Clone)]
+    struct ScribblingBackend(CountingBackend);
+
+    #[cfg(feature = "restore-buffers")]
+    impl AeadBackend for ScribblingBackend {
+        type Alg = ChaCha20Poly1305;
+
+        fn new(key: &AeadKey<ChaCha20Poly1305>) -> Self {
+            ScribblingBackend(CountingBackend::new(key))
+        }
+
+        fn seal_in_place(
+            &self,
+            _nonce: &AeadNonce<ChaCha20Poly1305>,
+            _aad: &[u8],
+            buf: &mut [u8],
+        ) -> Result<AeadTag<ChaCha20Poly1305>, HpkeError> {
+            // Every seal fails, halfway through
+            for b in buf.iter_mut() {
+                *b = 0xff;
+            }
+            Err(HpkeError::Encryption)
+        }
+
+        fn open_in_place(
+            &self,
+            nonce: &AeadNonce<ChaCha20Poly1305>,
+            aad: &[u8],
+            buf: &mut [u8],
+            tag: &AeadTag<ChaCha20Poly1305>,
+        ) -> Result<(), HpkeError> {
+            let res = self.0.open_in_place(nonce, aad, buf, tag);
+            if res.is_err() {
+                for b in buf.iter_mut() {
+                    *b = 0xff;
+                }
+            }
+            res
+        }
+    }
+
+    /// Tests that with restore-buffers on, a backend that garbles buffers on error can't garble
+    /// the caller's
+    #[cfg(feature = "restore-buffers")]
+    macro_rules! test_restore_buffers {
+        ($test_name:ident, $kem_ty:ty) => {
+            #[test]
+            fn $test_name() {
+                type Kdf = HkdfSha256;
+                type Kem = $kem_ty;
+                type Hw = Offload<ScribblingBackend>;
+
+                let (mut sender_ctx, mut receiver_ctx) = gen_ctx_simple_pair::<Hw, Kdf, Kem>();
+
+                let mut plaintext = b"leave me be".to_vec();
+                assert!(matches!(
+                    sender_ctx.seal(&mut plaintext, b""),
+                    Err(HpkeError::Encryption)
+                ));
+                assert_eq!(&plaintext[..], b"leave me be");
+
+                let tag = AeadTag::<Hw>::from_bytes(&[0u8; 16]).unwrap();
+                assert!(matches!(
+                    receiver_ctx.open(&mut plaintext, b"", &tag),
+                    Err(HpkeError::InvalidTag)
+                ));
+                assert_eq!(&plaintext[..], b"leave me be");
+            }
+        };
+    }
+
+    #[cfg(all(feature = "restore-buffers", feature = "x25519-dalek"))]
+    test_restore_buffers!(test_restore_buffers_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(all(feature = "restore-buffers", feature = "p256"))]
+    test_restore_buffers!(test_restore_buffers_p256, crate::kem::DhP256HkdfSha256);
+
+    #[cfg(feature = "x25519-dalek")]
+    test_offload!(test_offload_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_offload!(test_offload_p256, crate::kem::DhP256HkdfSha256);
+
     #[cfg(feature = "x25519-dalek")]
     test_export_idempotence!(test_export_idempotence_x25519, crate::kem::X25519HkdfSha256);
     #[cfg(feature = "p256")]
     test_export_idempotence!(test_export_idempotence_p256, crate::kem::DhP256HkdfSha256);
 
+    /// Tests that the sequence number is XORed into the nonce as a big-endian integer, whatever
+    /// the byte order of the target
+    #[test]
+    fn test_mix_nonce_big_endian() {
+        type A = ChaCha20Poly1305;
+
+        let base_nonce = AeadNonce::<A>::clone_from_slice(&[0xff; 12]);
+        let nonce = mix_nonce::<A>(&base_nonce, &Seq(0x0102_0304_0506_0708));
+        assert_eq!(
+            nonce.as_slice(),
+            [0xff, 0xff, 0xff, 0xff, 0xfe, 0xfd, 0xfc, 0xfb, 0xfa, 0xf9, 0xf8, 0xf7]
+        );
+    }
+
+    /// Tests that the sequence number is a full 64 bits on every target, and doesn't wrap at the
+    /// pointer width
+    #[cfg(feature = "x25519-dalek")]
+    #[test]
+    fn test_seq_past_u32() {
+        type A = ChaCha20Poly1305;
+        type Kem = crate::kem::X25519HkdfSha256;
+
+        let (mut sender_ctx, mut receiver_ctx) = gen_ctx_simple_pair::<A, HkdfSha256, Kem>();
+        let start = u64::from(u32::MAX);
+        sender_ctx.0.seq = Seq(start);
+        receiver_ctx.0.seq = Seq(start);
+
+        for seq in start..start + 2 {
+            let mut msg = *b"past 2^32";
+            let tag = sender_ctx.seal(&mut msg, b"").unwrap();
+            receiver_ctx.open(&mut msg, b"", &tag).unwrap();
+            assert_eq!(sender_ctx.0.seq.0, seq + 1);
+        }
+        assert_ne!(
+            mix_nonce::<A>(&sender_ctx.0.nonce, &Seq(1 << 32)),
+            mix_nonce::<A>(&sender_ctx.0.nonce, &Seq(0))
+        );
+    }
+
+    /// Tests that the chunk arithmetic of large messages doesn't overflow for lengths near the
+    /// top of the address space
+    #[test]
+    fn test_large_chunk_extremes() {
+        let len = usize::MAX;
+        let num_chunks = large_chunk_count(len);
+        let last = large_chunk_range(num_chunks - 1, len);
+        assert_eq!(last.end, len);
+        assert!(last.end - last.start <= LARGE_CHUNK_SIZE);
+
+        // Every chunk but the last is full
+        let second_last = large_chunk_range(num_chunks - 2, len);
+        assert_eq!(second_last.end - second_last.start, LARGE_CHUNK_SIZE);
+        assert_eq!(second_last.end, last.start);
+    }
+
+    /// Tests the chunk count of the largest message on a 32-bit target, where it's small enough to
+    /// be a real concern
+    #[cfg(target_pointer_width = "32")]
+    #[test]
+    fn test_large_chunk_count_32bit() {
+        assert_eq!(large_chunk_count(usize::MAX), 1 << 16);
+    }
+
+    // Property tests for the invariants that nonce safety rests on: distinct sequence numbers
+    // give distinct nonces, every seal advances the sequence number by exactly one, and a context
+    // that starts at a given sequence number agrees with one that counted up to it
+    mod prop {
+        use super::{
+            mix_nonce, AeadNonce, AeadTag, ChaCha20Poly1305, HkdfSha256, Seq, Serializable,
+        };
+        use crate::test_util::gen_ctx_simple_pair;
+
+        use alloc::{collections::BTreeSet, vec::Vec};
+
+        use proptest::prelude::*;
+
+        type A = ChaCha20Poly1305;
+
+        proptest! {
+            /// Tests that `mix_nonce` never maps two sequence numbers to the same nonce, and that
+            /// it only touches the last 8 bytes of the base nonce
+            #[test]
+            fn prop_mix_nonce_unique(
+                base in any::<[u8; 12]>(),
+                seqs in proptest::collection::btree_set(any::<u64>(), 1..64),
+            ) {
+                let base_nonce = AeadNonce::<A>::clone_from_slice(&base);
+                let nonces: BTreeSet<Vec<u8>> = seqs
+                    .iter()
+                    .map(|&seq| mix_nonce::<A>(&base_nonce, &Seq(seq)).to_vec())
+                    .collect();
+                prop_assert_eq!(nonces.len(), seqs.len());
+
+                for nonce in nonces.iter() {
+                    prop_assert_eq!(&nonce[..4], &base[..4]);
+                }
+            }
+
+            /// Tests that sealing from an arbitrary starting point uses consecutive nonces, bumps
+            /// the sequence number by one each time, and that a receiver starting at the same point
+            /// opens every message
+            #[cfg(feature = "x25519-dalek")]
+            #[test]
+            fn prop_seq_monotonic(start in any::<u64>(), count in 1u64..16) {
+                type Kem = crate::kem::X25519HkdfSha256;
+
+                let (mut sender_ctx, mut receiver_ctx) =
+                    gen_ctx_simple_pair::<A, HkdfSha256, Kem>();
+                sender_ctx.0.seq = Seq(start);
+                receiver_ctx.0.seq = Seq(start);
+
+                // Near the end of the seq space, we can only do as many as remain
+                let count = count.min(u64::MAX - start);
+                for i in 0..count {
+                    let seq = start + i;
+                    prop_assert_eq!(sender_ctx.0.seq.0, seq);
+                    #[cfg(feature = "danger")]
+                    {
+                        let nonce = mix_nonce::<A>(&sender_ctx.0.nonce, &sender_ctx.0.seq);
+                        prop_assert_eq!(nonce, sender_ctx.compute_nonce(seq));
+                    }
+
+                    let mut msg = seq.to_be_bytes();
+                    let tag = sender_ctx.seal(&mut msg, b"").unwrap();
+                    prop_assert_eq!(sender_ctx.0.seq.0, seq + 1);
+
+                    receiver_ctx.open(&mut msg, b"", &tag).unwrap();
+                    prop_assert_eq!(msg, seq.to_be_bytes());
+                    prop_assert_eq!(receiver_ctx.0.seq.0, seq + 1);
+                }
+            }
+
+            /// Tests that a context moved straight to sequence number `n` seals exactly what a
+            /// context that sealed `n` messages before it does
+            #[cfg(feature = "x25519-dalek")]
+            #[test]
+            fn prop_jump_matches_sequential(n in 0u64..32) {
+                type Kem = crate::kem::X25519HkdfSha256;
+
+                let (mut sequential_ctx, _) = gen_ctx_simple_pair::<A, HkdfSha256, Kem>();
+                let mut jumped_ctx = sequential_ctx.clone();
+                for _ in 0..n {
+                    sequential_ctx.seal(&mut [], b"").unwrap();
+                }
+                jumped_ctx.0.seq = Seq(n);
+
+                let mut msg1 = *b"same message";
+                let mut msg2 = msg1;
+                let tag1: AeadTag<A> = sequential_ctx.seal(&mut msg1, b"aad").unwrap();
+                let tag2 = jumped_ctx.seal(&mut msg2, b"aad").unwrap();
+                prop_assert_eq!(msg1, msg2);
+                prop_assert_eq!(tag1.to_bytes(), tag2.to_bytes());
+            }
+        }
+    }
+
+    #[cfg(feature = "x25519-dalek")]
+    test_stats!(test_stats_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_stats!(test_stats_p256, crate::kem::DhP256HkdfSha256);
+
+    #[cfg(feature = "x25519-dalek")]
+    test_open_batch!(test_open_batch_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_open_batch!(test_open_batch_p256, crate::kem::DhP256HkdfSha256);
+
+    /// Tests that seal_large and open_large round-trip, and that dropping or reordering chunks is
+    /// caught
+    macro_rules! test_large {
+        ($test_name:ident, $kem_ty:ty) => {
+            #[test]
+            fn $test_name() {
+                type Kem = $kem_ty;
+                type Kdf = HkdfSha256;
+                // This logic is cipher-agnostic
+                type A = ChaCha20Poly1305;
+
+                // 3.5 chunks
+                let plaintext: Vec<u8> = (0..7 * LARGE_CHUNK_SIZE / 2).map(|i| i as u8).collect();
+
+                // Round trip
+                let (mut sender_ctx, mut receiver_ctx) = gen_ctx_simple_pair::<A, Kdf, Kem>();
+                let mut msg = plaintext.clone();
+                let tags = sender_ctx.seal_large(&mut msg, b"aad").unwrap();
+                assert_eq!(tags.len(), 4);
+                assert_eq!(sender_ctx.stats().messages, 4);
+                receiver_ctx.open_large(&mut msg, b"aad", &tags).unwrap();
+                assert_eq!(msg, plaintext);
+
+                // An empty plaintext is one chunk
+                let tags = sender_ctx.seal_large(&mut [], b"aad").unwrap();
+                assert_eq!(tags.len(), 1);
+                receiver_ctx.open_large(&mut [], b"aad", &tags).unwrap();
+
+                // Dropping the last chunk fails, even though every remaining tag is valid
+                let (mut sender_ctx, mut receiver_ctx) = gen_ctx_simple_pair::<A, Kdf, Kem>();
+                let mut msg = plaintext.clone();
+                let tags = sender_ctx.seal_large(&mut msg, b"aad").unwrap();
+                assert!(matches!(
+                    receiver_ctx.open_large(&mut msg[..3 * LARGE_CHUNK_SIZE], b"aad", &tags[..3]),
+                    Err(HpkeError::InvalidTag)
+                ));
+
+                // So does a tag count that doesn't match the length
+                let (mut sender_ctx, mut receiver_ctx) = gen_ctx_simple_pair::<A, Kdf, Kem>();
+                let mut msg = plaintext.clone();
+                let tags = sender_ctx.seal_large(&mut msg, b"aad").unwrap();
+                assert!(matches!(
+                    receiver_ctx.open_large(&mut msg, b"aad", &tags[..3]),
+                    Err(HpkeError::InvalidTag)
+                ));
+                assert_eq!(receiver_ctx.stats().messages, 0);
+
+                // And so does swapping two chunks
+                let (first, rest) = msg.split_at_mut(LARGE_CHUNK_SIZE);
+                first.swap_with_slice(&mut rest[..LARGE_CHUNK_SIZE]);
+                let mut tags = tags;
+                tags.swap(0, 1);
+                assert!(matches!(
+                    receiver_ctx.open_large(&mut msg, b"aad", &tags),
+                    Err(HpkeError::InvalidTag)
+                ));
+            }
+        };
+    }
+
+    #[cfg(feature = "x25519-dalek")]
+    test_large!(test_large_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_large!(test_large_p256, crate::kem::DhP256HkdfSha256);
+
     #[cfg(feature = "x25519-dalek")]
     test_overflow!(test_overflow_x25519, crate::kem::X25519HkdfSha256);
     #[cfg(feature = "p256")]
@@ -556,4 +2048,104 @@ mod test {
         ChaCha20Poly1305,
         crate::kem::DhP256HkdfSha256
     );
+
+    // The vectors below are from the base mode X25519, HKDF-SHA256 test vectors in
+    // test-vectors-403bf8c.json. They all seal the same plaintext under the same AAD as the first
+    // message of the context, and export from the same exporter context.
+    macro_rules! test_aead_vector {
+        (
+            $test_name:ident,
+            $aead_ty:ty,
+            key = $key:expr,
+            nonce = $nonce:expr,
+            exporter_secret = $exporter_secret:expr,
+            ciphertext = $ciphertext:expr,
+            export = $export:expr $(,)?
+        ) => {
+            /// Tests seal, open, and export against a known answer
+            #[test]
+            fn $test_name() {
+                type A = $aead_ty;
+                type Kdf = HkdfSha256;
+                type Kem = crate::kem::X25519HkdfSha256;
+
+                let plaintext =
+                    hex::decode("4265617574792069732074727574682c20747275746820626561757479")
+                        .unwrap();
+                let aad = hex::decode("436f756e742d30").unwrap();
+                let expected_ciphertext = hex::decode($ciphertext).unwrap();
+
+                let make_ctx = || {
+                    AeadCtx::<A, Kdf, Kem>::new(
+                        AeadKey::<A>::clone_from_slice(&hex::decode($key).unwrap()),
+                        AeadNonce::<A>::clone_from_slice(&hex::decode($nonce).unwrap()),
+                        ExporterSecret::<Kdf>::clone_from_slice(
+                            &hex::decode($exporter_secret).unwrap(),
+                        ),
+                    )
+                };
+                let mut sender_ctx = AeadCtxS::from(make_ctx());
+                let mut receiver_ctx = AeadCtxR::from(make_ctx());
+
+                // The vector's ciphertext is the encrypted plaintext followed by the tag
+                let mut msg = plaintext.clone();
+                let tag = sender_ctx.seal(&mut msg, &aad).unwrap();
+                let (ct, tag_bytes) = expected_ciphertext.split_at(plaintext.len());
+                assert_eq!(msg.as_slice(), ct);
+                assert_eq!(tag.to_bytes().as_slice(), tag_bytes);
+
+                // Open the vector's ciphertext
+                let mut msg = ct.to_vec();
+                let tag = AeadTag::<A>::from_bytes(tag_bytes).unwrap();
+                receiver_ctx.open(&mut msg, &aad, &tag).unwrap();
+                assert_eq!(msg, plaintext);
+
+                let mut exported = [0u8; 32];
+                receiver_ctx
+                    .export(&hex::decode("436f6e746578742d30").unwrap(), &mut exported)
+                    .unwrap();
+                assert_eq!(hex::encode(exported), $export);
+            }
+        };
+    }
+
+    #[cfg(feature = "x25519-dalek")]
+    test_aead_vector!(
+        test_aead_vector_aes128,
+        AesGcm128,
+        key = "f73c75623822357593eccf35565b07f4",
+        nonce = "7530e5ad98ff49a760326cb9",
+        exporter_secret = "84b158baff479e2a1e0392d62f6ebcd5ef5ce6002b7a06d1c91d62c9612761ba",
+        ciphertext = concat!(
+            "ba37f063ff1588b5225183b560063fc7bbae3b1f68aa2d00a45c73785f",
+            "de279d73028bfcdf105ccc1e93ae2d31",
+        ),
+        export = "98e60611893bc52d1cdd771e580c21444c64442abb068ae2a1ee7e0370de4eb4",
+    );
+    #[cfg(feature = "x25519-dalek")]
+    test_aead_vector!(
+        test_aead_vector_aes256,
+        AesGcm256,
+        key = "bda20abd224aaedc90ade6e7a2b16b32306c57ce12deff4f3e26fa31c0a1263b",
+        nonce = "045bcea1b40a7a5e6b177447",
+        exporter_secret = "3fc722a8f40bd3370b6c9a142093aa5b6177153f531468020e9de1ec72eb47aa",
+        ciphertext = concat!(
+            "dbe3e56f171ca2f83d7ab299d04cae6f5bb129e9ff82d8ca829d8516a2",
+            "abcaead44958af6613b2cde5f330ec2e",
+        ),
+        export = "48234a0872d4ecebeab05644875fcbfb6f121ab043aaf3926491014f65533f4c",
+    );
+    #[cfg(feature = "x25519-dalek")]
+    test_aead_vector!(
+        test_aead_vector_chacha,
+        ChaCha20Poly1305,
+        key = "41b9ddb0860f38a6c53e16f07400d1abe84aef89d14138bdc90732d60d1364c9",
+        nonce = "b66635166986772f1a51765b",
+        exporter_secret = "8859392535ad0f6e4b236d3eb906973095963835dc2f3d0a12be386dbc7f08ee",
+        ciphertext = concat!(
+            "3b68969454f0da698358337aabf28fe57992314080348f00aa805a1090",
+            "52aac0f98049ccaf64c61cac4940d32a",
+        ),
+        export = "35b600889b00bf8fef2407f26f89fac16b9b89d60bae75d4b513960b014f759c",
+    );
 }
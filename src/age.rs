@@ -0,0 +1,408 @@
+//! This module defines [`X25519Stanza`], the X25519 recipient stanza of the
+//! [age](https://age-encryption.org/v1) file format, and the `age1...` and `AGE-SECRET-KEY-1...`
+//! encodings of X25519 keys. With these, HPKE tooling that holds X25519 keys can unwrap the file
+//! keys of existing age files, and wrap file keys for age recipients. This is gated under the `age`
+//! feature.
+//!
+//! An age stanza is not HPKE. It does an X25519 key exchange with an ephemeral key, derives a
+//! wrapping key with HKDF-SHA256, and encrypts a 16-byte file key under it with
+//! ChaCha20Poly1305. Those are the same primitives as the `X25519HkdfSha256` KEM with
+//! `ChaCha20Poly1305`, but age labels and combines them differently, so the two aren't
+//! interchangeable. Use the same keypair with both only if your protocol allows it.
+//!
+//! Only the stanza is covered here. The rest of the age header, its MAC, and the payload are keyed
+//! by the file key, and are up to the caller, e.g., the `age` crate.
+//!
+//! Wire format
+//! ===========
+//! A stanza is `"-> X25519 " || base64(share) || "\n" || base64(body) || "\n"`, where `share` is
+//! the sender's ephemeral public key, and `body` is the encrypted file key followed by its tag.
+//! Base64 is the standard alphabet without padding.
+
+use crate::{
+    kem::{Kem as KemTrait, X25519HkdfSha256},
+    kex::{Deserializable, KeyExchange, Serializable, X25519},
+    HpkeError,
+};
+
+use alloc::{format, string::String};
+
+use aead::{AeadInPlace, NewAead};
+use base64ct::{Base64Unpadded, Encoding};
+use bech32::{FromBase32, ToBase32, Variant};
+use chacha20poly1305::ChaCha20Poly1305;
+use generic_array::GenericArray;
+use hkdf::Hkdf;
+use rand::{CryptoRng, RngCore};
+use sha2::Sha256;
+use zeroize::Zeroize;
+
+type PublicKey = <X25519 as KeyExchange>::PublicKey;
+type PrivateKey = <X25519 as KeyExchange>::PrivateKey;
+
+/// The tag of an X25519 recipient stanza, i.e., its first argument after `->`
+pub const X25519_STANZA_TAG: &str = "X25519";
+
+/// The size of an age file key, in bytes
+pub const FILE_KEY_SIZE: usize = 16;
+
+// The HKDF info string of the wrapping key
+const WRAP_KEY_INFO: &[u8] = b"age-encryption.org/v1/X25519";
+
+// The human-readable parts of the bech32 encodings of recipients and identities
+const RECIPIENT_HRP: &str = "age";
+const IDENTITY_HRP: &str = "age-secret-key-";
+
+// The size of a stanza body: the encrypted file key and its 16-byte tag
+const BODY_SIZE: usize = FILE_KEY_SIZE + 16;
+
+/// An X25519 recipient stanza, which holds a file key encrypted to one recipient
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct X25519Stanza {
+    ephemeral_share: [u8; 32],
+    body: [u8; BODY_SIZE],
+}
+
+impl X25519Stanza {
+    /// Encrypts `file_key` to the given recipient, the way age does
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::InvalidKeyExchange)` if `pk_recip` is a low-order point, which age
+    /// forbids. Otherwise returns the stanza.
+    pub fn wrap<R: CryptoRng + RngCore>(
+        file_key: &[u8; FILE_KEY_SIZE],
+        pk_recip: &PublicKey,
+        csprng: &mut R,
+    ) -> Result<Self, HpkeError> {
+        let (sk_eph, pk_eph) = X25519HkdfSha256::gen_keypair(csprng);
+        let mut ephemeral_share = [0u8; 32];
+        ephemeral_share.copy_from_slice(&pk_eph.to_bytes());
+
+        let mut wrap_key = derive_wrap_key(&sk_eph, pk_recip, &ephemeral_share, pk_recip)?;
+        let cipher = ChaCha20Poly1305::new(GenericArray::from_slice(&wrap_key));
+        wrap_key.zeroize();
+
+        // Every wrapping key is fresh, so the nonce is always zero
+        let mut body = [0u8; BODY_SIZE];
+        body[..FILE_KEY_SIZE].copy_from_slice(file_key);
+        let tag = cipher
+            .encrypt_in_place_detached(&Default::default(), b"", &mut body[..FILE_KEY_SIZE])
+            .map_err(|_| HpkeError::Encryption)?;
+        body[FILE_KEY_SIZE..].copy_from_slice(&tag);
+
+        Ok(X25519Stanza {
+            ephemeral_share,
+            body,
+        })
+    }
+
+    /// Decrypts the file key in this stanza with the recipient's private key
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::InvalidTag)` if the stanza isn't for this recipient, or was
+    /// tampered with. Returns `Err(HpkeError::InvalidKeyExchange)` if the ephemeral share is a
+    /// low-order point. Otherwise returns the file key.
+    pub fn unwrap(&self, sk_recip: &PrivateKey) -> Result<[u8; FILE_KEY_SIZE], HpkeError> {
+        let pk_eph = PublicKey::from_bytes(&self.ephemeral_share)?;
+        let pk_recip = X25519::sk_to_pk(sk_recip);
+
+        let mut wrap_key = derive_wrap_key(sk_recip, &pk_eph, &self.ephemeral_share, &pk_recip)?;
+        let cipher = ChaCha20Poly1305::new(GenericArray::from_slice(&wrap_key));
+        wrap_key.zeroize();
+
+        let mut file_key = [0u8; FILE_KEY_SIZE];
+        file_key.copy_from_slice(&self.body[..FILE_KEY_SIZE]);
+        let tag = GenericArray::from_slice(&self.body[FILE_KEY_SIZE..]);
+        match cipher.decrypt_in_place_detached(&Default::default(), b"", &mut file_key, tag) {
+            Ok(()) => Ok(file_key),
+            Err(_) => {
+                file_key.zeroize();
+                Err(HpkeError::InvalidTag)
+            }
+        }
+    }
+
+    /// Makes a stanza out of the parts an age header parser gives, e.g., the `tag`, `args`, and
+    /// `body` of an `age_core::format::Stanza`
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::SuiteMismatch)` if `tag` isn't [`X25519_STANZA_TAG`], i.e., the
+    /// stanza is for another kind of recipient. Returns `Err(HpkeError::InvalidEncoding)` if the
+    /// arguments or body are malformed. Otherwise returns the stanza.
+    pub fn from_parts<S: AsRef<str>>(
+        tag: &str,
+        args: &[S],
+        body: &[u8],
+    ) -> Result<Self, HpkeError> {
+        if tag != X25519_STANZA_TAG {
+            return Err(HpkeError::SuiteMismatch);
+        }
+        let share = match args {
+            [share] => share.as_ref(),
+            _ => return Err(HpkeError::InvalidEncoding),
+        };
+
+        let mut stanza = X25519Stanza {
+            ephemeral_share: [0u8; 32],
+            body: [0u8; BODY_SIZE],
+        };
+        decode_exact(share, &mut stanza.ephemeral_share)?;
+        if body.len() != BODY_SIZE {
+            return Err(HpkeError::InvalidEncoding);
+        }
+        stanza.body.copy_from_slice(body);
+        Ok(stanza)
+    }
+
+    /// Returns the stanza's one argument, the base64 encoding of the ephemeral share
+    pub fn arg(&self) -> String {
+        Base64Unpadded::encode_string(&self.ephemeral_share)
+    }
+
+    /// Returns the stanza's body, the encrypted file key followed by its tag
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Encodes this stanza the way it appears in an age header, including the final newline
+    pub fn encode(&self) -> String {
+        format!(
+            "-> {} {}\n{}\n",
+            X25519_STANZA_TAG,
+            self.arg(),
+            Base64Unpadded::encode_string(&self.body)
+        )
+    }
+
+    /// Decodes a stanza made by [`encode`](Self::encode). This is strict, like age: the base64
+    /// must be canonical, and there's no room for extra whitespace.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::SuiteMismatch)` if `encoded` is a stanza for another kind of
+    /// recipient. Returns `Err(HpkeError::InvalidEncoding)` if it's malformed. Otherwise returns
+    /// the stanza.
+    pub fn decode(encoded: &str) -> Result<Self, HpkeError> {
+        let (first_line, rest) = split_line(encoded).ok_or(HpkeError::InvalidEncoding)?;
+        let (body_line, rest) = split_line(rest).ok_or(HpkeError::InvalidEncoding)?;
+        if !rest.is_empty() {
+            return Err(HpkeError::InvalidEncoding);
+        }
+
+        let mut words = first_line.split(' ');
+        if words.next() != Some("->") {
+            return Err(HpkeError::InvalidEncoding);
+        }
+        let tag = words.next().ok_or(HpkeError::InvalidEncoding)?;
+        let args: alloc::vec::Vec<&str> = words.collect();
+
+        let mut body = [0u8; BODY_SIZE];
+        if tag == X25519_STANZA_TAG {
+            decode_exact(body_line, &mut body)?;
+        }
+        Self::from_parts(tag, &args, &body)
+    }
+}
+
+/// Encodes an X25519 public key as an age recipient, i.e., `age1...`
+pub fn recipient_to_string(pk: &PublicKey) -> String {
+    bech32::encode(RECIPIENT_HRP, pk.to_bytes().to_base32(), Variant::Bech32)
+        .expect("recipient is a valid bech32 string")
+}
+
+/// Decodes an age X25519 recipient, i.e., `age1...`. Returns `HpkeError::InvalidEncoding` if it's
+/// malformed or isn't an X25519 recipient.
+pub fn recipient_from_str(encoded: &str) -> Result<PublicKey, HpkeError> {
+    let key = decode_bech32(encoded, RECIPIENT_HRP)?;
+    PublicKey::from_bytes(&key)
+}
+
+/// Encodes an X25519 private key as an age identity, i.e., `AGE-SECRET-KEY-1...`. Private keys are
+/// stored clamped, so this might not be the same string the key was decoded from. Both strings
+/// encode the same key.
+pub fn identity_to_string(sk: &PrivateKey) -> String {
+    let mut key = sk.to_bytes();
+    let encoded = bech32::encode(IDENTITY_HRP, key.to_base32(), Variant::Bech32)
+        .expect("identity is a valid bech32 string");
+    key.zeroize();
+    encoded.to_uppercase()
+}
+
+/// Decodes an age X25519 identity, i.e., `AGE-SECRET-KEY-1...`. Returns
+/// `HpkeError::InvalidEncoding` if it's malformed or isn't an X25519 identity.
+pub fn identity_from_str(encoded: &str) -> Result<PrivateKey, HpkeError> {
+    let mut key = decode_bech32(encoded, IDENTITY_HRP)?;
+    let sk = PrivateKey::from_bytes(&key);
+    key.zeroize();
+    sk
+}
+
+// Derives the wrapping key from the DH of sk and pk:
+//   salt = ephemeral_share || recipient
+//   wrap_key = HKDF-SHA256(ikm = DH(sk, pk), salt, info = "age-encryption.org/v1/X25519")
+fn derive_wrap_key(
+    sk: &PrivateKey,
+    pk: &PublicKey,
+    ephemeral_share: &[u8; 32],
+    pk_recip: &PublicKey,
+) -> Result<[u8; 32], HpkeError> {
+    let shared_secret = X25519::kex(sk, pk)?;
+
+    let mut salt = [0u8; 64];
+    salt[..32].copy_from_slice(ephemeral_share);
+    salt[32..].copy_from_slice(&pk_recip.to_bytes());
+
+    let mut wrap_key = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(&salt), &shared_secret.to_bytes())
+        .expand(WRAP_KEY_INFO, &mut wrap_key)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    Ok(wrap_key)
+}
+
+// Decodes canonical unpadded base64 into exactly out.len() bytes
+fn decode_exact(encoded: &str, out: &mut [u8]) -> Result<(), HpkeError> {
+    let expected_len = out.len();
+    match Base64Unpadded::decode(encoded, out) {
+        Ok(decoded) if decoded.len() == expected_len => Ok(()),
+        _ => Err(HpkeError::InvalidEncoding),
+    }
+}
+
+// Splits off the first line of s, without its newline. Returns None if there's no newline.
+fn split_line(s: &str) -> Option<(&str, &str)> {
+    let end = s.find('\n')?;
+    Some((&s[..end], &s[end + 1..]))
+}
+
+// Decodes a bech32 string with the given human-readable part into a 32-byte key
+fn decode_bech32(encoded: &str, hrp: &str) -> Result<[u8; 32], HpkeError> {
+    let (decoded_hrp, data, variant) =
+        bech32::decode(encoded).map_err(|_| HpkeError::InvalidEncoding)?;
+    if decoded_hrp != hrp || variant != Variant::Bech32 {
+        return Err(HpkeError::InvalidEncoding);
+    }
+
+    let mut bytes =
+        alloc::vec::Vec::<u8>::from_base32(&data).map_err(|_| HpkeError::InvalidEncoding)?;
+    let mut key = [0u8; 32];
+    let res = if bytes.len() == key.len() {
+        key.copy_from_slice(&bytes);
+        Ok(key)
+    } else {
+        Err(HpkeError::InvalidEncoding)
+    };
+    bytes.zeroize();
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        identity_from_str, identity_to_string, recipient_from_str, recipient_to_string,
+        X25519Stanza,
+    };
+    use crate::{
+        kem::{Kem as KemTrait, X25519HkdfSha256},
+        kex::{KeyExchange, Serializable, X25519},
+        HpkeError,
+    };
+
+    use alloc::string::ToString;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Tests that a wrapped file key unwraps with the recipient's key and not with others, and
+    /// survives encoding and decoding
+    #[test]
+    fn test_stanza_round_trip() {
+        let mut csprng = StdRng::from_entropy();
+        let (sk_recip, pk_recip) = X25519HkdfSha256::gen_keypair(&mut csprng);
+        let (sk_other, _) = X25519HkdfSha256::gen_keypair(&mut csprng);
+        let file_key = *b"sixteen byte key";
+
+        let stanza = X25519Stanza::wrap(&file_key, &pk_recip, &mut csprng).unwrap();
+        assert_eq!(stanza.unwrap(&sk_recip).unwrap(), file_key);
+        assert!(matches!(
+            stanza.unwrap(&sk_other),
+            Err(HpkeError::InvalidTag)
+        ));
+
+        let encoded = stanza.encode();
+        assert!(encoded.starts_with("-> X25519 "));
+        let decoded = X25519Stanza::decode(&encoded).unwrap();
+        assert_eq!(decoded, stanza);
+        assert_eq!(
+            X25519Stanza::from_parts("X25519", &[stanza.arg()], stanza.body()).unwrap(),
+            stanza
+        );
+
+        // Flipping a bit of the body breaks the tag
+        let mut body = stanza.body().to_vec();
+        body[0] ^= 1;
+        let tampered = X25519Stanza::from_parts("X25519", &[stanza.arg()], &body).unwrap();
+        assert!(matches!(
+            tampered.unwrap(&sk_recip),
+            Err(HpkeError::InvalidTag)
+        ));
+    }
+
+    /// Tests that decoding rejects stanzas for other recipients, and malformed ones
+    #[test]
+    fn test_stanza_decode_errors() {
+        let mut csprng = StdRng::from_entropy();
+        let (_, pk_recip) = X25519HkdfSha256::gen_keypair(&mut csprng);
+        let stanza = X25519Stanza::wrap(b"sixteen byte key", &pk_recip, &mut csprng).unwrap();
+        let encoded = stanza.encode();
+
+        assert!(matches!(
+            X25519Stanza::decode("-> scrypt c2FsdA 18\nYm9keQ\n"),
+            Err(HpkeError::SuiteMismatch)
+        ));
+
+        let malformed = [
+            // No final newline
+            encoded.trim_end().to_string(),
+            // Extra argument
+            encoded.replacen('\n', " extra\n", 1),
+            // Padded base64
+            encoded.replacen('\n', "=\n", 1),
+            // Trailing data
+            format!("{}\n", encoded),
+            // Wrong body length
+            format!("-> X25519 {}\nYm9keQ\n", stanza.arg()),
+        ];
+        for s in malformed.iter() {
+            assert!(
+                matches!(X25519Stanza::decode(s), Err(HpkeError::InvalidEncoding)),
+                "accepted {:?}",
+                s
+            );
+        }
+    }
+
+    /// Tests the bech32 encodings of keys against a known identity and recipient, and that they
+    /// round-trip
+    #[test]
+    fn test_key_encoding() {
+        // From the tests of the age crate
+        let identity = "AGE-SECRET-KEY-1GQ9778VQXMMJVE8SK7J6VT8UJ4HDQAJUVSFCWCM02D8GEWQ72PVQ2Y5J33";
+        let recipient = "age1t7rxyev2z3rw82stdlrrepyc39nvn86l5078zqkf5uasdy86jp6svpy7pa";
+
+        let sk = identity_from_str(identity).unwrap();
+        let pk = recipient_from_str(recipient).unwrap();
+        assert_eq!(X25519::sk_to_pk(&sk).to_bytes(), pk.to_bytes());
+        assert_eq!(recipient_to_string(&pk), recipient);
+        assert_eq!(
+            identity_from_str(&identity_to_string(&sk))
+                .unwrap()
+                .to_bytes(),
+            sk.to_bytes()
+        );
+
+        // The encodings can't be swapped
+        assert!(recipient_from_str(identity).is_err());
+        assert!(identity_from_str(recipient).is_err());
+    }
+}
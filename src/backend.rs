@@ -0,0 +1,246 @@
+//! This module reports which implementations of the underlying primitives are in use. AES, GHASH,
+//! and SHA-2 all have hardware-accelerated backends, some picked at compile time and some at
+//! runtime, so throughput can differ a lot from one machine to the next. [`backend_info`] says
+//! what this build ended up with on this CPU.
+//!
+//! On x86 and x86-64, AES uses AES-NI only if the crate is compiled with the `aes` and `sse2`
+//! target features. GHASH uses CLMUL, SHA-256 uses the SHA extensions, and SHA-512 uses AVX2,
+//! whenever the CPU supports them.
+//!
+//! On aarch64, the AES and GHASH implementations this crate builds on have no ARMv8 backends, so
+//! AES-GCM always runs in software. ChaCha20Poly1305 is usually the faster choice on those
+//! devices. SHA-256 uses the ARMv8 SHA-2 instructions when the CPU has them, but only with the
+//! `sha2-asm` feature. SHA-512 always runs in software.
+//!
+//! The `opt-size` feature leaves out the hardware backends of GHASH and SHA-2, so they always run
+//! in software.
+
+/// The implementation of the AES block cipher used by [`AesGcm128`](crate::aead::AesGcm128) and
+/// [`AesGcm256`](crate::aead::AesGcm256)
+#[cfg(feature = "aes-gcm")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AesBackend {
+    /// The constant-time bitsliced software implementation
+    Soft,
+    /// The AES-NI instructions
+    AesNi,
+}
+
+/// The implementation of GHASH used by [`AesGcm128`](crate::aead::AesGcm128) and
+/// [`AesGcm256`](crate::aead::AesGcm256)
+#[cfg(feature = "aes-gcm")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GhashBackend {
+    /// The constant-time software implementation
+    Soft,
+    /// The PCLMULQDQ carryless multiplication instruction
+    Clmul,
+}
+
+/// The backends that AES-GCM uses
+#[cfg(feature = "aes-gcm")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AesGcmBackend {
+    pub aes: AesBackend,
+    pub ghash: GhashBackend,
+}
+
+/// The implementation of a SHA-2 compression function
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sha2Backend {
+    /// The portable Rust implementation
+    Soft,
+    /// The portable assembly implementation, from the `sha2-asm` feature
+    Asm,
+    /// The x86 SHA extensions
+    ShaNi,
+    /// The x86 AVX2 instructions
+    Avx2,
+    /// The ARMv8 SHA-2 instructions
+    Armv8,
+}
+
+/// The backends that this build uses on this CPU
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BackendInfo {
+    #[cfg(feature = "aes-gcm")]
+    pub aes_gcm: AesGcmBackend,
+    /// Used by HKDF-SHA256, and so by every KEM
+    pub sha256: Sha2Backend,
+    /// Used by HKDF-SHA384 and HKDF-SHA512
+    pub sha512: Sha2Backend,
+}
+
+// These are the same checks that the dependencies make to pick their backends
+
+#[cfg(all(
+    feature = "aes-gcm",
+    any(target_arch = "x86", target_arch = "x86_64"),
+    not(any(feature = "aes-force-soft", feature = "opt-size"))
+))]
+cpufeatures::new!(clmul_cpuid, "pclmulqdq", "sse4.1");
+
+#[cfg(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    not(feature = "opt-size")
+))]
+cpufeatures::new!(shani_cpuid, "sha", "sse2", "ssse3", "sse4.1");
+
+#[cfg(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    not(feature = "opt-size")
+))]
+cpufeatures::new!(avx2_cpuid, "avx2");
+
+#[cfg(all(
+    target_arch = "aarch64",
+    feature = "sha2-asm",
+    not(feature = "opt-size")
+))]
+cpufeatures::new!(sha2_hwcap, "sha2");
+
+/// Returns the backends that AES-GCM uses. With the `aes-force-soft` feature, both AES and GHASH
+/// always use the software backends. With the `opt-size` feature, GHASH does.
+#[cfg(feature = "aes-gcm")]
+pub fn aes_gcm_backend() -> AesGcmBackend {
+    let aes = if cfg!(all(
+        any(target_arch = "x86", target_arch = "x86_64"),
+        target_feature = "aes",
+        target_feature = "sse2",
+        not(feature = "aes-force-soft")
+    )) {
+        AesBackend::AesNi
+    } else {
+        AesBackend::Soft
+    };
+
+    #[cfg(all(
+        any(target_arch = "x86", target_arch = "x86_64"),
+        not(any(feature = "aes-force-soft", feature = "opt-size"))
+    ))]
+    let ghash = if clmul_cpuid::get() {
+        GhashBackend::Clmul
+    } else {
+        GhashBackend::Soft
+    };
+    #[cfg(not(all(
+        any(target_arch = "x86", target_arch = "x86_64"),
+        not(any(feature = "aes-force-soft", feature = "opt-size"))
+    )))]
+    let ghash = GhashBackend::Soft;
+
+    AesGcmBackend { aes, ghash }
+}
+
+// The software fallback on x86. With the sha2-asm feature, this is assembly.
+#[cfg(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    not(feature = "opt-size")
+))]
+fn sha2_fallback() -> Sha2Backend {
+    if cfg!(feature = "sha2-asm") {
+        Sha2Backend::Asm
+    } else {
+        Sha2Backend::Soft
+    }
+}
+
+#[cfg(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    not(feature = "opt-size")
+))]
+fn sha2_backends() -> (Sha2Backend, Sha2Backend) {
+    let sha256 = if shani_cpuid::get() {
+        Sha2Backend::ShaNi
+    } else {
+        sha2_fallback()
+    };
+    let sha512 = if avx2_cpuid::get() {
+        Sha2Backend::Avx2
+    } else {
+        sha2_fallback()
+    };
+    (sha256, sha512)
+}
+
+// On aarch64, the assembly is only used for the ARMv8 instructions. There's no SHA-512 backend.
+#[cfg(all(target_arch = "aarch64", not(feature = "opt-size")))]
+fn sha2_backends() -> (Sha2Backend, Sha2Backend) {
+    #[cfg(feature = "sha2-asm")]
+    let sha256 = if sha2_hwcap::get() {
+        Sha2Backend::Armv8
+    } else {
+        Sha2Backend::Soft
+    };
+    #[cfg(not(feature = "sha2-asm"))]
+    let sha256 = Sha2Backend::Soft;
+
+    (sha256, Sha2Backend::Soft)
+}
+
+// With opt-size, SHA-2 is always software, even with sha2-asm
+#[cfg(any(
+    feature = "opt-size",
+    not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64"))
+))]
+fn sha2_backends() -> (Sha2Backend, Sha2Backend) {
+    (Sha2Backend::Soft, Sha2Backend::Soft)
+}
+
+/// Returns the backends that this build uses on this CPU. CPU feature detection is done once and
+/// cached, so this is cheap to call.
+pub fn backend_info() -> BackendInfo {
+    let (sha256, sha512) = sha2_backends();
+    BackendInfo {
+        #[cfg(feature = "aes-gcm")]
+        aes_gcm: aes_gcm_backend(),
+        sha256,
+        sha512,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{backend_info, Sha2Backend};
+
+    /// Tests that the reported backends are consistent with the build configuration
+    #[test]
+    fn test_backend_info() {
+        let info = backend_info();
+
+        #[cfg(feature = "aes-force-soft")]
+        assert_eq!(
+            info.aes_gcm,
+            super::AesGcmBackend {
+                aes: super::AesBackend::Soft,
+                ghash: super::GhashBackend::Soft,
+            }
+        );
+
+        #[cfg(feature = "opt-size")]
+        {
+            assert_eq!(info.sha256, Sha2Backend::Soft);
+            assert_eq!(info.sha512, Sha2Backend::Soft);
+            #[cfg(feature = "aes-gcm")]
+            assert_eq!(info.aes_gcm.ghash, super::GhashBackend::Soft);
+        }
+
+        // The assembly backend is opt-in
+        if cfg!(not(feature = "sha2-asm")) {
+            assert_ne!(info.sha256, Sha2Backend::Asm);
+            assert_ne!(info.sha512, Sha2Backend::Asm);
+        }
+        // Hardware backends only exist on x86 and aarch64
+        if cfg!(not(any(
+            target_arch = "x86",
+            target_arch = "x86_64",
+            target_arch = "aarch64"
+        ))) {
+            assert_eq!(info.sha256, Sha2Backend::Soft);
+        }
+        // There's no hardware SHA-512 on aarch64
+        if cfg!(target_arch = "aarch64") {
+            assert_eq!(info.sha512, Sha2Backend::Soft);
+        }
+    }
+}
@@ -0,0 +1,390 @@
+//! This module describes, at runtime, what went into this build of the crate: the crate features
+//! that were enabled, the versions of the crates that implement the primitives, the KEMs, KDFs,
+//! and AEADs that were compiled in, and the backends picked on this CPU. It's for operators who
+//! need to check exactly which crypto paths a deployed binary contains, e.g., for an audit, or to
+//! confirm that a feature like `danger` was left off.
+//!
+//! Everything in [`BuildInfo`] except [`backends`](BuildInfo::backends) is fixed at compile time.
+//! [`BuildInfo::config_digest`] hashes exactly that part, so two binaries built with the same
+//! configuration of this crate report the same digest, whichever machine they run on. Comparing
+//! digests is a quick way to check that a fleet runs one configuration.
+//!
+//! The dependency versions are the version requirements this crate declares. The exact versions
+//! are the ones in the `Cargo.lock` of the binary, which always match these requirements.
+
+use crate::backend::{backend_info, BackendInfo};
+
+use core::fmt;
+
+use digest::Digest;
+use sha2::Sha256;
+
+/// A dependency that implements some of the primitives
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Dependency {
+    /// The name of the crate
+    pub name: &'static str,
+    /// The version requirement this crate declares on it, e.g., `"0.13"`
+    pub version_req: &'static str,
+}
+
+/// A ciphersuite, as its KEM, KDF, and AEAD IDs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CipherSuite {
+    pub kem_id: u16,
+    pub kdf_id: u16,
+    pub aead_id: u16,
+}
+
+/// A description of this build. See the [module docs](self).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BuildInfo {
+    /// The version of this crate
+    pub crate_version: &'static str,
+    /// The enabled crate features, in the order they're declared, not including `default`
+    pub features: &'static [&'static str],
+    /// The enabled dependencies that implement primitives
+    pub dependencies: &'static [Dependency],
+    /// The IDs of the compiled KEMs
+    pub kem_ids: &'static [u16],
+    /// The IDs of the compiled KDFs
+    pub kdf_ids: &'static [u16],
+    /// The IDs of the compiled AEADs. This always includes the export-only AEAD, `0xFFFF`.
+    pub aead_ids: &'static [u16],
+    /// The backends picked on this CPU
+    pub backends: BackendInfo,
+}
+
+// Only features that change what the crate contains are listed. Keep this in the order of
+// Cargo.toml. test_features_exist checks the names.
+const FEATURES: &[&str] = &[
+    #[cfg(feature = "p256")]
+    "p256",
+    #[cfg(feature = "p256-base-table")]
+    "p256-base-table",
+    #[cfg(feature = "k256")]
+    "k256",
+    #[cfg(feature = "x25519")]
+    "x25519",
+    #[cfg(feature = "aes-gcm")]
+    "aes-gcm",
+    #[cfg(feature = "aes-force-soft")]
+    "aes-force-soft",
+    #[cfg(feature = "chacha20poly1305")]
+    "chacha20poly1305",
+    #[cfg(feature = "hkdf-sha256")]
+    "hkdf-sha256",
+    #[cfg(feature = "hkdf-sha384")]
+    "hkdf-sha384",
+    #[cfg(feature = "hkdf-sha512")]
+    "hkdf-sha512",
+    #[cfg(feature = "opt-size")]
+    "opt-size",
+    #[cfg(feature = "sha2-asm")]
+    "sha2-asm",
+    #[cfg(feature = "signcryption")]
+    "signcryption",
+    #[cfg(feature = "serde_impls")]
+    "serde_impls",
+    #[cfg(feature = "pkcs8")]
+    "pkcs8",
+    #[cfg(feature = "pem")]
+    "pem",
+    #[cfg(feature = "age")]
+    "age",
+    #[cfg(feature = "jwk")]
+    "jwk",
+    #[cfg(feature = "danger")]
+    "danger",
+    #[cfg(feature = "restore-buffers")]
+    "restore-buffers",
+    #[cfg(feature = "tight-buffers")]
+    "tight-buffers",
+    #[cfg(feature = "fixtures")]
+    "fixtures",
+    #[cfg(feature = "transcript")]
+    "transcript",
+    #[cfg(feature = "heapless")]
+    "heapless",
+    #[cfg(feature = "events")]
+    "events",
+    #[cfg(feature = "tracing")]
+    "tracing",
+    #[cfg(feature = "threshold")]
+    "threshold",
+    #[cfg(feature = "pkcs8_encryption")]
+    "pkcs8_encryption",
+    #[cfg(feature = "std")]
+    "std",
+];
+
+// Keep these in sync with Cargo.toml. test_dependency_versions checks them.
+const DEPENDENCIES: &[Dependency] = &[
+    #[cfg(feature = "aes-gcm")]
+    Dependency {
+        name: "aes-gcm",
+        version_req: "0.6",
+    },
+    #[cfg(feature = "aes-force-soft")]
+    Dependency {
+        name: "aes-soft",
+        version_req: "0.4",
+    },
+    #[cfg(feature = "aes-gcm")]
+    Dependency {
+        name: "polyval",
+        version_req: "0.4",
+    },
+    #[cfg(feature = "chacha20poly1305")]
+    Dependency {
+        name: "chacha20poly1305",
+        version_req: "0.5",
+    },
+    #[cfg(feature = "chacha20poly1305")]
+    Dependency {
+        name: "poly1305",
+        version_req: "0.6",
+    },
+    Dependency {
+        name: "hkdf",
+        version_req: "0.9",
+    },
+    Dependency {
+        name: "sha2",
+        version_req: "0.9",
+    },
+    #[cfg(feature = "p256")]
+    Dependency {
+        name: "p256",
+        version_req: "0.13",
+    },
+    #[cfg(feature = "k256")]
+    Dependency {
+        name: "k256",
+        version_req: "0.13",
+    },
+    #[cfg(feature = "x25519-dalek")]
+    Dependency {
+        name: "x25519-dalek",
+        version_req: "2",
+    },
+];
+
+const KEM_IDS: &[u16] = &[
+    #[cfg(feature = "p256")]
+    <crate::kem::DhP256HkdfSha256 as crate::kem::Kem>::KEM_ID,
+    #[cfg(feature = "x25519-dalek")]
+    <crate::kem::X25519HkdfSha256 as crate::kem::Kem>::KEM_ID,
+    #[cfg(feature = "k256")]
+    <crate::kem::DhK256HkdfSha256 as crate::kem::Kem>::KEM_ID,
+];
+
+const KDF_IDS: &[u16] = &[
+    #[cfg(feature = "hkdf-sha256")]
+    <crate::kdf::HkdfSha256 as crate::kdf::Kdf>::KDF_ID,
+    #[cfg(feature = "hkdf-sha384")]
+    <crate::kdf::HkdfSha384 as crate::kdf::Kdf>::KDF_ID,
+    #[cfg(feature = "hkdf-sha512")]
+    <crate::kdf::HkdfSha512 as crate::kdf::Kdf>::KDF_ID,
+];
+
+const AEAD_IDS: &[u16] = &[
+    #[cfg(feature = "aes-gcm")]
+    <crate::aead::AesGcm128 as crate::aead::Aead>::AEAD_ID,
+    #[cfg(feature = "aes-gcm")]
+    <crate::aead::AesGcm256 as crate::aead::Aead>::AEAD_ID,
+    #[cfg(feature = "chacha20poly1305")]
+    <crate::aead::ChaCha20Poly1305 as crate::aead::Aead>::AEAD_ID,
+    <crate::aead::ExportOnly as crate::aead::Aead>::AEAD_ID,
+];
+
+impl BuildInfo {
+    /// Returns every compiled ciphersuite, i.e., every combination of a compiled KEM, KDF, and
+    /// AEAD, ordered by KEM, then KDF, then AEAD
+    pub fn suites(&self) -> impl Iterator<Item = CipherSuite> + 'static {
+        let (kdf_ids, aead_ids) = (self.kdf_ids, self.aead_ids);
+        self.kem_ids.iter().flat_map(move |&kem_id| {
+            kdf_ids.iter().flat_map(move |&kdf_id| {
+                aead_ids.iter().map(move |&aead_id| CipherSuite {
+                    kem_id,
+                    kdf_id,
+                    aead_id,
+                })
+            })
+        })
+    }
+
+    /// Returns a SHA-256 hash of everything in this description that's fixed at compile time,
+    /// i.e., everything but the backends
+    pub fn config_digest(&self) -> [u8; 32] {
+        // Every string is length-prefixed and every list is count-prefixed, so distinct
+        // descriptions can't encode to the same bytes
+        fn put_str(hasher: &mut Sha256, s: &str) {
+            hasher.update((s.len() as u32).to_be_bytes());
+            hasher.update(s.as_bytes());
+        }
+
+        let mut hasher = Sha256::new();
+
+        hasher.update(b"hpke build info v1");
+        put_str(&mut hasher, self.crate_version);
+        hasher.update((self.features.len() as u32).to_be_bytes());
+        for feature in self.features {
+            put_str(&mut hasher, feature);
+        }
+        hasher.update((self.dependencies.len() as u32).to_be_bytes());
+        for dep in self.dependencies {
+            put_str(&mut hasher, dep.name);
+            put_str(&mut hasher, dep.version_req);
+        }
+        for ids in [self.kem_ids, self.kdf_ids, self.aead_ids].iter() {
+            hasher.update((ids.len() as u32).to_be_bytes());
+            for id in ids.iter() {
+                hasher.update(id.to_be_bytes());
+            }
+        }
+
+        hasher.finalize().into()
+    }
+}
+
+// Writes ids as comma-separated hex
+fn write_ids(f: &mut fmt::Formatter, ids: &[u16]) -> fmt::Result {
+    for (i, id) in ids.iter().enumerate() {
+        if i > 0 {
+            f.write_str(",")?;
+        }
+        write!(f, "0x{:04x}", id)?;
+    }
+    Ok(())
+}
+
+/// Writes one `key: value` line per field, for logging at startup. The lines for the features,
+/// dependencies, algorithm IDs, and digest don't depend on the machine.
+impl fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "hpke: {}", self.crate_version)?;
+        writeln!(f, "features: {}", self.features.join(","))?;
+        f.write_str("dependencies: ")?;
+        for (i, dep) in self.dependencies.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{} {}", dep.name, dep.version_req)?;
+        }
+        f.write_str("\nkems: ")?;
+        write_ids(f, self.kem_ids)?;
+        f.write_str("\nkdfs: ")?;
+        write_ids(f, self.kdf_ids)?;
+        f.write_str("\naeads: ")?;
+        write_ids(f, self.aead_ids)?;
+        f.write_str("\nconfig digest: ")?;
+        for b in self.config_digest().iter() {
+            write!(f, "{:02x}", b)?;
+        }
+        write!(f, "\nbackends: {:?}", self.backends)
+    }
+}
+
+/// Returns a description of this build: its features, dependency versions, compiled algorithms,
+/// and the backends picked on this CPU
+pub fn build_info() -> BuildInfo {
+    BuildInfo {
+        crate_version: env!("CARGO_PKG_VERSION"),
+        features: FEATURES,
+        dependencies: DEPENDENCIES,
+        kem_ids: KEM_IDS,
+        kdf_ids: KDF_IDS,
+        aead_ids: AEAD_IDS,
+        backends: backend_info(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{build_info, CipherSuite};
+
+    use alloc::{string::ToString, vec::Vec};
+
+    const MANIFEST: &str = include_str!("../Cargo.toml");
+
+    // Returns the lines of the given table of the manifest
+    fn table(name: &str) -> impl Iterator<Item = &'static str> + '_ {
+        MANIFEST
+            .lines()
+            .skip_while(move |line| *line != name)
+            .skip(1)
+            .take_while(|line| !line.starts_with('['))
+    }
+
+    /// Tests that every reported feature is a feature of the crate
+    #[test]
+    fn test_features_exist() {
+        let declared: Vec<&str> = table("[features]")
+            .filter_map(|line| line.split(" = ").next())
+            .filter(|key| !key.starts_with('#') && !key.starts_with(' ') && !key.is_empty())
+            .collect();
+        for feature in build_info().features {
+            assert!(declared.contains(feature), "unknown feature {}", feature);
+        }
+        assert_eq!(
+            build_info().features.contains(&"danger"),
+            cfg!(feature = "danger")
+        );
+    }
+
+    /// Tests that the reported version requirements are the ones in the manifest
+    #[test]
+    fn test_dependency_versions() {
+        for dep in build_info().dependencies {
+            // Either `name = "req"`, `name = { version = "req", ... }`, or a table of its own
+            let inline = table("[dependencies]").find_map(|line| {
+                line.strip_prefix(dep.name)
+                    .and_then(|rest| rest.strip_prefix(" = "))
+            });
+            let spec = inline.unwrap_or_else(|| {
+                table(&alloc::format!("[dependencies.{}]", dep.name))
+                    .find_map(|line| line.strip_prefix("version = "))
+                    .unwrap()
+            });
+            let req = alloc::format!("\"{}\"", dep.version_req);
+            assert!(
+                spec.contains(&req),
+                "{} isn't {} in Cargo.toml",
+                dep.name,
+                req
+            );
+        }
+    }
+
+    /// Tests that the suites are every combination of the algorithms, that the digest only
+    /// depends on what's fixed at compile time, and that the report includes it
+    #[test]
+    fn test_suites_and_digest() {
+        let info = build_info();
+        let suites: Vec<CipherSuite> = info.suites().collect();
+        assert_eq!(
+            suites.len(),
+            info.kem_ids.len() * info.kdf_ids.len() * info.aead_ids.len()
+        );
+        #[cfg(all(feature = "x25519-dalek", feature = "chacha20poly1305"))]
+        assert!(suites.contains(&CipherSuite {
+            kem_id: 0x0020,
+            kdf_id: 0x0001,
+            aead_id: 0x0003,
+        }));
+
+        // A different set of algorithms is a different configuration
+        let mut other = info;
+        other.aead_ids = &info.aead_ids[1..];
+        assert_ne!(other.config_digest(), info.config_digest());
+        // The backends aren't part of it
+        let digest = info.config_digest();
+        assert_eq!(build_info().config_digest(), digest);
+
+        let report = info.to_string();
+        assert!(report.starts_with(&alloc::format!("hpke: {}\n", env!("CARGO_PKG_VERSION"))));
+        assert!(report.contains(&alloc::format!("{:02x}{:02x}", digest[0], digest[1])));
+        assert!(report.contains("0xffff"));
+    }
+}
@@ -0,0 +1,836 @@
+//! This module defines [`HpkeChannel`], a two-way channel built on a single HPKE setup. HPKE only
+//! encrypts from sender to recipient. For the recipient to answer, a common pattern is to export a
+//! secret from the context and key a second AEAD with it, as OHTTP does. `HpkeChannel` does this
+//! for you, so both sides get a `send` and a `recv` and never touch exporter labels.
+//!
+//! The initiator calls [`HpkeChannel::initiate`] and sends the encapsulated key to the responder,
+//! who calls [`HpkeChannel::accept`]. The initiator's messages go over the HPKE context itself.
+//! The responder's messages go over a context keyed with secrets exported from it.
+//!
+//! Each side must receive messages in the order they were sent, since every message uses up one
+//! sequence number in its direction.
+//!
+//! Long-lived channels can roll their keys with [`HpkeChannel::update_send_key`]. This sends a
+//! key update message and switches the sending direction to keys exported from its current
+//! context. The other side switches its receiving direction when it receives the message. Each
+//! direction counts its updates in an epoch, starting at 0.
+//!
+//! Channels opened with [`HpkeChannel::initiate_in`] and [`HpkeChannel::accept_in`] derive all
+//! their keys in the given [`ExportNamespace`], so that two protocols that open channels the same
+//! way still end up with unrelated keys. Both ends must use the same namespace.
+//!
+//! A side that doesn't want its message lengths to show, e.g., an OHTTP gateway sending
+//! responses, can pad everything it sends up to a fixed set of sizes with
+//! [`HpkeChannel::set_padding`]. Padded messages are always understood by the other side, so only
+//! the sending side needs to be configured.
+//!
+//! Wire format
+//! ===========
+//! Every message is `type || ciphertext || tag`, where `type` is one byte and is the AAD of the
+//! ciphertext. Type 0 is application data. Type 1 is a key update, whose plaintext is
+//! `epoch || confirm`. Here `epoch` is the new epoch as a big-endian u64, and `confirm` is 32
+//! bytes exported from the new context under the exporter context
+//! `"rust-hpke channel key update confirm"`. This lets the receiver check that it derived the
+//! same keys. In a namespaced channel, every exporter context, including this one, is taken in
+//! the namespace. Type 2 is padded application data, whose plaintext is `data || 0x80 || zeros`,
+//! with as many zeros as it takes to reach the size picked by the sender's [`PaddingProfile`].
+
+use crate::{
+    aead::{Aead, AeadCtx, AeadCtxR, AeadCtxS, AeadKey, AeadNonce, AeadTag, ExportNamespace},
+    kdf::Kdf as KdfTrait,
+    kem::{EncappedKey, Kem as KemTrait},
+    kex::{Deserializable, KeyExchange, Serializable},
+    op_mode::{OpModeR, OpModeS},
+    setup::{setup_receiver, setup_sender, ExporterSecret},
+    HpkeError,
+};
+
+use alloc::vec::Vec;
+use rand::{CryptoRng, RngCore};
+use subtle::ConstantTimeEq;
+
+// The exporter contexts used to derive the key, nonce, and exporter secret of a context from
+// another context. The first are for the responder-to-initiator context, the second for the next
+// epoch of either direction.
+const REVERSE_LABELS: [&[u8]; 3] = [
+    b"rust-hpke channel reverse key",
+    b"rust-hpke channel reverse nonce",
+    b"rust-hpke channel reverse exp",
+];
+const KEY_UPDATE_LABELS: [&[u8]; 3] = [
+    b"rust-hpke channel key update key",
+    b"rust-hpke channel key update nonce",
+    b"rust-hpke channel key update exp",
+];
+
+// The exporter context and size of the confirmation value in a key update message
+const KEY_UPDATE_CONFIRM_LABEL: &[u8] = b"rust-hpke channel key update confirm";
+const KEY_UPDATE_CONFIRM_SIZE: usize = 32;
+
+// The message types
+const MSG_DATA: u8 = 0;
+const MSG_KEY_UPDATE: u8 = 1;
+const MSG_PADDED_DATA: u8 = 2;
+
+// The byte that ends the data in a padded message
+const PADDING_MARKER: u8 = 0x80;
+
+/// The sizes that a channel pads the plaintexts of its messages to. A plaintext, plus the 1-byte
+/// padding marker, is padded to the smallest bucket it fits in. One that fits in no bucket is
+/// padded to a multiple of the largest bucket, so lengths past the largest bucket are only
+/// hidden up to that granularity.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PaddingProfile {
+    buckets: Vec<usize>,
+}
+
+impl PaddingProfile {
+    /// Makes a profile with the given bucket sizes, in any order
+    ///
+    /// Panics
+    /// ======
+    /// Panics if `buckets` is empty or contains 0.
+    pub fn new(buckets: &[usize]) -> Self {
+        assert!(!buckets.is_empty(), "a padding profile needs a bucket");
+        assert!(!buckets.contains(&0), "padding buckets can't be empty");
+        let mut buckets = buckets.to_vec();
+        buckets.sort_unstable();
+        buckets.dedup();
+        PaddingProfile { buckets }
+    }
+
+    /// Returns the bucket sizes, from smallest to largest
+    pub fn buckets(&self) -> &[usize] {
+        &self.buckets
+    }
+
+    /// Returns the size that a plaintext of length `len` is padded to, including the padding
+    /// marker
+    pub fn padded_len(&self, len: usize) -> usize {
+        let min_len = len + 1;
+        match self.buckets.iter().find(|&&bucket| bucket >= min_len) {
+            Some(&bucket) => bucket,
+            None => {
+                let largest = self.buckets[self.buckets.len() - 1];
+                match min_len % largest {
+                    0 => min_len,
+                    rem => min_len + largest - rem,
+                }
+            }
+        }
+    }
+}
+
+/// One end of a two-way channel. See the [module docs](self).
+pub struct HpkeChannel<A: Aead, Kdf: KdfTrait, Kem: KemTrait> {
+    sender: AeadCtxS<A, Kdf, Kem>,
+    receiver: AeadCtxR<A, Kdf, Kem>,
+    send_epoch: u64,
+    recv_epoch: u64,
+    namespace: Option<ExportNamespace>,
+    padding: Option<PaddingProfile>,
+}
+
+impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> HpkeChannel<A, Kdf, Kem> {
+    /// Opens a channel to the given recipient public key. This does a [`setup_sender`].
+    ///
+    /// Return Value
+    /// ============
+    /// On success, returns the encapsulated key, which must be sent to the responder, and this end
+    /// of the channel. Fails the same way as [`setup_sender`].
+    pub fn initiate<R: CryptoRng + RngCore>(
+        mode: &OpModeS<Kem::Kex>,
+        pk_recip: &<Kem::Kex as KeyExchange>::PublicKey,
+        info: &[u8],
+        csprng: &mut R,
+    ) -> Result<(EncappedKey<Kem>, Self), HpkeError> {
+        Self::initiate_with(None, mode, pk_recip, info, csprng)
+    }
+
+    /// Like [`initiate`](Self::initiate), but derives the channel's keys in the given namespace.
+    /// The responder must use [`accept_in`](Self::accept_in) with the same namespace.
+    pub fn initiate_in<R: CryptoRng + RngCore>(
+        namespace: &ExportNamespace,
+        mode: &OpModeS<Kem::Kex>,
+        pk_recip: &<Kem::Kex as KeyExchange>::PublicKey,
+        info: &[u8],
+        csprng: &mut R,
+    ) -> Result<(EncappedKey<Kem>, Self), HpkeError> {
+        Self::initiate_with(Some(namespace.clone()), mode, pk_recip, info, csprng)
+    }
+
+    fn initiate_with<R: CryptoRng + RngCore>(
+        namespace: Option<ExportNamespace>,
+        mode: &OpModeS<Kem::Kex>,
+        pk_recip: &<Kem::Kex as KeyExchange>::PublicKey,
+        info: &[u8],
+        csprng: &mut R,
+    ) -> Result<(EncappedKey<Kem>, Self), HpkeError> {
+        let (encapped_key, sender) = setup_sender::<A, Kdf, Kem, R>(mode, pk_recip, info, csprng)?;
+        let reverse = derive_exported_ctx(namespace.as_ref(), &REVERSE_LABELS, |label, out| {
+            sender.export(label, out)
+        })?;
+        let channel = HpkeChannel {
+            sender,
+            receiver: reverse.into(),
+            send_epoch: 0,
+            recv_epoch: 0,
+            namespace,
+            padding: None,
+        };
+        Ok((encapped_key, channel))
+    }
+
+    /// Accepts a channel opened by [`HpkeChannel::initiate`]. This does a [`setup_receiver`].
+    ///
+    /// Return Value
+    /// ============
+    /// On success, returns this end of the channel. Fails the same way as [`setup_receiver`].
+    pub fn accept(
+        mode: &OpModeR<Kem::Kex>,
+        sk_recip: &<Kem::Kex as KeyExchange>::PrivateKey,
+        encapped_key: &EncappedKey<Kem>,
+        info: &[u8],
+    ) -> Result<Self, HpkeError> {
+        Self::accept_with(None, mode, sk_recip, encapped_key, info)
+    }
+
+    /// Like [`accept`](Self::accept), but derives the channel's keys in the given namespace. This
+    /// accepts a channel opened by [`initiate_in`](Self::initiate_in) with the same namespace.
+    pub fn accept_in(
+        namespace: &ExportNamespace,
+        mode: &OpModeR<Kem::Kex>,
+        sk_recip: &<Kem::Kex as KeyExchange>::PrivateKey,
+        encapped_key: &EncappedKey<Kem>,
+        info: &[u8],
+    ) -> Result<Self, HpkeError> {
+        Self::accept_with(Some(namespace.clone()), mode, sk_recip, encapped_key, info)
+    }
+
+    fn accept_with(
+        namespace: Option<ExportNamespace>,
+        mode: &OpModeR<Kem::Kex>,
+        sk_recip: &<Kem::Kex as KeyExchange>::PrivateKey,
+        encapped_key: &EncappedKey<Kem>,
+        info: &[u8],
+    ) -> Result<Self, HpkeError> {
+        let receiver = setup_receiver::<A, Kdf, Kem>(mode, sk_recip, encapped_key, info)?;
+        let reverse = derive_exported_ctx(namespace.as_ref(), &REVERSE_LABELS, |label, out| {
+            receiver.export(label, out)
+        })?;
+        Ok(HpkeChannel {
+            sender: reverse.into(),
+            receiver,
+            send_epoch: 0,
+            recv_epoch: 0,
+            namespace,
+            padding: None,
+        })
+    }
+
+    /// Returns how many times this side has updated its sending keys
+    pub fn send_epoch(&self) -> u64 {
+        self.send_epoch
+    }
+
+    /// Returns how many key updates this side has received from the other side
+    pub fn recv_epoch(&self) -> u64 {
+        self.recv_epoch
+    }
+
+    /// Sets the profile that [`send`](Self::send) pads plaintexts with, or turns padding off if
+    /// `padding` is `None`. Key update messages aren't padded, since they're all the same size.
+    pub fn set_padding(&mut self, padding: Option<PaddingProfile>) {
+        self.padding = padding;
+    }
+
+    /// Returns the profile that [`send`](Self::send) pads plaintexts with, if any
+    pub fn padding(&self) -> Option<&PaddingProfile> {
+        self.padding.as_ref()
+    }
+
+    /// Encrypts a message to the other end of the channel. The output is
+    /// `type || ciphertext || tag`, as described in the [module docs](self). If this side has a
+    /// padding profile, the plaintext is padded first.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns the framed message on success. Fails the same way as [`AeadCtxS::seal`].
+    pub fn send(&mut self, plaintext: &[u8]) -> Result<Vec<u8>, HpkeError> {
+        match &self.padding {
+            Some(padding) => {
+                let padded_len = padding.padded_len(plaintext.len());
+                let mut body = Vec::with_capacity(padded_len);
+                body.extend_from_slice(plaintext);
+                body.push(PADDING_MARKER);
+                body.resize(padded_len, 0);
+                self.send_frame(MSG_PADDED_DATA, &body)
+            }
+            None => self.send_frame(MSG_DATA, plaintext),
+        }
+    }
+
+    /// Moves the sending direction to the next epoch. This returns a key update message, which
+    /// must be sent to the other side before anything that [`send`](Self::send) returns after
+    /// this.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns the key update message on success. If the epoch counter would overflow, returns
+    /// `Err(HpkeError::SeqOverflow)`. Otherwise, fails the same way as [`AeadCtxS::seal`]. On
+    /// failure, the keys are unchanged.
+    pub fn update_send_key(&mut self) -> Result<Vec<u8>, HpkeError> {
+        let epoch = self
+            .send_epoch
+            .checked_add(1)
+            .ok_or(HpkeError::SeqOverflow)?;
+        let sender = &self.sender;
+        let namespace = self.namespace.as_ref();
+        let next: AeadCtxS<A, Kdf, Kem> =
+            derive_exported_ctx(namespace, &KEY_UPDATE_LABELS, |label, out| {
+                sender.export(label, out)
+            })?
+            .into();
+
+        // body = epoch || confirm
+        let mut body = [0u8; 8 + KEY_UPDATE_CONFIRM_SIZE];
+        body[..8].copy_from_slice(&epoch.to_be_bytes());
+        next.export(
+            &exporter_ctx(namespace, KEY_UPDATE_CONFIRM_LABEL),
+            &mut body[8..],
+        )?;
+
+        // The update goes out under the old keys. Everything after it uses the new ones.
+        let frame = self.send_frame(MSG_KEY_UPDATE, &body)?;
+        self.sender = next;
+        self.send_epoch = epoch;
+        Ok(frame)
+    }
+
+    /// Decrypts a message made by [`send`](Self::send) or
+    /// [`update_send_key`](Self::update_send_key) on the other end of the channel. A key update
+    /// message moves the receiving direction to the next epoch, and carries no data.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns the plaintext on success, with any padding removed. This is empty for a key update
+    /// message. If `frame` is too short, has an unknown type, or is padded wrong, returns
+    /// `Err(HpkeError::InvalidEncoding)`. If a key update
+    /// message skips an epoch, returns `Err(HpkeError::InvalidEncoding)`, and if its confirmation
+    /// value is wrong, returns `Err(HpkeError::InvalidTag)`. A rejected key update leaves the
+    /// receiving direction as it was, sequence number included. Otherwise, fails the same way as
+    /// [`AeadCtxR::open`].
+    pub fn recv(&mut self, frame: &[u8]) -> Result<Vec<u8>, HpkeError> {
+        let tag_size = AeadTag::<A>::size();
+        if frame.len() < 1 + tag_size {
+            return Err(HpkeError::InvalidEncoding);
+        }
+        let (msg_type, rest) = frame.split_at(1);
+        if ![MSG_DATA, MSG_KEY_UPDATE, MSG_PADDED_DATA].contains(&msg_type[0]) {
+            return Err(HpkeError::InvalidEncoding);
+        }
+
+        let (ciphertext, tag_bytes) = rest.split_at(rest.len() - tag_size);
+        let tag = AeadTag::<A>::from_bytes(tag_bytes)?;
+        let mut plaintext = ciphertext.to_vec();
+
+        if msg_type[0] == MSG_KEY_UPDATE {
+            // The update is checked before its sequence number is used up, so a bad one can't
+            // knock this side out of step with the other
+            let namespace = self.namespace.as_ref();
+            let recv_epoch = self.recv_epoch;
+            let (next, epoch) =
+                self.receiver
+                    .open_checked(&mut plaintext, msg_type, &tag, |receiver, body| {
+                        check_key_update(namespace, receiver, recv_epoch, body)
+                    })?;
+            self.receiver = next;
+            self.recv_epoch = epoch;
+            return Ok(Vec::new());
+        }
+
+        self.receiver.open(&mut plaintext, msg_type, &tag)?;
+        if msg_type[0] == MSG_PADDED_DATA {
+            // The data ends at the last nonzero byte, which has to be the marker
+            match plaintext.iter().rposition(|&b| b != 0) {
+                Some(end) if plaintext[end] == PADDING_MARKER => plaintext.truncate(end),
+                _ => return Err(HpkeError::InvalidEncoding),
+            }
+        }
+        Ok(plaintext)
+    }
+
+    // Frames and seals a message of the given type
+    fn send_frame(&mut self, msg_type: u8, body: &[u8]) -> Result<Vec<u8>, HpkeError> {
+        let mut frame = Vec::with_capacity(1 + body.len() + AeadTag::<A>::size());
+        frame.push(msg_type);
+        frame.extend_from_slice(body);
+        let tag = self.sender.seal(&mut frame[1..], &[msg_type])?;
+        frame.extend_from_slice(&tag.to_bytes());
+        Ok(frame)
+    }
+}
+
+// Checks the body of a key update message received in the given epoch. On success, returns the
+// receiving context and epoch to move to.
+fn check_key_update<A, Kdf, Kem>(
+    namespace: Option<&ExportNamespace>,
+    receiver: &AeadCtxR<A, Kdf, Kem>,
+    recv_epoch: u64,
+    body: &[u8],
+) -> Result<(AeadCtxR<A, Kdf, Kem>, u64), HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    if body.len() != 8 + KEY_UPDATE_CONFIRM_SIZE {
+        return Err(HpkeError::InvalidEncoding);
+    }
+    let (epoch, confirm) = body.split_at(8);
+    let mut epoch_bytes = [0u8; 8];
+    epoch_bytes.copy_from_slice(epoch);
+    let epoch = u64::from_be_bytes(epoch_bytes);
+    if Some(epoch) != recv_epoch.checked_add(1) {
+        return Err(HpkeError::InvalidEncoding);
+    }
+
+    let next: AeadCtxR<A, Kdf, Kem> =
+        derive_exported_ctx(namespace, &KEY_UPDATE_LABELS, |label, out| {
+            receiver.export(label, out)
+        })?
+        .into();
+    let mut expected_confirm = [0u8; KEY_UPDATE_CONFIRM_SIZE];
+    next.export(
+        &exporter_ctx(namespace, KEY_UPDATE_CONFIRM_LABEL),
+        &mut expected_confirm,
+    )?;
+    if !bool::from(expected_confirm.ct_eq(confirm)) {
+        return Err(HpkeError::InvalidTag);
+    }
+
+    Ok((next, epoch))
+}
+
+// Returns the exporter context for the given label, in the namespace if there is one
+fn exporter_ctx(namespace: Option<&ExportNamespace>, label: &[u8]) -> Vec<u8> {
+    match namespace {
+        Some(namespace) => namespace.exporter_ctx(label),
+        None => label.to_vec(),
+    }
+}
+
+// Derives a context from the key, nonce, and exporter secret exported from another context under
+// the given labels, in the namespace if there is one. Both ends of a direction pass the export
+// function of their half of it, which give the same output.
+fn derive_exported_ctx<A, Kdf, Kem, F>(
+    namespace: Option<&ExportNamespace>,
+    labels: &[&[u8]; 3],
+    export: F,
+) -> Result<AeadCtx<A, Kdf, Kem>, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    F: Fn(&[u8], &mut [u8]) -> Result<(), HpkeError>,
+{
+    let mut key = AeadKey::<A>::default();
+    let mut nonce = AeadNonce::<A>::default();
+    let mut exporter_secret = ExporterSecret::<Kdf>::default();
+    export(&exporter_ctx(namespace, labels[0]), key.as_mut_slice())?;
+    export(&exporter_ctx(namespace, labels[1]), nonce.as_mut_slice())?;
+    export(
+        &exporter_ctx(namespace, labels[2]),
+        exporter_secret.as_mut_slice(),
+    )?;
+
+    Ok(AeadCtx::new(key, nonce, exporter_secret))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        HpkeChannel, PaddingProfile, KEY_UPDATE_CONFIRM_SIZE, MSG_KEY_UPDATE, MSG_PADDED_DATA,
+    };
+    use crate::{
+        aead::{ChaCha20Poly1305, ExportNamespace},
+        kdf::HkdfSha256,
+        kem::Kem as KemTrait,
+        op_mode::{OpModeR, OpModeS},
+        HpkeError, Serializable,
+    };
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Tests that messages go through in both directions, and that tampered, truncated, and
+    /// reflected messages are rejected
+    macro_rules! test_channel {
+        ($test_name:ident, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type A = ChaCha20Poly1305;
+                type Kdf = HkdfSha256;
+                type Kem = $kem;
+                let mut csprng = StdRng::from_entropy();
+
+                let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+                let info = b"channel test";
+                let (encapped_key, mut initiator) = HpkeChannel::<A, Kdf, Kem>::initiate(
+                    &OpModeS::Base,
+                    &pk_recip,
+                    info,
+                    &mut csprng,
+                )
+                .unwrap();
+                let mut responder = HpkeChannel::<A, Kdf, Kem>::accept(
+                    &OpModeR::Base,
+                    &sk_recip,
+                    &encapped_key,
+                    info,
+                )
+                .unwrap();
+
+                // Request and response, twice
+                for (req, resp) in
+                    [(&b"request 1"[..], &b"response 1"[..]), (b"request 2", b"")].iter()
+                {
+                    let frame = initiator.send(req).unwrap();
+                    assert_eq!(responder.recv(&frame).unwrap(), *req);
+                    let frame = responder.send(resp).unwrap();
+                    assert_eq!(initiator.recv(&frame).unwrap(), *resp);
+                }
+
+                // A message can't be reflected back to its sender, since the directions use
+                // different keys
+                let frame = initiator.send(b"to myself").unwrap();
+                assert!(matches!(initiator.recv(&frame), Err(HpkeError::InvalidTag)));
+
+                // Tampered and truncated frames are rejected
+                let mut frame = responder.send(b"tamper with me").unwrap();
+                frame[1] ^= 1;
+                assert!(matches!(initiator.recv(&frame), Err(HpkeError::InvalidTag)));
+                assert!(matches!(
+                    initiator.recv(&frame[..15]),
+                    Err(HpkeError::InvalidEncoding)
+                ));
+            }
+        };
+    }
+
+    /// Tests that both sides can update their keys mid-session, and that bad key update messages
+    /// are rejected
+    macro_rules! test_key_update {
+        ($test_name:ident, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type A = ChaCha20Poly1305;
+                type Kdf = HkdfSha256;
+                type Kem = $kem;
+                let mut csprng = StdRng::from_entropy();
+
+                let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+                let info = b"key update test";
+                let (encapped_key, mut initiator) = HpkeChannel::<A, Kdf, Kem>::initiate(
+                    &OpModeS::Base,
+                    &pk_recip,
+                    info,
+                    &mut csprng,
+                )
+                .unwrap();
+                let mut responder = HpkeChannel::<A, Kdf, Kem>::accept(
+                    &OpModeR::Base,
+                    &sk_recip,
+                    &encapped_key,
+                    info,
+                )
+                .unwrap();
+
+                // The initiator updates twice, with data in between
+                for epoch in 1..=2 {
+                    let old_frame = initiator.send(b"old keys").unwrap();
+                    let update = initiator.update_send_key().unwrap();
+                    let new_frame = initiator.send(b"new keys").unwrap();
+                    assert_eq!(initiator.send_epoch(), epoch);
+
+                    assert_eq!(responder.recv(&old_frame).unwrap(), b"old keys");
+                    assert!(responder.recv(&update).unwrap().is_empty());
+                    assert_eq!(responder.recv_epoch(), epoch);
+                    assert_eq!(responder.recv(&new_frame).unwrap(), b"new keys");
+                }
+
+                // The responder's direction is independent
+                assert_eq!(responder.send_epoch(), 0);
+                let update = responder.update_send_key().unwrap();
+                let frame = responder.send(b"response").unwrap();
+                assert!(initiator.recv(&update).unwrap().is_empty());
+                assert_eq!(initiator.recv(&frame).unwrap(), b"response");
+                assert_eq!(initiator.recv_epoch(), 1);
+
+                // An update that skips an epoch is rejected
+                let mut body = [0u8; 8 + KEY_UPDATE_CONFIRM_SIZE];
+                body[..8].copy_from_slice(&3u64.to_be_bytes());
+                let frame = responder.send_frame(MSG_KEY_UPDATE, &body).unwrap();
+                assert!(matches!(
+                    initiator.recv(&frame),
+                    Err(HpkeError::InvalidEncoding)
+                ));
+
+                // So is one with the wrong confirmation value
+                body[..8].copy_from_slice(&2u64.to_be_bytes());
+                let frame = responder.send_frame(MSG_KEY_UPDATE, &body).unwrap();
+                assert!(matches!(initiator.recv(&frame), Err(HpkeError::InvalidTag)));
+                assert_eq!(initiator.recv_epoch(), 1);
+            }
+        };
+    }
+
+    /// Tests that a rejected key update doesn't use up a sequence number, so the next good message
+    /// still goes through
+    macro_rules! test_bad_key_update_recovery {
+        ($test_name:ident, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type A = ChaCha20Poly1305;
+                type Kdf = HkdfSha256;
+                type Kem = $kem;
+                let mut csprng = StdRng::from_entropy();
+
+                let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+                let info = b"bad key update test";
+                let (encapped_key, mut initiator) = HpkeChannel::<A, Kdf, Kem>::initiate(
+                    &OpModeS::Base,
+                    &pk_recip,
+                    info,
+                    &mut csprng,
+                )
+                .unwrap();
+                let mut responder = HpkeChannel::<A, Kdf, Kem>::accept(
+                    &OpModeR::Base,
+                    &sk_recip,
+                    &encapped_key,
+                    info,
+                )
+                .unwrap();
+
+                // Seals a key update with the given epoch and a wrong confirmation value, under
+                // the initiator's next sequence number, without the initiator moving past it
+                let bad_update = |initiator: &HpkeChannel<A, Kdf, Kem>, epoch: u64| {
+                    let mut frame = [MSG_KEY_UPDATE; 1 + 8 + KEY_UPDATE_CONFIRM_SIZE].to_vec();
+                    frame[1..9].copy_from_slice(&epoch.to_be_bytes());
+                    let tag = initiator
+                        .sender
+                        .clone()
+                        .seal(&mut frame[1..], &[MSG_KEY_UPDATE])
+                        .unwrap();
+                    frame.extend_from_slice(&tag.to_bytes());
+                    frame
+                };
+
+                // Neither a bad confirmation value nor a skipped epoch costs the responder the
+                // sequence number they came under
+                let frame = bad_update(&initiator, 1);
+                assert!(matches!(responder.recv(&frame), Err(HpkeError::InvalidTag)));
+                let frame = bad_update(&initiator, 2);
+                assert!(matches!(
+                    responder.recv(&frame),
+                    Err(HpkeError::InvalidEncoding)
+                ));
+                assert_eq!(responder.recv_epoch(), 0);
+
+                // The next good data message goes through, and so does a good key update
+                let frame = initiator.send(b"still in step").unwrap();
+                assert_eq!(responder.recv(&frame).unwrap(), b"still in step");
+                let update = initiator.update_send_key().unwrap();
+                let frame = initiator.send(b"new keys").unwrap();
+                assert!(responder.recv(&update).unwrap().is_empty());
+                assert_eq!(responder.recv(&frame).unwrap(), b"new keys");
+                assert_eq!(responder.recv_epoch(), 1);
+            }
+        };
+    }
+
+    /// Tests that namespaced channels work when both ends agree on the namespace, and don't when
+    /// they disagree or only one end uses one
+    macro_rules! test_channel_namespace {
+        ($test_name:ident, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type A = ChaCha20Poly1305;
+                type Kdf = HkdfSha256;
+                type Kem = $kem;
+                let mut csprng = StdRng::from_entropy();
+
+                let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+                let info = b"namespace test";
+                let ns = ExportNamespace::new(b"protocol one");
+                let other_ns = ExportNamespace::new(b"protocol two");
+
+                let (encapped_key, mut initiator) = HpkeChannel::<A, Kdf, Kem>::initiate_in(
+                    &ns,
+                    &OpModeS::Base,
+                    &pk_recip,
+                    info,
+                    &mut csprng,
+                )
+                .unwrap();
+
+                // The same namespace works in both directions and across a key update
+                let mut responder = HpkeChannel::<A, Kdf, Kem>::accept_in(
+                    &ns,
+                    &OpModeR::Base,
+                    &sk_recip,
+                    &encapped_key,
+                    info,
+                )
+                .unwrap();
+                let update = responder.update_send_key().unwrap();
+                let frame = responder.send(b"response").unwrap();
+                assert!(initiator.recv(&update).unwrap().is_empty());
+                assert_eq!(initiator.recv(&frame).unwrap(), b"response");
+
+                // Another namespace, or none, gives different reverse keys
+                let mut responder = HpkeChannel::<A, Kdf, Kem>::accept_in(
+                    &other_ns,
+                    &OpModeR::Base,
+                    &sk_recip,
+                    &encapped_key,
+                    info,
+                )
+                .unwrap();
+                let frame = responder.send(b"response").unwrap();
+                assert!(matches!(initiator.recv(&frame), Err(HpkeError::InvalidTag)));
+                let mut responder = HpkeChannel::<A, Kdf, Kem>::accept(
+                    &OpModeR::Base,
+                    &sk_recip,
+                    &encapped_key,
+                    info,
+                )
+                .unwrap();
+                let frame = responder.send(b"response").unwrap();
+                assert!(matches!(initiator.recv(&frame), Err(HpkeError::InvalidTag)));
+
+                // Namespaced exports differ from plain ones and from other namespaces
+                let (_, ctx) = crate::setup::setup_sender::<A, Kdf, Kem, _>(
+                    &OpModeS::Base,
+                    &pk_recip,
+                    info,
+                    &mut csprng,
+                )
+                .unwrap();
+                let mut plain = [0u8; 32];
+                let mut namespaced = [0u8; 32];
+                let mut other_namespaced = [0u8; 32];
+                ctx.export(b"label", &mut plain).unwrap();
+                ctx.export_in(&ns, b"label", &mut namespaced).unwrap();
+                ctx.export_in(&other_ns, b"label", &mut other_namespaced)
+                    .unwrap();
+                assert_ne!(plain, namespaced);
+                assert_ne!(namespaced, other_namespaced);
+            }
+        };
+    }
+
+    /// Tests the bucket a plaintext is padded to
+    #[test]
+    fn test_padded_len() {
+        let padding = PaddingProfile::new(&[256, 64, 64]);
+        assert_eq!(padding.buckets(), [64, 256]);
+        assert_eq!(padding.padded_len(0), 64);
+        assert_eq!(padding.padded_len(63), 64);
+        assert_eq!(padding.padded_len(64), 256);
+        assert_eq!(padding.padded_len(255), 256);
+        assert_eq!(padding.padded_len(256), 512);
+        assert_eq!(padding.padded_len(1000), 1024);
+    }
+
+    /// Tests that padded messages all come out at bucket sizes and are unpadded on receipt, and
+    /// that badly padded messages are rejected
+    macro_rules! test_channel_padding {
+        ($test_name:ident, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type A = ChaCha20Poly1305;
+                type Kdf = HkdfSha256;
+                type Kem = $kem;
+                let mut csprng = StdRng::from_entropy();
+
+                let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+                let (encapped_key, mut initiator) = HpkeChannel::<A, Kdf, Kem>::initiate(
+                    &OpModeS::Base,
+                    &pk_recip,
+                    b"",
+                    &mut csprng,
+                )
+                .unwrap();
+                let mut responder = HpkeChannel::<A, Kdf, Kem>::accept(
+                    &OpModeR::Base,
+                    &sk_recip,
+                    &encapped_key,
+                    b"",
+                )
+                .unwrap();
+                responder.set_padding(Some(PaddingProfile::new(&[32, 128])));
+
+                // Responses of different lengths in the same bucket are the same length. Trailing
+                // zeros in the data survive the padding.
+                let frame1 = responder.send(b"").unwrap();
+                let frame2 = responder.send(b"a longer response\0\0").unwrap();
+                assert_eq!(frame1.len(), frame2.len());
+                assert_eq!(frame1[0], MSG_PADDED_DATA);
+                assert_eq!(initiator.recv(&frame1).unwrap(), b"");
+                assert_eq!(initiator.recv(&frame2).unwrap(), b"a longer response\0\0");
+                let frame3 = responder.send(&[0xaa; 100]).unwrap();
+                assert_eq!(frame3.len(), 1 + 128 + 16);
+                assert_eq!(initiator.recv(&frame3).unwrap(), [0xaa; 100]);
+
+                // The other direction isn't padded unless it's asked to be
+                let frame = initiator.send(b"request").unwrap();
+                assert_eq!(frame.len(), 1 + 7 + 16);
+                assert_eq!(responder.recv(&frame).unwrap(), b"request");
+
+                // Padding with no marker is rejected
+                let frame = responder.send_frame(MSG_PADDED_DATA, &[0u8; 32]).unwrap();
+                assert!(matches!(
+                    initiator.recv(&frame),
+                    Err(HpkeError::InvalidEncoding)
+                ));
+                let frame = responder.send_frame(MSG_PADDED_DATA, b"ab").unwrap();
+                assert!(matches!(
+                    initiator.recv(&frame),
+                    Err(HpkeError::InvalidEncoding)
+                ));
+            }
+        };
+    }
+
+    #[cfg(feature = "x25519-dalek")]
+    test_channel!(test_channel_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_channel!(test_channel_p256, crate::kem::DhP256HkdfSha256);
+
+    #[cfg(feature = "x25519-dalek")]
+    test_key_update!(test_key_update_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_key_update!(test_key_update_p256, crate::kem::DhP256HkdfSha256);
+
+    #[cfg(feature = "x25519-dalek")]
+    test_bad_key_update_recovery!(
+        test_bad_key_update_recovery_x25519,
+        crate::kem::X25519HkdfSha256
+    );
+    #[cfg(feature = "p256")]
+    test_bad_key_update_recovery!(
+        test_bad_key_update_recovery_p256,
+        crate::kem::DhP256HkdfSha256
+    );
+
+    #[cfg(feature = "x25519-dalek")]
+    test_channel_namespace!(test_channel_namespace_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_channel_namespace!(test_channel_namespace_p256, crate::kem::DhP256HkdfSha256);
+
+    #[cfg(feature = "x25519-dalek")]
+    test_channel_padding!(test_channel_padding_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_channel_padding!(test_channel_padding_p256, crate::kem::DhP256HkdfSha256);
+}
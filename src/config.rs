@@ -0,0 +1,528 @@
+//! This module defines [`ReceiverConfig`], everything a server needs to turn an incoming
+//! encapsulated key into a decryption context. A server usually has one private key, one info
+//! string, one mode, and a fixed list of ciphersuites it accepts, and sets up a context per
+//! connection. Holding those in a `ReceiverConfig` means each connection only has to supply what
+//! actually varies: the encapsulated key and the suite the client picked.
+//!
+//! [`Recipient`] is the other end: a recipient's public key, the suite to send with, and the info
+//! string, which is what a client usually passes around. It can be made from the recipient's
+//! published [`KeyConfig`].
+//!
+//! Both can be given a [`ValidityWindow`], outside of which they refuse to set up contexts with
+//! `HpkeError::KeyExpired`. This crate doesn't read the clock, so every method that sets up a
+//! context takes the current time as `now`, in the same units as the window.
+
+use crate::{
+    aead::{Aead, AeadCtxR, AeadCtxS},
+    kdf::Kdf as KdfTrait,
+    kem::{EncappedKey, Kem as KemTrait},
+    kex::KeyExchange,
+    key_config::{KeyConfig, SymmetricSuite},
+    op_mode::{OpModeR, OpModeS},
+    psk::ValidityWindow,
+    sealed::SealedMessage,
+    setup::{setup_receiver_from_bytes, setup_sender},
+    HpkeError,
+};
+
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use rand::{CryptoRng, RngCore};
+
+type PublicKey<Kem> = <<Kem as KemTrait>::Kex as KeyExchange>::PublicKey;
+type PrivateKey<Kem> = <<Kem as KemTrait>::Kex as KeyExchange>::PrivateKey;
+
+/// A receiver's private key, mode, info string, and the symmetric suites it accepts. The KEM is
+/// determined by `Kem`. A new config is in base mode and accepts no suites, so at least one suite
+/// has to be added before it can [`accept`](ReceiverConfig::accept) anything.
+pub struct ReceiverConfig<'a, Kem: KemTrait> {
+    sk_recip: PrivateKey<Kem>,
+    mode: OpModeR<'a, Kem::Kex>,
+    suites: Vec<SymmetricSuite>,
+    info: Vec<u8>,
+    validity: Option<ValidityWindow>,
+}
+
+impl<'a, Kem: KemTrait> ReceiverConfig<'a, Kem> {
+    /// Makes a base mode config with the given private key and info string, which accepts no
+    /// suites
+    pub fn new(sk_recip: PrivateKey<Kem>, info: &[u8]) -> Self {
+        ReceiverConfig {
+            sk_recip,
+            mode: OpModeR::Base,
+            suites: Vec::new(),
+            info: info.to_vec(),
+            validity: None,
+        }
+    }
+
+    /// Makes a base mode config with the given private key and info string, which accepts the
+    /// suites listed in `key_config` during its validity window, if it has one. `sk_recip` should
+    /// be the private key of `key_config.public_key()`.
+    pub fn from_key_config(
+        sk_recip: PrivateKey<Kem>,
+        key_config: &KeyConfig<Kem>,
+        info: &[u8],
+    ) -> Self {
+        let mut config = Self::new(sk_recip, info);
+        config.suites = key_config.suites().to_vec();
+        config.validity = key_config.validity();
+        config
+    }
+
+    /// Sets the mode that every context is set up in, e.g., `OpModeR::Auth` to only accept
+    /// senders holding a given identity key
+    pub fn mode(mut self, mode: OpModeR<'a, Kem::Kex>) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Sets when the private key may be used
+    pub fn validity(mut self, validity: ValidityWindow) -> Self {
+        self.validity = Some(validity);
+        self
+    }
+
+    /// Adds the given KDF and AEAD to the accepted suites
+    pub fn suite<Kdf: KdfTrait, A: Aead>(self) -> Self {
+        self.suite_ids(SymmetricSuite::new::<Kdf, A>())
+    }
+
+    /// Adds the given suite to the accepted suites
+    pub fn suite_ids(mut self, suite: SymmetricSuite) -> Self {
+        if !self.suites.contains(&suite) {
+            self.suites.push(suite);
+        }
+        self
+    }
+
+    /// Returns the symmetric suites this config accepts
+    pub fn suites(&self) -> &[SymmetricSuite] {
+        &self.suites
+    }
+
+    /// Returns the info string that every context is set up with
+    pub fn info(&self) -> &[u8] {
+        &self.info
+    }
+
+    /// Returns whether this config accepts the given suite
+    pub fn accepts(&self, suite: SymmetricSuite) -> bool {
+        self.suites.contains(&suite)
+    }
+
+    /// Sets up a decryption context for the encapsulated key `encapped_key`, which a sender made
+    /// for the suite `suite`. `Kdf` and `A` are the KDF and AEAD the caller dispatched to for
+    /// `suite`, and `now` is the current time.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::KeyExpired)` if `now` is outside this config's validity window.
+    /// Returns `Err(HpkeError::SuiteMismatch)` if this config doesn't accept `suite`, or if `suite`
+    /// isn't the suite of `Kdf` and `A`. Otherwise, returns whatever
+    /// [`setup_receiver_from_bytes`] does.
+    pub fn accept<A: Aead, Kdf: KdfTrait>(
+        &self,
+        encapped_key: &[u8],
+        suite: SymmetricSuite,
+        now: u64,
+    ) -> Result<AeadCtxR<A, Kdf, Kem>, HpkeError> {
+        if !is_valid_at(self.validity, now) {
+            return Err(HpkeError::KeyExpired);
+        }
+        if !self.accepts(suite) || suite != SymmetricSuite::new::<Kdf, A>() {
+            return Err(HpkeError::SuiteMismatch);
+        }
+
+        setup_receiver_from_bytes::<A, Kdf, Kem>(
+            &self.mode,
+            &self.sk_recip,
+            encapped_key,
+            &self.info,
+        )
+    }
+
+    /// Opens a single-shot message that a sender sealed to this config's key, e.g., with
+    /// [`Recipient::seal`]. `now` is the current time.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::KeyExpired)` if `now` is outside this config's validity window.
+    /// Returns `Err(HpkeError::SuiteMismatch)` if this config doesn't accept the suite of `Kdf`
+    /// and `A`. Otherwise, fails the same way as [`SealedMessage::open`].
+    pub fn open<A: Aead, Kdf: KdfTrait>(
+        &self,
+        msg: &SealedMessage<(A, Kdf, Kem)>,
+        aad: &[u8],
+        now: u64,
+    ) -> Result<Vec<u8>, HpkeError> {
+        if !is_valid_at(self.validity, now) {
+            return Err(HpkeError::KeyExpired);
+        }
+        if !self.accepts(SymmetricSuite::new::<Kdf, A>()) {
+            return Err(HpkeError::SuiteMismatch);
+        }
+
+        msg.open(&self.mode, &self.sk_recip, &self.info, aad)
+    }
+}
+
+/// A recipient's public key, together with the suite and info string to send to it with. The
+/// suite is `A`, `Kdf`, and `Kem`. A new recipient is in base mode.
+pub struct Recipient<'a, A: Aead, Kdf: KdfTrait, Kem: KemTrait> {
+    pk_recip: PublicKey<Kem>,
+    key_id: Option<u8>,
+    mode: OpModeS<'a, Kem::Kex>,
+    info: Vec<u8>,
+    validity: Option<ValidityWindow>,
+    suite: PhantomData<fn() -> (A, Kdf)>,
+}
+
+impl<'a, A: Aead, Kdf: KdfTrait, Kem: KemTrait> Recipient<'a, A, Kdf, Kem> {
+    /// Makes a base mode recipient with the given public key and info string
+    pub fn new(pk_recip: PublicKey<Kem>, info: &[u8]) -> Self {
+        Recipient {
+            pk_recip,
+            key_id: None,
+            mode: OpModeS::Base,
+            info: info.to_vec(),
+            validity: None,
+            suite: PhantomData,
+        }
+    }
+
+    /// Makes a base mode recipient out of the public key, key ID, and validity window in
+    /// `key_config`, with the given info string
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::SuiteMismatch)` if `key_config` doesn't list the suite of `Kdf` and
+    /// `A`.
+    pub fn from_key_config(key_config: &KeyConfig<Kem>, info: &[u8]) -> Result<Self, HpkeError> {
+        if !key_config.supports::<Kdf, A>() {
+            return Err(HpkeError::SuiteMismatch);
+        }
+
+        let mut recipient = Self::new(key_config.public_key().clone(), info);
+        recipient.key_id = Some(key_config.key_id());
+        recipient.validity = key_config.validity();
+        Ok(recipient)
+    }
+
+    /// Sets the mode that every message is sent in, e.g., `OpModeS::Psk` to send with a PSK
+    pub fn mode(mut self, mode: OpModeS<'a, Kem::Kex>) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Sets when the recipient's public key may be used
+    pub fn validity(mut self, validity: ValidityWindow) -> Self {
+        self.validity = Some(validity);
+        self
+    }
+
+    /// Returns the recipient's public key
+    pub fn public_key(&self) -> &PublicKey<Kem> {
+        &self.pk_recip
+    }
+
+    /// Returns the ID of the recipient's key, if this was made from a key config
+    pub fn key_id(&self) -> Option<u8> {
+        self.key_id
+    }
+
+    /// Returns the symmetric suite that messages are sent with
+    pub fn suite(&self) -> SymmetricSuite {
+        SymmetricSuite::new::<Kdf, A>()
+    }
+
+    /// Returns the info string that every context is set up with
+    pub fn info(&self) -> &[u8] {
+        &self.info
+    }
+
+    /// Sets up an encryption context to this recipient. `now` is the current time.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::KeyExpired)` if `now` is outside the recipient key's validity
+    /// window. Otherwise, fails the same way as [`setup_sender`].
+    pub fn setup_sender<R: CryptoRng + RngCore>(
+        &self,
+        now: u64,
+        csprng: &mut R,
+    ) -> Result<(EncappedKey<Kem>, AeadCtxS<A, Kdf, Kem>), HpkeError> {
+        if !is_valid_at(self.validity, now) {
+            return Err(HpkeError::KeyExpired);
+        }
+        setup_sender::<A, Kdf, Kem, R>(&self.mode, &self.pk_recip, &self.info, csprng)
+    }
+
+    /// Seals `plaintext` to this recipient as a single-shot message. `now` is the current time.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::KeyExpired)` if `now` is outside the recipient key's validity
+    /// window. Otherwise, fails the same way as [`SealedMessage::seal`].
+    pub fn seal<R: CryptoRng + RngCore>(
+        &self,
+        plaintext: &[u8],
+        aad: &[u8],
+        now: u64,
+        csprng: &mut R,
+    ) -> Result<SealedMessage<(A, Kdf, Kem)>, HpkeError> {
+        if !is_valid_at(self.validity, now) {
+            return Err(HpkeError::KeyExpired);
+        }
+        SealedMessage::seal(
+            &self.mode,
+            &self.pk_recip,
+            &self.info,
+            plaintext,
+            aad,
+            csprng,
+        )
+    }
+}
+
+// A key with no validity window is always valid
+fn is_valid_at(validity: Option<ValidityWindow>, now: u64) -> bool {
+    match validity {
+        Some(validity) => validity.contains(now),
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ReceiverConfig, Recipient};
+    use crate::{
+        aead::ChaCha20Poly1305,
+        kdf::HkdfSha256,
+        kem::Kem as KemTrait,
+        kex::Serializable,
+        key_config::{KeyConfig, SymmetricSuite},
+        op_mode::{OpModeR, OpModeS, PskBundle},
+        psk::ValidityWindow,
+        setup::setup_sender,
+        HpkeError,
+    };
+
+    #[cfg(feature = "aes-gcm")]
+    use crate::aead::AesGcm128;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Tests that a config accepts the suites of its key config and sets up contexts with its
+    /// info, and that it refuses other suites
+    macro_rules! test_receiver_config {
+        ($test_name:ident, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type A = ChaCha20Poly1305;
+                type Kdf = HkdfSha256;
+                type Kem = $kem;
+                let mut csprng = StdRng::from_entropy();
+
+                let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+                let key_config = KeyConfig::<Kem>::builder(1, pk_recip.clone())
+                    .suite::<Kdf, A>()
+                    .build()
+                    .unwrap();
+                let config = ReceiverConfig::from_key_config(sk_recip, &key_config, b"info");
+                let suite = SymmetricSuite::new::<Kdf, A>();
+                assert!(config.accepts(suite));
+                assert_eq!(config.info(), b"info");
+
+                let (encapped_key, mut sender_ctx) =
+                    setup_sender::<A, Kdf, Kem, _>(&OpModeS::Base, &pk_recip, b"info", &mut csprng)
+                        .unwrap();
+                let enc = encapped_key.to_bytes();
+                let mut receiver_ctx = config.accept::<A, Kdf>(&enc, suite, 0).unwrap();
+
+                let mut msg = *b"per connection";
+                let tag = sender_ctx.seal(&mut msg, b"").unwrap();
+                receiver_ctx.open(&mut msg, b"", &tag).unwrap();
+                assert_eq!(&msg, b"per connection");
+
+                // A suite the config doesn't list is refused, and so is a listed suite that the
+                // caller dispatched to the wrong types
+                let other_suite = SymmetricSuite {
+                    kdf_id: 0x1337,
+                    aead_id: suite.aead_id,
+                };
+                let res = config.accept::<A, Kdf>(&enc, other_suite, 0);
+                assert!(matches!(res, Err(HpkeError::SuiteMismatch)));
+                #[cfg(feature = "aes-gcm")]
+                {
+                    let res = config.accept::<AesGcm128, Kdf>(&enc, suite, 0);
+                    assert!(matches!(res, Err(HpkeError::SuiteMismatch)));
+                }
+
+                // A malformed encapsulated key is refused
+                let res = config.accept::<A, Kdf>(&enc[1..], suite, 0);
+                assert!(matches!(res, Err(HpkeError::InvalidEncoding)));
+            }
+        };
+    }
+
+    /// Tests that a config's mode applies to every context it sets up
+    macro_rules! test_receiver_config_mode {
+        ($test_name:ident, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type A = ChaCha20Poly1305;
+                type Kdf = HkdfSha256;
+                type Kem = $kem;
+                let mut csprng = StdRng::from_entropy();
+
+                let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+                let psk = PskBundle {
+                    psk: b"a psk",
+                    psk_id: b"psk id",
+                };
+                let config = ReceiverConfig::<Kem>::new(sk_recip, b"")
+                    .mode(OpModeR::Psk(psk))
+                    .suite::<Kdf, A>();
+                let suite = SymmetricSuite::new::<Kdf, A>();
+
+                // A sender in PSK mode gets through, and one in base mode doesn't
+                for (sender_mode, should_open) in
+                    [(OpModeS::Psk(psk), true), (OpModeS::Base, false)]
+                {
+                    let (encapped_key, mut sender_ctx) =
+                        setup_sender::<A, Kdf, Kem, _>(&sender_mode, &pk_recip, b"", &mut csprng)
+                            .unwrap();
+                    let mut receiver_ctx = config
+                        .accept::<A, Kdf>(&encapped_key.to_bytes(), suite, 0)
+                        .unwrap();
+
+                    let mut msg = *b"psk only";
+                    let tag = sender_ctx.seal(&mut msg, b"").unwrap();
+                    let res = receiver_ctx.open(&mut msg, b"", &tag);
+                    assert_eq!(res.is_ok(), should_open);
+                }
+            }
+        };
+    }
+
+    /// Tests that a recipient made from a key config seals messages that the matching receiver
+    /// config accepts, and that it can't be made for a suite the key config doesn't list
+    macro_rules! test_recipient {
+        ($test_name:ident, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type A = ChaCha20Poly1305;
+                type Kdf = HkdfSha256;
+                type Kem = $kem;
+                let mut csprng = StdRng::from_entropy();
+
+                let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+                let key_config = KeyConfig::<Kem>::builder(7, pk_recip)
+                    .suite::<Kdf, A>()
+                    .build()
+                    .unwrap();
+                let recipient =
+                    Recipient::<A, Kdf, Kem>::from_key_config(&key_config, b"info").unwrap();
+                assert_eq!(recipient.key_id(), Some(7));
+                assert_eq!(recipient.suite(), SymmetricSuite::new::<Kdf, A>());
+                let config =
+                    ReceiverConfig::from_key_config(sk_recip.clone(), &key_config, b"info");
+
+                // Single-shot
+                let sealed = recipient.seal(b"hello", b"aad", 0, &mut csprng).unwrap();
+                let plaintext = sealed
+                    .open(&OpModeR::Base, &sk_recip, b"info", b"aad")
+                    .unwrap();
+                assert_eq!(&plaintext[..], b"hello");
+
+                // Context
+                let (encapped_key, mut sender_ctx) =
+                    recipient.setup_sender(0, &mut csprng).unwrap();
+                let mut receiver_ctx = config
+                    .accept::<A, Kdf>(&encapped_key.to_bytes(), recipient.suite(), 0)
+                    .unwrap();
+                let mut msg = *b"again";
+                let tag = sender_ctx.seal(&mut msg, b"").unwrap();
+                receiver_ctx.open(&mut msg, b"", &tag).unwrap();
+                assert_eq!(&msg, b"again");
+
+                #[cfg(feature = "aes-gcm")]
+                {
+                    let res = Recipient::<AesGcm128, Kdf, Kem>::from_key_config(&key_config, b"");
+                    assert!(matches!(res, Err(HpkeError::SuiteMismatch)));
+                }
+            }
+        };
+    }
+
+    /// Tests that both ends refuse to set up contexts outside the key config's validity window
+    macro_rules! test_config_validity {
+        ($test_name:ident, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type A = ChaCha20Poly1305;
+                type Kdf = HkdfSha256;
+                type Kem = $kem;
+                let mut csprng = StdRng::from_entropy();
+
+                let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+                let window = ValidityWindow {
+                    not_before: 1000,
+                    not_after: 2000,
+                };
+                let key_config = KeyConfig::<Kem>::builder(1, pk_recip)
+                    .suite::<Kdf, A>()
+                    .validity(window)
+                    .build()
+                    .unwrap();
+                assert!(key_config.is_valid_at(1500));
+                assert!(!key_config.is_valid_at(2001));
+                let recipient =
+                    Recipient::<A, Kdf, Kem>::from_key_config(&key_config, b"").unwrap();
+                let config = ReceiverConfig::from_key_config(sk_recip, &key_config, b"");
+
+                for now in [999, 2001] {
+                    let res = recipient.seal(b"", b"", now, &mut csprng);
+                    assert!(matches!(res, Err(HpkeError::KeyExpired)));
+                    let res = recipient.setup_sender(now, &mut csprng);
+                    assert!(matches!(res, Err(HpkeError::KeyExpired)));
+                }
+
+                let (encapped_key, _) = recipient.setup_sender(1000, &mut csprng).unwrap();
+                let enc = encapped_key.to_bytes();
+                let suite = recipient.suite();
+                assert!(config.accept::<A, Kdf>(&enc, suite, 2000).is_ok());
+                let res = config.accept::<A, Kdf>(&enc, suite, 2001);
+                assert!(matches!(res, Err(HpkeError::KeyExpired)));
+
+                // The window isn't part of the published key config
+                let decoded = KeyConfig::<Kem>::from_bytes(&key_config.to_bytes()).unwrap();
+                assert_eq!(decoded.validity(), None);
+            }
+        };
+    }
+
+    #[cfg(feature = "x25519-dalek")]
+    test_receiver_config!(test_receiver_config_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_receiver_config!(test_receiver_config_p256, crate::kem::DhP256HkdfSha256);
+
+    #[cfg(feature = "x25519-dalek")]
+    test_receiver_config_mode!(
+        test_receiver_config_mode_x25519,
+        crate::kem::X25519HkdfSha256
+    );
+    #[cfg(feature = "p256")]
+    test_receiver_config_mode!(test_receiver_config_mode_p256, crate::kem::DhP256HkdfSha256);
+
+    #[cfg(feature = "x25519-dalek")]
+    test_recipient!(test_recipient_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_recipient!(test_recipient_p256, crate::kem::DhP256HkdfSha256);
+
+    #[cfg(feature = "x25519-dalek")]
+    test_config_validity!(test_config_validity_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_config_validity!(test_config_validity_p256, crate::kem::DhP256HkdfSha256);
+}
@@ -0,0 +1,265 @@
+//! This module is the part of the API meant for environments that have to qualify every code
+//! path they use, e.g., automotive or medical software. It's small on purpose: context setup,
+//! seal, open, and export, on caller-provided buffers and fixed-size types. A user who sticks to
+//! it only has to review what's listed here, and what it calls.
+//!
+//! The subset is:
+//!
+//! * The single-shot functions in this module: [`seal`], [`open`], [`send_export`], and
+//!   [`receive_export`]
+//! * [`setup_sender`] and [`setup_receiver_from_bytes`]
+//! * [`AeadCtxS::seal`], [`AeadCtxR::open`], and the `export` methods of both contexts
+//! * The types these take and return, which are all fixed-size: [`EncappedKey`], [`AeadTag`],
+//!   the key types of the KEM, [`OpModeS`], [`OpModeR`], and [`PskBundle`]
+//!
+//! Every input that comes from outside, i.e., encapsulated keys and tags, is taken as bytes, so a
+//! malformed one is an error rather than something the caller has to parse first.
+//!
+//! Guarantees
+//! ==========
+//! With the KEMs, KDFs, and AEADs built into this crate, nothing in the subset allocates, and
+//! nothing in it panics, on any input. Every failure is an [`HpkeError`]. The few `expect`s on
+//! these paths check buffer lengths that the types already fix, so they can't fire.
+//! `tests/core_api.rs` checks both guarantees: it counts allocations around every function in the
+//! subset, on both good and bad inputs, and feeds the single-shot functions arbitrary bytes.
+//!
+//! These guarantees don't cover third-party KEMs, KDFs, or AEADs, which can do what they like.
+//! They also don't cover the sinks of the `events` and `tracing` features, which run inside these
+//! functions when they're installed. Leave both features off, or install no sink, to keep the
+//! guarantees. The `transcript` feature copies the setup inputs into every context, which
+//! allocates, so it has to be off too. The rest of the crate, including the other methods of the
+//! contexts, makes no such promises. Some of it allocates, e.g., [`AeadCtxS::seal_large`].
+
+use crate::{
+    kdf::Kdf as KdfTrait,
+    kem::{EncappedKey, Kem as KemTrait},
+    kex::{Deserializable, KeyExchange},
+    HpkeError,
+};
+
+use rand::{CryptoRng, RngCore};
+
+#[doc(inline)]
+pub use crate::{
+    aead::{Aead, AeadCtxR, AeadCtxS, AeadTag},
+    op_mode::{OpModeR, OpModeS, PskBundle},
+    setup::{setup_receiver_from_bytes, setup_sender},
+};
+
+/// Encrypts `plaintext` in place to `pk_recip`, as the only message of a fresh context
+///
+/// Return Value
+/// ============
+/// Returns `Ok((encapped_key, tag))` on success. Otherwise, fails the same way as
+/// [`setup_sender`] and [`AeadCtxS::seal`].
+pub fn seal<A, Kdf, Kem, R>(
+    mode: &OpModeS<Kem::Kex>,
+    pk_recip: &<Kem::Kex as KeyExchange>::PublicKey,
+    info: &[u8],
+    plaintext: &mut [u8],
+    aad: &[u8],
+    csprng: &mut R,
+) -> Result<(EncappedKey<Kem>, AeadTag<A>), HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    R: CryptoRng + RngCore,
+{
+    let (encapped_key, mut ctx) = setup_sender::<A, Kdf, Kem, R>(mode, pk_recip, info, csprng)?;
+    let tag = ctx.seal(plaintext, aad)?;
+    Ok((encapped_key, tag))
+}
+
+/// Decrypts `ciphertext` in place, given the encapsulated key and tag that [`seal`] gave, as
+/// bytes
+///
+/// Return Value
+/// ============
+/// Returns `Ok(())` on success. If `encapped_key` or `tag` is malformed, returns
+/// `Err(HpkeError::InvalidEncoding)`. Otherwise, fails the same way as
+/// [`setup_receiver_from_bytes`] and [`AeadCtxR::open`]. On failure, `ciphertext` is unmodified.
+pub fn open<A, Kdf, Kem>(
+    mode: &OpModeR<Kem::Kex>,
+    sk_recip: &<Kem::Kex as KeyExchange>::PrivateKey,
+    encapped_key: &[u8],
+    info: &[u8],
+    ciphertext: &mut [u8],
+    aad: &[u8],
+    tag: &[u8],
+) -> Result<(), HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    // Parse the tag first, so a bad one doesn't cost a decap
+    let tag = AeadTag::<A>::from_bytes(tag)?;
+    let mut ctx = setup_receiver_from_bytes::<A, Kdf, Kem>(mode, sk_recip, encapped_key, info)?;
+    ctx.open(ciphertext, aad, &tag)
+}
+
+/// Sets up a context to `pk_recip` and fills `out_buf` with a secret exported from it. The
+/// recipient gets the same secret from [`receive_export`].
+///
+/// Return Value
+/// ============
+/// Returns the encapsulated key on success. Otherwise, fails the same way as [`setup_sender`] and
+/// the `export` methods of the contexts.
+pub fn send_export<Kdf, Kem, R>(
+    mode: &OpModeS<Kem::Kex>,
+    pk_recip: &<Kem::Kex as KeyExchange>::PublicKey,
+    info: &[u8],
+    exporter_ctx: &[u8],
+    out_buf: &mut [u8],
+    csprng: &mut R,
+) -> Result<EncappedKey<Kem>, HpkeError>
+where
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    R: CryptoRng + RngCore,
+{
+    let (encapped_key, ctx) =
+        setup_sender::<crate::aead::ExportOnly, Kdf, Kem, R>(mode, pk_recip, info, csprng)?;
+    ctx.export(exporter_ctx, out_buf)?;
+    Ok(encapped_key)
+}
+
+/// Sets up a context from the encapsulated key that [`send_export`] gave, as bytes, and fills
+/// `out_buf` with the secret exported from it
+///
+/// Return Value
+/// ============
+/// Returns `Ok(())` on success. Otherwise, fails the same way as [`setup_receiver_from_bytes`]
+/// and the `export` methods of the contexts.
+pub fn receive_export<Kdf, Kem>(
+    mode: &OpModeR<Kem::Kex>,
+    sk_recip: &<Kem::Kex as KeyExchange>::PrivateKey,
+    encapped_key: &[u8],
+    info: &[u8],
+    exporter_ctx: &[u8],
+    out_buf: &mut [u8],
+) -> Result<(), HpkeError>
+where
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    let ctx = setup_receiver_from_bytes::<crate::aead::ExportOnly, Kdf, Kem>(
+        mode,
+        sk_recip,
+        encapped_key,
+        info,
+    )?;
+    ctx.export(exporter_ctx, out_buf)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{open, receive_export, seal, send_export};
+    use crate::{
+        aead::ChaCha20Poly1305,
+        kdf::HkdfSha256,
+        kem::Kem as KemTrait,
+        kex::Serializable,
+        test_util::{new_op_mode_pair, OpModeKind},
+        HpkeError,
+    };
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Tests that the single-shot functions agree with each other in every mode, and that bad
+    /// encodings are errors
+    macro_rules! test_core_api {
+        ($test_name:ident, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type A = ChaCha20Poly1305;
+                type Kdf = HkdfSha256;
+                type Kem = $kem;
+                let mut csprng = StdRng::from_entropy();
+
+                let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+                let info = b"core api test";
+                for kind in [
+                    OpModeKind::Base,
+                    OpModeKind::Auth,
+                    OpModeKind::Psk,
+                    OpModeKind::AuthPsk,
+                ]
+                .iter()
+                {
+                    let (mode_s, mode_r) = new_op_mode_pair::<<Kem as KemTrait>::Kex>(
+                        *kind,
+                        b"a psk with plenty of entropy",
+                        b"psk id",
+                    );
+
+                    let mut msg = *b"qualified";
+                    let (encapped_key, tag) = seal::<A, Kdf, Kem, _>(
+                        &mode_s,
+                        &pk_recip,
+                        info,
+                        &mut msg,
+                        b"ad",
+                        &mut csprng,
+                    )
+                    .unwrap();
+                    let (enc, tag) = (encapped_key.to_bytes(), tag.to_bytes());
+
+                    // A short tag or encapsulated key is an error, and leaves the message alone
+                    let res = open::<A, Kdf, Kem>(
+                        &mode_r,
+                        &sk_recip,
+                        &enc,
+                        info,
+                        &mut msg,
+                        b"ad",
+                        &tag[1..],
+                    );
+                    assert!(matches!(res, Err(HpkeError::InvalidEncoding)));
+                    let res = open::<A, Kdf, Kem>(
+                        &mode_r,
+                        &sk_recip,
+                        &enc[1..],
+                        info,
+                        &mut msg,
+                        b"ad",
+                        &tag,
+                    );
+                    assert!(matches!(res, Err(HpkeError::InvalidEncoding)));
+
+                    open::<A, Kdf, Kem>(&mode_r, &sk_recip, &enc, info, &mut msg, b"ad", &tag)
+                        .unwrap();
+                    assert_eq!(&msg, b"qualified");
+
+                    let mut sender_secret = [0u8; 32];
+                    let encapped_key = send_export::<Kdf, Kem, _>(
+                        &mode_s,
+                        &pk_recip,
+                        info,
+                        b"exp",
+                        &mut sender_secret,
+                        &mut csprng,
+                    )
+                    .unwrap();
+                    let mut receiver_secret = [0u8; 32];
+                    receive_export::<Kdf, Kem>(
+                        &mode_r,
+                        &sk_recip,
+                        &encapped_key.to_bytes(),
+                        info,
+                        b"exp",
+                        &mut receiver_secret,
+                    )
+                    .unwrap();
+                    assert_eq!(sender_secret, receiver_secret);
+                }
+            }
+        };
+    }
+
+    #[cfg(feature = "x25519-dalek")]
+    test_core_api!(test_core_api_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_core_api!(test_core_api_p256, crate::kem::DhP256HkdfSha256);
+}
@@ -0,0 +1,293 @@
+//! This module collects the APIs that step outside of what the HPKE spec analyzes: ones that take
+//! randomness from the caller, hand out raw secrets, or start the key schedule from a secret this
+//! crate didn't derive. They all need the `danger` feature, so a build that doesn't enable it
+//! can't reach any of them. A reviewer auditing a downstream build only has to check whether
+//! `danger` is on, and if so, which of these are called.
+//!
+//! The full list is:
+//!
+//! * [`encap_deterministic`] encapsulates with a caller-provided ephemeral key
+//! * [`encap_traced`] does the same, and also returns an [`EncapTrace`] of the intermediate
+//!   values. This only exists in debug builds.
+//! * [`decap_from_dh`] finishes a decap whose DH was done elsewhere, e.g., in an HSM
+//! * [`derive_receiver_ctx`] runs the key schedule on a caller-provided shared secret
+//! * [`SharedSecret::into_bytes_dangerous`] returns the raw KEM output
+//! * [`AeadCtxS::key_dangerous`](crate::AeadCtxS::key_dangerous) and
+//!   [`AeadCtxR::key_dangerous`](crate::AeadCtxR::key_dangerous) return the AEAD key
+//! * [`AeadCtxS::next_nonce_dangerous`](crate::AeadCtxS::next_nonce_dangerous) and
+//!   [`AeadCtxR::next_nonce_dangerous`](crate::AeadCtxR::next_nonce_dangerous) hand out nonces
+//!   for an external AEAD
+//! * [`AeadCtxS::compute_nonce`](crate::AeadCtxS::compute_nonce),
+//!   [`AeadCtxS::compute_nonce_checked`](crate::AeadCtxS::compute_nonce_checked), and
+//!   [`AeadCtxR::compute_nonce`](crate::AeadCtxR::compute_nonce) return the nonce for a given
+//!   sequence number, for an external AEAD
+//!
+//! Contexts can't be serialized, with or without this feature. Private keys are
+//! [`Serializable`](crate::Serializable) regardless, since that's how they're stored.
+
+use crate::{
+    aead::{Aead, AeadCtxR},
+    kdf::Kdf as KdfTrait,
+    kem::{self, EncappedKey, Kem as KemTrait, SharedSecret},
+    kex::KeyExchange,
+    op_mode::OpModeR,
+    setup, HpkeError,
+};
+
+#[cfg(debug_assertions)]
+use crate::kex::Serializable;
+#[cfg(debug_assertions)]
+use alloc::vec::Vec;
+#[cfg(debug_assertions)]
+use core::fmt;
+
+// Convenience types for the KEM's keys
+type KemPubkey<Kem> = <<Kem as KemTrait>::Kex as KeyExchange>::PublicKey;
+type KemPrivkey<Kem> = <<Kem as KemTrait>::Kex as KeyExchange>::PrivateKey;
+
+/// Encapsulates to `pk_recip` with the given ephemeral private key, rather than a fresh random
+/// one. If `sender_id_keypair` is given, the sender's identity is tied to the shared secret. This
+/// is for test vectors. Reusing an ephemeral key reuses the shared secret.
+///
+/// Return Value
+/// ============
+/// Returns a shared secret and encapped key on success. If an error happened during key exchange,
+/// returns `Err(HpkeError::InvalidKeyExchange)`.
+pub fn encap_deterministic<Kem: KemTrait>(
+    pk_recip: &KemPubkey<Kem>,
+    sender_id_keypair: Option<&(KemPrivkey<Kem>, KemPubkey<Kem>)>,
+    sk_eph: KemPrivkey<Kem>,
+) -> Result<(SharedSecret<Kem>, EncappedKey<Kem>), HpkeError> {
+    kem::encap_with_eph::<Kem>(pk_recip, sender_id_keypair, sk_eph)
+}
+
+/// The intermediate values of an encap, for comparing against the test vectors of another
+/// implementation. Every field is secret except `pk_eph` and `kem_context`. Its `Display` impl
+/// prints one field per line, in lowercase hex, in the order they're computed.
+#[cfg(debug_assertions)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EncapTrace {
+    /// The serialized ephemeral private key, i.e., `skEm`
+    pub sk_eph: Vec<u8>,
+    /// The serialized ephemeral public key, i.e., `pkEm`, which is also the encapsulated key
+    pub pk_eph: Vec<u8>,
+    /// The DH result. In an auth encap, this is both DH results concatenated.
+    pub dh: Vec<u8>,
+    /// `enc || pkRm`, or `enc || pkRm || pkSm` in an auth encap
+    pub kem_context: Vec<u8>,
+    /// The secret extracted from `dh`, which the shared secret is expanded from
+    pub eae_prk: Vec<u8>,
+}
+
+#[cfg(debug_assertions)]
+impl fmt::Display for EncapTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields: [(&str, &[u8]); 5] = [
+            ("sk_eph", &self.sk_eph),
+            ("pk_eph", &self.pk_eph),
+            ("dh", &self.dh),
+            ("kem_context", &self.kem_context),
+            ("eae_prk", &self.eae_prk),
+        ];
+        for (i, (name, bytes)) in fields.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}: ", name)?;
+            for b in bytes.iter() {
+                write!(f, "{:02x}", b)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Does the same as [`encap_deterministic`], and also records the intermediate values. This is
+/// for generating test vectors and for finding where this crate and another implementation
+/// diverge. It only exists in debug builds, so that it can't end up in a release.
+///
+/// Return Value
+/// ============
+/// Returns a shared secret, encapped key, and trace on success. If an error happened during key
+/// exchange, returns `Err(HpkeError::InvalidKeyExchange)`.
+#[cfg(debug_assertions)]
+pub fn encap_traced<Kem: KemTrait>(
+    pk_recip: &KemPubkey<Kem>,
+    sender_id_keypair: Option<&(KemPrivkey<Kem>, KemPubkey<Kem>)>,
+    sk_eph: KemPrivkey<Kem>,
+) -> Result<(SharedSecret<Kem>, EncappedKey<Kem>, EncapTrace), HpkeError> {
+    let sk_eph_bytes = sk_eph.to_bytes().to_vec();
+    let mut trace = None;
+    let (shared_secret, encapped_key) =
+        kem::encap_with_eph_observed::<Kem>(pk_recip, sender_id_keypair, sk_eph, |internals| {
+            trace = Some((
+                internals.dh.to_vec(),
+                internals.kem_context.to_vec(),
+                internals.eae_prk.to_vec(),
+            ));
+        })?;
+
+    // The KEM always calls the observer when it succeeds
+    let (dh, kem_context, eae_prk) = trace.expect("encap didn't record its internals");
+    let trace = EncapTrace {
+        sk_eph: sk_eph_bytes,
+        pk_eph: encapped_key.to_bytes().to_vec(),
+        dh,
+        kem_context,
+        eae_prk,
+    };
+    Ok((shared_secret, encapped_key, trace))
+}
+
+/// Finishes an unauthenticated decap, given `dh`, the serialized DH result of the recipient's
+/// private key and the encapsulated key. This is for recipients whose private key lives in an HSM
+/// or enclave that only does the DH. This crate can't check that `dh` is what it claims to be.
+///
+/// Return Value
+/// ============
+/// Returns the shared secret. This doesn't fail.
+pub fn decap_from_dh<Kem: KemTrait>(
+    dh: &[u8],
+    pk_recip: &KemPubkey<Kem>,
+    encapped_key: &EncappedKey<Kem>,
+) -> Result<SharedSecret<Kem>, HpkeError> {
+    kem::decap_from_dh::<Kem>(dh, pk_recip, encapped_key)
+}
+
+/// Runs the key schedule on `shared_secret` to get a receiver context. This is the second half of
+/// `setup_receiver`, for a shared secret that came from [`decap_from_dh`] or from outside this
+/// crate.
+pub fn derive_receiver_ctx<A, Kdf, Kem>(
+    mode: &OpModeR<Kem::Kex>,
+    shared_secret: SharedSecret<Kem>,
+    info: &[u8],
+) -> AeadCtxR<A, Kdf, Kem>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    setup::derive_receiver_ctx::<A, Kdf, Kem>(mode, shared_secret, info)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decap_from_dh, derive_receiver_ctx, encap_deterministic};
+    use crate::{
+        aead::ChaCha20Poly1305,
+        kdf::HkdfSha256,
+        kem::Kem as KemTrait,
+        kex::{Deserializable, KeyExchange, Serializable},
+        op_mode::{OpModeR, OpModeS},
+        setup::setup_sender,
+    };
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Tests that deterministic encap is deterministic, and that a decap split between an outside
+    /// DH and this crate gets the same shared secret
+    macro_rules! test_split_decap {
+        ($test_name:ident, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type Kem = $kem;
+                type Kex = <Kem as KemTrait>::Kex;
+                let mut csprng = StdRng::from_entropy();
+
+                let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+                let (sk_eph, _) = Kem::gen_keypair(&mut csprng);
+
+                let (ss1, enc1) =
+                    encap_deterministic::<Kem>(&pk_recip, None, sk_eph.clone()).unwrap();
+                let (ss2, enc2) = encap_deterministic::<Kem>(&pk_recip, None, sk_eph).unwrap();
+                assert_eq!(enc1.to_bytes(), enc2.to_bytes());
+                assert_eq!(ss2.into_bytes_dangerous(), ss1.into_bytes_dangerous());
+
+                // Do the DH "in the HSM", and the rest here
+                let (encapped_key, mut sender_ctx) =
+                    setup_sender::<ChaCha20Poly1305, HkdfSha256, Kem, _>(
+                        &OpModeS::Base,
+                        &pk_recip,
+                        b"info",
+                        &mut csprng,
+                    )
+                    .unwrap();
+                let pk_eph =
+                    <Kex as KeyExchange>::PublicKey::from_bytes(&encapped_key.to_bytes()).unwrap();
+                let dh = Kex::kex(&sk_recip, &pk_eph).unwrap().to_bytes();
+
+                let shared_secret = decap_from_dh::<Kem>(&dh, &pk_recip, &encapped_key).unwrap();
+                let mut receiver_ctx = derive_receiver_ctx::<ChaCha20Poly1305, HkdfSha256, Kem>(
+                    &OpModeR::Base,
+                    shared_secret,
+                    b"info",
+                );
+
+                let mut msg = b"split decap".to_vec();
+                let tag = sender_ctx.seal(&mut msg, b"").unwrap();
+                receiver_ctx.open(&mut msg, b"", &tag).unwrap();
+                assert_eq!(&msg[..], b"split decap");
+            }
+        };
+    }
+
+    /// Tests the trace of an encap against the X25519 base mode test vector, and that tracing
+    /// doesn't change the result
+    #[cfg(all(debug_assertions, feature = "x25519-dalek"))]
+    #[test]
+    fn test_encap_traced() {
+        use super::encap_traced;
+        use alloc::string::ToString;
+
+        type Kem = crate::kem::X25519HkdfSha256;
+        type Kex = <Kem as KemTrait>::Kex;
+
+        let pk_recip = <Kex as KeyExchange>::PublicKey::from_bytes(
+            &hex::decode("61ac165a79c974295b32c508719358ed407546172af79385237befb9f0360870")
+                .unwrap(),
+        )
+        .unwrap();
+        let sk_eph = <Kex as KeyExchange>::PrivateKey::from_bytes(
+            &hex::decode("3e6e0099ed0dbba3664e3d6f126f216cd115e6dbf38aea3055b0dc3e04d0239c")
+                .unwrap(),
+        )
+        .unwrap();
+        let enc = hex::decode("c8a9520417e8cb7aa606c36a5bef9f5d2db300b9ab9c908b4cb588f51418d351")
+            .unwrap();
+
+        let (ss, encapped_key, trace) =
+            encap_traced::<Kem>(&pk_recip, None, sk_eph.clone()).unwrap();
+        assert_eq!(encapped_key.to_bytes().as_slice(), enc.as_slice());
+        assert_eq!(
+            ss.into_bytes_dangerous().as_slice(),
+            hex::decode("033ca57fd76a73143519a19cd609fd2a2cf92b3926c10932802cf892dff579ad")
+                .unwrap()
+                .as_slice()
+        );
+
+        assert_eq!(trace.sk_eph, sk_eph.to_bytes().to_vec());
+        assert_eq!(trace.pk_eph, enc);
+        assert_eq!(
+            trace.dh,
+            Kex::kex(&sk_eph, &pk_recip).unwrap().to_bytes().to_vec()
+        );
+        assert_eq!(trace.kem_context, [&enc[..], &pk_recip.to_bytes()].concat());
+        assert_eq!(trace.eae_prk.len(), 32);
+        assert_eq!(trace.to_string().lines().count(), 5);
+        assert!(trace.to_string().starts_with("sk_eph: "));
+
+        // Tracing doesn't change the encap
+        let (ss2, enc2) = encap_deterministic::<Kem>(&pk_recip, None, sk_eph).unwrap();
+        assert_eq!(enc2.to_bytes(), encapped_key.to_bytes());
+        assert_eq!(
+            ss2.into_bytes_dangerous().as_slice(),
+            &hex::decode("033ca57fd76a73143519a19cd609fd2a2cf92b3926c10932802cf892dff579ad")
+                .unwrap()[..]
+        );
+    }
+
+    #[cfg(feature = "x25519-dalek")]
+    test_split_decap!(test_split_decap_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_split_decap!(test_split_decap_p256, crate::kem::DhP256HkdfSha256);
+}
@@ -0,0 +1,442 @@
+//! This module defines [`DatagramSender`] and [`DatagramReceiver`], which carry messages of any
+//! size over a datagram transport with a fixed MTU, e.g., UDP. The sender splits each message
+//! into fragments that each fit in one datagram, and seals each fragment under the next sequence
+//! number of its context. Every datagram names its sequence number, so the receiver can open
+//! datagrams in any order, and reassembles a message once all its fragments are in.
+//!
+//! Datagrams can be lost, duplicated, and reordered:
+//!
+//! * A repeated datagram is rejected by the receiver's windowed [`SeqGuard`]. So is any datagram
+//!   more than 63 sequence numbers behind the newest one it has opened, since the guard can't
+//!   tell whether it was already seen.
+//! * A message missing a fragment is never delivered. The receiver only keeps a bounded number
+//!   of partial messages, and drops the oldest one when it runs out of room. From then on, it
+//!   rejects the fragments of that message, and of any message that started before it, so that a
+//!   late fragment can't bring a dropped message back.
+//!
+//! The sender side is an ordinary [`AeadCtxS`] from [`setup_sender`](crate::setup_sender), and
+//! the receiver side is the matching [`AeadCtxR`]. The contexts mustn't be used for anything
+//! else, since every seal moves the sender's sequence number.
+//!
+//! Wire format
+//! ===========
+//! Every datagram is `seq || index || [count] || ciphertext || tag`. Here `seq` is the sequence
+//! number the fragment was sealed under, as a big-endian u64, and `index` is the position of the
+//! fragment in its message, as a big-endian u16. The fragments of a message have consecutive
+//! sequence numbers, so the message starts at `seq - index`. Only the first fragment, with index
+//! 0, has `count`, which is the number of fragments in the message as a big-endian u16.
+//! Everything before the ciphertext is the AAD.
+
+use crate::{
+    aead::{Aead, AeadCtxR, AeadCtxS, AeadTag, Stats},
+    kdf::Kdf as KdfTrait,
+    kem::Kem as KemTrait,
+    kex::{Deserializable, Serializable},
+    seq_guard::SeqGuard,
+    HpkeError,
+};
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::convert::TryFrom;
+
+// The sizes of the headers of the first fragment of a message, which has a count, and of the
+// rest, which don't
+const FIRST_HEADER_SIZE: usize = 8 + 2 + 2;
+const HEADER_SIZE: usize = 8 + 2;
+
+/// The sending side of a datagram transport. See the [module docs](self).
+pub struct DatagramSender<A: Aead, Kdf: KdfTrait, Kem: KemTrait> {
+    ctx: AeadCtxS<A, Kdf, Kem>,
+    mtu: usize,
+}
+
+impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> DatagramSender<A, Kdf, Kem> {
+    /// Makes a sender that seals with `ctx` and makes datagrams of at most `mtu` bytes
+    ///
+    /// Panics
+    /// ======
+    /// Panics if `mtu` leaves no room for data after the header and tag.
+    pub fn new(ctx: AeadCtxS<A, Kdf, Kem>, mtu: usize) -> Self {
+        assert!(
+            mtu > FIRST_HEADER_SIZE + AeadTag::<A>::size(),
+            "MTU is too small for a fragment"
+        );
+        DatagramSender { ctx, mtu }
+    }
+
+    /// Returns the maximum size of the datagrams this sender makes
+    pub fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    /// Returns the number of message bytes that fit in one datagram
+    pub fn fragment_capacity(&self) -> usize {
+        self.mtu - FIRST_HEADER_SIZE - AeadTag::<A>::size()
+    }
+
+    /// Splits `msg` into fragments and seals each one into a datagram, in order. An empty `msg`
+    /// still makes one datagram.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns the datagrams on success. If `msg` needs more than 65535 fragments, returns
+    /// `Err(HpkeError::BufferTooSmall)` without sealing anything. Otherwise, fails the same way as
+    /// [`AeadCtxS::seal`]. If sealing fails partway through, the sequence numbers of the fragments
+    /// sealed so far are used up.
+    pub fn send(&mut self, msg: &[u8]) -> Result<Vec<Vec<u8>>, HpkeError> {
+        let capacity = self.fragment_capacity();
+        let num_fragments = match msg.len() {
+            0 => 1,
+            len => (len - 1) / capacity + 1,
+        };
+        let count = u16::try_from(num_fragments).map_err(|_| HpkeError::BufferTooSmall)?;
+
+        let mut datagrams = Vec::with_capacity(num_fragments);
+        for index in 0..count {
+            let start = usize::from(index) * capacity;
+            let chunk = &msg[start.min(msg.len())..(start + capacity).min(msg.len())];
+
+            // header = seq || index || [count]
+            let mut datagram = Vec::with_capacity(self.mtu);
+            datagram.extend_from_slice(&self.ctx.next_seq().to_be_bytes());
+            datagram.extend_from_slice(&index.to_be_bytes());
+            if index == 0 {
+                datagram.extend_from_slice(&count.to_be_bytes());
+            }
+            let header_len = datagram.len();
+            datagram.extend_from_slice(chunk);
+
+            let (header, body) = datagram.split_at_mut(header_len);
+            let tag = self.ctx.seal(body, header)?;
+            datagram.extend_from_slice(&tag.to_bytes());
+            datagrams.push(datagram);
+        }
+
+        Ok(datagrams)
+    }
+}
+
+// A message that some, but not all, fragments have arrived for
+#[derive(Default)]
+struct Partial {
+    // The number of fragments, once the first one is in
+    count: Option<u16>,
+    // Maps the index of each fragment that's in to its plaintext
+    fragments: BTreeMap<u16, Vec<u8>>,
+}
+
+/// The receiving side of a datagram transport. See the [module docs](self).
+pub struct DatagramReceiver<A: Aead, Kdf: KdfTrait, Kem: KemTrait> {
+    ctx: AeadCtxR<A, Kdf, Kem>,
+    guard: SeqGuard,
+    max_partials: usize,
+    // Maps the sequence number of the first fragment of each partial message to the message
+    partials: BTreeMap<u64, Partial>,
+    // Messages whose first fragment's sequence number is below this were dropped, or started
+    // before one that was. Their fragments are rejected.
+    dropped_below: u64,
+}
+
+impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> DatagramReceiver<A, Kdf, Kem> {
+    /// Makes a receiver that opens with `ctx` and keeps at most `max_partials` partial messages
+    /// at once
+    ///
+    /// Panics
+    /// ======
+    /// Panics if `max_partials` is 0.
+    pub fn new(ctx: AeadCtxR<A, Kdf, Kem>, max_partials: usize) -> Self {
+        assert!(
+            max_partials > 0,
+            "a receiver needs room for a partial message"
+        );
+        DatagramReceiver {
+            ctx,
+            guard: SeqGuard::windowed(),
+            max_partials,
+            partials: BTreeMap::new(),
+            dropped_below: 0,
+        }
+    }
+
+    /// Returns the number of messages that some, but not all, fragments have arrived for
+    pub fn partials(&self) -> usize {
+        self.partials.len()
+    }
+
+    /// Returns the number of fragments and bytes this receiver's context has opened, and the
+    /// number of failed opens
+    pub fn stats(&self) -> Stats {
+        self.ctx.stats()
+    }
+
+    /// Opens a datagram made by [`DatagramSender::send`] on the other end
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(Some(msg))` if this datagram completes a message, and `Ok(None)` if the message
+    /// is still missing fragments. If `datagram` is too short, has a count of 0, or has an index
+    /// that's out of range for its message, returns `Err(HpkeError::InvalidEncoding)`. If its
+    /// sequence number was already seen, or is too far behind the newest one to tell, or if its
+    /// message was dropped or started before one that was, returns `Err(HpkeError::ReusedSeq)`.
+    /// Otherwise, fails the same way as [`AeadCtxR::open`]. A datagram that fails doesn't change
+    /// which datagrams are accepted later, but if it fails to open, that's counted in
+    /// [`stats`](Self::stats).
+    pub fn recv(&mut self, datagram: &[u8]) -> Result<Option<Vec<u8>>, HpkeError> {
+        let tag_size = AeadTag::<A>::size();
+        if datagram.len() < HEADER_SIZE + tag_size {
+            return Err(HpkeError::InvalidEncoding);
+        }
+        let mut seq_bytes = [0u8; 8];
+        seq_bytes.copy_from_slice(&datagram[..8]);
+        let seq = u64::from_be_bytes(seq_bytes);
+        let index = u16::from_be_bytes([datagram[8], datagram[9]]);
+
+        let header_len = if index == 0 {
+            FIRST_HEADER_SIZE
+        } else {
+            HEADER_SIZE
+        };
+        if datagram.len() < header_len + tag_size {
+            return Err(HpkeError::InvalidEncoding);
+        }
+        let first_seq = seq
+            .checked_sub(u64::from(index))
+            .ok_or(HpkeError::InvalidEncoding)?;
+        if self.guard.is_rejected(seq) || first_seq < self.dropped_below {
+            return Err(HpkeError::ReusedSeq);
+        }
+
+        let (header, rest) = datagram.split_at(header_len);
+        let (ciphertext, tag_bytes) = rest.split_at(rest.len() - tag_size);
+        let tag = AeadTag::<A>::from_bytes(tag_bytes)?;
+        let mut plaintext = ciphertext.to_vec();
+        self.ctx.open_at(seq, &mut plaintext, header, &tag)?;
+
+        let count = if index == 0 {
+            match u16::from_be_bytes([header[10], header[11]]) {
+                0 => return Err(HpkeError::InvalidEncoding),
+                count => Some(count),
+            }
+        } else {
+            None
+        };
+        if let Some(partial) = self.partials.get(&first_seq) {
+            let out_of_range = match count.or(partial.count) {
+                Some(count) => index >= count || partial.fragments.keys().any(|&i| i >= count),
+                None => false,
+            };
+            if out_of_range {
+                return Err(HpkeError::InvalidEncoding);
+            }
+        } else if count == Some(1) {
+            // A single-fragment message doesn't need reassembly
+            self.guard.check_and_insert(seq)?;
+            return Ok(Some(plaintext));
+        } else if matches!(count, Some(count) if index >= count) {
+            return Err(HpkeError::InvalidEncoding);
+        }
+
+        // The datagram checks out. Record it.
+        self.guard.check_and_insert(seq)?;
+        let partial = self.partials.entry(first_seq).or_default();
+        if count.is_some() {
+            partial.count = count;
+        }
+        partial.fragments.insert(index, plaintext);
+
+        if partial.count == Some(partial.fragments.len() as u16) {
+            let partial = self.partials.remove(&first_seq).unwrap();
+            return Ok(Some(
+                partial.fragments.values().flatten().copied().collect(),
+            ));
+        }
+
+        // Make room by dropping the message that started the longest ago
+        if self.partials.len() > self.max_partials {
+            let oldest = *self.partials.keys().next().unwrap();
+            self.partials.remove(&oldest);
+            self.dropped_below = oldest + 1;
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DatagramReceiver, DatagramSender};
+    use crate::{
+        aead::ChaCha20Poly1305, kdf::HkdfSha256, test_util::gen_ctx_simple_pair, HpkeError,
+    };
+
+    use alloc::vec::Vec;
+
+    /// Tests that messages split into fragments are put back together, whatever order the
+    /// datagrams arrive in
+    macro_rules! test_datagram_reassembly {
+        ($test_name:ident, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type A = ChaCha20Poly1305;
+                type Kdf = HkdfSha256;
+                type Kem = $kem;
+
+                let (sender_ctx, receiver_ctx) = gen_ctx_simple_pair::<A, Kdf, Kem>();
+                let mut sender = DatagramSender::new(sender_ctx, 64);
+                let mut receiver = DatagramReceiver::new(receiver_ctx, 4);
+
+                let msg1: Vec<u8> = (0..200u8).collect();
+                let msg2 = b"short".to_vec();
+                let datagrams1 = sender.send(&msg1).unwrap();
+                let datagrams2 = sender.send(&msg2).unwrap();
+                let empty = sender.send(b"").unwrap();
+                assert_eq!(datagrams1.len(), 200 / sender.fragment_capacity() + 1);
+                assert_eq!(datagrams2.len(), 1);
+                assert_eq!(empty.len(), 1);
+                assert!(datagrams1.iter().all(|d| d.len() <= sender.mtu()));
+
+                // The second message overtakes the first, whose fragments come in backwards
+                assert_eq!(receiver.recv(&datagrams2[0]).unwrap(), Some(msg2));
+                for datagram in datagrams1[1..].iter().rev() {
+                    assert_eq!(receiver.recv(datagram).unwrap(), None);
+                }
+                assert_eq!(receiver.partials(), 1);
+                assert_eq!(receiver.recv(&datagrams1[0]).unwrap(), Some(msg1));
+                assert_eq!(receiver.partials(), 0);
+                assert_eq!(receiver.recv(&empty[0]).unwrap(), Some(Vec::new()));
+
+                // Nothing is delivered twice
+                for datagram in datagrams1.iter().chain(&datagrams2) {
+                    assert!(matches!(receiver.recv(datagram), Err(HpkeError::ReusedSeq)));
+                }
+            }
+        };
+    }
+
+    /// Tests that tampered, truncated, and oversized inputs are rejected, and that partial
+    /// messages are dropped oldest first
+    macro_rules! test_datagram_errors {
+        ($test_name:ident, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type A = ChaCha20Poly1305;
+                type Kdf = HkdfSha256;
+                type Kem = $kem;
+
+                let (sender_ctx, receiver_ctx) = gen_ctx_simple_pair::<A, Kdf, Kem>();
+                let mut sender = DatagramSender::new(sender_ctx, 32);
+                let mut receiver = DatagramReceiver::new(receiver_ctx, 1);
+                assert_eq!(sender.fragment_capacity(), 4);
+
+                // The header is bound into the tag
+                let datagrams = sender.send(b"0123456789").unwrap();
+                for pos in 0..12 {
+                    let mut tampered = datagrams[0].clone();
+                    tampered[pos] ^= 1;
+                    assert!(receiver.recv(&tampered).is_err());
+                }
+                assert!(matches!(
+                    receiver.recv(&datagrams[1][..12]),
+                    Err(HpkeError::InvalidEncoding)
+                ));
+
+                // Only one partial message fits, so starting another drops the first
+                receiver.recv(&datagrams[0]).unwrap();
+                let others = sender.send(b"abcdefgh").unwrap();
+                receiver.recv(&others[0]).unwrap();
+                assert_eq!(receiver.partials(), 1);
+                assert!(matches!(
+                    receiver.recv(&datagrams[1]),
+                    Err(HpkeError::ReusedSeq)
+                ));
+                assert_eq!(receiver.partials(), 1);
+                assert_eq!(
+                    receiver.recv(&others[1]).unwrap(),
+                    Some(b"abcdefgh".to_vec())
+                );
+
+                // A message can have at most 65535 fragments
+                let too_big = vec![0u8; 4 * 65536];
+                assert!(matches!(
+                    sender.send(&too_big),
+                    Err(HpkeError::BufferTooSmall)
+                ));
+            }
+        };
+    }
+
+    /// Tests that once a partial message is dropped, fragments of it, and of messages that
+    /// started before it, are rejected without touching the receiver, and that a failed open is
+    /// counted but not recorded
+    macro_rules! test_datagram_late_fragments {
+        ($test_name:ident, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type A = ChaCha20Poly1305;
+                type Kdf = HkdfSha256;
+                type Kem = $kem;
+
+                let (sender_ctx, receiver_ctx) = gen_ctx_simple_pair::<A, Kdf, Kem>();
+                let mut sender = DatagramSender::new(sender_ctx, 32);
+                let mut receiver = DatagramReceiver::new(receiver_ctx, 1);
+
+                // Three messages of three fragments each, sent in this order
+                let early = sender.send(b"early msg...").unwrap();
+                let dropped = sender.send(b"dropped msg.").unwrap();
+                let kept = sender.send(b"kept message").unwrap();
+                assert!([&early, &dropped, &kept].iter().all(|d| d.len() == 3));
+
+                // Starting the third message drops the second
+                assert_eq!(receiver.recv(&dropped[0]).unwrap(), None);
+                assert_eq!(receiver.recv(&kept[2]).unwrap(), None);
+                assert_eq!(receiver.partials(), 1);
+
+                // The rest of the dropped message, and all of the one before it, are turned away
+                // before they're opened
+                let stats = receiver.stats();
+                for datagram in dropped[1..].iter().chain(&early) {
+                    assert!(matches!(receiver.recv(datagram), Err(HpkeError::ReusedSeq)));
+                    assert_eq!(receiver.partials(), 1);
+                }
+                assert_eq!(receiver.stats(), stats);
+
+                // A datagram that fails to open is counted, but its seq can still be used
+                let mut tampered = kept[1].clone();
+                *tampered.last_mut().unwrap() ^= 1;
+                assert!(matches!(
+                    receiver.recv(&tampered),
+                    Err(HpkeError::InvalidTag)
+                ));
+                assert_eq!(receiver.stats().failures, stats.failures + 1);
+                assert_eq!(receiver.recv(&kept[1]).unwrap(), None);
+                assert_eq!(
+                    receiver.recv(&kept[0]).unwrap(),
+                    Some(b"kept message".to_vec())
+                );
+            }
+        };
+    }
+
+    #[cfg(feature = "x25519-dalek")]
+    test_datagram_reassembly!(
+        test_datagram_reassembly_x25519,
+        crate::kem::X25519HkdfSha256
+    );
+    #[cfg(feature = "p256")]
+    test_datagram_reassembly!(test_datagram_reassembly_p256, crate::kem::DhP256HkdfSha256);
+
+    #[cfg(feature = "x25519-dalek")]
+    test_datagram_errors!(test_datagram_errors_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_datagram_errors!(test_datagram_errors_p256, crate::kem::DhP256HkdfSha256);
+
+    #[cfg(feature = "x25519-dalek")]
+    test_datagram_late_fragments!(
+        test_datagram_late_fragments_x25519,
+        crate::kem::X25519HkdfSha256
+    );
+    #[cfg(feature = "p256")]
+    test_datagram_late_fragments!(
+        test_datagram_late_fragments_p256,
+        crate::kem::DhP256HkdfSha256
+    );
+}
@@ -0,0 +1,245 @@
+//! This module defines hooks for monitoring HPKE usage. Install an [`EventSink`] with
+//! [`set_event_sink`], and every context setup, seal, open, and failure in the process gets
+//! reported to it as an [`Event`]. This lets you count and alert on HPKE usage without wrapping
+//! every call site.
+//!
+//! Events never contain key material, plaintexts, or PSKs. The info string and PSK ID are reported
+//! as SHA-256 hashes, so that they can be correlated without being logged.
+
+use crate::{aead::Aead, kdf::Kdf as KdfTrait, kem::Kem as KemTrait, HpkeError};
+
+use std::sync::{PoisonError, RwLock};
+
+use digest::Digest;
+use sha2::Sha256;
+
+/// Which side of the conversation an event happened on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Sender,
+    Receiver,
+}
+
+/// The operation that an [`Event::Failure`] happened in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    Setup,
+    Seal,
+    Open,
+    Export,
+}
+
+/// The algorithm IDs of a ciphersuite
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SuiteIds {
+    pub kem_id: u16,
+    pub kdf_id: u16,
+    pub aead_id: u16,
+}
+
+impl SuiteIds {
+    pub(crate) fn of<A: Aead, Kdf: KdfTrait, Kem: KemTrait>() -> Self {
+        SuiteIds {
+            kem_id: Kem::KEM_ID,
+            kdf_id: Kdf::KDF_ID,
+            aead_id: A::AEAD_ID,
+        }
+    }
+}
+
+/// Something that happened in this crate
+#[derive(Clone, Copy, Debug)]
+pub enum Event {
+    /// A sender or receiver context was set up
+    Setup {
+        role: Role,
+        suite: SuiteIds,
+        /// The mode ID, as defined in the spec. 0 is Base, 1 is Psk, 2 is Auth, 3 is AuthPsk.
+        mode_id: u8,
+        /// The SHA-256 hash of the PSK ID. The PSK ID is empty in non-PSK modes.
+        psk_id_hash: [u8; 32],
+        /// The SHA-256 hash of the info string
+        info_hash: [u8; 32],
+    },
+    /// A plaintext was sealed
+    Seal {
+        suite: SuiteIds,
+        plaintext_len: usize,
+    },
+    /// A ciphertext was opened
+    Open {
+        suite: SuiteIds,
+        ciphertext_len: usize,
+    },
+    /// An operation failed
+    Failure {
+        role: Role,
+        op: Operation,
+        suite: SuiteIds,
+        error: HpkeError,
+    },
+}
+
+/// Receives [`Event`]s. Implementations should be quick, since they're called inline with every
+/// operation.
+pub trait EventSink: Send + Sync {
+    fn on_event(&self, event: &Event);
+}
+
+static EVENT_SINK: RwLock<Option<&'static dyn EventSink>> = RwLock::new(None);
+
+/// Installs the given sink, replacing any previously installed one. From now on, all events in
+/// this process are sent to `sink`.
+pub fn set_event_sink(sink: &'static dyn EventSink) {
+    *EVENT_SINK.write().unwrap_or_else(PoisonError::into_inner) = Some(sink);
+}
+
+/// Uninstalls the current sink, if any
+pub fn clear_event_sink() {
+    *EVENT_SINK.write().unwrap_or_else(PoisonError::into_inner) = None;
+}
+
+/// Reports the outcome of an operation. On failure, this sends an [`Event::Failure`]. On success,
+/// this sends whatever `on_success` returns, if anything.
+pub(crate) fn emit_result<A, Kdf, Kem, T, F>(
+    res: &Result<T, HpkeError>,
+    role: Role,
+    op: Operation,
+    on_success: F,
+) where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    F: FnOnce(SuiteIds) -> Option<Event>,
+{
+    let sink = *EVENT_SINK.read().unwrap_or_else(PoisonError::into_inner);
+    if let Some(sink) = sink {
+        let suite = SuiteIds::of::<A, Kdf, Kem>();
+        let event = match res {
+            Ok(_) => on_success(suite),
+            Err(error) => Some(Event::Failure {
+                role,
+                op,
+                suite,
+                error: *error,
+            }),
+        };
+        if let Some(event) = event {
+            sink.on_event(&event);
+        }
+    }
+}
+
+/// Returns the SHA-256 hash of the given bytes
+pub(crate) fn hash(bytes: &[u8]) -> [u8; 32] {
+    let mut buf = [0u8; 32];
+    buf.copy_from_slice(&Sha256::digest(bytes));
+    buf
+}
+
+#[cfg(test)]
+mod test {
+    use super::{clear_event_sink, hash, set_event_sink, Event, EventSink, Operation, Role};
+    use crate::{
+        aead::{AeadTag, ChaCha20Poly1305},
+        kdf::HkdfSha256,
+        kem::Kem as KemTrait,
+        op_mode::{OpModeR, OpModeS},
+        setup::{setup_receiver, setup_sender},
+        Deserializable, HpkeError,
+    };
+
+    use std::{sync::Mutex, vec::Vec};
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    struct RecordingSink(Mutex<Vec<Event>>);
+
+    impl EventSink for RecordingSink {
+        fn on_event(&self, event: &Event) {
+            self.0.lock().unwrap().push(*event);
+        }
+    }
+
+    static SINK: RecordingSink = RecordingSink(Mutex::new(Vec::new()));
+
+    /// Tests that setup, seal, open, and failures are reported. Other tests run concurrently and
+    /// also report to the sink, so this only looks for events with its own info hash and lengths.
+    #[cfg(feature = "x25519-dalek")]
+    #[test]
+    fn test_events() {
+        type A = ChaCha20Poly1305;
+        type Kdf = HkdfSha256;
+        type Kem = crate::kem::X25519HkdfSha256;
+
+        // An unusual length, so we can spot our own seal/open events
+        const MSG_LEN: usize = 1723;
+        let info = b"test_events info string";
+        let mut csprng = StdRng::from_entropy();
+        let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+
+        set_event_sink(&SINK);
+
+        let (encapped_key, mut sender_ctx) =
+            setup_sender::<A, Kdf, Kem, _>(&OpModeS::Base, &pk_recip, info, &mut csprng).unwrap();
+        let mut receiver_ctx =
+            setup_receiver::<A, Kdf, Kem>(&OpModeR::Base, &sk_recip, &encapped_key, info).unwrap();
+
+        let mut msg = [0u8; MSG_LEN];
+        let tag = sender_ctx.seal(&mut msg, b"").unwrap();
+        receiver_ctx.open(&mut msg, b"", &tag).unwrap();
+
+        // Open with a bad tag
+        let bad_tag = AeadTag::<A>::from_bytes(&[0u8; 16]).unwrap();
+        assert!(receiver_ctx.open(&mut msg, b"", &bad_tag).is_err());
+
+        clear_event_sink();
+
+        let events = SINK.0.lock().unwrap();
+        let info_hash = hash(info);
+        let setups: Vec<_> = events
+            .iter()
+            .filter_map(|e| match e {
+                Event::Setup {
+                    role,
+                    suite,
+                    mode_id,
+                    psk_id_hash,
+                    info_hash: h,
+                } if h == &info_hash => Some((*role, *suite, *mode_id, *psk_id_hash)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(setups.len(), 2);
+        assert!(setups.iter().any(|s| s.0 == Role::Sender));
+        assert!(setups.iter().any(|s| s.0 == Role::Receiver));
+        for (_, suite, mode_id, psk_id_hash) in setups {
+            assert_eq!(suite.kem_id, Kem::KEM_ID);
+            assert_eq!(mode_id, 0);
+            assert_eq!(psk_id_hash, hash(b""));
+        }
+        assert!(events.iter().any(|e| matches!(
+            e,
+            Event::Seal {
+                plaintext_len: MSG_LEN,
+                ..
+            }
+        )));
+        assert!(events.iter().any(|e| matches!(
+            e,
+            Event::Open {
+                ciphertext_len: MSG_LEN,
+                ..
+            }
+        )));
+        assert!(events.iter().any(|e| matches!(
+            e,
+            Event::Failure {
+                role: Role::Receiver,
+                op: Operation::Open,
+                error: HpkeError::InvalidTag,
+                ..
+            }
+        )));
+    }
+}
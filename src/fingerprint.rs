@@ -0,0 +1,171 @@
+//! This module defines public key fingerprints. A fingerprint is a short, stable identifier for a
+//! public key, suitable for putting in logs, key configs, and UIs.
+
+use crate::{
+    kex::{Deserializable, Serializable},
+    HpkeError,
+};
+
+use core::fmt;
+
+use digest::Digest;
+use generic_array::{typenum, GenericArray};
+use sha2::Sha256;
+
+// Fingerprints are bound to this string rather than the HPKE version label, so that fingerprints
+// stored in configs don't change when the crate moves to a new draft
+const FINGERPRINT_DOMAIN: &[u8] = b"rust-hpke pubkey fingerprint";
+
+/// Implemented by public key types. This lets you compute the fingerprint of a key.
+pub trait Fingerprintable: Serializable {
+    /// The name of the key's algorithm. This goes into the fingerprint, so that keys of different
+    /// algorithms never share a fingerprint, even if their serializations happen to coincide.
+    #[doc(hidden)]
+    const FINGERPRINT_LABEL: &'static [u8];
+
+    /// Computes the fingerprint of this key. This is
+    /// `SHA-256(domain || I2OSP(len(label), 1) || label || serialized_key)` where `label` is the
+    /// name of the key's algorithm.
+    fn fingerprint(&self) -> Fingerprint {
+        let mut hasher = Sha256::new();
+        hasher.update(FINGERPRINT_DOMAIN);
+        hasher.update([Self::FINGERPRINT_LABEL.len() as u8]);
+        hasher.update(Self::FINGERPRINT_LABEL);
+        hasher.update(self.to_bytes());
+
+        let mut buf = [0u8; 32];
+        buf.copy_from_slice(&hasher.finalize());
+        Fingerprint(buf)
+    }
+}
+
+/// The SHA-256 fingerprint of a public key. Its `Display` impl prints the whole thing as lowercase
+/// hex. For something more readable, see [`Fingerprint::short`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Fingerprint([u8; 32]);
+
+impl Fingerprint {
+    /// Returns the raw bytes of this fingerprint
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// Returns a human-readable prefix of this fingerprint. Its `Display` impl prints the first 8
+    /// bytes as lowercase hex in groups of 4 digits, e.g., `3f2a-91c0-5be7-0d44`. This is meant for
+    /// people to eyeball, not for uniquely identifying keys.
+    pub fn short(&self) -> ShortFingerprint {
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(&self.0[..8]);
+        ShortFingerprint(buf)
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for b in self.0.iter() {
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+impl Serializable for Fingerprint {
+    type OutputSize = typenum::U32;
+
+    fn to_bytes(&self) -> GenericArray<u8, typenum::U32> {
+        GenericArray::clone_from_slice(&self.0)
+    }
+}
+
+impl Deserializable for Fingerprint {
+    fn from_bytes(encoded: &[u8]) -> Result<Self, HpkeError> {
+        if encoded.len() != Self::size() {
+            return Err(HpkeError::InvalidEncoding);
+        }
+
+        let mut buf = [0u8; 32];
+        buf.copy_from_slice(encoded);
+        Ok(Fingerprint(buf))
+    }
+}
+
+/// The short, human-readable form of a [`Fingerprint`]. See [`Fingerprint::short`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ShortFingerprint([u8; 8]);
+
+impl fmt::Display for ShortFingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, pair) in self.0.chunks(2).enumerate() {
+            if i > 0 {
+                f.write_str("-")?;
+            }
+            write!(f, "{:02x}{:02x}", pair[0], pair[1])?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Fingerprintable;
+    use crate::{kex::KeyExchange, test_util::kex_gen_keypair, Deserializable, Serializable};
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Tests that fingerprints are deterministic, distinguish keys, and survive serialization
+    macro_rules! test_fingerprint_correctness {
+        ($test_name:ident, $kex:ty) => {
+            #[test]
+            fn $test_name() {
+                type Kex = $kex;
+                let mut csprng = StdRng::from_entropy();
+                let (_, pk1) = kex_gen_keypair::<Kex, _>(&mut csprng);
+                let (_, pk2) = kex_gen_keypair::<Kex, _>(&mut csprng);
+
+                let pk1_copy =
+                    <Kex as KeyExchange>::PublicKey::from_bytes(&pk1.to_bytes()).unwrap();
+                assert_eq!(pk1.fingerprint(), pk1_copy.fingerprint());
+                assert_ne!(pk1.fingerprint(), pk2.fingerprint());
+
+                let fpr = pk1.fingerprint();
+                let fpr_copy = super::Fingerprint::from_bytes(&fpr.to_bytes()).unwrap();
+                assert_eq!(fpr, fpr_copy);
+            }
+        };
+    }
+
+    #[cfg(feature = "x25519-dalek")]
+    test_fingerprint_correctness!(
+        test_fingerprint_correctness_x25519,
+        crate::kex::x25519::X25519
+    );
+    #[cfg(feature = "p256")]
+    test_fingerprint_correctness!(
+        test_fingerprint_correctness_p256,
+        crate::kex::ecdh_nistp::DhP256
+    );
+    #[cfg(feature = "k256")]
+    test_fingerprint_correctness!(
+        test_fingerprint_correctness_k256,
+        crate::kex::secp256k1::DhK256
+    );
+
+    /// Tests the fingerprint of a fixed key, so that fingerprints don't silently change between
+    /// versions. The key is the X25519 key from RFC 8037 §A.6.
+    #[cfg(feature = "x25519-dalek")]
+    #[test]
+    fn test_fingerprint_stability() {
+        use crate::kex::x25519::PublicKey;
+
+        let pk_bytes =
+            hex::decode("de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f")
+                .unwrap();
+        let fpr = PublicKey::from_bytes(&pk_bytes).unwrap().fingerprint();
+
+        assert_eq!(
+            format!("{}", fpr),
+            "eed3b713ec9da008f8ad5035cfe1ade9bfd7508ca5098b2d3bd29a1c6e04feb9"
+        );
+        assert_eq!(format!("{}", fpr.short()), "eed3-b713-ec9d-a008");
+    }
+}
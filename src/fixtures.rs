@@ -0,0 +1,186 @@
+//! This module has deterministic fixtures for the tests of crates built on this one: keypairs,
+//! pairs of agreeing encryption contexts, and pairs of agreeing op modes. Every fixture is made
+//! from a `u64` seed, so a failing test can be rerun on the exact same keys. The same seed always
+//! gives the same fixture, for a given version of this crate and of `rand`. Different seeds give
+//! independent ones.
+//!
+//! The keys made here come from seeds that anyone can guess, so this module is only for tests. It
+//! needs the `fixtures` feature, which should only ever be turned on in `[dev-dependencies]`.
+
+use crate::{
+    aead::{Aead, AeadCtx, AeadCtxR, AeadCtxS, AeadKey, AeadNonce},
+    kdf::Kdf as KdfTrait,
+    kem::Kem as KemTrait,
+    kex::{KeyExchange, Serializable},
+    op_mode::{OpModeR, OpModeS, PskBundle},
+    setup::ExporterSecret,
+};
+
+use generic_array::GenericArray;
+use rand::{rngs::StdRng, CryptoRng, RngCore, SeedableRng};
+
+/// Returns an RNG whose output is determined by `seed`
+pub fn seeded_rng(seed: u64) -> StdRng {
+    StdRng::seed_from_u64(seed)
+}
+
+/// Generates a keypair from the given seed
+pub fn gen_keypair<Kem: KemTrait>(
+    seed: u64,
+) -> (
+    <Kem::Kex as KeyExchange>::PrivateKey,
+    <Kem::Kex as KeyExchange>::PublicKey,
+) {
+    Kem::gen_keypair(&mut seeded_rng(seed))
+}
+
+/// Generates a keypair without the need of a KEM
+pub fn kex_gen_keypair<Kex: KeyExchange, R: CryptoRng + RngCore>(
+    csprng: &mut R,
+) -> (Kex::PrivateKey, Kex::PublicKey) {
+    // Make some keying material that's the size of a private key
+    let mut ikm: GenericArray<u8, <Kex::PrivateKey as Serializable>::OutputSize> =
+        GenericArray::default();
+    // Fill it with randomness
+    csprng.fill_bytes(&mut ikm);
+    // Run derive_keypair with a nonsense ciphersuite. We use SHA-256 because every KEM enables it.
+    Kex::derive_keypair::<crate::kdf::HkdfSha256>(b"31337", &ikm)
+}
+
+/// Creates a pair of agreeing contexts from the given seed, without doing a key exchange. Their
+/// keys, nonces, and exporter secrets come straight from the seed.
+pub fn gen_ctx_simple_pair<A, Kdf, Kem>(seed: u64) -> (AeadCtxS<A, Kdf, Kem>, AeadCtxR<A, Kdf, Kem>)
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    let mut csprng = seeded_rng(seed);
+
+    // Initialize the key and nonce
+    let key = {
+        let mut buf = AeadKey::<A>::default();
+        csprng.fill_bytes(buf.as_mut_slice());
+        buf
+    };
+    let nonce = {
+        let mut buf = AeadNonce::<A>::default();
+        csprng.fill_bytes(buf.as_mut_slice());
+        buf
+    };
+    let exporter_secret = {
+        let mut buf = ExporterSecret::<Kdf>::default();
+        csprng.fill_bytes(buf.as_mut_slice());
+        buf
+    };
+
+    let ctx1 = AeadCtx::new(key.clone(), nonce.clone(), exporter_secret.clone());
+    let ctx2 = AeadCtx::new(key, nonce, exporter_secret);
+
+    (ctx1.into(), ctx2.into())
+}
+
+/// The variants of [`OpModeS`] and [`OpModeR`], for picking which pair [`new_op_mode_pair`] makes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OpModeKind {
+    /// No PSK and no sender key
+    Base,
+    /// A sender key
+    Auth,
+    /// A PSK
+    Psk,
+    /// A sender key and a PSK
+    AuthPsk,
+}
+
+/// Makes an agreeing pair of `OpMode`s of the specified variant. In the authenticated modes, the
+/// sender's keypair comes from the given seed.
+pub fn new_op_mode_pair<'a, Kex: KeyExchange>(
+    kind: OpModeKind,
+    psk: &'a [u8],
+    psk_id: &'a [u8],
+    seed: u64,
+) -> (OpModeS<'a, Kex>, OpModeR<'a, Kex>) {
+    let (sk_sender, pk_sender) = kex_gen_keypair::<Kex, _>(&mut seeded_rng(seed));
+    let psk_bundle = PskBundle { psk, psk_id };
+
+    match kind {
+        OpModeKind::Base => {
+            let sender_mode = OpModeS::Base;
+            let receiver_mode = OpModeR::Base;
+            (sender_mode, receiver_mode)
+        }
+        OpModeKind::Psk => {
+            let sender_mode = OpModeS::Psk(psk_bundle);
+            let receiver_mode = OpModeR::Psk(psk_bundle);
+            (sender_mode, receiver_mode)
+        }
+        OpModeKind::Auth => {
+            let sender_mode = OpModeS::Auth((sk_sender, pk_sender.clone()));
+            let receiver_mode = OpModeR::Auth(pk_sender);
+            (sender_mode, receiver_mode)
+        }
+        OpModeKind::AuthPsk => {
+            let sender_mode = OpModeS::AuthPsk((sk_sender, pk_sender.clone()), psk_bundle);
+            let receiver_mode = OpModeR::AuthPsk(pk_sender, psk_bundle);
+            (sender_mode, receiver_mode)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{gen_ctx_simple_pair, gen_keypair, new_op_mode_pair, OpModeKind};
+    use crate::{kex::Serializable, op_mode::OpModeR};
+
+    /// Tests that the same seed gives the same fixtures, and that different seeds don't
+    macro_rules! test_fixtures_deterministic {
+        ($test_name:ident, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type A = crate::aead::ChaCha20Poly1305;
+                type Kdf = crate::kdf::HkdfSha256;
+                type Kem = $kem;
+                type Kex = <Kem as crate::kem::Kem>::Kex;
+
+                let (_, pk1) = gen_keypair::<Kem>(1);
+                let (_, pk1_again) = gen_keypair::<Kem>(1);
+                let (_, pk2) = gen_keypair::<Kem>(2);
+                assert_eq!(pk1.to_bytes(), pk1_again.to_bytes());
+                assert_ne!(pk1.to_bytes(), pk2.to_bytes());
+
+                // Contexts from the same seed agree, even across calls
+                let (mut sender_ctx, _) = gen_ctx_simple_pair::<A, Kdf, Kem>(1);
+                let (_, mut receiver_ctx) = gen_ctx_simple_pair::<A, Kdf, Kem>(1);
+                let mut msg = *b"same seed";
+                let tag = sender_ctx.seal(&mut msg, b"").unwrap();
+                receiver_ctx.open(&mut msg, b"", &tag).unwrap();
+                assert_eq!(&msg, b"same seed");
+
+                let (_, mut other_ctx) = gen_ctx_simple_pair::<A, Kdf, Kem>(2);
+                let tag = sender_ctx.seal(&mut msg, b"").unwrap();
+                assert!(other_ctx.open(&mut msg, b"", &tag).is_err());
+
+                // The sender's key in the authenticated modes comes from the seed
+                let sender_pk =
+                    |seed| match new_op_mode_pair::<Kex>(OpModeKind::Auth, b"", b"", seed).1 {
+                        OpModeR::Auth(pk) => pk.to_bytes(),
+                        _ => unreachable!(),
+                    };
+                assert_eq!(sender_pk(1), sender_pk(1));
+                assert_ne!(sender_pk(1), sender_pk(2));
+            }
+        };
+    }
+
+    #[cfg(feature = "x25519-dalek")]
+    test_fixtures_deterministic!(
+        test_fixtures_deterministic_x25519,
+        crate::kem::X25519HkdfSha256
+    );
+    #[cfg(feature = "p256")]
+    test_fixtures_deterministic!(
+        test_fixtures_deterministic_p256,
+        crate::kem::DhP256HkdfSha256
+    );
+}
@@ -0,0 +1,277 @@
+//! This module defines [`MessageHeader`], an optional header that says which recipient key a
+//! message was encrypted to. A recipient who rotates keys publishes each public key under a fresh
+//! key ID and keeps the old private keys in a [`Keyring`] until their messages stop arriving. The
+//! sender puts the key ID in the header alongside the encapsulated key, and the recipient uses it
+//! to pick the right private key, so there's no need to trial-decrypt or to cut over all senders
+//! at once.
+//!
+//! The key ID length is fixed per deployment, e.g., OHTTP uses 1-byte key IDs. Both sides have to
+//! agree on it ahead of time, since it isn't encoded in the header.
+//!
+//! The key ID isn't authenticated by the ciphertext. Changing it can only make the recipient pick
+//! the wrong key, which makes decryption fail.
+
+use crate::{
+    aead::{Aead, AeadTag},
+    kdf::Kdf as KdfTrait,
+    kem::{EncappedKey, Kem as KemTrait},
+    kex::{Deserializable, KeyExchange, Serializable},
+    keyring::Keyring,
+    op_mode::{OpModeR, OpModeS},
+    single_shot::{single_shot_open, single_shot_seal},
+    HpkeError,
+};
+
+use alloc::vec::Vec;
+use rand::{CryptoRng, RngCore};
+
+/// A message header consisting of a recipient key ID and an encapsulated key. Its serialization
+/// is `key_id || enc`.
+pub struct MessageHeader<Kem: KemTrait> {
+    key_id: Vec<u8>,
+    encapped_key: EncappedKey<Kem>,
+}
+
+impl<Kem: KemTrait> MessageHeader<Kem> {
+    /// Makes a header out of the given key ID and encapsulated key
+    pub fn new(key_id: &[u8], encapped_key: EncappedKey<Kem>) -> Self {
+        MessageHeader {
+            key_id: key_id.to_vec(),
+            encapped_key,
+        }
+    }
+
+    /// Returns the ID of the recipient key that this message was encrypted to
+    pub fn key_id(&self) -> &[u8] {
+        &self.key_id
+    }
+
+    /// Returns the encapsulated key of this message
+    pub fn encapped_key(&self) -> &EncappedKey<Kem> {
+        &self.encapped_key
+    }
+
+    /// Returns the size (in bytes) of a serialized header whose key ID is `key_id_len` bytes long
+    pub fn size(key_id_len: usize) -> usize {
+        key_id_len + EncappedKey::<Kem>::size()
+    }
+
+    /// Serializes this header as `key_id || enc`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(Self::size(self.key_id.len()));
+        out.extend_from_slice(&self.key_id);
+        out.extend_from_slice(&self.encapped_key.to_bytes());
+        out
+    }
+
+    /// Like [`to_bytes`](Self::to_bytes), but serializes into a [`heapless::Vec`] with capacity
+    /// `N`
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::BufferTooSmall)` if the serialized header is longer than `N`.
+    #[cfg(feature = "heapless")]
+    pub fn to_heapless<const N: usize>(&self) -> Result<heapless::Vec<u8, N>, HpkeError> {
+        let mut out = heapless::Vec::new();
+        out.extend_from_slice(&self.key_id)
+            .and_then(|_| out.extend_from_slice(&self.encapped_key.to_bytes()))
+            .map_err(|_| HpkeError::BufferTooSmall)?;
+        Ok(out)
+    }
+
+    /// Deserializes a header whose key ID is `key_id_len` bytes long. `encoded` must be exactly
+    /// `MessageHeader::size(key_id_len)` bytes, otherwise this returns
+    /// `Err(HpkeError::InvalidEncoding)`.
+    pub fn from_bytes(encoded: &[u8], key_id_len: usize) -> Result<Self, HpkeError> {
+        if encoded.len() != Self::size(key_id_len) {
+            return Err(HpkeError::InvalidEncoding);
+        }
+
+        let (key_id, enc) = encoded.split_at(key_id_len);
+        Ok(MessageHeader::new(key_id, EncappedKey::from_bytes(enc)?))
+    }
+}
+
+/// Does a [`single_shot_seal`] to the recipient public key with the given key ID, and returns a
+/// header carrying that key ID and the encapsulated key, along with the tag.
+pub fn single_shot_seal_with_header<A, Kdf, Kem, R>(
+    mode: &OpModeS<Kem::Kex>,
+    key_id: &[u8],
+    pk_recip: &<Kem::Kex as KeyExchange>::PublicKey,
+    info: &[u8],
+    plaintext: &mut [u8],
+    aad: &[u8],
+    csprng: &mut R,
+) -> Result<(MessageHeader<Kem>, AeadTag<A>), HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    R: CryptoRng + RngCore,
+{
+    let (encapped_key, tag) =
+        single_shot_seal::<A, Kdf, Kem, R>(mode, pk_recip, info, plaintext, aad, csprng)?;
+    Ok((MessageHeader::new(key_id, encapped_key), tag))
+}
+
+/// Does a [`single_shot_open`] using the private key in `keyring` whose ID is in the header.
+///
+/// Return Value
+/// ============
+/// Returns `Err(HpkeError::UnknownKeyId)` if the keyring has no private key under the header's
+/// key ID. Otherwise this behaves like [`single_shot_open`].
+pub fn single_shot_open_with_header<A, Kdf, Kem>(
+    mode: &OpModeR<Kem::Kex>,
+    keyring: &Keyring<Kem>,
+    header: &MessageHeader<Kem>,
+    info: &[u8],
+    ciphertext: &mut [u8],
+    aad: &[u8],
+    tag: &AeadTag<A>,
+) -> Result<(), HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    let sk_recip = keyring
+        .get(header.key_id())
+        .and_then(|entry| entry.private_key())
+        .ok_or(HpkeError::UnknownKeyId)?;
+    single_shot_open::<A, Kdf, Kem>(
+        mode,
+        sk_recip,
+        header.encapped_key(),
+        info,
+        ciphertext,
+        aad,
+        tag,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::{single_shot_open_with_header, single_shot_seal_with_header, MessageHeader};
+    use crate::{
+        aead::ChaCha20Poly1305, kdf::HkdfSha256, kem::Kem as KemTrait, keyring::Keyring,
+        op_mode::OpModeR, op_mode::OpModeS, HpkeError,
+    };
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Tests that a recipient with several keys opens messages sent to any of them, including
+    /// after the header goes over the wire, and rejects messages to keys it doesn't have
+    macro_rules! test_header_rotation {
+        ($test_name:ident, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type A = ChaCha20Poly1305;
+                type Kdf = HkdfSha256;
+                type Kem = $kem;
+                let mut csprng = StdRng::from_entropy();
+
+                // The recipient has an old key and a new key. It also knows a public key it has
+                // no private key for.
+                let (sk_old, pk_old) = Kem::gen_keypair(&mut csprng);
+                let (sk_new, pk_new) = Kem::gen_keypair(&mut csprng);
+                let (_, pk_other) = Kem::gen_keypair(&mut csprng);
+                let mut keyring = Keyring::<Kem>::new();
+                keyring.insert_private_key(&[1], sk_old);
+                keyring.insert_private_key(&[2], sk_new);
+                keyring.insert_public_key(&[3], pk_other.clone());
+
+                let msg = b"rotate your keys";
+                let aad = b"every now and then";
+                let info = b"header test";
+
+                for (key_id, pk) in [([1u8], &pk_old), ([2u8], &pk_new)].iter() {
+                    let mut ciphertext = *msg;
+                    let (header, tag) = single_shot_seal_with_header::<A, Kdf, Kem, _>(
+                        &OpModeS::Base,
+                        key_id,
+                        pk,
+                        info,
+                        &mut ciphertext,
+                        aad,
+                        &mut csprng,
+                    )
+                    .unwrap();
+
+                    // Send the header over the wire
+                    let header_bytes = header.to_bytes();
+                    assert_eq!(header_bytes.len(), MessageHeader::<Kem>::size(1));
+                    assert_eq!(header_bytes[0], key_id[0]);
+                    #[cfg(feature = "heapless")]
+                    {
+                        let heapless_bytes = header.to_heapless::<128>().unwrap();
+                        assert_eq!(&heapless_bytes[..], &header_bytes[..]);
+                        let res = header.to_heapless::<1>();
+                        assert!(matches!(res, Err(HpkeError::BufferTooSmall)));
+                    }
+                    let header = MessageHeader::<Kem>::from_bytes(&header_bytes, 1).unwrap();
+                    assert_eq!(header.key_id(), key_id);
+
+                    single_shot_open_with_header::<A, Kdf, Kem>(
+                        &OpModeR::Base,
+                        &keyring,
+                        &header,
+                        info,
+                        &mut ciphertext,
+                        aad,
+                        &tag,
+                    )
+                    .unwrap();
+                    assert_eq!(&ciphertext, msg);
+                }
+
+                // Messages to unknown keys and to public-only keys can't be opened
+                for key_id in [[3u8], [4u8]].iter() {
+                    let mut ciphertext = *msg;
+                    let (header, tag) = single_shot_seal_with_header::<A, Kdf, Kem, _>(
+                        &OpModeS::Base,
+                        key_id,
+                        &pk_other,
+                        info,
+                        &mut ciphertext,
+                        aad,
+                        &mut csprng,
+                    )
+                    .unwrap();
+                    let res = single_shot_open_with_header::<A, Kdf, Kem>(
+                        &OpModeR::Base,
+                        &keyring,
+                        &header,
+                        info,
+                        &mut ciphertext,
+                        aad,
+                        &tag,
+                    );
+                    assert!(matches!(res, Err(HpkeError::UnknownKeyId)));
+                }
+
+                // Headers of the wrong length don't parse
+                let (_, pk) = Kem::gen_keypair(&mut csprng);
+                let mut ciphertext = *msg;
+                let (header, _) = single_shot_seal_with_header::<A, Kdf, Kem, _>(
+                    &OpModeS::Base,
+                    b"abc",
+                    &pk,
+                    info,
+                    &mut ciphertext,
+                    aad,
+                    &mut csprng,
+                )
+                .unwrap();
+                let header_bytes = header.to_bytes();
+                assert!(MessageHeader::<Kem>::from_bytes(&header_bytes, 3).is_ok());
+                assert!(MessageHeader::<Kem>::from_bytes(&header_bytes, 2).is_err());
+                assert!(MessageHeader::<Kem>::from_bytes(&header_bytes[1..], 3).is_err());
+            }
+        };
+    }
+
+    #[cfg(feature = "x25519-dalek")]
+    test_header_rotation!(test_header_rotation_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_header_rotation!(test_header_rotation_p256, crate::kem::DhP256HkdfSha256);
+}
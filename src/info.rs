@@ -0,0 +1,115 @@
+//! This module defines [`InfoBuilder`], which encodes an info string out of a protocol name and a
+//! sequence of fields. Two applications that use the same recipient key with different info
+//! strings can't decrypt each other's messages, but only if their info strings really are
+//! different. Ad hoc strings like `"v1"` or `"encrypt"` collide easily, and gluing fields together
+//! with plain concatenation is ambiguous. The builder starts every info string with the protocol
+//! name, and length-prefixes everything, so applications with different names never produce the
+//! same info.
+//!
+//! The [`protocols`] module has the labels that some published protocols put in their info
+//! strings. Those protocols fix their own encoding, which [`labeled_info`] produces.
+//!
+//! Encoding
+//! ========
+//! The protocol name is its length as a 2-byte big-endian integer followed by its bytes. Each
+//! field is its length as a 4-byte big-endian integer followed by its bytes.
+
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+/// The info labels of published protocols built on HPKE
+pub mod protocols {
+    /// The label of Encrypted Client Hello. The info is `"tls ech" || 0x00 || ECHConfig`.
+    pub const ECH: &[u8] = b"tls ech";
+    /// The label of Oblivious DNS over HTTPS queries. The info is just the label.
+    pub const ODOH_QUERY: &[u8] = b"odoh query";
+    /// The label of Oblivious HTTP requests. The info is
+    /// `"message/bhttp request" || 0x00 || header`, where `header` is the request header.
+    pub const OHTTP_REQUEST: &[u8] = b"message/bhttp request";
+}
+
+/// Returns `label || 0x00 || context`, the info encoding used by, e.g., ECH and Oblivious HTTP
+pub fn labeled_info(label: &[u8], context: &[u8]) -> Vec<u8> {
+    let mut info = Vec::with_capacity(label.len() + 1 + context.len());
+    info.extend_from_slice(label);
+    info.push(0);
+    info.extend_from_slice(context);
+    info
+}
+
+/// Builds an info string out of a protocol name and fields, with an unambiguous encoding
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InfoBuilder {
+    buf: Vec<u8>,
+}
+
+impl InfoBuilder {
+    /// Starts an info string for the given protocol. The name should say which application and
+    /// version it is, e.g., `"my-app/v1"`.
+    ///
+    /// Panics
+    /// ======
+    /// Panics if `protocol` is longer than 65535 bytes.
+    pub fn for_protocol(protocol: &str) -> Self {
+        let len = u16::try_from(protocol.len()).expect("protocol name is too long");
+        let mut buf = Vec::with_capacity(2 + protocol.len());
+        buf.extend_from_slice(&len.to_be_bytes());
+        buf.extend_from_slice(protocol.as_bytes());
+        InfoBuilder { buf }
+    }
+
+    /// Appends a field, prefixed with its 4-byte big-endian length
+    ///
+    /// Panics
+    /// ======
+    /// Panics if `val` is longer than `u32::MAX` bytes.
+    pub fn field(mut self, val: &[u8]) -> Self {
+        let len = u32::try_from(val.len()).expect("info field is too long");
+        self.buf.extend_from_slice(&len.to_be_bytes());
+        self.buf.extend_from_slice(val);
+        self
+    }
+
+    /// Returns the info encoded so far
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// Returns the encoded info
+    pub fn build(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{labeled_info, protocols, InfoBuilder};
+
+    /// Tests the encoding of a protocol name and its fields
+    #[test]
+    fn test_encoding() {
+        let info = InfoBuilder::for_protocol("app").field(b"hi").build();
+        assert_eq!(info, [0, 3, b'a', b'p', b'p', 0, 0, 0, 2, b'h', b'i']);
+
+        let info = labeled_info(protocols::ECH, b"config");
+        assert_eq!(info, b"tls ech\0config");
+    }
+
+    /// Tests that moving bytes between the name and the fields, or between fields, changes the
+    /// encoding
+    #[test]
+    fn test_unambiguous() {
+        let a = InfoBuilder::for_protocol("ab").field(b"c");
+        let b = InfoBuilder::for_protocol("a").field(b"bc");
+        assert_ne!(a.as_bytes(), b.as_bytes());
+
+        let a = InfoBuilder::for_protocol("p").field(b"ab").field(b"");
+        let b = InfoBuilder::for_protocol("p").field(b"a").field(b"b");
+        assert_ne!(a, b);
+
+        // A protocol with no fields is still different from one with an empty field
+        let a = InfoBuilder::for_protocol("p");
+        let b = InfoBuilder::for_protocol("p").field(b"");
+        assert_ne!(a, b);
+    }
+}
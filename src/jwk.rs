@@ -0,0 +1,325 @@
+//! This module defines JSON Web Key (JWK) encoding for private and public keys, so that keys can
+//! be exchanged with JOSE tooling and cloud key management systems. This is gated under the `jwk`
+//! feature.
+//!
+//! X25519 keys are `OKP` keys as in [RFC 8037](https://tools.ietf.org/html/rfc8037). P-256 keys are
+//! `EC` keys as in [RFC 7518 §6.2](https://tools.ietf.org/html/rfc7518#section-6.2). Private key
+//! JWKs include the public key members as well. When decoding, members this crate doesn't use (e.g.,
+//! `kid`, `use`, `alg`) are ignored.
+
+use crate::HpkeError;
+
+use alloc::string::String;
+// The helpers that use these are only needed by the KEMs' impls
+#[cfg(any(feature = "x25519-dalek", feature = "p256"))]
+use {
+    crate::kex::{Deserializable, KeyExchange, Serializable},
+    alloc::vec::Vec,
+    base64ct::{Base64UrlUnpadded, Encoding},
+    serde_json::{Map, Value},
+};
+
+/// Implemented by private key types that can be encoded as and decoded from a JWK
+pub trait JwkPrivateKey: Sized {
+    /// Encodes this private key as a JWK JSON object. The public key members are included.
+    fn to_jwk(&self) -> String;
+
+    /// Decodes a private key from a JWK JSON object. Returns `HpkeError::InvalidEncoding` if the
+    /// JWK is malformed, is for a different algorithm, or has public key members that don't match
+    /// the private key.
+    fn from_jwk(jwk: &str) -> Result<Self, HpkeError>;
+}
+
+/// Implemented by public key types that can be encoded as and decoded from a JWK
+pub trait JwkPublicKey: Sized {
+    /// Encodes this public key as a JWK JSON object
+    fn to_jwk(&self) -> String;
+
+    /// Decodes a public key from a JWK JSON object. Returns `HpkeError::InvalidEncoding` if the
+    /// JWK is malformed or is for a different algorithm. Private key JWKs are accepted, and their
+    /// private members are ignored.
+    fn from_jwk(jwk: &str) -> Result<Self, HpkeError>;
+}
+
+// Makes a JWK with the given key type and curve. The other members get added later.
+#[cfg(any(feature = "x25519-dalek", feature = "p256"))]
+fn new_jwk(kty: &str, crv: &str) -> Map<String, Value> {
+    let mut jwk = Map::new();
+    jwk.insert("kty".into(), kty.into());
+    jwk.insert("crv".into(), crv.into());
+    jwk
+}
+
+// Adds the given bytes to the JWK as a base64url member
+#[cfg(any(feature = "x25519-dalek", feature = "p256"))]
+fn insert_bytes(jwk: &mut Map<String, Value>, name: &str, bytes: &[u8]) {
+    jwk.insert(name.into(), Base64UrlUnpadded::encode_string(bytes).into());
+}
+
+// Serializes a JWK to JSON
+#[cfg(any(feature = "x25519-dalek", feature = "p256"))]
+fn encode_jwk(jwk: &Map<String, Value>) -> String {
+    serde_json::to_string(jwk).expect("couldn't serialize JWK")
+}
+
+// Parses a JWK and checks that it has the given key type and curve
+#[cfg(any(feature = "x25519-dalek", feature = "p256"))]
+fn parse_jwk(jwk: &str, kty: &str, crv: &str) -> Result<Map<String, Value>, HpkeError> {
+    let jwk: Map<String, Value> =
+        serde_json::from_str(jwk).map_err(|_| HpkeError::InvalidEncoding)?;
+    if jwk.get("kty").and_then(Value::as_str) == Some(kty)
+        && jwk.get("crv").and_then(Value::as_str) == Some(crv)
+    {
+        Ok(jwk)
+    } else {
+        Err(HpkeError::InvalidEncoding)
+    }
+}
+
+// Gets the given base64url member from the JWK. Returns None if it's not there.
+#[cfg(any(feature = "x25519-dalek", feature = "p256"))]
+fn get_bytes(jwk: &Map<String, Value>, name: &str) -> Result<Option<Vec<u8>>, HpkeError> {
+    match jwk.get(name) {
+        None => Ok(None),
+        Some(Value::String(s)) => Base64UrlUnpadded::decode_vec(s)
+            .map(Some)
+            .map_err(|_| HpkeError::InvalidEncoding),
+        Some(_) => Err(HpkeError::InvalidEncoding),
+    }
+}
+
+// Gets the given base64url member from the JWK. Returns an error if it's not there.
+#[cfg(any(feature = "x25519-dalek", feature = "p256"))]
+fn get_required_bytes(jwk: &Map<String, Value>, name: &str) -> Result<Vec<u8>, HpkeError> {
+    get_bytes(jwk, name)?.ok_or(HpkeError::InvalidEncoding)
+}
+
+// Decodes a private key from a JWK whose public members are decoded by decode_pk. The public
+// members are required, and they must match the private key.
+#[cfg(any(feature = "x25519-dalek", feature = "p256"))]
+fn decode_private_jwk<Kex, F>(
+    jwk: &Map<String, Value>,
+    decode_pk: F,
+) -> Result<Kex::PrivateKey, HpkeError>
+where
+    Kex: KeyExchange,
+    F: Fn(&Map<String, Value>) -> Result<Kex::PublicKey, HpkeError>,
+{
+    let sk = Kex::PrivateKey::from_bytes(&get_required_bytes(jwk, "d")?)?;
+    let pk = decode_pk(jwk)?;
+    if Kex::sk_to_pk(&sk).to_bytes() == pk.to_bytes() {
+        Ok(sk)
+    } else {
+        Err(HpkeError::InvalidEncoding)
+    }
+}
+
+#[cfg(feature = "x25519-dalek")]
+mod x25519_impls {
+    use super::*;
+    use crate::kex::x25519;
+
+    // RFC 8037 §2: X25519 keys are octet key pairs
+    const KTY: &str = "OKP";
+    const CRV: &str = "X25519";
+
+    // Makes a public key JWK. The public key is just the x member.
+    fn encode_pk(pk: &x25519::PublicKey) -> Map<String, Value> {
+        let mut jwk = new_jwk(KTY, CRV);
+        insert_bytes(&mut jwk, "x", &pk.to_bytes());
+        jwk
+    }
+
+    fn decode_pk(jwk: &Map<String, Value>) -> Result<x25519::PublicKey, HpkeError> {
+        x25519::PublicKey::from_bytes(&get_required_bytes(jwk, "x")?)
+    }
+
+    impl JwkPrivateKey for x25519::PrivateKey {
+        fn to_jwk(&self) -> String {
+            let mut jwk = encode_pk(&<x25519::X25519 as KeyExchange>::sk_to_pk(self));
+            insert_bytes(&mut jwk, "d", &self.to_bytes());
+            encode_jwk(&jwk)
+        }
+
+        fn from_jwk(jwk: &str) -> Result<Self, HpkeError> {
+            let jwk = parse_jwk(jwk, KTY, CRV)?;
+            decode_private_jwk::<x25519::X25519, _>(&jwk, decode_pk)
+        }
+    }
+
+    impl JwkPublicKey for x25519::PublicKey {
+        fn to_jwk(&self) -> String {
+            encode_jwk(&encode_pk(self))
+        }
+
+        fn from_jwk(jwk: &str) -> Result<Self, HpkeError> {
+            decode_pk(&parse_jwk(jwk, KTY, CRV)?)
+        }
+    }
+}
+
+#[cfg(feature = "p256")]
+mod p256_impls {
+    use super::*;
+    use crate::kex::ecdh_nistp;
+
+    // RFC 7518 §6.2.1
+    const KTY: &str = "EC";
+    const CRV: &str = "P-256";
+
+    // Makes a public key JWK. The x and y members are the affine coordinates, which is just the
+    // uncompressed point without its leading 0x04.
+    fn encode_pk(pk: &ecdh_nistp::PublicKey) -> Map<String, Value> {
+        let pk_bytes = pk.to_bytes();
+        let mut jwk = new_jwk(KTY, CRV);
+        insert_bytes(&mut jwk, "x", &pk_bytes[1..33]);
+        insert_bytes(&mut jwk, "y", &pk_bytes[33..]);
+        jwk
+    }
+
+    fn decode_pk(jwk: &Map<String, Value>) -> Result<ecdh_nistp::PublicKey, HpkeError> {
+        let x = get_required_bytes(jwk, "x")?;
+        let y = get_required_bytes(jwk, "y")?;
+        // RFC 7518 §6.2.1.2: The coordinates MUST be the full size of a field element
+        if x.len() != 32 || y.len() != 32 {
+            return Err(HpkeError::InvalidEncoding);
+        }
+
+        // Reassemble the uncompressed point
+        let mut pk_bytes = [0u8; 65];
+        pk_bytes[0] = 0x04;
+        pk_bytes[1..33].copy_from_slice(&x);
+        pk_bytes[33..].copy_from_slice(&y);
+        ecdh_nistp::PublicKey::from_bytes(&pk_bytes)
+    }
+
+    impl JwkPrivateKey for ecdh_nistp::PrivateKey {
+        fn to_jwk(&self) -> String {
+            let mut jwk = encode_pk(&<ecdh_nistp::DhP256 as KeyExchange>::sk_to_pk(self));
+            insert_bytes(&mut jwk, "d", &self.to_bytes());
+            encode_jwk(&jwk)
+        }
+
+        fn from_jwk(jwk: &str) -> Result<Self, HpkeError> {
+            let jwk = parse_jwk(jwk, KTY, CRV)?;
+            decode_private_jwk::<ecdh_nistp::DhP256, _>(&jwk, decode_pk)
+        }
+    }
+
+    impl JwkPublicKey for ecdh_nistp::PublicKey {
+        fn to_jwk(&self) -> String {
+            encode_jwk(&encode_pk(self))
+        }
+
+        fn from_jwk(jwk: &str) -> Result<Self, HpkeError> {
+            decode_pk(&parse_jwk(jwk, KTY, CRV)?)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{JwkPrivateKey, JwkPublicKey};
+    use crate::{kex::KeyExchange, test_util::kex_gen_keypair, Serializable};
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Tests that encoding then decoding a keypair ends up at the same keypair, and that mismatched
+    /// keys are rejected
+    macro_rules! test_jwk_roundtrip {
+        ($test_name:ident, $kex:ty) => {
+            #[test]
+            fn $test_name() {
+                type Kex = $kex;
+                let mut csprng = StdRng::from_entropy();
+                let (sk, pk) = kex_gen_keypair::<Kex, _>(&mut csprng);
+
+                let sk_jwk = sk.to_jwk();
+                let pk_jwk = pk.to_jwk();
+                let new_sk = <Kex as KeyExchange>::PrivateKey::from_jwk(&sk_jwk).unwrap();
+                let new_pk = <Kex as KeyExchange>::PublicKey::from_jwk(&pk_jwk).unwrap();
+                assert_eq!(sk.to_bytes(), new_sk.to_bytes());
+                assert_eq!(pk.to_bytes(), new_pk.to_bytes());
+
+                // A private JWK has the public key in it. A public JWK has no private key in it.
+                let pk_from_sk_jwk = <Kex as KeyExchange>::PublicKey::from_jwk(&sk_jwk).unwrap();
+                assert_eq!(pk.to_bytes(), pk_from_sk_jwk.to_bytes());
+                assert!(<Kex as KeyExchange>::PrivateKey::from_jwk(&pk_jwk).is_err());
+
+                // Splice the private key of one JWK into the public key of another. This should
+                // fail to decode.
+                let (other_sk, _) = kex_gen_keypair::<Kex, _>(&mut csprng);
+                let mut spliced: serde_json::Value = serde_json::from_str(&pk_jwk).unwrap();
+                let other_sk_jwk: serde_json::Value =
+                    serde_json::from_str(&other_sk.to_jwk()).unwrap();
+                spliced["d"] = other_sk_jwk["d"].clone();
+                let res = <Kex as KeyExchange>::PrivateKey::from_jwk(
+                    &serde_json::to_string(&spliced).unwrap(),
+                );
+                assert!(res.is_err());
+            }
+        };
+    }
+
+    /// Tests that we decode JWKs made elsewhere. The X25519 key is from RFC 8037 §A.6. The P-256
+    /// key is from RFC 7517 §A.2. We check the public keys rather than the private keys because
+    /// X25519 private keys get clamped on deserialization.
+    macro_rules! test_jwk_vector {
+        ($test_name:ident, $kex:ty, $sk_jwk:expr, $pk_bytes:expr) => {
+            #[test]
+            fn $test_name() {
+                type Kex = $kex;
+                let sk = <Kex as KeyExchange>::PrivateKey::from_jwk($sk_jwk).unwrap();
+                let pk = <Kex as KeyExchange>::PublicKey::from_jwk($sk_jwk).unwrap();
+                let pk_bytes = hex::decode($pk_bytes).unwrap();
+                assert_eq!(
+                    Kex::sk_to_pk(&sk).to_bytes().as_slice(),
+                    pk_bytes.as_slice()
+                );
+                assert_eq!(pk.to_bytes().as_slice(), pk_bytes.as_slice());
+            }
+        };
+    }
+
+    /// Tests that the two algorithms don't accept each other's encodings
+    #[cfg(all(feature = "x25519-dalek", feature = "p256"))]
+    #[test]
+    fn test_algorithm_mismatch() {
+        use crate::kex::{ecdh_nistp::DhP256, x25519::X25519};
+
+        let mut csprng = StdRng::from_entropy();
+        let (x_sk, x_pk) = kex_gen_keypair::<X25519, _>(&mut csprng);
+        let (p_sk, p_pk) = kex_gen_keypair::<DhP256, _>(&mut csprng);
+
+        assert!(<DhP256 as KeyExchange>::PrivateKey::from_jwk(&x_sk.to_jwk()).is_err());
+        assert!(<DhP256 as KeyExchange>::PublicKey::from_jwk(&x_pk.to_jwk()).is_err());
+        assert!(<X25519 as KeyExchange>::PrivateKey::from_jwk(&p_sk.to_jwk()).is_err());
+        assert!(<X25519 as KeyExchange>::PublicKey::from_jwk(&p_pk.to_jwk()).is_err());
+    }
+
+    #[cfg(feature = "x25519-dalek")]
+    test_jwk_roundtrip!(test_jwk_roundtrip_x25519, crate::kex::x25519::X25519);
+    #[cfg(feature = "p256")]
+    test_jwk_roundtrip!(test_jwk_roundtrip_p256, crate::kex::ecdh_nistp::DhP256);
+
+    #[cfg(feature = "x25519-dalek")]
+    test_jwk_vector!(
+        test_jwk_vector_x25519,
+        crate::kex::x25519::X25519,
+        r#"{"kty":"OKP","crv":"X25519","kid":"Bob",
+            "x":"3p7bfXt9wbTTW2HC7OQ1Nz-DQ8hbeGdNrfx-FG-IK08",
+            "d":"XasIfmJKikt54X-Lg4AO5m87sSkmGLb9HC-LJ_-I4Os"}"#,
+        "de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f"
+    );
+    #[cfg(feature = "p256")]
+    test_jwk_vector!(
+        test_jwk_vector_p256,
+        crate::kex::ecdh_nistp::DhP256,
+        r#"{"kty":"EC","crv":"P-256","use":"enc","kid":"1",
+            "x":"MKBCTNIcKUSDii11ySs3526iDZ8AiTo7Tu6KPAqv7D4",
+            "y":"4Etl6SRW2YiLUrN5vfvVHuhp7x8PxltmWWlbbM4IFyM",
+            "d":"870MB6gfuTJ4HtUnUvYMyJpr5eUZNP4Bk43bVdj3eAE"}"#,
+        "0430a0424cd21c2944838a2d75c92b37e76ea20d9f00893a3b4eee8a3c0aafec3ee04b65e92456d9888b52b3\
+         79bdfbd51ee869ef1f0fc65b6659695b6cce081723"
+    );
+}
@@ -207,14 +207,14 @@ fn test_case<A: Aead, Kdf: KdfTrait, Kem: KemTrait>(tv: MainTestVector) {
 
     // Assert that the derived shared secret key is identical to the one provided
     assert_eq!(
-        shared_secret.as_slice(),
+        shared_secret.as_bytes(),
         tv.shared_secret.as_slice(),
         "shared_secret doesn't match"
     );
 
     // Assert that the derived encapped key is identical to the one provided
     {
-        let provided_encapped_key = EncappedKey::<Kem::Kex>::from_bytes(&tv.encapped_key).unwrap();
+        let provided_encapped_key = EncappedKey::<Kem>::from_bytes(&tv.encapped_key).unwrap();
         assert_serializable_eq!(
             encapped_key,
             provided_encapped_key,
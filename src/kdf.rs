@@ -1,15 +1,25 @@
 use crate::kem::Kem as KemTrait;
 
-use byteorder::{BigEndian, ByteOrder};
 use digest::{BlockInput, Digest, FixedOutput, Reset, Update};
 use generic_array::GenericArray;
-use sha2::{Sha256, Sha384, Sha512};
 
 // This has a space because LabeledExtract calls for a space between the RFC string and the label
 const RFC_STR: &[u8] = b"HPKE-05 ";
 
-// This is currently the maximum value of Nh. It is achieved by HKDF-SHA512.
-pub(crate) const MAX_DIGEST_SIZE: usize = 512;
+// This is currently the maximum value of Nh, in bytes. It is achieved by HKDF-SHA512.
+#[cfg(not(feature = "tight-buffers"))]
+pub(crate) const MAX_DIGEST_SIZE: usize = 64;
+
+// With tight-buffers, the maximum is over the enabled KDFs only
+#[cfg(feature = "tight-buffers")]
+pub(crate) const MAX_DIGEST_SIZE: usize = crate::util::max_of(&[
+    #[cfg(feature = "hkdf-sha256")]
+    <<sha2::Sha256 as FixedOutput>::OutputSize as generic_array::typenum::Unsigned>::USIZE,
+    #[cfg(feature = "hkdf-sha384")]
+    <<sha2::Sha384 as FixedOutput>::OutputSize as generic_array::typenum::Unsigned>::USIZE,
+    #[cfg(feature = "hkdf-sha512")]
+    <<sha2::Sha512 as FixedOutput>::OutputSize as generic_array::typenum::Unsigned>::USIZE,
+]);
 
 // Pretty much all the KDF functionality is covered by the hkdf crate
 
@@ -27,36 +37,42 @@ pub trait Kdf {
 // We use Kdf as a type parameter, so this is to avoid ambiguity.
 use Kdf as KdfTrait;
 
+#[cfg(feature = "hkdf-sha256")]
 /// The implementation of HKDF-SHA256
 pub struct HkdfSha256 {}
 
 // The KDF_ID constant below come from §7.2
 
+#[cfg(feature = "hkdf-sha256")]
 impl KdfTrait for HkdfSha256 {
     #[doc(hidden)]
-    type HashImpl = Sha256;
+    type HashImpl = sha2::Sha256;
 
     #[doc(hidden)]
     const KDF_ID: u16 = 0x0001;
 }
 
+#[cfg(feature = "hkdf-sha384")]
 /// The implementation of HKDF-SHA384
 pub struct HkdfSha384 {}
 
+#[cfg(feature = "hkdf-sha384")]
 impl KdfTrait for HkdfSha384 {
     #[doc(hidden)]
-    type HashImpl = Sha384;
+    type HashImpl = sha2::Sha384;
 
     #[doc(hidden)]
     const KDF_ID: u16 = 0x0002;
 }
 
+#[cfg(feature = "hkdf-sha512")]
 /// The implementation of HKDF-SHA512
 pub struct HkdfSha512 {}
 
+#[cfg(feature = "hkdf-sha512")]
 impl KdfTrait for HkdfSha512 {
     #[doc(hidden)]
-    type HashImpl = Sha512;
+    type HashImpl = sha2::Sha512;
 
     #[doc(hidden)]
     const KDF_ID: u16 = 0x0003;
@@ -67,18 +83,22 @@ impl KdfTrait for HkdfSha512 {
 //   shared_secret = LabeledExpand(eae_prk, "shared_secret", kemContext, Nsecret)
 //   return shared_secret
 /// Uses the given IKM to extract a secret, and then uses that secret, plus the given suite ID and
-/// info string, to expand to the output buffer
+/// info string, to expand to the output buffer. Returns the extracted secret, i.e., `eae_prk`.
 pub(crate) fn extract_and_expand<Kem: KemTrait>(
     ikm: &[u8],
     suite_id: &[u8],
     info: &[u8],
     out: &mut [u8],
-) -> Result<(), hkdf::InvalidLength> {
+) -> Result<
+    GenericArray<u8, <<<Kem as KemTrait>::Kdf as KdfTrait>::HashImpl as FixedOutput>::OutputSize>,
+    hkdf::InvalidLength,
+> {
     // Construct the labels
     // Extract using given IKM
-    let (_, hkdf_ctx) = labeled_extract::<Kem::Kdf>(&[], suite_id, b"eae_prk", ikm);
+    let (prk, hkdf_ctx) = labeled_extract::<Kem::Kdf>(&[], suite_id, b"eae_prk", ikm);
     // Expand using given info string
-    hkdf_ctx.labeled_expand(suite_id, b"shared_secret", info, out)
+    hkdf_ctx.labeled_expand(suite_id, b"shared_secret", info, out)?;
+    Ok(prk)
 }
 
 // def LabeledExtract(salt, label, ikm):
@@ -131,11 +151,68 @@ impl<D: Update + BlockInput + FixedOutput + Reset + Default + Clone> LabeledExpa
         assert!(out.len() <= u16::MAX as usize);
 
         // Encode the output length in the info string
-        let mut len_buf = [0u8; 2];
-        BigEndian::write_u16(&mut len_buf, out.len() as u16);
+        let len_buf = (out.len() as u16).to_be_bytes();
 
         // Call HKDF-Expand() with the info string set to the concatenation of all of the above
         let labeled_info = [&len_buf, RFC_STR, suite_id, label, info];
         self.expand_multi_info(&labeled_info, out)
     }
 }
+
+#[cfg(all(test, feature = "hkdf-sha256"))]
+mod test {
+    use super::{labeled_extract, HkdfSha256, LabeledExpand};
+
+    // The inputs and outputs below are from the base mode X25519, HKDF-SHA256, AES-GCM-128 test
+    // vector in test-vectors-403bf8c.json
+    /// Tests the labeled extract and expand of the key schedule against a known answer
+    #[test]
+    fn test_vector_key_schedule() {
+        type Kdf = HkdfSha256;
+        let suite_id = b"HPKE\x00\x20\x00\x01\x00\x01";
+        let info = hex::decode("4f6465206f6e2061204772656369616e2055726e").unwrap();
+        let shared_secret =
+            hex::decode("033ca57fd76a73143519a19cd609fd2a2cf92b3926c10932802cf892dff579ad")
+                .unwrap();
+
+        // Base mode has an empty PSK and PSK ID
+        let (psk_id_hash, _) = labeled_extract::<Kdf>(&[], suite_id, b"psk_id_hash", &[]);
+        let (info_hash, _) = labeled_extract::<Kdf>(&[], suite_id, b"info_hash", &info);
+        let sched_context = [&[0u8][..], &psk_id_hash, &info_hash].concat();
+        assert_eq!(
+            hex::encode(&sched_context),
+            concat!(
+                "00",
+                "0c085d4e6d2e6a568b5dcf334f7badd56222cd79f2ac98b6f99059f311c3f16a",
+                "44c484c33962433c90728ac6c2893f828d58cebf58ba4fdae59b0a8f7ab84ff8",
+            )
+        );
+
+        let (psk_hash, _) = labeled_extract::<Kdf>(&[], suite_id, b"psk_hash", &[]);
+        let (secret, secret_ctx) =
+            labeled_extract::<Kdf>(&psk_hash, suite_id, b"secret", &shared_secret);
+        assert_eq!(
+            hex::encode(secret),
+            "9079eed1a86fddafb4d54e3573290177e44c47ad794f57fac1ead0502a547185"
+        );
+
+        let mut key = [0u8; 16];
+        let mut nonce = [0u8; 12];
+        let mut exporter_secret = [0u8; 32];
+        secret_ctx
+            .labeled_expand(suite_id, b"key", &sched_context, &mut key)
+            .unwrap();
+        secret_ctx
+            .labeled_expand(suite_id, b"nonce", &sched_context, &mut nonce)
+            .unwrap();
+        secret_ctx
+            .labeled_expand(suite_id, b"exp", &sched_context, &mut exporter_secret)
+            .unwrap();
+        assert_eq!(hex::encode(key), "f73c75623822357593eccf35565b07f4");
+        assert_eq!(hex::encode(nonce), "7530e5ad98ff49a760326cb9");
+        assert_eq!(
+            hex::encode(exporter_secret),
+            "84b158baff479e2a1e0392d62f6ebcd5ef5ce6002b7a06d1c91d62c9612761ba"
+        );
+    }
+}
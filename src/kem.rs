@@ -1,22 +1,40 @@
 use crate::{
     kdf::{extract_and_expand, Kdf as KdfTrait},
-    kex::{Deserializable, KeyExchange, Serializable, MAX_PUBKEY_SIZE},
+    kex::{Deserializable, KeyExchange, Serializable, MAX_DH_SIZE, MAX_PUBKEY_SIZE},
     util::kem_suite_id,
     HpkeError,
 };
 
-use digest::FixedOutput;
-use generic_array::GenericArray;
+use alloc::vec::Vec;
+
+use generic_array::{ArrayLength, GenericArray};
 use rand::{CryptoRng, RngCore};
+use zeroize::Zeroize;
 
 /// Defines a combination of key exchange mechanism and a KDF, which together form a KEM
 pub trait Kem: Sized {
     type Kex: KeyExchange;
-    #[doc(hidden)]
+    /// The KDF used inside the KEM, for `DeriveKeyPair` and `ExtractAndExpand`. This is fixed by
+    /// the KEM, and has nothing to do with the KDF of the ciphersuite. For example,
+    /// DHKEM(X25519, HKDF-SHA256) uses HKDF-SHA256 even when the key schedule uses HKDF-SHA512.
     type Kdf: KdfTrait;
+    /// The length of the shared secret, i.e., `Nsecret`. This is a property of the KEM, not of its
+    /// KDF. It happens to be the KDF's digest size for the DHKEMs here, but not for every KEM.
+    type SharedSecretSize: ArrayLength<u8>;
 
     const KEM_ID: u16;
 
+    /// The length of a serialized encapsulated key, i.e., `Nenc`. For a DHKEM, this is the length
+    /// of a public key, which is the default. A KEM whose encapsulated key isn't a public key
+    /// overrides it.
+    const N_ENC: usize = <KemPubkey<Self> as Serializable>::SIZE;
+    /// The length of a serialized public key, i.e., `Npk`
+    const N_PK: usize = <KemPubkey<Self> as Serializable>::SIZE;
+    /// Whether the KEM has `AuthEncap` and `AuthDecap`, which the auth modes need. Every DHKEM
+    /// does, which is the default. The mode is picked at runtime, so setting up an auth mode with
+    /// a KEM that doesn't fails with `HpkeError::UnsupportedMode` before any key exchange happens.
+    const SUPPORTS_AUTH: bool = true;
+
     /// Deterministically derives a keypair from the given input keying material
     ///
     /// Requirements
@@ -51,6 +69,41 @@ pub trait Kem: Sized {
         // Run derive_keypair using the KEM's KDF
         Self::derive_keypair(&ikm)
     }
+
+    /// Generates `n` random keypairs using the given RNG. This draws the keying material for all
+    /// of them in a single call to the RNG, which is cheaper for RNGs with a high per-call cost,
+    /// like the OS RNG. Each keypair is then derived on its own, so this doesn't speed up the
+    /// public key computations. In particular, P-256 public keys aren't normalized with a single
+    /// shared inversion, since the p256 crate doesn't expose the field inversion that needs.
+    ///
+    /// Panics
+    /// ======
+    /// Panics if the keying material for `n` keypairs, i.e., `n` private keys' worth of bytes,
+    /// would take more than `usize::MAX` bytes.
+    fn gen_keypairs<R: CryptoRng + RngCore>(
+        n: usize,
+        csprng: &mut R,
+    ) -> Vec<(
+        <Self::Kex as KeyExchange>::PrivateKey,
+        <Self::Kex as KeyExchange>::PublicKey,
+    )> {
+        let ikm_len = <<Self::Kex as KeyExchange>::PrivateKey as Serializable>::size();
+
+        // Make keying material for every keypair at once, then split it up
+        let total_len = n
+            .checked_mul(ikm_len)
+            .expect("too many keypairs to generate at once");
+        let mut ikms = vec![0u8; total_len];
+        csprng.fill_bytes(&mut ikms);
+        let keypairs = ikms
+            .chunks_exact(ikm_len)
+            .map(Self::derive_keypair)
+            .collect();
+
+        // The keying material is as secret as the keys
+        ikms.as_mut_slice().zeroize();
+        keypairs
+    }
 }
 
 // Kem is also used as a type parameter everywhere. To avoid confusion, alias it
@@ -64,6 +117,8 @@ pub struct X25519HkdfSha256 {}
 impl Kem for X25519HkdfSha256 {
     type Kex = crate::kex::X25519;
     type Kdf = crate::kdf::HkdfSha256;
+    // §7.1: Nsecret of DHKEM(X25519, HKDF-SHA256) is 32
+    type SharedSecretSize = generic_array::typenum::U32;
 
     // §7.1: DHKEM(X25519, HKDF-SHA256)
     const KEM_ID: u16 = 0x0020;
@@ -77,43 +132,151 @@ pub struct DhP256HkdfSha256 {}
 impl Kem for DhP256HkdfSha256 {
     type Kex = crate::kex::DhP256;
     type Kdf = crate::kdf::HkdfSha256;
+    // §7.1: Nsecret of DHKEM(P-256, HKDF-SHA256) is 32
+    type SharedSecretSize = generic_array::typenum::U32;
 
     // §7.1: DHKEM(P-256, HKDF-SHA256)
     const KEM_ID: u16 = 0x0010;
 }
 
+#[cfg(feature = "k256")]
+/// Represents DHKEM(secp256k1, HKDF-SHA256)
+pub struct DhK256HkdfSha256 {}
+
+#[cfg(feature = "k256")]
+impl Kem for DhK256HkdfSha256 {
+    type Kex = crate::kex::DhK256;
+    type Kdf = crate::kdf::HkdfSha256;
+    // Nsecret of DHKEM(secp256k1, HKDF-SHA256) is 32
+    type SharedSecretSize = generic_array::typenum::U32;
+
+    // DHKEM(secp256k1, HKDF-SHA256), as registered with IANA
+    const KEM_ID: u16 = 0x0016;
+}
+
 /// Convenience types representing public/private keys corresponding to a KEM's underlying DH alg
 type KemPubkey<Kem> = <<Kem as KemTrait>::Kex as KeyExchange>::PublicKey;
 type KemPrivkey<Kem> = <<Kem as KemTrait>::Kex as KeyExchange>::PrivateKey;
+type KemKexResult<Kem> = <<Kem as KemTrait>::Kex as KeyExchange>::KexResult;
 
 /// Holds the content of an encapsulated secret. This is what the receiver uses to derive the
-/// shared secret.
-// This just wraps a pubkey, because that's all an encapsulated key is in a DH-KEM
-pub struct EncappedKey<Kex: KeyExchange>(Kex::PublicKey);
+/// shared secret. Its serialized size is the KEM's `Nenc`.
+///
+/// Encapsulated keys are public, so they can be compared, hashed, and cloned freely, e.g., to
+/// deduplicate messages or to key a cache. Two encapsulated keys are equal if their encodings are.
+// This just wraps a pubkey, because that's all an encapsulated key is in a DH-KEM. It's
+// parameterized by the KEM rather than the key exchange, since that's what determines its format.
+// The encoding is kept alongside the pubkey, since it's what gets compared, hashed, and fed to the
+// KEM's key derivation.
+pub struct EncappedKey<Kem: KemTrait>(KemPubkey<Kem>, GenericArray<u8, EncappedKeySize<Kem>>);
+
+// The length of an encoded encapsulated key, i.e., the KEM's Nenc
+type EncappedKeySize<Kem> = <KemPubkey<Kem> as Serializable>::OutputSize;
 
 // EncappedKeys need to be serializable, since they're gonna be sent over the wire. Underlyingly,
 // they're just DH pubkeys, so we just serialize them the same way
-impl<Kex: KeyExchange> Serializable for EncappedKey<Kex> {
-    type OutputSize = <Kex::PublicKey as Serializable>::OutputSize;
+impl<Kem: KemTrait> Serializable for EncappedKey<Kem> {
+    type OutputSize = EncappedKeySize<Kem>;
 
-    // Pass to underlying to_bytes() impl
     fn to_bytes(&self) -> GenericArray<u8, Self::OutputSize> {
-        self.0.to_bytes()
+        self.1.clone()
     }
 }
 
-impl<Kex: KeyExchange> Deserializable for EncappedKey<Kex> {
+// Can't derive these because that would require Kem: Clone, etc.
+impl<Kem: KemTrait> Clone for EncappedKey<Kem> {
+    fn clone(&self) -> Self {
+        EncappedKey(self.0.clone(), self.1.clone())
+    }
+}
+
+impl<Kem: KemTrait> PartialEq for EncappedKey<Kem> {
+    fn eq(&self, other: &Self) -> bool {
+        self.1 == other.1
+    }
+}
+
+impl<Kem: KemTrait> Eq for EncappedKey<Kem> {}
+
+impl<Kem: KemTrait> core::hash::Hash for EncappedKey<Kem> {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.1.hash(state);
+    }
+}
+
+impl<Kem: KemTrait> EncappedKey<Kem> {
+    // Wraps the given pubkey, and remembers its encoding
+    fn from_pubkey(pubkey: KemPubkey<Kem>) -> Self {
+        let encoded = pubkey.to_bytes();
+        EncappedKey(pubkey, encoded)
+    }
+
+    // Returns the public key this encapsulated key encodes
+    #[cfg(feature = "threshold")]
+    pub(crate) fn pubkey(&self) -> &KemPubkey<Kem> {
+        &self.0
+    }
+
+    /// Returns the encoding of this encapsulated key. This is the same as
+    /// [`to_bytes`](Serializable::to_bytes), without the copy.
+    pub fn as_array(&self) -> &GenericArray<u8, <Self as Serializable>::OutputSize> {
+        &self.1
+    }
+
+    /// Deserializes an encapsulated key like [`from_bytes`](Deserializable::from_bytes), but with
+    /// [`KeyExchange::pubkey_from_bytes_strict`], which also rejects non-canonical encodings and
+    /// says why an input was rejected.
+    ///
+    /// Return Value
+    /// ============
+    /// Fails the same way as [`KeyExchange::pubkey_from_bytes_strict`].
+    pub fn from_bytes_strict(encoded: &[u8]) -> Result<Self, HpkeError> {
+        let pubkey = <Kem::Kex as KeyExchange>::pubkey_from_bytes_strict(encoded)?;
+        Ok(EncappedKey::from_pubkey(pubkey))
+    }
+}
+
+impl<Kem: KemTrait> Deserializable for EncappedKey<Kem> {
     // Pass to underlying from_bytes() impl
     fn from_bytes(encoded: &[u8]) -> Result<Self, HpkeError> {
-        let pubkey = <Kex::PublicKey as Deserializable>::from_bytes(encoded)?;
-        Ok(EncappedKey(pubkey))
+        let pubkey = <KemPubkey<Kem> as Deserializable>::from_bytes(encoded)?;
+        Ok(EncappedKey::from_pubkey(pubkey))
     }
 }
 
-/// A convenience type representing the fixed-size byte array of the same length as a serialized
-/// `KexResult`
-pub(crate) type SharedSecret<Kem> =
-    GenericArray<u8, <<<Kem as KemTrait>::Kdf as KdfTrait>::HashImpl as FixedOutput>::OutputSize>;
+// The fixed-size byte array that holds a shared secret. Its length is the KEM's Nsecret.
+type SharedSecretBytes<Kem> = GenericArray<u8, <Kem as KemTrait>::SharedSecretSize>;
+
+/// The shared secret that a KEM outputs. This is only meant to be fed to the key schedule, so it
+/// has no `Debug`, `Clone`, or byte accessor, and it's zeroed when dropped. If you really need
+/// the bytes, the `danger` feature adds `into_bytes_dangerous`.
+pub struct SharedSecret<Kem: KemTrait>(SharedSecretBytes<Kem>);
+
+impl<Kem: KemTrait> SharedSecret<Kem> {
+    // Makes an all-zero shared secret, to be filled in by the KEM
+    fn zeroed() -> Self {
+        SharedSecret(SharedSecretBytes::<Kem>::default())
+    }
+
+    // The key schedule reads the secret through this
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        self.0.as_slice()
+    }
+
+    /// Returns the raw bytes of this shared secret. They are not zeroed on drop, and anything
+    /// other than the HPKE key schedule that uses them is outside what the spec analyzes. This
+    /// exists for testing and for protocols that specifically need a raw KEM output.
+    #[cfg(feature = "danger")]
+    pub fn into_bytes_dangerous(self) -> SharedSecretBytes<Kem> {
+        self.0.clone()
+    }
+}
+
+impl<Kem: KemTrait> Drop for SharedSecret<Kem> {
+    fn drop(&mut self) {
+        self.0.as_mut_slice().zeroize();
+    }
+}
 
 // def Encap(pkR):
 //   skE, pkE = GenerateKeyPair()
@@ -142,22 +305,62 @@ pub(crate) type SharedSecret<Kem> =
 /// ============
 /// Returns a shared secret and encapped key on success. If an error happened during key exchange,
 /// returns `Err(HpkeError::InvalidKeyExchange)`.
+#[cfg(any(feature = "danger", test))]
 pub(crate) fn encap_with_eph<Kem: KemTrait>(
     pk_recip: &KemPubkey<Kem>,
     sender_id_keypair: Option<&(KemPrivkey<Kem>, KemPubkey<Kem>)>,
     sk_eph: KemPrivkey<Kem>,
-) -> Result<(SharedSecret<Kem>, EncappedKey<Kem::Kex>), HpkeError> {
+) -> Result<(SharedSecret<Kem>, EncappedKey<Kem>), HpkeError> {
+    encap_with_eph_observed::<Kem>(pk_recip, sender_id_keypair, sk_eph, |_| ())
+}
+
+// The intermediate values of an encap. Only danger::EncapTrace looks at them.
+#[cfg_attr(not(all(feature = "danger", debug_assertions)), allow(dead_code))]
+pub(crate) struct EncapInternals<'a> {
+    pub(crate) dh: &'a [u8],
+    pub(crate) kem_context: &'a [u8],
+    pub(crate) eae_prk: &'a [u8],
+}
+
+// The body of encap_with_eph. Hands the intermediate values to `observe` before they're dropped.
+#[cfg(any(feature = "danger", test))]
+pub(crate) fn encap_with_eph_observed<Kem: KemTrait>(
+    pk_recip: &KemPubkey<Kem>,
+    sender_id_keypair: Option<&(KemPrivkey<Kem>, KemPubkey<Kem>)>,
+    sk_eph: KemPrivkey<Kem>,
+    observe: impl FnOnce(&EncapInternals<'_>),
+) -> Result<(SharedSecret<Kem>, EncappedKey<Kem>), HpkeError> {
+    encap_with_kex::<Kem>(
+        pk_recip,
+        sender_id_keypair,
+        || {
+            let kex_res_eph = Kem::Kex::kex(&sk_eph, pk_recip)?;
+            Ok((kex_res_eph, Kem::Kex::sk_to_pk(&sk_eph)))
+        },
+        observe,
+    )
+}
+
+// The body of encap_with_eph_observed and encap. `eph_kex` returns the DH of an ephemeral secret
+// key with `pk_recip`, and the ephemeral pubkey. It's only called once the mode is known to be
+// supported.
+fn encap_with_kex<Kem: KemTrait>(
+    pk_recip: &KemPubkey<Kem>,
+    sender_id_keypair: Option<&(KemPrivkey<Kem>, KemPubkey<Kem>)>,
+    eph_kex: impl FnOnce() -> Result<(KemKexResult<Kem>, KemPubkey<Kem>), HpkeError>,
+    observe: impl FnOnce(&EncapInternals<'_>),
+) -> Result<(SharedSecret<Kem>, EncappedKey<Kem>), HpkeError> {
+    if sender_id_keypair.is_some() && !Kem::SUPPORTS_AUTH {
+        return Err(HpkeError::UnsupportedMode);
+    }
+
     // Put together the binding context used for all KDF operations
     let suite_id = kem_suite_id::<Kem>();
 
-    // Compute the shared secret from the ephemeral inputs
-    let kex_res_eph = Kem::Kex::kex(&sk_eph, pk_recip)?;
-
-    // The encapped key is the ephemeral pubkey
-    let encapped_key = {
-        let pk_eph = Kem::Kex::sk_to_pk(&sk_eph);
-        EncappedKey(pk_eph)
-    };
+    // Compute the shared secret from the ephemeral inputs. The encapped key is the ephemeral
+    // pubkey.
+    let (kex_res_eph, pk_eph) = eph_kex()?;
+    let encapped_key = EncappedKey::from_pubkey(pk_eph);
 
     // The shared secret is either gonna be kex_res_eph, or that along with another shared secret
     // that's tied to the sender's identity.
@@ -166,10 +369,9 @@ pub(crate) fn encap_with_eph<Kem: KemTrait>(
         // We concat without allocation by making a buffer of the maximum possible size, then
         // taking the appropriately sized slice.
         let (kem_context_buf, kem_context_size) = concat_with_known_maxlen!(
-            MAX_PUBKEY_SIZE,
-            &encapped_key.to_bytes(),
-            &pk_recip.to_bytes(),
-            &pk_sender_id.to_bytes()
+            &encapped_key.to_bytes() => MAX_PUBKEY_SIZE,
+            &pk_recip.to_bytes() => MAX_PUBKEY_SIZE,
+            &pk_sender_id.to_bytes() => MAX_PUBKEY_SIZE
         );
         let kem_context = &kem_context_buf[..kem_context_size];
 
@@ -180,9 +382,8 @@ pub(crate) fn encap_with_eph<Kem: KemTrait>(
         // concatted_secrets = kex_res_eph || kex_res_identity
         // Same no-alloc concat trick as above
         let (concatted_secrets_buf, concatted_secret_size) = concat_with_known_maxlen!(
-            MAX_PUBKEY_SIZE,
-            &kex_res_eph.to_bytes(),
-            &kex_res_identity.to_bytes()
+            &kex_res_eph.to_bytes() => MAX_DH_SIZE,
+            &kex_res_identity.to_bytes() => MAX_DH_SIZE
         );
         let concatted_secrets = &concatted_secrets_buf[..concatted_secret_size];
 
@@ -190,18 +391,23 @@ pub(crate) fn encap_with_eph<Kem: KemTrait>(
         // recipient pubkey, and the KEX of the identity input with the recipient pubkey. The
         // HKDF-Expand call only errors if the output values are 255x the digest size of the hash
         // function. Since these values are fixed at compile time, we don't worry about it.
-        let mut buf = <SharedSecret<Kem> as Default>::default();
-        extract_and_expand::<Kem>(&concatted_secrets, &suite_id, &kem_context, &mut buf)
-            .expect("shared secret is way too big");
+        let mut buf = SharedSecret::<Kem>::zeroed();
+        let eae_prk =
+            extract_and_expand::<Kem>(concatted_secrets, &suite_id, kem_context, &mut buf.0)
+                .expect("shared secret is way too big");
+        observe(&EncapInternals {
+            dh: concatted_secrets,
+            kem_context,
+            eae_prk: &eae_prk,
+        });
         buf
     } else {
         // kem_context = encapped_key || pk_recip
         // We concat without allocation by making a buffer of the maximum possible size, then
         // taking the appropriately sized slice.
         let (kem_context_buf, kem_context_size) = concat_with_known_maxlen!(
-            MAX_PUBKEY_SIZE,
-            &encapped_key.to_bytes(),
-            &pk_recip.to_bytes()
+            &encapped_key.to_bytes() => MAX_PUBKEY_SIZE,
+            &pk_recip.to_bytes() => MAX_PUBKEY_SIZE
         );
         let kem_context = &kem_context_buf[..kem_context_size];
 
@@ -209,9 +415,15 @@ pub(crate) fn encap_with_eph<Kem: KemTrait>(
         // the recipient pubkey. The HKDF-Expand call only errors if the output values are 255x the
         // digest size of the hash function. Since these values are fixed at compile time, we don't
         // worry about it.
-        let mut buf = <SharedSecret<Kem> as Default>::default();
-        extract_and_expand::<Kem>(&kex_res_eph.to_bytes(), &suite_id, &kem_context, &mut buf)
+        let dh = kex_res_eph.to_bytes();
+        let mut buf = SharedSecret::<Kem>::zeroed();
+        let eae_prk = extract_and_expand::<Kem>(&dh, &suite_id, kem_context, &mut buf.0)
             .expect("shared secret is way too big");
+        observe(&EncapInternals {
+            dh: &dh,
+            kem_context,
+            eae_prk: &eae_prk,
+        });
         buf
     };
 
@@ -221,33 +433,42 @@ pub(crate) fn encap_with_eph<Kem: KemTrait>(
 /// Derives a shared secret and an ephemeral pubkey that the owner of the reciepint's pubkey can
 /// use to derive the same shared secret. If `sk_sender_id` is given, the sender's identity will be
 /// tied to the shared secret.
-/// All this does is generate an ephemeral keypair and pass to `encap_with_eph`.
+/// This is `encap_with_eph` with a random ephemeral keypair. The KEX makes the keypair, so it can
+/// keep the private key in a type that can't be exported, e.g., X25519's `ReusableSecret`.
 ///
 /// Return Value
 /// ============
 /// Returns a shared secret and encapped key on success. If an error happened during key exchange,
 /// returns `Err(HpkeError::InvalidKeyExchange)`.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", skip_all, fields(kem_id = Kem::KEM_ID))
+)]
 pub(crate) fn encap<Kem: KemTrait, R>(
     pk_recip: &KemPubkey<Kem>,
     sender_id_keypair: Option<&(KemPrivkey<Kem>, KemPubkey<Kem>)>,
     csprng: &mut R,
-) -> Result<(SharedSecret<Kem>, EncappedKey<Kem::Kex>), HpkeError>
+) -> Result<(SharedSecret<Kem>, EncappedKey<Kem>), HpkeError>
 where
     Kem: KemTrait,
     R: CryptoRng + RngCore,
 {
-    // Generate a new ephemeral keypair
-    let (sk_eph, _) = Kem::gen_keypair(csprng);
-    // Now pass to encap_with_eph
-    encap_with_eph::<Kem>(pk_recip, sender_id_keypair, sk_eph)
+    // Generate a new ephemeral keypair and do the KEX with it
+    encap_with_kex::<Kem>(
+        pk_recip,
+        sender_id_keypair,
+        || Kem::Kex::ephemeral_kex::<Kem::Kdf, _>(&kem_suite_id::<Kem>(), pk_recip, csprng),
+        |_| (),
+    )
 }
 
-/// unauth decap with shared secret provided externally
-/// WARNING: temporary measure
-pub fn decap_external<Kem: KemTrait>(
+// Does an unauthenticated decap, given the DH result of the recipient's secret key and the
+// encapsulated key. Exposed as danger::decap_from_dh, and used by threshold decap.
+#[cfg(any(feature = "danger", feature = "threshold"))]
+pub(crate) fn decap_from_dh<Kem: KemTrait>(
     kex_res_eph_marshalled: &[u8],
     pk_recip: &KemPubkey<Kem>,
-    encapped_key: &EncappedKey<Kem::Kex>,
+    encapped_key: &EncappedKey<Kem>,
 ) -> Result<SharedSecret<Kem>, HpkeError> {
     // Put together the binding context used for all KDF operations
     let suite_id = kem_suite_id::<Kem>();
@@ -256,9 +477,8 @@ pub fn decap_external<Kem: KemTrait>(
     // We concat without allocation by making a buffer of the maximum possible size, then
     // taking the appropriately sized slice.
     let (kem_context_buf, kem_context_size) = concat_with_known_maxlen!(
-        MAX_PUBKEY_SIZE,
-        &encapped_key.to_bytes(),
-        &pk_recip.to_bytes()
+        &encapped_key.to_bytes() => MAX_PUBKEY_SIZE,
+        &pk_recip.to_bytes() => MAX_PUBKEY_SIZE
     );
     let kem_context = &kem_context_buf[..kem_context_size];
 
@@ -266,12 +486,12 @@ pub fn decap_external<Kem: KemTrait>(
     // recipient pubkey. The HKDF-Expand call only errors if the output values are 255x the
     // digest size of the hash function. Since these values are fixed at compile time, we don't
     // worry about it.
-    let mut shared_secret = <SharedSecret<Kem> as Default>::default();
+    let mut shared_secret = SharedSecret::<Kem>::zeroed();
     extract_and_expand::<Kem>(
         &kex_res_eph_marshalled,
         &suite_id,
         &kem_context,
-        &mut shared_secret,
+        &mut shared_secret.0,
     )
     .expect("shared secret is way too big");
     Ok(shared_secret)
@@ -307,13 +527,53 @@ pub fn decap_external<Kem: KemTrait>(
 pub(crate) fn decap<Kem: KemTrait>(
     sk_recip: &KemPrivkey<Kem>,
     pk_sender_id: Option<&KemPubkey<Kem>>,
-    encapped_key: &EncappedKey<Kem::Kex>,
+    encapped_key: &EncappedKey<Kem>,
+) -> Result<SharedSecret<Kem>, HpkeError> {
+    decap_with_enc::<Kem>(
+        sk_recip,
+        pk_sender_id,
+        &encapped_key.0,
+        encapped_key.as_array(),
+    )
+}
+
+/// Like [`decap`], but takes the encapsulated key as bytes. The bytes are parsed, and then used
+/// as-is in the KEM context, rather than being serialized again.
+///
+/// Return Value
+/// ============
+/// Returns a shared secret on success. If `encapped_key` is malformed, returns
+/// `Err(HpkeError::InvalidEncoding)`. If an error happened during key exchange, returns
+/// `Err(HpkeError::InvalidKeyExchange)`.
+pub(crate) fn decap_from_bytes<Kem: KemTrait>(
+    sk_recip: &KemPrivkey<Kem>,
+    pk_sender_id: Option<&KemPubkey<Kem>>,
+    encapped_key: &[u8],
 ) -> Result<SharedSecret<Kem>, HpkeError> {
+    let pk_eph = KemPubkey::<Kem>::from_bytes(encapped_key)?;
+    decap_with_enc::<Kem>(sk_recip, pk_sender_id, &pk_eph, encapped_key)
+}
+
+// The body of decap. `enc` is the serialization of `pk_eph`.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(name = "decap", level = "debug", skip_all, fields(kem_id = Kem::KEM_ID))
+)]
+fn decap_with_enc<Kem: KemTrait>(
+    sk_recip: &KemPrivkey<Kem>,
+    pk_sender_id: Option<&KemPubkey<Kem>>,
+    pk_eph: &KemPubkey<Kem>,
+    enc: &[u8],
+) -> Result<SharedSecret<Kem>, HpkeError> {
+    if pk_sender_id.is_some() && !Kem::SUPPORTS_AUTH {
+        return Err(HpkeError::UnsupportedMode);
+    }
+
     // Put together the binding context used for all KDF operations
     let suite_id = kem_suite_id::<Kem>();
 
     // Compute the shared secret from the ephemeral inputs
-    let kex_res_eph = Kem::Kex::kex(&sk_recip, &encapped_key.0)?;
+    let kex_res_eph = Kem::Kex::kex(sk_recip, pk_eph)?;
 
     // Compute the sender's pubkey from their privkey
     let pk_recip = Kem::Kex::sk_to_pk(sk_recip);
@@ -325,10 +585,9 @@ pub(crate) fn decap<Kem: KemTrait>(
         // We concat without allocation by making a buffer of the maximum possible size, then
         // taking the appropriately sized slice.
         let (kem_context_buf, kem_context_size) = concat_with_known_maxlen!(
-            MAX_PUBKEY_SIZE,
-            &encapped_key.to_bytes(),
-            &pk_recip.to_bytes(),
-            &pk_sender_id.to_bytes()
+            enc => MAX_PUBKEY_SIZE,
+            &pk_recip.to_bytes() => MAX_PUBKEY_SIZE,
+            &pk_sender_id.to_bytes() => MAX_PUBKEY_SIZE
         );
         let kem_context = &kem_context_buf[..kem_context_size];
 
@@ -339,9 +598,8 @@ pub(crate) fn decap<Kem: KemTrait>(
         // concatted_secrets = kex_res_eph || kex_res_identity
         // Same no-alloc concat trick as above
         let (concatted_secrets_buf, concatted_secret_size) = concat_with_known_maxlen!(
-            MAX_PUBKEY_SIZE,
-            &kex_res_eph.to_bytes(),
-            &kex_res_identity.to_bytes()
+            &kex_res_eph.to_bytes() => MAX_DH_SIZE,
+            &kex_res_identity.to_bytes() => MAX_DH_SIZE
         );
         let concatted_secrets = &concatted_secrets_buf[..concatted_secret_size];
 
@@ -349,12 +607,12 @@ pub(crate) fn decap<Kem: KemTrait>(
         // recipient pubkey, and the kex of the identity input with the recipient pubkey. The
         // HKDF-Expand call only errors if the output values are 255x the digest size of the hash
         // function. Since these values are fixed at compile time, we don't worry about it.
-        let mut shared_secret = <SharedSecret<Kem> as Default>::default();
+        let mut shared_secret = SharedSecret::<Kem>::zeroed();
         extract_and_expand::<Kem>(
             &concatted_secrets,
             &suite_id,
             &kem_context,
-            &mut shared_secret,
+            &mut shared_secret.0,
         )
         .expect("shared secret is way too big");
         Ok(shared_secret)
@@ -363,9 +621,8 @@ pub(crate) fn decap<Kem: KemTrait>(
         // We concat without allocation by making a buffer of the maximum possible size, then
         // taking the appropriately sized slice.
         let (kem_context_buf, kem_context_size) = concat_with_known_maxlen!(
-            MAX_PUBKEY_SIZE,
-            &encapped_key.to_bytes(),
-            &pk_recip.to_bytes()
+            enc => MAX_PUBKEY_SIZE,
+            &pk_recip.to_bytes() => MAX_PUBKEY_SIZE
         );
         let kem_context = &kem_context_buf[..kem_context_size];
 
@@ -373,12 +630,12 @@ pub(crate) fn decap<Kem: KemTrait>(
         // recipient pubkey. The HKDF-Expand call only errors if the output values are 255x the
         // digest size of the hash function. Since these values are fixed at compile time, we don't
         // worry about it.
-        let mut shared_secret = <SharedSecret<Kem> as Default>::default();
+        let mut shared_secret = SharedSecret::<Kem>::zeroed();
         extract_and_expand::<Kem>(
             &kex_res_eph.to_bytes(),
             &suite_id,
             &kem_context,
-            &mut shared_secret,
+            &mut shared_secret.0,
         )
         .expect("shared secret is way too big");
         Ok(shared_secret)
@@ -387,9 +644,13 @@ pub(crate) fn decap<Kem: KemTrait>(
 
 #[cfg(test)]
 mod tests {
-    use crate::kem::{decap, encap, Deserializable, EncappedKey, Kem as KemTrait, Serializable};
+    use crate::kem::{
+        decap, encap, encap_with_eph, Deserializable, EncappedKey, Kem as KemTrait, Serializable,
+    };
+    use crate::kex::{KeyExchange, MAX_DH_SIZE, MAX_PUBKEY_SIZE};
 
-    use rand::{rngs::StdRng, SeedableRng};
+    use generic_array::typenum::Unsigned;
+    use rand::{rngs::StdRng, RngCore, SeedableRng};
 
     macro_rules! test_encap_correctness {
         ($test_name:ident, $kem_ty:ty) => {
@@ -410,7 +671,10 @@ mod tests {
                     decap::<Kem>(&sk_recip, None, &encapped_key).unwrap();
 
                 // Ensure that the encapsulated secret is what decap() derives
-                assert_eq!(auth_shared_secret, decapped_auth_shared_secret);
+                assert_eq!(
+                    auth_shared_secret.as_bytes(),
+                    decapped_auth_shared_secret.as_bytes()
+                );
 
                 //
                 // Now do it with the auth, i.e., using the sender's identity keys
@@ -432,7 +696,20 @@ mod tests {
                     decap::<Kem>(&sk_recip, Some(&pk_sender_id), &encapped_key).unwrap();
 
                 // Ensure that the encapsulated secret is what decap() derives
-                assert_eq!(auth_shared_secret, decapped_auth_shared_secret);
+                assert_eq!(
+                    auth_shared_secret.as_bytes(),
+                    decapped_auth_shared_secret.as_bytes()
+                );
+
+                // The raw bytes are the same ones the key schedule sees
+                #[cfg(feature = "danger")]
+                {
+                    let expected = auth_shared_secret.as_bytes().to_vec();
+                    assert_eq!(
+                        auth_shared_secret.into_bytes_dangerous().as_slice(),
+                        &expected[..]
+                    );
+                }
             }
         };
     }
@@ -453,8 +730,7 @@ mod tests {
                 // Serialize it
                 let encapped_key_bytes = encapped_key.to_bytes();
                 // Deserialize it
-                let new_encapped_key =
-                    EncappedKey::<<Kem as KemTrait>::Kex>::from_bytes(&encapped_key_bytes).unwrap();
+                let new_encapped_key = EncappedKey::<Kem>::from_bytes(&encapped_key_bytes).unwrap();
 
                 assert!(
                     new_encapped_key.0 == encapped_key.0,
@@ -464,13 +740,257 @@ mod tests {
         };
     }
 
+    // A 64-bit FNV-1a hasher, since std's hashers aren't available here
+    struct Fnv(u64);
+
+    impl core::hash::Hasher for Fnv {
+        fn write(&mut self, bytes: &[u8]) {
+            for b in bytes {
+                self.0 = (self.0 ^ u64::from(*b)).wrapping_mul(0x100000001b3);
+            }
+        }
+
+        fn finish(&self) -> u64 {
+            self.0
+        }
+    }
+
+    fn fnv_hash<T: core::hash::Hash>(val: &T) -> u64 {
+        let mut hasher = Fnv(0xcbf29ce484222325);
+        val.hash(&mut hasher);
+        core::hash::Hasher::finish(&hasher)
+    }
+
+    /// Tests that clones and decoded copies of an encapped key are equal to it and hash the same,
+    /// that other encapped keys aren't, and that `as_array` is the encoding
+    macro_rules! test_encapped_eq_hash {
+        ($test_name:ident, $kem_ty:ty) => {
+            #[test]
+            fn $test_name() {
+                type Kem = $kem_ty;
+
+                let mut csprng = StdRng::from_entropy();
+                let (_, pk_recip) = Kem::gen_keypair(&mut csprng);
+                let encapped_key = encap::<Kem, _>(&pk_recip, None, &mut csprng).unwrap().1;
+                let other_encapped_key = encap::<Kem, _>(&pk_recip, None, &mut csprng).unwrap().1;
+
+                assert_eq!(encapped_key.as_array(), &encapped_key.to_bytes());
+                assert_eq!(encapped_key.as_array().len(), Kem::N_ENC);
+
+                let cloned = encapped_key.clone();
+                let decoded = EncappedKey::<Kem>::from_bytes(encapped_key.as_array()).unwrap();
+                assert!(cloned == encapped_key);
+                assert!(decoded == encapped_key);
+                assert_eq!(fnv_hash(&cloned), fnv_hash(&encapped_key));
+                assert_eq!(fnv_hash(&decoded), fnv_hash(&encapped_key));
+
+                assert!(other_encapped_key != encapped_key);
+                assert_ne!(fnv_hash(&other_encapped_key), fnv_hash(&encapped_key));
+            }
+        };
+    }
+
+    /// Tests that batch keypair generation gives the same keys as deriving them one by one from
+    /// the same randomness
+    macro_rules! test_gen_keypairs {
+        ($test_name:ident, $kem_ty:ty) => {
+            #[test]
+            fn $test_name() {
+                type Kem = $kem_ty;
+                type Kex = <Kem as KemTrait>::Kex;
+
+                let n = 10;
+                let keypairs = Kem::gen_keypairs(n, &mut StdRng::seed_from_u64(0xfeed));
+                assert_eq!(keypairs.len(), n);
+
+                // Redo it with the same RNG output, one keypair at a time
+                let mut ikms = [[0u8; 32]; 10];
+                let mut csprng = StdRng::seed_from_u64(0xfeed);
+                for ikm in ikms.iter_mut() {
+                    csprng.fill_bytes(ikm);
+                }
+                for ((sk, pk), ikm) in keypairs.iter().zip(ikms.iter()) {
+                    let (expected_sk, expected_pk) = Kem::derive_keypair(ikm);
+                    assert_eq!(sk.to_bytes(), expected_sk.to_bytes());
+                    assert_eq!(pk.to_bytes(), expected_pk.to_bytes());
+                    assert_eq!(Kex::sk_to_pk(sk).to_bytes(), pk.to_bytes());
+                }
+
+                // They're all different
+                for (i, (sk1, _)) in keypairs.iter().enumerate() {
+                    for (sk2, _) in keypairs[i + 1..].iter() {
+                        assert_ne!(sk1.to_bytes(), sk2.to_bytes());
+                    }
+                }
+
+                assert!(Kem::gen_keypairs(0, &mut csprng).is_empty());
+            }
+        };
+    }
+
+    /// Tests that asking for more keypairs than fit in memory panics up front, rather than
+    /// overflowing the size of the keying material
+    #[cfg(feature = "x25519-dalek")]
+    #[test]
+    #[should_panic(expected = "too many keypairs")]
+    fn test_gen_keypairs_overflow() {
+        type Kem = crate::kem::X25519HkdfSha256;
+        Kem::gen_keypairs(usize::MAX / 32 + 1, &mut StdRng::seed_from_u64(0xfeed));
+    }
+
+    macro_rules! test_const_sizes {
+        ($test_name:ident, $kem_ty:ty) => {
+            /// Tests that serialized sizes can be used as array lengths, and agree with `size()`
+            #[test]
+            fn $test_name() {
+                type Kem = $kem_ty;
+                type Kex = <Kem as KemTrait>::Kex;
+
+                let mut csprng = StdRng::from_entropy();
+                let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+                let (shared_secret, encapped_key) =
+                    encap::<Kem, _>(&pk_recip, None, &mut csprng).unwrap();
+
+                let mut enc_buf = [0u8; EncappedKey::<Kem>::SIZE];
+                enc_buf.copy_from_slice(&encapped_key.to_bytes());
+                let mut pk_buf = [0u8; <Kex as KeyExchange>::PublicKey::SIZE];
+                pk_buf.copy_from_slice(&pk_recip.to_bytes());
+                let mut sk_buf = [0u8; <Kex as KeyExchange>::PrivateKey::SIZE];
+                sk_buf.copy_from_slice(&sk_recip.to_bytes());
+
+                assert_eq!(EncappedKey::<Kem>::SIZE, EncappedKey::<Kem>::size());
+                assert_eq!(
+                    <Kex as KeyExchange>::PublicKey::SIZE,
+                    <Kex as KeyExchange>::PublicKey::size()
+                );
+                assert_eq!(
+                    <Kex as KeyExchange>::PrivateKey::SIZE,
+                    <Kex as KeyExchange>::PrivateKey::size()
+                );
+                assert_eq!(
+                    shared_secret.as_bytes().len(),
+                    <Kem as KemTrait>::SharedSecretSize::USIZE
+                );
+                assert_eq!(Kem::N_ENC, encapped_key.to_bytes().len());
+                assert_eq!(Kem::N_PK, pk_recip.to_bytes().len());
+
+                // The scratch buffers of encap and decap fit this KEM
+                assert!(Kem::N_ENC <= MAX_PUBKEY_SIZE);
+                assert!(Kem::N_PK <= MAX_PUBKEY_SIZE);
+                assert!(<Kex as KeyExchange>::KexResult::SIZE <= MAX_DH_SIZE);
+            }
+        };
+    }
+
+    #[cfg(feature = "x25519-dalek")]
+    test_const_sizes!(test_const_sizes_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_const_sizes!(test_const_sizes_p256, crate::kem::DhP256HkdfSha256);
+    #[cfg(feature = "k256")]
+    test_const_sizes!(test_const_sizes_k256, crate::kem::DhK256HkdfSha256);
+
+    // The vectors below are from the base mode, HKDF-SHA256, AES-GCM-128 test vectors in
+    // test-vectors-403bf8c.json
+    macro_rules! test_kem_vector {
+        (
+            $test_name:ident,
+            $kem_ty:ty,
+            seed_r = $seed_r:expr,
+            sk_r = $sk_r:expr,
+            pk_r = $pk_r:expr,
+            sk_e = $sk_e:expr,
+            enc = $enc:expr,
+            shared_secret = $shared_secret:expr $(,)?
+        ) => {
+            /// Tests keygen, encap, and decap against a known answer
+            #[test]
+            fn $test_name() {
+                type Kem = $kem_ty;
+                type Kex = <Kem as KemTrait>::Kex;
+
+                // Keygen
+                let (sk_recip, pk_recip) = Kem::derive_keypair(&hex::decode($seed_r).unwrap());
+                // Compare private keys after a round trip, since X25519 clamps its scalars
+                let expected_sk_recip =
+                    <Kex as KeyExchange>::PrivateKey::from_bytes(&hex::decode($sk_r).unwrap())
+                        .unwrap();
+                assert_eq!(sk_recip.to_bytes(), expected_sk_recip.to_bytes());
+                assert_eq!(hex::encode(pk_recip.to_bytes()), $pk_r);
+
+                // Encap with the vector's ephemeral key
+                let sk_eph =
+                    <Kex as KeyExchange>::PrivateKey::from_bytes(&hex::decode($sk_e).unwrap())
+                        .unwrap();
+                let (shared_secret, encapped_key) =
+                    encap_with_eph::<Kem>(&pk_recip, None, sk_eph).unwrap();
+                assert_eq!(hex::encode(encapped_key.to_bytes()), $enc);
+                assert_eq!(hex::encode(shared_secret.as_bytes()), $shared_secret);
+
+                // Decap a freshly deserialized encapped key
+                let encapped_key =
+                    EncappedKey::<Kem>::from_bytes(&hex::decode($enc).unwrap()).unwrap();
+                let shared_secret = decap::<Kem>(&sk_recip, None, &encapped_key).unwrap();
+                assert_eq!(hex::encode(shared_secret.as_bytes()), $shared_secret);
+            }
+        };
+    }
+
+    #[cfg(feature = "x25519-dalek")]
+    test_kem_vector!(
+        test_kem_vector_x25519,
+        crate::kem::X25519HkdfSha256,
+        seed_r = "786b486b443b9b02b4ad58f40b2ff25849d90bfec2243e58633fb44a57b00ceb",
+        sk_r = "004cc3a1b9cda8f434b95ae616f598bba60fd409d3404b332e509e867d9aae8b",
+        pk_r = "61ac165a79c974295b32c508719358ed407546172af79385237befb9f0360870",
+        sk_e = "3e6e0099ed0dbba3664e3d6f126f216cd115e6dbf38aea3055b0dc3e04d0239c",
+        enc = "c8a9520417e8cb7aa606c36a5bef9f5d2db300b9ab9c908b4cb588f51418d351",
+        shared_secret = "033ca57fd76a73143519a19cd609fd2a2cf92b3926c10932802cf892dff579ad",
+    );
+    #[cfg(feature = "p256")]
+    test_kem_vector!(
+        test_kem_vector_p256,
+        crate::kem::DhP256HkdfSha256,
+        seed_r = "896313940ea96eadddfacb7fdcb59831fd2420da1abfc11ad5721220f1a2bd61",
+        sk_r = "23c7dc36c7e078f59c1f8b2bfbbd4180ee01695ce77224a073cdadc26478054f",
+        pk_r = concat!(
+            "04",
+            "09c3a7baf237672a1d3013b794d36081c5fbd60915160b76df36c4a6d346f1fc",
+            "c3027c81af6d67fdc75f7566231fd9ebc5f722612b1eb14eba454d84fdb698b8",
+        ),
+        sk_e = "24993538efc2b47e8fbdf1210e26f37d4b0cc41a655dbff47325f3faf317c171",
+        enc = concat!(
+            "04",
+            "c3a974e42246acba5d65b7acde0d9718b2d9ee1738465be496b28203a35cc90a",
+            "3b2c2fd2d1015b3940e068402c3eb5d777e2d387338fddbf2935e5e58a1e5380",
+        ),
+        shared_secret = "9ef5d8cb9235818b356404d047db0b2b8fbebef433913e185949e9bb40d0189a",
+    );
+
     #[cfg(feature = "x25519-dalek")]
     test_encap_correctness!(test_encap_correctness_x25519, crate::kem::X25519HkdfSha256);
     #[cfg(feature = "p256")]
     test_encap_correctness!(test_encap_correctness_p256, crate::kem::DhP256HkdfSha256);
+    #[cfg(feature = "k256")]
+    test_encap_correctness!(test_encap_correctness_k256, crate::kem::DhK256HkdfSha256);
 
     #[cfg(feature = "x25519-dalek")]
     test_encapped_serialize!(test_encapped_serialize_x25519, crate::kem::X25519HkdfSha256);
     #[cfg(feature = "p256")]
     test_encapped_serialize!(test_encapped_serialize_p256, crate::kem::DhP256HkdfSha256);
+    #[cfg(feature = "k256")]
+    test_encapped_serialize!(test_encapped_serialize_k256, crate::kem::DhK256HkdfSha256);
+
+    #[cfg(feature = "x25519-dalek")]
+    test_encapped_eq_hash!(test_encapped_eq_hash_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_encapped_eq_hash!(test_encapped_eq_hash_p256, crate::kem::DhP256HkdfSha256);
+    #[cfg(feature = "k256")]
+    test_encapped_eq_hash!(test_encapped_eq_hash_k256, crate::kem::DhK256HkdfSha256);
+
+    #[cfg(feature = "x25519-dalek")]
+    test_gen_keypairs!(test_gen_keypairs_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_gen_keypairs!(test_gen_keypairs_p256, crate::kem::DhP256HkdfSha256);
+    #[cfg(feature = "k256")]
+    test_gen_keypairs!(test_gen_keypairs_k256, crate::kem::DhK256HkdfSha256);
 }
@@ -1,22 +1,54 @@
-use crate::{kdf::Kdf as KdfTrait, util::KemSuiteId, HpkeError};
+use crate::{fingerprint::Fingerprintable, kdf::Kdf as KdfTrait, util::KemSuiteId, HpkeError};
 
 use generic_array::{typenum::marker_traits::Unsigned, ArrayLength, GenericArray};
+use rand::{CryptoRng, RngCore};
 
 #[cfg(feature = "serde_impls")]
 use serde::{Deserialize as SerdeDeserialize, Serialize as SerdeSerialize};
 
-// This is currently the maximum value of all of Npk, Ndh, and Nenc. It's achieved by P-521
+// This is currently the maximum value of Npk and Nenc. It's achieved by P-521
+#[cfg(not(feature = "tight-buffers"))]
 pub(crate) const MAX_PUBKEY_SIZE: usize = 133;
 
+// This is currently the maximum value of Ndh. It's achieved by P-521
+#[cfg(not(feature = "tight-buffers"))]
+pub(crate) const MAX_DH_SIZE: usize = 66;
+
+// With tight-buffers, the maxima are over the enabled KEMs only
+#[cfg(feature = "tight-buffers")]
+pub(crate) const MAX_PUBKEY_SIZE: usize = crate::util::max_of(&[
+    #[cfg(feature = "p256")]
+    <<DhP256 as KeyExchange>::PublicKey as Serializable>::SIZE,
+    #[cfg(feature = "k256")]
+    <<DhK256 as KeyExchange>::PublicKey as Serializable>::SIZE,
+    #[cfg(feature = "x25519-dalek")]
+    <<X25519 as KeyExchange>::PublicKey as Serializable>::SIZE,
+]);
+
+#[cfg(feature = "tight-buffers")]
+pub(crate) const MAX_DH_SIZE: usize = crate::util::max_of(&[
+    #[cfg(feature = "p256")]
+    <<DhP256 as KeyExchange>::KexResult as Serializable>::SIZE,
+    #[cfg(feature = "k256")]
+    <<DhK256 as KeyExchange>::KexResult as Serializable>::SIZE,
+    #[cfg(feature = "x25519-dalek")]
+    <<X25519 as KeyExchange>::KexResult as Serializable>::SIZE,
+]);
+
 /// Implemented by types that have a fixed-length byte representation
 pub trait Serializable {
     type OutputSize: ArrayLength<u8>;
 
+    /// The size (in bytes) of this type when serialized. Since this is a constant, it can be used
+    /// as an array length for a concrete type, e.g., `[u8; EncappedKey::<X25519HkdfSha256>::SIZE]`.
+    const SIZE: usize = Self::OutputSize::USIZE;
+
     fn to_bytes(&self) -> GenericArray<u8, Self::OutputSize>;
 
-    /// Returns the size (in bytes) of this type when serialized
+    /// Returns the size (in bytes) of this type when serialized. This is the same as
+    /// [`SIZE`](Self::SIZE).
     fn size() -> usize {
-        Self::OutputSize::to_usize()
+        Self::SIZE
     }
 }
 
@@ -25,16 +57,14 @@ pub trait Deserializable: Serializable + Sized {
     fn from_bytes(encoded: &[u8]) -> Result<Self, HpkeError>;
 }
 
-/// for converting kex result
-pub trait ToPubkeyBytes {
-    type OutputSize: ArrayLength<u8>;
-
-    fn to_pubkey_bytes(&self) -> GenericArray<u8, Self::OutputSize>;
-
-    /// Returns the size (in bytes) of this type when serialized
-    fn size() -> usize {
-        Self::OutputSize::to_usize()
-    }
+/// Implemented by the result of a key exchange, which is a group element. There are two ways to
+/// encode it. [`Serializable::to_bytes`] gives the spec's encoding of a DH result, which is `Ndh`
+/// bytes, e.g., just the x-coordinate. [`to_pubkey_bytes`](ToPubkeyBytes::to_pubkey_bytes) gives
+/// the encoding of the same element as a public key of type `Pk`, which is `Npk` bytes and is
+/// accepted by `Pk::from_bytes`.
+pub trait ToPubkeyBytes<Pk: Serializable>: Serializable {
+    /// Encodes this group element the way `Pk` encodes public keys
+    fn to_pubkey_bytes(&self) -> GenericArray<u8, Pk::OutputSize>;
 }
 
 /// This trait captures the requirements of a key exchange mechanism. It must have a way to
@@ -51,12 +81,13 @@ pub trait KeyExchange {
     type PublicKey: Clone
         + Serializable
         + Deserializable
+        + Fingerprintable
         + SerdeSerialize
         + for<'a> SerdeDeserialize<'a>;
     /// The key exchange's public key type. If you want to generate a keypair, see
     /// `Kem::gen_keypair` or `Kem::derive_keypair`
     #[cfg(not(feature = "serde_impls"))]
-    type PublicKey: Clone + Serializable + Deserializable;
+    type PublicKey: Clone + Serializable + Deserializable + Fingerprintable;
 
     /// The key exchange's private key type. If you want to generate a keypair, see
     /// `Kem::gen_keypair` or `Kem::derive_keypair`
@@ -71,20 +102,64 @@ pub trait KeyExchange {
     #[cfg(not(feature = "serde_impls"))]
     type PrivateKey: Clone + Serializable + Deserializable;
 
-    #[doc(hidden)]
-    type KexResult: Serializable + ToPubkeyBytes;
+    /// The output of [`kex`](KeyExchange::kex). Its [`to_bytes`](Serializable::to_bytes) is the
+    /// raw DH shared secret, as the spec defines it, e.g., the x-coordinate of the shared point.
+    /// See [`ToPubkeyBytes`] for its other encoding.
+    type KexResult: ToPubkeyBytes<Self::PublicKey>;
 
     #[doc(hidden)]
     fn sk_to_pk(sk: &Self::PrivateKey) -> Self::PublicKey;
 
-    #[doc(hidden)]
+    /// Does a raw DH operation between `sk` and `pk`. This is for protocols that use the same
+    /// keys for HPKE and for a DH of their own, e.g., a hybrid handshake or a Noise pattern, so
+    /// that they don't need a second curve library.
+    ///
+    /// This is a low-level primitive. The output is a raw group element, not a uniformly random
+    /// key, so always run it through a KDF with a label of your own before using it. Don't use a
+    /// keypair for both HPKE and another protocol unless that protocol's analysis allows it.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::InvalidKeyExchange)` if the DH result is the identity, i.e., all
+    /// zeros. The spec requires this check, so it's done here too.
     fn kex(sk: &Self::PrivateKey, pk: &Self::PublicKey) -> Result<Self::KexResult, HpkeError>;
 
+    /// Parses a public key like [`Deserializable::from_bytes`], but also rejects well-formed
+    /// encodings that aren't canonical, and says why an input was rejected. This is for servers
+    /// that want to tell garbled inputs apart from deliberately crafted ones in their logs.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::InvalidEncoding)` if `encoded` is malformed, e.g., the wrong
+    /// length. Returns `Err(HpkeError::NonCanonicalEncoding)` if it's a non-canonical encoding,
+    /// e.g., of an out-of-range coordinate. Returns `Err(HpkeError::InvalidPoint)` if it's a
+    /// canonical encoding of something that isn't a valid public key, e.g., a point that isn't on
+    /// the curve or that has low order.
+    fn pubkey_from_bytes_strict(encoded: &[u8]) -> Result<Self::PublicKey, HpkeError> {
+        <Self::PublicKey as Deserializable>::from_bytes(encoded)
+    }
+
     #[doc(hidden)]
     fn derive_keypair<Kdf: KdfTrait>(
         suite_id: &KemSuiteId,
         ikm: &[u8],
     ) -> (Self::PrivateKey, Self::PublicKey);
+
+    /// Makes a random ephemeral keypair and does a DH between it and `pk`. Returns the DH result
+    /// and the ephemeral pubkey. The private key never leaves this function, so an implementation
+    /// can use a secret type that can't be exported or stored. By default, this derives the
+    /// keypair from `Nsk` random bytes, like `Kem::gen_keypair`.
+    #[doc(hidden)]
+    fn ephemeral_kex<Kdf: KdfTrait, R: CryptoRng + RngCore>(
+        suite_id: &KemSuiteId,
+        pk: &Self::PublicKey,
+        csprng: &mut R,
+    ) -> Result<(Self::KexResult, Self::PublicKey), HpkeError> {
+        let mut ikm = GenericArray::<u8, <Self::PrivateKey as Serializable>::OutputSize>::default();
+        csprng.fill_bytes(&mut ikm);
+        let (sk, pk_eph) = Self::derive_keypair::<Kdf>(suite_id, &ikm);
+        Ok((Self::kex(&sk, pk)?, pk_eph))
+    }
 }
 
 #[cfg(feature = "p256")]
@@ -92,6 +167,11 @@ pub(crate) mod ecdh_nistp;
 #[cfg(feature = "p256")]
 pub use ecdh_nistp::DhP256;
 
+#[cfg(feature = "k256")]
+pub(crate) mod secp256k1;
+#[cfg(feature = "k256")]
+pub use secp256k1::DhK256;
+
 #[cfg(feature = "x25519-dalek")]
 pub(crate) mod x25519;
 #[cfg(feature = "x25519-dalek")]
@@ -1,4 +1,5 @@
 use crate::{
+    fingerprint::Fingerprintable,
     kdf::{labeled_extract, Kdf as KdfTrait, LabeledExpand},
     kex::{Deserializable, KeyExchange, Serializable, ToPubkeyBytes},
     util::KemSuiteId,
@@ -8,13 +9,21 @@ use crate::{
 use generic_array::{typenum, GenericArray};
 use p256::{
     elliptic_curve::{
-        weierstrass::{
-            point::{UncompressedPoint, UncompressedPointSize},
-            public_key::FromPublicKey,
-        },
-        Curve, FromBytes,
+        ops::Reduce,
+        point::AffineCoordinates,
+        sec1::{FromEncodedPoint, ToEncodedPoint, UncompressedPointSize},
+        Curve, Field, PrimeField,
     },
-    AffinePoint, NistP256, ProjectivePoint, Scalar,
+    AffinePoint, EncodedPoint, NistP256, ProjectivePoint, Scalar, U256,
+};
+#[cfg(feature = "p256-base-table")]
+use subtle::{ConditionallySelectable, ConstantTimeEq};
+#[cfg(feature = "threshold")]
+use {
+    crate::threshold::{KeyShare, PartialDh, ThresholdKex},
+    alloc::vec::Vec,
+    rand::{CryptoRng, RngCore},
+    zeroize::Zeroize,
 };
 
 /// An ECDH-P256 public key
@@ -30,6 +39,12 @@ pub struct PrivateKey(Scalar);
 // A bare DH computation result
 pub struct KexResult(AffinePoint);
 
+// The field modulus 2^256 - 2^224 + 2^192 + 2^96 - 1, big-endian
+const MODULUS_BE: [u8; 32] = [
+    0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+];
+
 // Everything is serialized and deserialized in uncompressed form
 impl Serializable for PublicKey {
     // A fancy way of saying "65 bytes"
@@ -38,7 +53,7 @@ impl Serializable for PublicKey {
 
     fn to_bytes(&self) -> GenericArray<u8, Self::OutputSize> {
         // Uncompressed pubkey
-        GenericArray::clone_from_slice(&self.0.to_pubkey(false).as_bytes())
+        GenericArray::clone_from_slice(self.0.to_encoded_point(false).as_bytes())
     }
 }
 
@@ -52,19 +67,14 @@ impl PublicKey {
             return None;
         }
 
-        // Parse as uncompressed curve point. This checks that the encoded point is well-formed,
-        // but does not check that the point is on the curve.
-        let uncompressed = {
-            let byte_arr = GenericArray::clone_from_slice(encoded);
-            UncompressedPoint::from_bytes(byte_arr)?
-        };
+        // Parse as a SEC1 point. Given the length, this checks that the tag is the uncompressed
+        // one, but does not check that the point is on the curve.
+        let uncompressed = EncodedPoint::from_bytes(encoded).ok()?;
 
-        // Convert to an affine point. This will fail if the point is not on the curve or if the
-        // point is the point at infinity. Both of these are invalid DH pubkeys.
-        let aff = {
-            let pubkey = p256::PublicKey::from(uncompressed);
-            AffinePoint::from_public_key(&pubkey)
-        };
+        // Convert to an affine point. This will fail if a coordinate isn't reduced or if the point
+        // is not on the curve. The point at infinity has no uncompressed encoding, so it can't
+        // show up here. All of these are invalid DH pubkeys.
+        let aff = AffinePoint::from_encoded_point(&uncompressed);
 
         if aff.is_some().into() {
             Some(PublicKey(aff.unwrap()))
@@ -82,17 +92,24 @@ impl Deserializable for PublicKey {
     }
 }
 
+impl Fingerprintable for PublicKey {
+    const FINGERPRINT_LABEL: &'static [u8] = b"P-256";
+}
+
 impl Serializable for PrivateKey {
     // A fancy way of saying "32 bytes"
     // §7.1: Nsecret of DHKEM(P-256, HKDF-SHA256) is 32
-    type OutputSize = <NistP256 as Curve>::ElementSize;
+    type OutputSize = <NistP256 as Curve>::FieldBytesSize;
 
     fn to_bytes(&self) -> GenericArray<u8, Self::OutputSize> {
         // Scalars already know how to convert to bytes
-        self.0.into()
+        self.0.to_bytes()
     }
 }
 
+// Private keys are parsed strictly: the big-endian integer has to be in the range `[1,p)`, the same
+// as in SEC1 and other HPKE implementations. A key that's out of range isn't a key, and reducing it
+// would mean two different byte strings import as the same key.
 impl Deserializable for PrivateKey {
     fn from_bytes(encoded: &[u8]) -> Result<Self, HpkeError> {
         // Check the length
@@ -103,14 +120,43 @@ impl Deserializable for PrivateKey {
         // Copy the bytes into a fixed-size array
         let arr = GenericArray::<u8, Self::OutputSize>::clone_from_slice(encoded);
 
-        // We do not allow private keys to be 0. This is so that we can avoid checking the output
-        // of the P256::kex() function (see docs there for more detail)
-        let scalar = Scalar::from_bytes_reduced(&arr);
-        if scalar.is_zero().into() {
+        // Reject anything at or above the group order
+        let scalar = Scalar::from_repr(arr);
+        if scalar.is_none().into() {
             return Err(HpkeError::InvalidEncoding);
         }
 
-        Ok(PrivateKey(scalar))
+        PrivateKey::from_nonzero(scalar.unwrap())
+    }
+}
+
+impl PrivateKey {
+    /// Imports a private key, reducing it mod the group order if it's out of range. This is the
+    /// behavior older versions of this crate had in [`Deserializable::from_bytes`]. It's only for
+    /// reading keys that were stored by something that didn't reduce them. A key imported this way
+    /// serializes to different bytes than it was imported from.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::InvalidEncoding)` if `encoded` isn't 32 bytes long, or if it's 0 mod
+    /// the group order.
+    pub fn from_bytes_reduced(encoded: &[u8]) -> Result<Self, HpkeError> {
+        if encoded.len() != 32 {
+            return Err(HpkeError::InvalidEncoding);
+        }
+
+        let arr = GenericArray::<u8, <Self as Serializable>::OutputSize>::clone_from_slice(encoded);
+        PrivateKey::from_nonzero(<Scalar as Reduce<U256>>::reduce_bytes(&arr))
+    }
+
+    // We do not allow private keys to be 0. This is so that we can avoid checking the output of
+    // the P256::kex() function (see docs there for more detail)
+    fn from_nonzero(scalar: Scalar) -> Result<Self, HpkeError> {
+        if scalar.is_zero().into() {
+            Err(HpkeError::InvalidEncoding)
+        } else {
+            Ok(PrivateKey(scalar))
+        }
     }
 }
 
@@ -121,23 +167,62 @@ impl Serializable for KexResult {
 
     // §4.1: Representation of the KEX result is the serialization of the x-coordinate
     fn to_bytes(&self) -> GenericArray<u8, Self::OutputSize> {
-        // The tagged compressed representation (according to SECG SEC-1) is 0x02 || x-coord or
-        // 0x03 || x-coord, depending on the parity of the y-coord (note it cannot be the point at
-        // infinity because it is not representable by the underlying type AffinePoint). Since the
-        // KEX result is defined by HPKE to just be the x-coord, we strip the first byte and return
-        // the rest.
-        let compressed_pubkey = self.0.to_pubkey(true);
-        let tagged_bytes = compressed_pubkey.as_bytes();
-        GenericArray::<u8, Self::OutputSize>::clone_from_slice(&tagged_bytes[1..])
+        // The result is never the point at infinity (see DhP256::kex()), so it has an
+        // x-coordinate, which is already serialized big-endian
+        self.0.x()
+    }
+}
+
+// The DH result is a curve point, so it's encoded as an uncompressed pubkey, like PublicKey is
+impl ToPubkeyBytes<PublicKey> for KexResult {
+    fn to_pubkey_bytes(&self) -> GenericArray<u8, UncompressedPointSize<NistP256>> {
+        GenericArray::clone_from_slice(self.0.to_encoded_point(false).as_bytes())
     }
 }
 
-impl ToPubkeyBytes for KexResult {
-    type OutputSize = typenum::U33;
+// Multiples of the generator, for fixed-base scalar multiplication. Entry 15*i + (d-1) is
+// d * 16^i * G, for 0 <= i < 64 and 1 <= d <= 15. Each entry is an uncompressed point without the
+// leading 0x04 tag. test_base_table recomputes all of it.
+#[cfg(feature = "p256-base-table")]
+static BASE_TABLE: &[u8; 64 * 15 * 64] = include_bytes!("p256_base_table.bin");
+
+/// Computes `k * G` using `BASE_TABLE`. The scalar is split into 64 windows of 4 bits, and each
+/// window adds one table entry, looked up in constant time. That's 64 additions, rather than the
+/// 256 doublings and 256 additions of the generic multiplication.
+#[cfg(feature = "p256-base-table")]
+fn mul_by_generator(k: &Scalar) -> ProjectivePoint {
+    // Big-endian bytes of the scalar
+    let k_bytes = k.to_bytes();
+
+    let mut acc = ProjectivePoint::IDENTITY;
+    for i in 0..64 {
+        // Window i is the ith least significant nibble
+        let byte = k_bytes[31 - i / 2];
+        let digit = if i % 2 == 0 { byte & 0x0f } else { byte >> 4 };
+
+        // Scan the whole row for entry `digit`. If digit is 0, there's no entry, so we leave the
+        // first one in place and don't add it below.
+        let row = &BASE_TABLE[i * 15 * 64..(i + 1) * 15 * 64];
+        let mut entry = GenericArray::<u8, UncompressedPointSize<NistP256>>::default();
+        entry[0] = 0x04;
+        entry[1..].copy_from_slice(&row[..64]);
+        for (d, candidate) in (1u8..).zip(row.chunks_exact(64)) {
+            let choice = digit.ct_eq(&d);
+            for (e, c) in entry[1..].iter_mut().zip(candidate) {
+                e.conditional_assign(c, choice);
+            }
+        }
 
-    fn to_pubkey_bytes(&self) -> GenericArray<u8, typenum::U33> {
-        GenericArray::<u8, Self::OutputSize>::clone_from_slice(&self.0.to_pubkey(true).as_bytes())
+        // Every entry is a 0x04 tag and two coordinates of a point on the curve, as
+        // test_base_table checks, so neither of these can fail
+        let point = EncodedPoint::from_bytes(entry).expect("base table entry has the wrong size");
+        let point = Option::<AffinePoint>::from(AffinePoint::from_encoded_point(&point))
+            .expect("base table entry is not on the curve");
+        let sum = acc + point;
+        acc.conditional_assign(&sum, !digit.ct_eq(&0));
     }
+
+    acc
 }
 
 /// Represents ECDH functionality over NIST curve P-256
@@ -148,34 +233,52 @@ impl KeyExchange for DhP256 {
     type PublicKey = PublicKey;
     #[doc(hidden)]
     type PrivateKey = PrivateKey;
-    #[doc(hidden)]
     type KexResult = KexResult;
 
     /// Converts an P256 private key to a public key
     #[doc(hidden)]
     fn sk_to_pk(sk: &PrivateKey) -> PublicKey {
-        let pk = p256::ProjectivePoint::generator() * &sk.0;
-        // It's safe to unwrap() here, because PrivateKeys are guaranteed to never be 0 (see the
-        // from_bytes() implementation for details)
-        PublicKey(pk.to_affine().unwrap())
+        #[cfg(feature = "p256-base-table")]
+        let pk = mul_by_generator(&sk.0);
+        #[cfg(not(feature = "p256-base-table"))]
+        let pk = ProjectivePoint::GENERATOR * sk.0;
+        // PrivateKeys are guaranteed to never be 0 (see the from_bytes() implementation for
+        // details), so this is never the point at infinity
+        PublicKey(pk.to_affine())
     }
 
     /// Does the DH operation. Returns `HpkeError::InvalidKeyExchange` if and only if the DH
     /// result was all zeros. This is required by the HPKE spec.
-    #[doc(hidden)]
     fn kex(sk: &PrivateKey, pk: &PublicKey) -> Result<KexResult, HpkeError> {
         // Convert to a projective point so we can do arithmetic
         let pk_proj: ProjectivePoint = pk.0.into();
         // Do the DH operation
-        let dh_res_proj = pk_proj * &sk.0;
+        let dh_res_proj = pk_proj * sk.0;
 
-        // We can unwrap here because we know
+        // This is a valid public key because we know
         // 1. pk is not the point at infinity (since this has no affine representation)
         // 2. sk is not 0 mod p (due to the invariant we keep on PrivateKeys)
         // 3. Exponentiating a non-identity element of a prime-order group by something less than
         //    the order yields a non-identity value
         // Therefore, dh_res_proj cannot be the point at infinity
-        Ok(KexResult(dh_res_proj.to_affine().unwrap()))
+        Ok(KexResult(dh_res_proj.to_affine()))
+    }
+
+    /// Parses an uncompressed P-256 public key. A key whose x or y coordinate isn't reduced mod
+    /// the field modulus is non-canonical. A key that isn't on the curve is invalid.
+    fn pubkey_from_bytes_strict(encoded: &[u8]) -> Result<PublicKey, HpkeError> {
+        // An uncompressed point is 0x04 || x || y
+        if encoded.len() != PublicKey::size() || encoded[0] != 0x04 {
+            return Err(HpkeError::InvalidEncoding);
+        }
+
+        // The coordinates are big-endian, so they compare with the modulus lexicographically
+        let (x, y) = encoded[1..].split_at(32);
+        if x >= &MODULUS_BE[..] || y >= &MODULUS_BE[..] {
+            return Err(HpkeError::NonCanonicalEncoding);
+        }
+
+        PublicKey::from_bytes_helper(encoded).ok_or(HpkeError::InvalidPoint)
     }
 
     // From the DeriveKeyPair section
@@ -216,7 +319,7 @@ impl KeyExchange for DhP256 {
                 .unwrap();
 
             // Try to convert to a scalar
-            let sk_scalar = Scalar::from_bytes(&buf);
+            let sk_scalar = Scalar::from_repr(buf);
 
             // If the conversion succeeded, return the keypair
             if sk_scalar.is_some().into() {
@@ -232,12 +335,114 @@ impl KeyExchange for DhP256 {
     }
 }
 
+// Shares are Shamir shares over the scalar field, and partials are combined in the exponent with
+// Lagrange coefficients, so the whole key is never reconstructed
+#[cfg(feature = "threshold")]
+impl ThresholdKex for DhP256 {
+    fn split_private_key<R: CryptoRng + RngCore>(
+        sk: &PrivateKey,
+        threshold: u32,
+        num_shares: u32,
+        csprng: &mut R,
+    ) -> Vec<KeyShare<Self>> {
+        assert!(
+            threshold > 0 && threshold <= num_shares,
+            "threshold must be between 1 and the number of shares"
+        );
+
+        // Samples a uniform scalar. We can't use Scalar::random, since it takes a newer rand_core
+        let mut random_scalar = || {
+            let mut buf = GenericArray::<u8, <PrivateKey as Serializable>::OutputSize>::default();
+            loop {
+                csprng.fill_bytes(&mut buf);
+                let scalar = Scalar::from_repr(buf);
+                if scalar.is_some().into() {
+                    buf.as_mut_slice().zeroize();
+                    return scalar.unwrap();
+                }
+            }
+        };
+
+        loop {
+            // A random polynomial of degree threshold-1 whose constant term is the key
+            let mut coeffs = Vec::with_capacity(threshold as usize);
+            coeffs.push(sk.0);
+            coeffs.extend((1..threshold).map(|_| random_scalar()));
+
+            // Evaluate it at 1, ..., num_shares using Horner's rule
+            let mut shares: Vec<Scalar> = (1..=num_shares)
+                .map(|x| {
+                    let x = Scalar::from(u64::from(x));
+                    coeffs.iter().rev().fold(Scalar::ZERO, |acc, c| acc * x + c)
+                })
+                .collect();
+
+            // A share of 0 isn't a valid private key. This essentially never happens, so just
+            // pick another polynomial.
+            let key_shares = if shares.iter().any(|s| bool::from(s.is_zero())) {
+                None
+            } else {
+                Some(
+                    (1..=num_shares)
+                        .zip(shares.iter())
+                        .map(|(index, &share)| KeyShare {
+                            index,
+                            share: PrivateKey(share),
+                        })
+                        .collect(),
+                )
+            };
+
+            // The coefficients include the key, and the shares are enough to rebuild it. Zero
+            // both before they're freed.
+            coeffs.as_mut_slice().zeroize();
+            shares.as_mut_slice().zeroize();
+
+            if let Some(key_shares) = key_shares {
+                return key_shares;
+            }
+        }
+    }
+
+    fn combine_partials(partials: &[PartialDh<Self>]) -> Result<KexResult, HpkeError> {
+        // Indices must be nonzero and distinct for the interpolation to be defined
+        if partials.is_empty() {
+            return Err(HpkeError::InvalidKeyExchange);
+        }
+        for (i, p) in partials.iter().enumerate() {
+            if p.index == 0 || partials[..i].iter().any(|q| q.index == p.index) {
+                return Err(HpkeError::InvalidKeyExchange);
+            }
+        }
+
+        // The DH of the whole key is sum_i λ_i * partial_i, where λ_i = prod_{j != i} x_j/(x_j - x_i)
+        // is the Lagrange coefficient of x_i at 0
+        let mut sum = ProjectivePoint::IDENTITY;
+        for p in partials {
+            let x_i = Scalar::from(u64::from(p.index));
+            let mut lambda = Scalar::ONE;
+            for q in partials.iter().filter(|q| q.index != p.index) {
+                let x_j = Scalar::from(u64::from(q.index));
+                // The indices are distinct, so x_j - x_i is invertible
+                lambda *= x_j * (x_j - x_i).invert().unwrap();
+            }
+            sum += ProjectivePoint::from(p.value.0) * lambda;
+        }
+
+        // The identity has no x-coordinate. A real DH result is never the identity (see kex()).
+        if sum == ProjectivePoint::IDENTITY {
+            return Err(HpkeError::InvalidKeyExchange);
+        }
+        Ok(KexResult(sum.to_affine()))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{
         kex::{
             ecdh_nistp::{DhP256, PrivateKey, PublicKey},
-            Deserializable, KeyExchange, Serializable,
+            Deserializable, KeyExchange, Serializable, ToPubkeyBytes,
         },
         test_util::kex_gen_keypair,
     };
@@ -374,4 +579,176 @@ mod tests {
         assert!(new_sk == sk, "private key doesn't serialize correctly");
         assert!(new_pk == pk, "public key doesn't serialize correctly");
     }
+
+    /// Tests that the raw DH agrees on both sides
+    #[test]
+    fn test_kex_agreement() {
+        type Kex = DhP256;
+
+        let mut csprng = StdRng::from_entropy();
+        let (sk1, pk1) = kex_gen_keypair::<Kex, _>(&mut csprng);
+        let (sk2, pk2) = kex_gen_keypair::<Kex, _>(&mut csprng);
+
+        let dh1 = Kex::kex(&sk1, &pk2).unwrap().to_bytes();
+        let dh2 = Kex::kex(&sk2, &pk1).unwrap().to_bytes();
+        assert_eq!(dh1, dh2);
+    }
+
+    /// Tests that a DH result encoded as a pubkey parses as one, and has the DH result as its
+    /// x-coordinate
+    #[test]
+    fn test_kex_result_to_pubkey_bytes() {
+        type Kex = DhP256;
+
+        let mut csprng = StdRng::from_entropy();
+        let (sk, _) = kex_gen_keypair::<Kex, _>(&mut csprng);
+        let (_, pk) = kex_gen_keypair::<Kex, _>(&mut csprng);
+
+        let kex_res = Kex::kex(&sk, &pk).unwrap();
+        let pubkey_bytes = kex_res.to_pubkey_bytes();
+        assert_eq!(pubkey_bytes.len(), PublicKey::size());
+        assert!(PublicKey::from_bytes(&pubkey_bytes).is_ok());
+        assert_eq!(&pubkey_bytes[1..33], kex_res.to_bytes().as_slice());
+    }
+
+    /// Tests that private keys at or above the group order are rejected, unless the caller asks
+    /// for them to be reduced
+    #[test]
+    fn test_privkey_strict() {
+        use crate::HpkeError;
+        type Kex = DhP256;
+
+        // The group order, big-endian
+        let order = hex::decode("ffffffff00000000ffffffffffffffffbce6faada7179e84f3b9cac2fc632551")
+            .unwrap();
+        let mut order_plus_one = order.clone();
+        order_plus_one[31] += 1;
+        let mut order_minus_one = order.clone();
+        order_minus_one[31] -= 1;
+        let mut one = [0u8; 32];
+        one[31] = 1;
+
+        // The largest valid scalar parses either way, and round-trips
+        let sk = PrivateKey::from_bytes(&order_minus_one).unwrap();
+        assert_eq!(sk.to_bytes().as_slice(), order_minus_one.as_slice());
+        assert!(PrivateKey::from_bytes_reduced(&order_minus_one).unwrap() == sk);
+
+        // Strict parsing rejects the order and anything above it
+        for bad in [&order[..], &order_plus_one[..], &[0xff; 32][..]].iter() {
+            assert!(matches!(
+                PrivateKey::from_bytes(bad),
+                Err(HpkeError::InvalidEncoding)
+            ));
+        }
+
+        // Reduced parsing turns order + 1 into 1, with the same pubkey, and still rejects 0
+        let reduced = PrivateKey::from_bytes_reduced(&order_plus_one).unwrap();
+        assert_eq!(reduced.to_bytes().as_slice(), &one[..]);
+        assert!(Kex::sk_to_pk(&reduced) == Kex::sk_to_pk(&PrivateKey::from_bytes(&one).unwrap()));
+        assert!(matches!(
+            PrivateKey::from_bytes_reduced(&order),
+            Err(HpkeError::InvalidEncoding)
+        ));
+        assert!(matches!(
+            PrivateKey::from_bytes(&[0u8; 32]),
+            Err(HpkeError::InvalidEncoding)
+        ));
+        assert!(matches!(
+            PrivateKey::from_bytes_reduced(&one[..31]),
+            Err(HpkeError::InvalidEncoding)
+        ));
+    }
+
+    /// Tests that strict parsing tells malformed, non-canonical, and off-curve keys apart
+    #[test]
+    fn test_pubkey_strict() {
+        use super::MODULUS_BE;
+        use crate::HpkeError;
+        type Kex = DhP256;
+
+        let mut csprng = StdRng::from_entropy();
+        let (_, pk) = kex_gen_keypair::<Kex, _>(&mut csprng);
+        let pk_bytes = pk.to_bytes();
+        assert!(Kex::pubkey_from_bytes_strict(&pk_bytes).unwrap() == pk);
+
+        // A compressed tag or a truncated key is malformed
+        let mut compressed_tag = pk_bytes;
+        compressed_tag[0] = 0x02;
+        assert!(matches!(
+            Kex::pubkey_from_bytes_strict(&compressed_tag),
+            Err(HpkeError::InvalidEncoding)
+        ));
+        assert!(matches!(
+            Kex::pubkey_from_bytes_strict(&pk_bytes[..64]),
+            Err(HpkeError::InvalidEncoding)
+        ));
+
+        // A coordinate equal to the modulus is out of range
+        let mut unreduced_x = pk_bytes;
+        unreduced_x[1..33].copy_from_slice(&MODULUS_BE);
+        assert!(matches!(
+            Kex::pubkey_from_bytes_strict(&unreduced_x),
+            Err(HpkeError::NonCanonicalEncoding)
+        ));
+        let mut unreduced_y = pk_bytes;
+        unreduced_y[33..].copy_from_slice(&MODULUS_BE);
+        assert!(matches!(
+            Kex::pubkey_from_bytes_strict(&unreduced_y),
+            Err(HpkeError::NonCanonicalEncoding)
+        ));
+
+        // Changing y moves the point off the curve
+        let mut off_curve = pk_bytes;
+        off_curve[64] ^= 1;
+        assert!(matches!(
+            Kex::pubkey_from_bytes_strict(&off_curve),
+            Err(HpkeError::InvalidPoint)
+        ));
+    }
+
+    /// Tests that every entry of the precomputed table is the multiple of the generator it says
+    #[cfg(feature = "p256-base-table")]
+    #[test]
+    fn test_base_table() {
+        use super::BASE_TABLE;
+        use p256::{
+            elliptic_curve::{sec1::ToEncodedPoint, Group},
+            ProjectivePoint,
+        };
+
+        let mut entries = BASE_TABLE.chunks_exact(64);
+        // 16^i * G
+        let mut base = ProjectivePoint::GENERATOR;
+        for _ in 0..64 {
+            let mut multiple = base;
+            for _ in 1..16 {
+                let expected = multiple.to_affine().to_encoded_point(false);
+                assert_eq!(entries.next().unwrap(), &expected.as_bytes()[1..]);
+                multiple += &base;
+            }
+            base = base.double().double().double().double();
+        }
+        assert!(entries.next().is_none());
+    }
+
+    /// Tests that the table-based multiplication agrees with the generic one
+    #[cfg(feature = "p256-base-table")]
+    #[test]
+    fn test_mul_by_generator() {
+        use super::mul_by_generator;
+        use p256::{elliptic_curve::Field, ProjectivePoint, Scalar};
+
+        let mut csprng = StdRng::from_entropy();
+
+        // Scalars with zero windows, all-ones windows, and random windows
+        let mut scalars = vec![Scalar::ONE, -Scalar::ONE, Scalar::from(0x1000_0001u64)];
+        for _ in 0..16 {
+            scalars.push(kex_gen_keypair::<DhP256, _>(&mut csprng).0 .0);
+        }
+
+        for k in scalars.iter() {
+            let expected = (ProjectivePoint::GENERATOR * k).to_affine();
+            assert_eq!(mul_by_generator(k).to_affine(), expected);
+        }
+    }
 }
@@ -0,0 +1,414 @@
+use crate::{
+    fingerprint::Fingerprintable,
+    kdf::{labeled_extract, Kdf as KdfTrait, LabeledExpand},
+    kex::{Deserializable, KeyExchange, Serializable, ToPubkeyBytes},
+    util::KemSuiteId,
+    HpkeError,
+};
+
+use generic_array::{typenum, GenericArray};
+use k256::{
+    elliptic_curve::{
+        ops::Reduce,
+        point::AffineCoordinates,
+        sec1::{FromEncodedPoint, ToEncodedPoint, UncompressedPointSize},
+        Curve, PrimeField,
+    },
+    AffinePoint, EncodedPoint, ProjectivePoint, Scalar, Secp256k1, U256,
+};
+
+/// An ECDH-secp256k1 public key. This is the same kind of key that Bitcoin and Ethereum wallets
+/// use.
+#[derive(Clone)]
+pub struct PublicKey(AffinePoint);
+
+// Like with P-256, private keys are never 0, so that sk_to_pk is well-defined
+/// An ECDH-secp256k1 private key. This is a scalar in the range `[1,n)` where `n` is the group
+/// order.
+#[derive(Clone)]
+pub struct PrivateKey(Scalar);
+
+// A bare DH computation result
+pub struct KexResult(AffinePoint);
+
+// The field modulus 2^256 - 2^32 - 977, big-endian
+const MODULUS_BE: [u8; 32] = [
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe, 0xff, 0xff, 0xfc, 0x2f,
+];
+
+impl Serializable for PublicKey {
+    // A fancy way of saying "65 bytes"
+    type OutputSize = UncompressedPointSize<Secp256k1>;
+
+    fn to_bytes(&self) -> GenericArray<u8, Self::OutputSize> {
+        // Uncompressed pubkey
+        GenericArray::clone_from_slice(self.0.to_encoded_point(false).as_bytes())
+    }
+}
+
+impl PublicKey {
+    fn from_bytes_helper(encoded: &[u8]) -> Option<PublicKey> {
+        // Only uncompressed points are accepted. Wallets often store compressed keys. Those have
+        // to be decompressed before they're used here.
+        if encoded.len() != Self::size() {
+            return None;
+        }
+
+        // Parse as a SEC1 point. Given the length, this checks that the tag is the uncompressed
+        // one, but does not check that the point is on the curve.
+        let uncompressed = EncodedPoint::from_bytes(encoded).ok()?;
+
+        // Convert to an affine point. This fails if a coordinate isn't reduced or if the point is
+        // not on the curve. The point at infinity has no uncompressed encoding, so it can't show
+        // up here. All of these are invalid DH pubkeys.
+        let aff = AffinePoint::from_encoded_point(&uncompressed);
+
+        if aff.is_some().into() {
+            Some(PublicKey(aff.unwrap()))
+        } else {
+            None
+        }
+    }
+}
+
+impl Deserializable for PublicKey {
+    fn from_bytes(encoded: &[u8]) -> Result<Self, HpkeError> {
+        // Run the from_bytes helper method and treat `None` as an encoding error
+        Self::from_bytes_helper(encoded).ok_or(HpkeError::InvalidEncoding)
+    }
+}
+
+impl Fingerprintable for PublicKey {
+    const FINGERPRINT_LABEL: &'static [u8] = b"secp256k1";
+}
+
+impl Serializable for PrivateKey {
+    // A fancy way of saying "32 bytes"
+    type OutputSize = <Secp256k1 as Curve>::FieldBytesSize;
+
+    fn to_bytes(&self) -> GenericArray<u8, Self::OutputSize> {
+        // Scalars already know how to convert to bytes
+        self.0.to_bytes()
+    }
+}
+
+impl Deserializable for PrivateKey {
+    fn from_bytes(encoded: &[u8]) -> Result<Self, HpkeError> {
+        // Check the length
+        if encoded.len() != 32 {
+            return Err(HpkeError::InvalidEncoding);
+        }
+
+        // Copy the bytes into a fixed-size array
+        let arr = GenericArray::<u8, Self::OutputSize>::clone_from_slice(encoded);
+
+        // Reject anything at or above the group order
+        let scalar = Scalar::from_repr(arr);
+        if scalar.is_none().into() {
+            return Err(HpkeError::InvalidEncoding);
+        }
+
+        PrivateKey::from_nonzero(scalar.unwrap())
+    }
+}
+
+impl PrivateKey {
+    /// Imports a private key, reducing it mod the group order if it's out of range. Wallet keys
+    /// are always in range, so this is only for keys that came from something that didn't check.
+    /// A key imported this way serializes to different bytes than it was imported from.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::InvalidEncoding)` if `encoded` isn't 32 bytes long, or if it's 0 mod
+    /// the group order.
+    pub fn from_bytes_reduced(encoded: &[u8]) -> Result<Self, HpkeError> {
+        if encoded.len() != 32 {
+            return Err(HpkeError::InvalidEncoding);
+        }
+
+        let arr = GenericArray::<u8, <Self as Serializable>::OutputSize>::clone_from_slice(encoded);
+        PrivateKey::from_nonzero(<Scalar as Reduce<U256>>::reduce_bytes(&arr))
+    }
+
+    // Private keys are never 0. This is so that we can avoid checking the output of kex()
+    fn from_nonzero(scalar: Scalar) -> Result<Self, HpkeError> {
+        if scalar.is_zero().into() {
+            Err(HpkeError::InvalidEncoding)
+        } else {
+            Ok(PrivateKey(scalar))
+        }
+    }
+}
+
+impl Serializable for KexResult {
+    // The DH result is the serialization of the x-coordinate, like in the NIST-curve DHKEMs
+    type OutputSize = typenum::U32;
+
+    fn to_bytes(&self) -> GenericArray<u8, Self::OutputSize> {
+        // The result is never the point at infinity (see DhK256::kex()), so it has an
+        // x-coordinate, which is already serialized big-endian
+        self.0.x()
+    }
+}
+
+// The DH result is a curve point, so it's encoded as an uncompressed pubkey, like PublicKey is
+impl ToPubkeyBytes<PublicKey> for KexResult {
+    fn to_pubkey_bytes(&self) -> GenericArray<u8, UncompressedPointSize<Secp256k1>> {
+        GenericArray::clone_from_slice(self.0.to_encoded_point(false).as_bytes())
+    }
+}
+
+/// Represents ECDH functionality over the curve secp256k1
+pub struct DhK256 {}
+
+impl KeyExchange for DhK256 {
+    #[doc(hidden)]
+    type PublicKey = PublicKey;
+    #[doc(hidden)]
+    type PrivateKey = PrivateKey;
+    type KexResult = KexResult;
+
+    /// Converts a secp256k1 private key to a public key
+    #[doc(hidden)]
+    fn sk_to_pk(sk: &PrivateKey) -> PublicKey {
+        // PrivateKeys are never 0, so this is never the point at infinity
+        PublicKey((ProjectivePoint::GENERATOR * sk.0).to_affine())
+    }
+
+    /// Does the DH operation. Returns `HpkeError::InvalidKeyExchange` if and only if the DH
+    /// result was all zeros. This is required by the HPKE spec.
+    fn kex(sk: &PrivateKey, pk: &PublicKey) -> Result<KexResult, HpkeError> {
+        let pk_proj: ProjectivePoint = pk.0.into();
+        let dh_res_proj = pk_proj * sk.0;
+
+        // secp256k1 has prime order and cofactor 1, so, as with P-256, a nonzero scalar times a
+        // point other than the point at infinity is never the point at infinity
+        Ok(KexResult(dh_res_proj.to_affine()))
+    }
+
+    /// Parses an uncompressed secp256k1 public key. A key whose x or y coordinate isn't reduced
+    /// mod the field modulus is non-canonical. A key that isn't on the curve is invalid.
+    fn pubkey_from_bytes_strict(encoded: &[u8]) -> Result<PublicKey, HpkeError> {
+        // An uncompressed point is 0x04 || x || y
+        if encoded.len() != PublicKey::size() || encoded[0] != 0x04 {
+            return Err(HpkeError::InvalidEncoding);
+        }
+
+        // The coordinates are big-endian, so they compare with the modulus lexicographically
+        let (x, y) = encoded[1..].split_at(32);
+        if x >= &MODULUS_BE[..] || y >= &MODULUS_BE[..] {
+            return Err(HpkeError::NonCanonicalEncoding);
+        }
+
+        PublicKey::from_bytes_helper(encoded).ok_or(HpkeError::InvalidPoint)
+    }
+
+    // This is the DeriveKeyPair of the NIST-curve DHKEMs, with bitmask = 0xFF, i.e., rejection
+    // sampling of scalars below the group order
+    /// Deterministically derives a keypair from the given input keying material and ciphersuite
+    /// ID. The keying material SHOULD have as many bits of entropy as the bit length of a secret
+    /// key, i.e., 256.
+    #[doc(hidden)]
+    fn derive_keypair<Kdf: KdfTrait>(suite_id: &KemSuiteId, ikm: &[u8]) -> (PrivateKey, PublicKey) {
+        // Write the label into a byte buffer and extract from the IKM
+        let (_, hkdf_ctx) = labeled_extract::<Kdf>(&[], suite_id, b"dkp_prk", ikm);
+
+        // The buffer we hold the candidate scalar bytes in. This is the size of a private key.
+        let mut buf = GenericArray::<u8, <PrivateKey as Serializable>::OutputSize>::default();
+
+        // Try to generate a key 256 times. Practically, this will succeed and return early on the
+        // first iteration.
+        for counter in 0u8..=255 {
+            // This unwrap is fine. It only triggers if buf is way too big. It's only 32 bytes.
+            hkdf_ctx
+                .labeled_expand(suite_id, b"candidate", &[counter], &mut buf)
+                .unwrap();
+
+            // Try to convert to a scalar. This fails at or above the group order.
+            let sk_scalar = Scalar::from_repr(buf);
+
+            // Zero is in range, but isn't a valid private key
+            if sk_scalar.is_some().into() {
+                if let Ok(sk) = PrivateKey::from_nonzero(sk_scalar.unwrap()) {
+                    let pk = Self::sk_to_pk(&sk);
+                    return (sk, pk);
+                }
+            }
+        }
+
+        // The code should never ever get here. The group order is within 2^-127 of 2^256, so
+        // the likelihood that we get 256 bad samples in a row is negligible.
+        panic!("DeriveKeyPair failed all attempts");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        kex::{
+            secp256k1::{DhK256, PrivateKey, PublicKey},
+            Deserializable, KeyExchange, Serializable, ToPubkeyBytes,
+        },
+        test_util::kex_gen_keypair,
+        HpkeError,
+    };
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    // We need this in our serialize-deserialize tests
+    impl PartialEq for PrivateKey {
+        fn eq(&self, other: &PrivateKey) -> bool {
+            self.to_bytes() == other.to_bytes()
+        }
+    }
+
+    // We need this in our serialize-deserialize tests
+    impl PartialEq for PublicKey {
+        fn eq(&self, other: &PublicKey) -> bool {
+            self.0 == other.0
+        }
+    }
+
+    impl core::fmt::Debug for PublicKey {
+        fn fmt(&self, f: &mut core::fmt::Formatter) -> Result<(), core::fmt::Error> {
+            write!(f, "PublicKey({:?})", self.0)
+        }
+    }
+
+    // The generator G of secp256k1, from SEC 2, and 2G
+    const G: &str = concat!(
+        "04",
+        "79BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F81798",
+        "483ADA7726A3C4655DA4FBFC0E1108A8FD17B448A68554199C47D08FFB10D4B8",
+    );
+    const G2: &str = concat!(
+        "04",
+        "C6047F9441ED7D6D3045406E95C07CD85C778E4B8CEF3CA7ABAC09B95C709EE5",
+        "1AE168FEA63DC339A3C58419466CEAEEF7F632653266D0E1236431A950CFE52A",
+    );
+
+    // Returns the private key with the given small value
+    fn small_sk(n: u8) -> PrivateKey {
+        let mut bytes = [0u8; 32];
+        bytes[31] = n;
+        PrivateKey::from_bytes(&bytes).unwrap()
+    }
+
+    /// Tests sk_to_pk and kex against the known multiples of the generator
+    #[test]
+    fn test_vector_generator() {
+        type Kex = DhK256;
+
+        let g = PublicKey::from_bytes(&hex::decode(G).unwrap()).unwrap();
+        let g2 = PublicKey::from_bytes(&hex::decode(G2).unwrap()).unwrap();
+        assert_eq!(Kex::sk_to_pk(&small_sk(1)), g);
+        assert_eq!(Kex::sk_to_pk(&small_sk(2)), g2);
+
+        // 2 * G is 2G, and the DH result is its x-coordinate
+        let dh = Kex::kex(&small_sk(2), &g).unwrap();
+        assert_eq!(dh.to_bytes().as_slice(), &g2.to_bytes()[1..33]);
+        assert_eq!(dh.to_pubkey_bytes(), g2.to_bytes());
+    }
+
+    /// Tests that an deserialize-serialize round-trip on a DH keypair ends up at the same values
+    #[test]
+    fn test_dh_serialize_correctness() {
+        type Kex = DhK256;
+
+        let mut csprng = StdRng::from_entropy();
+
+        // Make a random keypair and serialize it
+        let (sk, pk) = kex_gen_keypair::<Kex, _>(&mut csprng);
+        let (sk_bytes, pk_bytes) = (sk.to_bytes(), pk.to_bytes());
+
+        // Now deserialize those bytes
+        let new_sk = <Kex as KeyExchange>::PrivateKey::from_bytes(&sk_bytes).unwrap();
+        let new_pk = <Kex as KeyExchange>::PublicKey::from_bytes(&pk_bytes).unwrap();
+
+        // See if the deserialized values are the same as the initial ones
+        assert!(new_sk == sk, "private key doesn't serialize correctly");
+        assert!(new_pk == pk, "public key doesn't serialize correctly");
+    }
+
+    /// Tests that the raw DH agrees on both sides
+    #[test]
+    fn test_kex_agreement() {
+        type Kex = DhK256;
+
+        let mut csprng = StdRng::from_entropy();
+        let (sk1, pk1) = kex_gen_keypair::<Kex, _>(&mut csprng);
+        let (sk2, pk2) = kex_gen_keypair::<Kex, _>(&mut csprng);
+
+        let dh1 = Kex::kex(&sk1, &pk2).unwrap().to_bytes();
+        let dh2 = Kex::kex(&sk2, &pk1).unwrap().to_bytes();
+        assert_eq!(dh1, dh2);
+    }
+
+    /// Tests that private keys at or above the group order are rejected, unless the caller asks
+    /// for them to be reduced
+    #[test]
+    fn test_privkey_strict() {
+        type Kex = DhK256;
+
+        // The group order, big-endian
+        let order = hex::decode("fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141")
+            .unwrap();
+        let mut order_plus_one = order.clone();
+        order_plus_one[31] += 1;
+        let mut order_minus_one = order.clone();
+        order_minus_one[31] -= 1;
+
+        // The largest valid scalar parses, and round-trips
+        let sk = PrivateKey::from_bytes(&order_minus_one).unwrap();
+        assert_eq!(sk.to_bytes().as_slice(), order_minus_one.as_slice());
+
+        // Strict parsing rejects the order, and reduced parsing turns order + 1 into 1
+        assert!(matches!(
+            PrivateKey::from_bytes(&order),
+            Err(HpkeError::InvalidEncoding)
+        ));
+        let reduced = PrivateKey::from_bytes_reduced(&order_plus_one).unwrap();
+        assert!(reduced == small_sk(1));
+        assert_eq!(Kex::sk_to_pk(&reduced), Kex::sk_to_pk(&small_sk(1)));
+        assert!(matches!(
+            PrivateKey::from_bytes(&[0u8; 32]),
+            Err(HpkeError::InvalidEncoding)
+        ));
+    }
+
+    /// Tests that strict parsing tells malformed, non-canonical, and off-curve keys apart
+    #[test]
+    fn test_pubkey_strict() {
+        use super::MODULUS_BE;
+        type Kex = DhK256;
+
+        let mut csprng = StdRng::from_entropy();
+        let (_, pk) = kex_gen_keypair::<Kex, _>(&mut csprng);
+        let pk_bytes = pk.to_bytes();
+        assert!(Kex::pubkey_from_bytes_strict(&pk_bytes).unwrap() == pk);
+
+        // A compressed key isn't accepted
+        let compressed = k256::elliptic_curve::sec1::ToEncodedPoint::to_encoded_point(&pk.0, true);
+        assert!(matches!(
+            Kex::pubkey_from_bytes_strict(compressed.as_bytes()),
+            Err(HpkeError::InvalidEncoding)
+        ));
+
+        // A coordinate equal to the modulus is out of range
+        let mut unreduced_x = pk_bytes;
+        unreduced_x[1..33].copy_from_slice(&MODULUS_BE);
+        assert!(matches!(
+            Kex::pubkey_from_bytes_strict(&unreduced_x),
+            Err(HpkeError::NonCanonicalEncoding)
+        ));
+
+        // Changing y moves the point off the curve
+        let mut off_curve = pk_bytes;
+        off_curve[64] ^= 1;
+        assert!(matches!(
+            Kex::pubkey_from_bytes_strict(&off_curve),
+            Err(HpkeError::InvalidPoint)
+        ));
+    }
+}
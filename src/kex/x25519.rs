@@ -1,4 +1,5 @@
 use crate::{
+    fingerprint::Fingerprintable,
     kdf::{labeled_extract, Kdf as KdfTrait, LabeledExpand},
     kex::{Deserializable, KeyExchange, Serializable, ToPubkeyBytes},
     util::KemSuiteId,
@@ -6,6 +7,7 @@ use crate::{
 };
 
 use generic_array::{typenum, GenericArray};
+use rand::{CryptoRng, RngCore};
 use subtle::ConstantTimeEq;
 
 // We wrap the types in order to abstract away the dalek dep
@@ -22,7 +24,7 @@ pub struct KexResult(x25519_dalek::SharedSecret);
 
 // Oh I love me an excuse to break out type-level integers
 impl Serializable for PublicKey {
-    // §7.1: Nsecret of DHKEM(X25519, HKDF-SHA256) is 32
+    // §7.1: Npk of DHKEM(X25519, HKDF-SHA256) is 32
     type OutputSize = typenum::U32;
 
     // Dalek lets us convert pubkeys to [u8; 32]
@@ -46,6 +48,10 @@ impl Deserializable for PublicKey {
     }
 }
 
+impl Fingerprintable for PublicKey {
+    const FINGERPRINT_LABEL: &'static [u8] = b"X25519";
+}
+
 impl Serializable for PrivateKey {
     type OutputSize = typenum::U32;
 
@@ -54,18 +60,68 @@ impl Serializable for PrivateKey {
         GenericArray::clone_from_slice(&self.0.to_bytes())
     }
 }
+
 impl Deserializable for PrivateKey {
-    // Dalek also lets us convert [u8; 32] to scalars
+    /// Same as [`PrivateKey::from_clamped_bytes`]
     fn from_bytes(encoded: &[u8]) -> Result<Self, HpkeError> {
+        PrivateKey::from_clamped_bytes(encoded)
+    }
+}
+
+// Returns the given bytes with RFC 7748 clamping applied: the low 3 bits cleared, bit 255 cleared,
+// and bit 254 set
+fn clamp(mut bytes: [u8; 32]) -> [u8; 32] {
+    bytes[0] &= 0b1111_1000;
+    bytes[31] &= 0b0111_1111;
+    bytes[31] |= 0b0100_0000;
+    bytes
+}
+
+// X25519 implementations differ in what they do with a private key's bytes. RFC 7748, libsodium,
+// and Go all clamp the bytes whenever the key is used, but keep the bytes as given. By default
+// this crate clamps once, on import, so a key imported from unclamped bytes serializes to
+// different bytes. Either way the DH results are the same. The two constructors below make the
+// choice explicit.
+impl PrivateKey {
+    /// Imports 32 arbitrary bytes as a private key, clamping them the way RFC 7748's
+    /// `decodeScalar25519` does. This is what [`Deserializable::from_bytes`] does. The key acts the
+    /// same as it would in libsodium or Go, but [`Serializable::to_bytes`] returns the clamped
+    /// bytes, which differ from the input unless it was already clamped.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::InvalidEncoding)` if `encoded` isn't 32 bytes long.
+    pub fn from_clamped_bytes(encoded: &[u8]) -> Result<Self, HpkeError> {
         if encoded.len() != 32 {
             // Privkeys must be 32 bytes
-            Err(HpkeError::InvalidEncoding)
-        } else {
-            // Copy to a fixed-size array
-            let mut arr = [0u8; 32];
-            arr.copy_from_slice(encoded);
-            Ok(PrivateKey(x25519_dalek::StaticSecret::from(arr)))
+            return Err(HpkeError::InvalidEncoding);
         }
+
+        // Copy to a fixed-size array. Dalek keeps the bytes as given and only clamps when it uses
+        // them, so we clamp here.
+        let mut arr = [0u8; 32];
+        arr.copy_from_slice(encoded);
+        Ok(PrivateKey(x25519_dalek::StaticSecret::from(clamp(arr))))
+    }
+
+    /// Imports 32 arbitrary bytes as a private key, keeping them as given, the way libsodium and
+    /// Go do. The bytes are clamped whenever the key is used, so the key acts the same as one made
+    /// with [`from_clamped_bytes`](Self::from_clamped_bytes), but [`Serializable::to_bytes`]
+    /// returns the bytes unchanged. Use this when the bytes have to survive a round trip exactly,
+    /// e.g., when comparing against a key stored elsewhere.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::InvalidEncoding)` if `encoded` isn't 32 bytes long.
+    pub fn from_raw_scalar(encoded: &[u8]) -> Result<Self, HpkeError> {
+        if encoded.len() != 32 {
+            return Err(HpkeError::InvalidEncoding);
+        }
+
+        // Dalek clamps when it uses the bytes, so they can go in as they are
+        let mut arr = [0u8; 32];
+        arr.copy_from_slice(encoded);
+        Ok(PrivateKey(x25519_dalek::StaticSecret::from(arr)))
     }
 }
 
@@ -81,9 +137,44 @@ impl Serializable for KexResult {
     }
 }
 
-impl ToPubkeyBytes for KexResult {
-    type OutputSize = typenum::U32;
-
+// The field modulus 2^255 - 19, big-endian
+const MODULUS_BE: [u8; 32] = [
+    0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xed,
+];
+
+// The canonical encodings of the u-coordinates of the points of order 1, 2, 4, and 8. Every one
+// of these makes the DH result all zeros.
+const LOW_ORDER_POINTS: [[u8; 32]; 5] = [
+    // 0
+    [0u8; 32],
+    // 1
+    [
+        0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00,
+    ],
+    // The two points of order 8
+    [
+        0xe0, 0xeb, 0x7a, 0x7c, 0x3b, 0x41, 0xb8, 0xae, 0x16, 0x56, 0xe3, 0xfa, 0xf1, 0x9f, 0xc4,
+        0x6a, 0xda, 0x09, 0x8d, 0xeb, 0x9c, 0x32, 0xb1, 0xfd, 0x86, 0x62, 0x05, 0x16, 0x5f, 0x49,
+        0xb8, 0x00,
+    ],
+    [
+        0x5f, 0x9c, 0x95, 0xbc, 0xa3, 0x50, 0x8c, 0x24, 0xb1, 0xd0, 0xb1, 0x55, 0x9c, 0x83, 0xef,
+        0x5b, 0x04, 0x44, 0x5c, 0xc4, 0x58, 0x1c, 0x8e, 0x86, 0xd8, 0x22, 0x4e, 0xdd, 0xd0, 0x9f,
+        0x11, 0x57,
+    ],
+    // p - 1
+    [
+        0xec, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0x7f,
+    ],
+];
+
+// X25519 public keys and DH results are both just u-coordinates
+impl ToPubkeyBytes<PublicKey> for KexResult {
     fn to_pubkey_bytes(&self) -> GenericArray<u8, typenum::U32> {
         GenericArray::clone_from_slice(self.0.as_bytes())
     }
@@ -97,7 +188,6 @@ impl KeyExchange for X25519 {
     type PublicKey = PublicKey;
     #[doc(hidden)]
     type PrivateKey = PrivateKey;
-    #[doc(hidden)]
     type KexResult = KexResult;
 
     /// Converts an X25519 private key to a public key
@@ -108,16 +198,36 @@ impl KeyExchange for X25519 {
 
     /// Does the DH operation. Returns `HpkeError::InvalidKeyExchange` if and only if the DH
     /// result was all zeros. This is required by the HPKE spec.
-    #[doc(hidden)]
     fn kex(sk: &PrivateKey, pk: &PublicKey) -> Result<KexResult, HpkeError> {
-        let res = sk.0.diffie_hellman(&pk.0);
-        // "Senders and recipients MUST check whether the shared secret is the all-zero value
-        // and abort if so"
-        if res.as_bytes().ct_eq(&[0u8; 32]).into() {
-            Err(HpkeError::InvalidKeyExchange)
-        } else {
-            Ok(KexResult(res))
+        check_kex_result(sk.0.diffie_hellman(&pk.0))
+    }
+
+    /// Parses an X25519 public key, rejecting the encodings that X25519 itself tolerates. A key
+    /// with the high bit set, or a u-coordinate that isn't reduced mod 2^255 - 19, is
+    /// non-canonical. A key of low order is invalid, since it'd make the DH result all zeros.
+    fn pubkey_from_bytes_strict(encoded: &[u8]) -> Result<PublicKey, HpkeError> {
+        if encoded.len() != 32 {
+            return Err(HpkeError::InvalidEncoding);
+        }
+
+        // RFC 7748 says the high bit is ignored, so a canonical encoding leaves it unset
+        if encoded[31] & 0x80 != 0 {
+            return Err(HpkeError::NonCanonicalEncoding);
+        }
+        // The u-coordinate is little-endian. Flip it to compare it with the modulus.
+        let mut u_be = [0u8; 32];
+        for (b, e) in u_be.iter_mut().zip(encoded.iter().rev()) {
+            *b = *e;
+        }
+        if u_be >= MODULUS_BE {
+            return Err(HpkeError::NonCanonicalEncoding);
+        }
+
+        if LOW_ORDER_POINTS.iter().any(|p| &p[..] == encoded) {
+            return Err(HpkeError::InvalidPoint);
         }
+
+        PublicKey::from_bytes(encoded)
     }
 
     // def DeriveKeyPair(ikm):
@@ -137,21 +247,77 @@ impl KeyExchange for X25519 {
             .labeled_expand(suite_id, b"sk", &[], &mut buf)
             .unwrap();
 
-        let sk = x25519_dalek::StaticSecret::from(buf);
+        // Clamp the key the same way from_bytes() does, so that it serializes the same way
+        let sk = x25519_dalek::StaticSecret::from(clamp(buf));
         let pk = x25519_dalek::PublicKey::from(&sk);
 
         (PrivateKey(sk), PublicKey(pk))
     }
+
+    /// Does the KEX with a random `ReusableSecret`, which, unlike a `StaticSecret`, can't be
+    /// serialized. It's dropped, and zeroed, before this returns.
+    #[doc(hidden)]
+    fn ephemeral_kex<Kdf: KdfTrait, R: CryptoRng + RngCore>(
+        _suite_id: &KemSuiteId,
+        pk: &PublicKey,
+        csprng: &mut R,
+    ) -> Result<(KexResult, PublicKey), HpkeError> {
+        let sk = x25519_dalek::ReusableSecret::random_from_rng(NewRng(csprng));
+        let pk_eph = x25519_dalek::PublicKey::from(&sk);
+        Ok((
+            check_kex_result(sk.diffie_hellman(&pk.0))?,
+            PublicKey(pk_eph),
+        ))
+    }
 }
 
+// "Senders and recipients MUST check whether the shared secret is the all-zero value and abort if
+// so"
+fn check_kex_result(res: x25519_dalek::SharedSecret) -> Result<KexResult, HpkeError> {
+    if res.as_bytes().ct_eq(&[0u8; 32]).into() {
+        Err(HpkeError::InvalidKeyExchange)
+    } else {
+        Ok(KexResult(res))
+    }
+}
+
+// Dalek takes RNGs with the traits of rand_core 0.6. This passes one of ours, which are rand 0.7's,
+// through to it.
+struct NewRng<'a, R>(&'a mut R);
+
+impl<R: RngCore> rand_core::RngCore for NewRng<'_, R> {
+    fn next_u32(&mut self) -> u32 {
+        self.0.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.0.fill_bytes(dest)
+    }
+
+    // The error types of the two versions don't convert, so this panics on failure like
+    // fill_bytes does
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.0.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl<R: CryptoRng + RngCore> rand_core::CryptoRng for NewRng<'_, R> {}
+
 #[cfg(test)]
 mod tests {
     use crate::{
+        kdf::HkdfSha256,
         kex::{
             x25519::{PrivateKey, PublicKey, X25519},
-            Deserializable, KeyExchange, Serializable,
+            Deserializable, KeyExchange, Serializable, ToPubkeyBytes,
         },
         test_util::kex_gen_keypair,
+        HpkeError,
     };
     use rand::{rngs::StdRng, RngCore, SeedableRng};
 
@@ -169,6 +335,81 @@ mod tests {
         }
     }
 
+    // Test vector comes from §6.1 of RFC7748
+    // https://tools.ietf.org/html/rfc7748
+    /// Tests `sk_to_pk` and the DH op against known answers
+    #[test]
+    fn test_vector_ecdh() {
+        type Kex = X25519;
+
+        let sk_alice_bytes =
+            hex::decode("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a")
+                .unwrap();
+        let pk_alice_bytes =
+            hex::decode("8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a")
+                .unwrap();
+        let pk_bob_bytes =
+            hex::decode("de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f")
+                .unwrap();
+        let dh_res_bytes =
+            hex::decode("4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742")
+                .unwrap();
+
+        // Deserialize Alice's privkey and Bob's pubkey
+        let sk_alice = <Kex as KeyExchange>::PrivateKey::from_bytes(&sk_alice_bytes).unwrap();
+        let pk_bob = <Kex as KeyExchange>::PublicKey::from_bytes(&pk_bob_bytes).unwrap();
+
+        // Check that Alice's pubkey and the DH result match the test vector
+        let pk_alice = <Kex as KeyExchange>::sk_to_pk(&sk_alice);
+        assert_eq!(pk_alice.to_bytes().as_slice(), pk_alice_bytes.as_slice());
+        let derived_dh = <Kex as KeyExchange>::kex(&sk_alice, &pk_bob).unwrap();
+        assert_eq!(derived_dh.to_bytes().as_slice(), dh_res_bytes.as_slice());
+    }
+
+    /// Tests that clamped import clamps, that raw import keeps the bytes as given, and that the
+    /// two give the same public key
+    #[test]
+    fn test_privkey_clamping() {
+        type Kex = X25519;
+
+        // RFC 7748's Alice key, which isn't clamped
+        let raw = hex::decode("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a")
+            .unwrap();
+        let mut clamped = raw.clone();
+        clamped[0] &= 0xf8;
+        clamped[31] = (clamped[31] & 0x7f) | 0x40;
+        assert_ne!(raw, clamped);
+
+        // Clamped import changes the bytes, but not the public key
+        let sk = PrivateKey::from_clamped_bytes(&raw).unwrap();
+        assert_eq!(sk.to_bytes().as_slice(), clamped.as_slice());
+        assert_eq!(
+            <Kex as KeyExchange>::sk_to_pk(&sk).to_bytes().as_slice(),
+            hex::decode("8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a")
+                .unwrap()
+                .as_slice()
+        );
+
+        // Raw import round-trips the bytes, and clamps them on use, so the public key is the same
+        let raw_sk = PrivateKey::from_raw_scalar(&raw).unwrap();
+        assert_eq!(raw_sk.to_bytes().as_slice(), raw.as_slice());
+        assert!(<Kex as KeyExchange>::sk_to_pk(&raw_sk) == <Kex as KeyExchange>::sk_to_pk(&sk));
+
+        // The two agree on clamped input
+        let raw_sk = PrivateKey::from_raw_scalar(&clamped).unwrap();
+        assert_eq!(raw_sk.to_bytes(), sk.to_bytes());
+
+        // Both check the length
+        assert!(matches!(
+            PrivateKey::from_raw_scalar(&clamped[..31]),
+            Err(HpkeError::InvalidEncoding)
+        ));
+        assert!(matches!(
+            PrivateKey::from_clamped_bytes(&raw[..31]),
+            Err(HpkeError::InvalidEncoding)
+        ));
+    }
+
     /// Tests that an serialize-deserialize round-trip ends up at the same pubkey
     #[test]
     fn test_pubkey_serialize_correctness() {
@@ -211,4 +452,97 @@ mod tests {
         assert!(new_sk == sk, "private key doesn't serialize correctly");
         assert!(new_pk == pk, "public key doesn't serialize correctly");
     }
+
+    /// Tests that the raw DH agrees on both sides, that its pubkey encoding is the DH result, and
+    /// that it rejects the all-zero result
+    #[test]
+    fn test_kex_agreement() {
+        type Kex = X25519;
+
+        let mut csprng = StdRng::from_entropy();
+        let (sk1, pk1) = kex_gen_keypair::<Kex, _>(&mut csprng);
+        let (sk2, pk2) = kex_gen_keypair::<Kex, _>(&mut csprng);
+
+        let kex_res = Kex::kex(&sk1, &pk2).unwrap();
+        let dh1 = kex_res.to_bytes();
+        let dh2 = Kex::kex(&sk2, &pk1).unwrap().to_bytes();
+        assert_eq!(dh1, dh2);
+
+        // Encoded as a pubkey, the DH result is the same u-coordinate
+        assert_eq!(kex_res.to_pubkey_bytes(), dh1);
+
+        // The zero point has small order, so the DH with it is all zeros
+        let zero_pk = PublicKey::from_bytes(&[0u8; 32]).unwrap();
+        assert!(matches!(
+            Kex::kex(&sk1, &zero_pk),
+            Err(crate::HpkeError::InvalidKeyExchange)
+        ));
+    }
+
+    /// Tests that the KEX with a random ephemeral key agrees with the recipient's, and that it
+    /// also rejects the all-zero result
+    #[test]
+    fn test_ephemeral_kex_agreement() {
+        type Kex = X25519;
+        let suite_id = *b"KEM\x00\x20";
+
+        let mut csprng = StdRng::from_entropy();
+        let (sk_recip, pk_recip) = kex_gen_keypair::<Kex, _>(&mut csprng);
+
+        let (kex_res, pk_eph) =
+            Kex::ephemeral_kex::<HkdfSha256, _>(&suite_id, &pk_recip, &mut csprng).unwrap();
+        let dh_recip = Kex::kex(&sk_recip, &pk_eph).unwrap();
+        assert_eq!(kex_res.to_bytes(), dh_recip.to_bytes());
+
+        let zero_pk = PublicKey::from_bytes(&[0u8; 32]).unwrap();
+        assert!(matches!(
+            Kex::ephemeral_kex::<HkdfSha256, _>(&suite_id, &zero_pk, &mut csprng),
+            Err(crate::HpkeError::InvalidKeyExchange)
+        ));
+    }
+
+    /// Tests that strict parsing rejects non-canonical and low-order keys, and nothing else
+    #[test]
+    fn test_pubkey_strict() {
+        use super::{LOW_ORDER_POINTS, MODULUS_BE};
+        use crate::HpkeError;
+        type Kex = X25519;
+
+        let mut csprng = StdRng::from_entropy();
+        let (sk, pk) = kex_gen_keypair::<Kex, _>(&mut csprng);
+        let pk_bytes = pk.to_bytes();
+        assert!(Kex::pubkey_from_bytes_strict(&pk_bytes).unwrap() == pk);
+
+        assert!(matches!(
+            Kex::pubkey_from_bytes_strict(&pk_bytes[..31]),
+            Err(HpkeError::InvalidEncoding)
+        ));
+
+        // Setting the high bit gives the same key, but a non-canonical encoding
+        let mut high_bit = pk_bytes;
+        high_bit[31] |= 0x80;
+        assert!(PublicKey::from_bytes(&high_bit).is_ok());
+        assert!(matches!(
+            Kex::pubkey_from_bytes_strict(&high_bit),
+            Err(HpkeError::NonCanonicalEncoding)
+        ));
+
+        // So does an unreduced u-coordinate
+        let mut modulus_le = MODULUS_BE;
+        modulus_le.reverse();
+        assert!(matches!(
+            Kex::pubkey_from_bytes_strict(&modulus_le),
+            Err(HpkeError::NonCanonicalEncoding)
+        ));
+
+        // Every low-order point gives an all-zero DH result, and is rejected up front
+        for p in LOW_ORDER_POINTS.iter() {
+            let low_order_pk = PublicKey::from_bytes(p).unwrap();
+            assert!(Kex::kex(&sk, &low_order_pk).is_err());
+            assert!(matches!(
+                Kex::pubkey_from_bytes_strict(p),
+                Err(HpkeError::InvalidPoint)
+            ));
+        }
+    }
 }
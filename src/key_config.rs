@@ -0,0 +1,379 @@
+//! This module defines [`KeyConfig`], the format that
+//! [Oblivious HTTP](https://www.rfc-editor.org/rfc/rfc9458#section-3) uses to publish a recipient's
+//! public key along with the ciphersuites it accepts. Other gateway protocols publish keys in the
+//! same shape, so it's useful beyond OHTTP.
+//!
+//! A key config is serialized as
+//!
+//! ```text
+//! Key Config {
+//!   Key Identifier (8),
+//!   HPKE KEM ID (16),
+//!   HPKE Public Key (Npk * 8),
+//!   HPKE Symmetric Algorithms Length (16) = 4..65532,
+//!   HPKE Symmetric Algorithms (32) ...,
+//! }
+//! ```
+//!
+//! where each symmetric algorithm is a KDF ID followed by an AEAD ID. A list of key configs, as
+//! served with the `application/ohttp-keys` media type, is the concatenation of the key configs,
+//! each prefixed by its 2-byte length.
+//!
+//! A key config can also carry a [`ValidityWindow`] saying when the key may be used. The format
+//! has no room for it, so it isn't serialized, and a deserialized key config has none. Publishers
+//! who want clients to stop using a key on time have to convey the window some other way, e.g., in
+//! the HTTP caching headers of the key config.
+
+use crate::{
+    aead::Aead,
+    kdf::Kdf as KdfTrait,
+    kem::Kem as KemTrait,
+    kex::{Deserializable, KeyExchange, Serializable},
+    psk::ValidityWindow,
+    HpkeError,
+};
+
+use alloc::vec::Vec;
+
+type PublicKey<Kem> = <<Kem as KemTrait>::Kex as KeyExchange>::PublicKey;
+
+/// A KDF and AEAD pair that a recipient accepts
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SymmetricSuite {
+    pub kdf_id: u16,
+    pub aead_id: u16,
+}
+
+impl SymmetricSuite {
+    /// Returns the suite made of the given KDF and AEAD
+    pub fn new<Kdf: KdfTrait, A: Aead>() -> Self {
+        SymmetricSuite {
+            kdf_id: Kdf::KDF_ID,
+            aead_id: A::AEAD_ID,
+        }
+    }
+}
+
+/// A recipient's public key, its key ID, and the symmetric suites it accepts. The KEM ID is
+/// determined by `Kem`. Use [`KeyConfig::builder`] to make one.
+pub struct KeyConfig<Kem: KemTrait> {
+    key_id: u8,
+    public_key: PublicKey<Kem>,
+    suites: Vec<SymmetricSuite>,
+    validity: Option<ValidityWindow>,
+}
+
+// Can't derive Clone because that would require Kem: Clone
+impl<Kem: KemTrait> Clone for KeyConfig<Kem> {
+    fn clone(&self) -> Self {
+        KeyConfig {
+            key_id: self.key_id,
+            public_key: self.public_key.clone(),
+            suites: self.suites.clone(),
+            validity: self.validity,
+        }
+    }
+}
+
+impl<Kem: KemTrait> KeyConfig<Kem> {
+    /// Starts building a key config for the given key ID and public key
+    pub fn builder(key_id: u8, public_key: PublicKey<Kem>) -> KeyConfigBuilder<Kem> {
+        KeyConfigBuilder {
+            key_id,
+            public_key,
+            suites: Vec::new(),
+            validity: None,
+        }
+    }
+
+    /// Returns the key ID
+    pub fn key_id(&self) -> u8 {
+        self.key_id
+    }
+
+    /// Returns the KEM ID. This is always `Kem::KEM_ID`.
+    pub fn kem_id(&self) -> u16 {
+        Kem::KEM_ID
+    }
+
+    /// Returns the recipient's public key
+    pub fn public_key(&self) -> &PublicKey<Kem> {
+        &self.public_key
+    }
+
+    /// Returns the symmetric suites the recipient accepts, in order of the recipient's preference
+    pub fn suites(&self) -> &[SymmetricSuite] {
+        &self.suites
+    }
+
+    /// Returns when the key may be used, if that's known. This is never set on a deserialized key
+    /// config.
+    pub fn validity(&self) -> Option<ValidityWindow> {
+        self.validity
+    }
+
+    /// Returns whether the key may be used at time `now`. A key config with no validity window is
+    /// always valid.
+    pub fn is_valid_at(&self, now: u64) -> bool {
+        match self.validity {
+            Some(validity) => validity.contains(now),
+            None => true,
+        }
+    }
+
+    /// Returns whether the recipient accepts the given KDF and AEAD
+    pub fn supports<Kdf: KdfTrait, A: Aead>(&self) -> bool {
+        self.suites.contains(&SymmetricSuite::new::<Kdf, A>())
+    }
+
+    /// Serializes this key config in the format of RFC 9458 §3.1
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.push(self.key_id);
+        out.extend_from_slice(&Kem::KEM_ID.to_be_bytes());
+        out.extend_from_slice(&self.public_key.to_bytes());
+        out.extend_from_slice(&((4 * self.suites.len()) as u16).to_be_bytes());
+        for suite in self.suites.iter() {
+            out.extend_from_slice(&suite.kdf_id.to_be_bytes());
+            out.extend_from_slice(&suite.aead_id.to_be_bytes());
+        }
+        out
+    }
+
+    /// Deserializes a key config in the format of RFC 9458 §3.1. Returns
+    /// `Err(HpkeError::InvalidEncoding)` if the encoding is malformed, is for a different KEM, or
+    /// lists no symmetric suites.
+    pub fn from_bytes(encoded: &[u8]) -> Result<Self, HpkeError> {
+        let (config, rest) = Self::read(encoded)?;
+        if !rest.is_empty() {
+            return Err(HpkeError::InvalidEncoding);
+        }
+        Ok(config)
+    }
+
+    /// Serializes a list of key configs in the `application/ohttp-keys` format of RFC 9458 §3.2
+    ///
+    /// Panics
+    /// ======
+    /// Panics if a key config is longer than 65535 bytes.
+    pub fn list_to_bytes(configs: &[Self]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for config in configs.iter() {
+            let encoded = config.to_bytes();
+            assert!(encoded.len() <= u16::MAX as usize, "key config is too long");
+            out.extend_from_slice(&(encoded.len() as u16).to_be_bytes());
+            out.extend_from_slice(&encoded);
+        }
+        out
+    }
+
+    /// Deserializes a list of key configs in the `application/ohttp-keys` format of RFC 9458
+    /// §3.2. Returns `Err(HpkeError::InvalidEncoding)` if any config fails to deserialize. This
+    /// includes configs for other KEMs; if you publish keys for several KEMs, split the list
+    /// yourself.
+    pub fn list_from_bytes(mut encoded: &[u8]) -> Result<Vec<Self>, HpkeError> {
+        let mut configs = Vec::new();
+        while !encoded.is_empty() {
+            let (len, rest) = split_at_checked(encoded, 2)?;
+            let (config, rest) = split_at_checked(rest, read_u16(len) as usize)?;
+            configs.push(Self::from_bytes(config)?);
+            encoded = rest;
+        }
+        Ok(configs)
+    }
+
+    // Reads a key config off the front of the given buffer, and returns the rest of the buffer
+    fn read(encoded: &[u8]) -> Result<(Self, &[u8]), HpkeError> {
+        let (key_id, rest) = split_at_checked(encoded, 1)?;
+        let (kem_id, rest) = split_at_checked(rest, 2)?;
+        if read_u16(kem_id) != Kem::KEM_ID {
+            return Err(HpkeError::InvalidEncoding);
+        }
+        let (pk_bytes, rest) = split_at_checked(rest, PublicKey::<Kem>::size())?;
+        let public_key = PublicKey::<Kem>::from_bytes(pk_bytes)?;
+
+        // The suites length must be a nonzero multiple of 4
+        let (suites_len, rest) = split_at_checked(rest, 2)?;
+        let suites_len = read_u16(suites_len) as usize;
+        let (suite_bytes, rest) = split_at_checked(rest, suites_len)?;
+        let suite_chunks = suite_bytes.chunks_exact(4);
+        if suites_len == 0 || !suite_chunks.remainder().is_empty() {
+            return Err(HpkeError::InvalidEncoding);
+        }
+        let suites = suite_chunks
+            .map(|chunk| SymmetricSuite {
+                kdf_id: read_u16(&chunk[..2]),
+                aead_id: read_u16(&chunk[2..]),
+            })
+            .collect();
+
+        let config = KeyConfig {
+            key_id: key_id[0],
+            public_key,
+            suites,
+            validity: None,
+        };
+        Ok((config, rest))
+    }
+}
+
+/// A builder for [`KeyConfig`]. Made by [`KeyConfig::builder`].
+pub struct KeyConfigBuilder<Kem: KemTrait> {
+    key_id: u8,
+    public_key: PublicKey<Kem>,
+    suites: Vec<SymmetricSuite>,
+    validity: Option<ValidityWindow>,
+}
+
+impl<Kem: KemTrait> KeyConfigBuilder<Kem> {
+    /// Adds the given KDF and AEAD to the accepted suites. Suites should be added in order of
+    /// preference.
+    pub fn suite<Kdf: KdfTrait, A: Aead>(self) -> Self {
+        self.suite_ids(SymmetricSuite::new::<Kdf, A>())
+    }
+
+    /// Adds the given suite to the accepted suites. This is for listing suites that this crate
+    /// doesn't implement. Suites should be added in order of preference.
+    pub fn suite_ids(mut self, suite: SymmetricSuite) -> Self {
+        if !self.suites.contains(&suite) {
+            self.suites.push(suite);
+        }
+        self
+    }
+
+    /// Sets when the key may be used. This isn't serialized.
+    pub fn validity(mut self, validity: ValidityWindow) -> Self {
+        self.validity = Some(validity);
+        self
+    }
+
+    /// Builds the key config. Returns `Err(HpkeError::InvalidEncoding)` if no suites were added,
+    /// or if there are too many suites to serialize.
+    pub fn build(self) -> Result<KeyConfig<Kem>, HpkeError> {
+        if self.suites.is_empty() || self.suites.len() > (u16::MAX / 4) as usize {
+            return Err(HpkeError::InvalidEncoding);
+        }
+
+        Ok(KeyConfig {
+            key_id: self.key_id,
+            public_key: self.public_key,
+            suites: self.suites,
+            validity: self.validity,
+        })
+    }
+}
+
+// Splits off the first `len` bytes of `buf`, or errors if `buf` is too short
+fn split_at_checked(buf: &[u8], len: usize) -> Result<(&[u8], &[u8]), HpkeError> {
+    if buf.len() < len {
+        Err(HpkeError::InvalidEncoding)
+    } else {
+        Ok(buf.split_at(len))
+    }
+}
+
+// Reads a big-endian u16 off the front of `buf`, which is at least 2 bytes long
+fn read_u16(buf: &[u8]) -> u16 {
+    u16::from_be_bytes([buf[0], buf[1]])
+}
+
+#[cfg(test)]
+mod test {
+    use super::{KeyConfig, SymmetricSuite};
+    use crate::{
+        aead::{AesGcm128, ChaCha20Poly1305},
+        kdf::{HkdfSha256, HkdfSha384},
+        kem::Kem as KemTrait,
+        Serializable,
+    };
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Tests that key configs and lists of them survive serialization, and that malformed
+    /// encodings are rejected
+    macro_rules! test_key_config_roundtrip {
+        ($test_name:ident, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type Kem = $kem;
+                let mut csprng = StdRng::from_entropy();
+                let (_, pk1) = Kem::gen_keypair(&mut csprng);
+                let (_, pk2) = Kem::gen_keypair(&mut csprng);
+
+                // Duplicate suites are dropped
+                let config1 = KeyConfig::<Kem>::builder(7, pk1)
+                    .suite::<HkdfSha256, AesGcm128>()
+                    .suite::<HkdfSha384, ChaCha20Poly1305>()
+                    .suite::<HkdfSha256, AesGcm128>()
+                    .build()
+                    .unwrap();
+                assert_eq!(config1.suites().len(), 2);
+                assert!(config1.supports::<HkdfSha384, ChaCha20Poly1305>());
+                assert!(!config1.supports::<HkdfSha384, AesGcm128>());
+                let config2 = KeyConfig::<Kem>::builder(8, pk2)
+                    .suite_ids(SymmetricSuite {
+                        kdf_id: 0xfff0,
+                        aead_id: 0xfff1,
+                    })
+                    .build()
+                    .unwrap();
+
+                let encoded = config1.to_bytes();
+                let decoded = KeyConfig::<Kem>::from_bytes(&encoded).unwrap();
+                assert_eq!(decoded.key_id(), 7);
+                assert_eq!(decoded.kem_id(), Kem::KEM_ID);
+                assert_eq!(
+                    decoded.public_key().to_bytes(),
+                    config1.public_key().to_bytes()
+                );
+                assert_eq!(decoded.suites(), config1.suites());
+
+                let list = KeyConfig::list_to_bytes(&[config1.clone(), config2.clone()]);
+                let decoded = KeyConfig::<Kem>::list_from_bytes(&list).unwrap();
+                assert_eq!(decoded.len(), 2);
+                assert_eq!(decoded[1].key_id(), 8);
+                assert_eq!(decoded[1].suites(), config2.suites());
+
+                // No suites, trailing bytes, truncations, and wrong KEMs are all errors
+                assert!(KeyConfig::<Kem>::builder(0, config1.public_key().clone())
+                    .build()
+                    .is_err());
+                let mut trailing = encoded.clone();
+                trailing.push(0);
+                assert!(KeyConfig::<Kem>::from_bytes(&trailing).is_err());
+                assert!(KeyConfig::<Kem>::from_bytes(&encoded[..encoded.len() - 1]).is_err());
+                assert!(KeyConfig::<Kem>::list_from_bytes(&list[..list.len() - 1]).is_err());
+                let mut bad_kem = encoded.clone();
+                bad_kem[2] ^= 0xff;
+                assert!(KeyConfig::<Kem>::from_bytes(&bad_kem).is_err());
+            }
+        };
+    }
+
+    #[cfg(feature = "x25519-dalek")]
+    test_key_config_roundtrip!(
+        test_key_config_roundtrip_x25519,
+        crate::kem::X25519HkdfSha256
+    );
+    #[cfg(feature = "p256")]
+    test_key_config_roundtrip!(test_key_config_roundtrip_p256, crate::kem::DhP256HkdfSha256);
+
+    /// Tests against the key config in RFC 9458 Appendix A
+    #[cfg(feature = "x25519-dalek")]
+    #[test]
+    fn test_key_config_rfc9458() {
+        type Kem = crate::kem::X25519HkdfSha256;
+
+        let encoded = hex::decode(
+            "01002031e1f05a740102115220e9af918f738674aec95f54db6e04eb705aae8e798155000800010001000\
+             10003",
+        )
+        .unwrap();
+        let config = KeyConfig::<Kem>::from_bytes(&encoded).unwrap();
+
+        assert_eq!(config.key_id(), 1);
+        assert!(config.supports::<HkdfSha256, AesGcm128>());
+        assert!(config.supports::<HkdfSha256, ChaCha20Poly1305>());
+        assert_eq!(config.to_bytes(), encoded);
+    }
+}
@@ -0,0 +1,163 @@
+//! This module defines [`KeyTransport`], which sends a fresh secret to the holder of a private
+//! key. It's for protocols that would otherwise do a static-ephemeral ECDH and hash the result:
+//! the sender gets a secret and an encapsulated key, sends the encapsulated key, and the
+//! recipient gets the same secret out of it. There are no AEAD types, sequence numbers, or
+//! exporter labels to deal with.
+//!
+//! Under the hood, this is a Base mode setup with the [`ExportOnly`] AEAD, followed by one
+//! export. The secret is the export of length `Nh` under the exporter context
+//! `"rust-hpke key transport"`, so any HPKE implementation can compute it.
+
+use crate::{
+    aead::ExportOnly,
+    kdf::Kdf as KdfTrait,
+    kem::{EncappedKey, Kem as KemTrait},
+    kex::KeyExchange,
+    op_mode::{OpModeR, OpModeS},
+    setup::{setup_receiver, setup_sender, ExporterSecret},
+    HpkeError,
+};
+
+use core::marker::PhantomData;
+
+use rand::{CryptoRng, RngCore};
+use zeroize::Zeroize;
+
+// The exporter context the secret is exported under
+const KEY_TRANSPORT_CTX: &[u8] = b"rust-hpke key transport";
+
+/// A secret sent with [`KeyTransport`]. It's as long as the KDF's digest, and it's zeroed when
+/// dropped.
+pub struct TransportedSecret<Kdf: KdfTrait>(ExporterSecret<Kdf>);
+
+impl<Kdf: KdfTrait> TransportedSecret<Kdf> {
+    /// Returns the bytes of the secret
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_slice()
+    }
+}
+
+impl<Kdf: KdfTrait> Drop for TransportedSecret<Kdf> {
+    fn drop(&mut self) {
+        self.0.as_mut_slice().zeroize();
+    }
+}
+
+/// Sends secrets to the holder of a `Kem` private key, deriving them with `Kdf`. See the
+/// [module docs](self).
+pub struct KeyTransport<Kdf: KdfTrait, Kem: KemTrait>(PhantomData<(Kdf, Kem)>);
+
+// Convenience types for the KEM's keys
+type KemPubkey<Kem> = <<Kem as KemTrait>::Kex as KeyExchange>::PublicKey;
+type KemPrivkey<Kem> = <<Kem as KemTrait>::Kex as KeyExchange>::PrivateKey;
+
+impl<Kdf: KdfTrait, Kem: KemTrait> KeyTransport<Kdf, Kem> {
+    /// Makes a fresh secret for the holder of `pk_recip`. `info` binds the secret to its
+    /// purpose, and the recipient must use the same `info`.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns the encapsulated key, which goes to the recipient, and the secret. If an error
+    /// happened during key exchange, returns `Err(HpkeError::InvalidKeyExchange)`.
+    pub fn send<R: CryptoRng + RngCore>(
+        pk_recip: &KemPubkey<Kem>,
+        info: &[u8],
+        csprng: &mut R,
+    ) -> Result<(EncappedKey<Kem>, TransportedSecret<Kdf>), HpkeError> {
+        let (encapped_key, ctx) =
+            setup_sender::<ExportOnly, Kdf, Kem, R>(&OpModeS::Base, pk_recip, info, csprng)?;
+
+        let mut secret = TransportedSecret(ExporterSecret::<Kdf>::default());
+        ctx.export(KEY_TRANSPORT_CTX, &mut secret.0)?;
+        Ok((encapped_key, secret))
+    }
+
+    /// Recovers the secret that [`send`](Self::send) made for `encapped_key`
+    ///
+    /// Return Value
+    /// ============
+    /// Returns the secret. If an error happened during key exchange, returns
+    /// `Err(HpkeError::InvalidKeyExchange)`.
+    pub fn receive(
+        sk_recip: &KemPrivkey<Kem>,
+        encapped_key: &EncappedKey<Kem>,
+        info: &[u8],
+    ) -> Result<TransportedSecret<Kdf>, HpkeError> {
+        let ctx =
+            setup_receiver::<ExportOnly, Kdf, Kem>(&OpModeR::Base, sk_recip, encapped_key, info)?;
+
+        let mut secret = TransportedSecret(ExporterSecret::<Kdf>::default());
+        ctx.export(KEY_TRANSPORT_CTX, &mut secret.0)?;
+        Ok(secret)
+    }
+}
+
+#[cfg(all(test, feature = "hkdf-sha384"))]
+mod test {
+    use super::{KeyTransport, KEY_TRANSPORT_CTX};
+    use crate::{
+        aead::{AeadTag, ExportOnly},
+        kdf::{HkdfSha256, HkdfSha384},
+        kem::Kem as KemTrait,
+        kex::Deserializable,
+        op_mode::OpModeR,
+        setup::setup_receiver,
+        HpkeError,
+    };
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Tests that both ends get the same secret, that it depends on the info string, and that it's
+    /// the plain export of an export-only context
+    macro_rules! test_key_transport {
+        ($test_name:ident, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type Kdf = HkdfSha384;
+                type Kem = $kem;
+                let mut csprng = StdRng::from_entropy();
+
+                let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+                let (encapped_key, secret) =
+                    KeyTransport::<Kdf, Kem>::send(&pk_recip, b"info", &mut csprng).unwrap();
+                assert_eq!(secret.as_bytes().len(), 48);
+
+                let received =
+                    KeyTransport::<Kdf, Kem>::receive(&sk_recip, &encapped_key, b"info").unwrap();
+                assert_eq!(secret.as_bytes(), received.as_bytes());
+
+                let other_info =
+                    KeyTransport::<Kdf, Kem>::receive(&sk_recip, &encapped_key, b"ofni").unwrap();
+                assert_ne!(secret.as_bytes(), other_info.as_bytes());
+
+                // A different KDF gives a different secret
+                let other_kdf =
+                    KeyTransport::<HkdfSha256, Kem>::receive(&sk_recip, &encapped_key, b"info")
+                        .unwrap();
+                assert_ne!(&secret.as_bytes()[..32], other_kdf.as_bytes());
+
+                // It's the export of an export-only context, which can't open anything
+                let mut ctx = setup_receiver::<ExportOnly, Kdf, Kem>(
+                    &OpModeR::Base,
+                    &sk_recip,
+                    &encapped_key,
+                    b"info",
+                )
+                .unwrap();
+                let mut exported = [0u8; 48];
+                ctx.export(KEY_TRANSPORT_CTX, &mut exported).unwrap();
+                assert_eq!(secret.as_bytes(), &exported[..]);
+
+                let mut buf = *b"ciphertext";
+                let tag = AeadTag::<ExportOnly>::from_bytes(&[]).unwrap();
+                let res = ctx.open(&mut buf, b"", &tag);
+                assert!(matches!(res, Err(HpkeError::InvalidTag)));
+            }
+        };
+    }
+
+    #[cfg(feature = "x25519-dalek")]
+    test_key_transport!(test_key_transport_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_key_transport!(test_key_transport_p256, crate::kem::DhP256HkdfSha256);
+}
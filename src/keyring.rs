@@ -0,0 +1,611 @@
+//! This module defines [`Keyring`], a collection of a recipient's keys indexed by key ID. Servers
+//! that rotate keys usually have a handful of live keypairs at a time, plus the public keys of
+//! peers they talk to. A `Keyring` holds all of these, lets you look them up by ID or by
+//! [`Fingerprint`], serializes to a single byte string, and can trial-decrypt a message when the
+//! sender didn't say which key it used.
+//!
+//! Each key can have a [`ValidityWindow`]. The keyring doesn't read the clock, so expired keys
+//! stay in it until [`Keyring::remove_expired`] is called with the current time.
+
+use crate::{
+    aead::{Aead, AeadTag},
+    fingerprint::{Fingerprint, Fingerprintable},
+    kdf::Kdf as KdfTrait,
+    kem::{EncappedKey, Kem as KemTrait},
+    kex::{Deserializable, KeyExchange, Serializable},
+    op_mode::OpModeR,
+    psk::ValidityWindow,
+    single_shot::single_shot_open,
+    HpkeError,
+};
+
+use alloc::{collections::BTreeMap, vec::Vec};
+
+// The tags that say what kind of key an entry holds in the serialized form of a keyring
+const ENTRY_PUBLIC: u8 = 0;
+const ENTRY_PRIVATE: u8 = 1;
+// Set in the kind of an entry that's followed by a validity window
+const ENTRY_HAS_VALIDITY: u8 = 2;
+
+type PublicKey<Kem> = <<Kem as KemTrait>::Kex as KeyExchange>::PublicKey;
+type PrivateKey<Kem> = <<Kem as KemTrait>::Kex as KeyExchange>::PrivateKey;
+
+/// A single key in a [`Keyring`]. This is either a full keypair or just a public key.
+pub struct KeyringEntry<Kem: KemTrait> {
+    private_key: Option<PrivateKey<Kem>>,
+    public_key: PublicKey<Kem>,
+    validity: Option<ValidityWindow>,
+}
+
+impl<Kem: KemTrait> KeyringEntry<Kem> {
+    /// Returns the public key of this entry
+    pub fn public_key(&self) -> &PublicKey<Kem> {
+        &self.public_key
+    }
+
+    /// Returns the private key of this entry, if it has one
+    pub fn private_key(&self) -> Option<&PrivateKey<Kem>> {
+        self.private_key.as_ref()
+    }
+
+    /// Returns the fingerprint of this entry's public key
+    pub fn fingerprint(&self) -> Fingerprint {
+        self.public_key.fingerprint()
+    }
+
+    /// Returns when this entry's key may be used, if that was set
+    pub fn validity(&self) -> Option<ValidityWindow> {
+        self.validity
+    }
+
+    /// Returns whether this entry's key may be used at time `now`. A key with no validity window
+    /// is always valid.
+    pub fn is_valid_at(&self, now: u64) -> bool {
+        match self.validity {
+            Some(validity) => validity.contains(now),
+            None => true,
+        }
+    }
+}
+
+// Can't derive Clone because that would require Kem: Clone
+impl<Kem: KemTrait> Clone for KeyringEntry<Kem> {
+    fn clone(&self) -> Self {
+        KeyringEntry {
+            private_key: self.private_key.clone(),
+            public_key: self.public_key.clone(),
+            validity: self.validity,
+        }
+    }
+}
+
+/// A collection of keys for a single KEM, indexed by key ID. Key IDs are arbitrary byte strings
+/// of at most 65535 bytes, chosen by the caller.
+///
+/// A keyring with private keys in it is secret. Treat its serialization the way you'd treat a
+/// private key.
+pub struct Keyring<Kem: KemTrait> {
+    entries: BTreeMap<Vec<u8>, KeyringEntry<Kem>>,
+}
+
+impl<Kem: KemTrait> Default for Keyring<Kem> {
+    fn default() -> Self {
+        Keyring {
+            entries: BTreeMap::new(),
+        }
+    }
+}
+
+impl<Kem: KemTrait> Clone for Keyring<Kem> {
+    fn clone(&self) -> Self {
+        Keyring {
+            entries: self.entries.clone(),
+        }
+    }
+}
+
+impl<Kem: KemTrait> Keyring<Kem> {
+    /// Makes an empty keyring
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of keys in this keyring
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns whether this keyring is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Inserts the keypair with the given private key under the given ID. The public key is
+    /// computed from the private key. Returns the entry previously stored under the ID, if any.
+    ///
+    /// Panics
+    /// ======
+    /// Panics if `key_id` is longer than 65535 bytes.
+    pub fn insert_private_key(
+        &mut self,
+        key_id: &[u8],
+        private_key: PrivateKey<Kem>,
+    ) -> Option<KeyringEntry<Kem>> {
+        let public_key = <Kem::Kex as KeyExchange>::sk_to_pk(&private_key);
+        self.insert_entry(
+            key_id,
+            KeyringEntry {
+                private_key: Some(private_key),
+                public_key,
+                validity: None,
+            },
+        )
+    }
+
+    /// Inserts the given public key under the given ID. Returns the entry previously stored under
+    /// the ID, if any.
+    ///
+    /// Panics
+    /// ======
+    /// Panics if `key_id` is longer than 65535 bytes.
+    pub fn insert_public_key(
+        &mut self,
+        key_id: &[u8],
+        public_key: PublicKey<Kem>,
+    ) -> Option<KeyringEntry<Kem>> {
+        self.insert_entry(
+            key_id,
+            KeyringEntry {
+                private_key: None,
+                public_key,
+                validity: None,
+            },
+        )
+    }
+
+    fn insert_entry(
+        &mut self,
+        key_id: &[u8],
+        entry: KeyringEntry<Kem>,
+    ) -> Option<KeyringEntry<Kem>> {
+        assert!(key_id.len() <= u16::MAX as usize, "key ID is too long");
+        self.entries.insert(key_id.to_vec(), entry)
+    }
+
+    /// Removes the key with the given ID. Returns the removed entry, if any.
+    pub fn remove(&mut self, key_id: &[u8]) -> Option<KeyringEntry<Kem>> {
+        self.entries.remove(key_id)
+    }
+
+    /// Sets when the key with the given ID may be used. Returns whether there was a key with that
+    /// ID.
+    pub fn set_validity(&mut self, key_id: &[u8], validity: ValidityWindow) -> bool {
+        match self.entries.get_mut(key_id) {
+            Some(entry) => {
+                entry.validity = Some(validity);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes every key whose validity window ended before `now`. Keys with no validity window,
+    /// and keys that aren't valid yet, are kept.
+    pub fn remove_expired(&mut self, now: u64) {
+        self.entries.retain(|_, entry| match entry.validity {
+            Some(validity) => now <= validity.not_after,
+            None => true,
+        });
+    }
+
+    /// Returns the key with the given ID, if any
+    pub fn get(&self, key_id: &[u8]) -> Option<&KeyringEntry<Kem>> {
+        self.entries.get(key_id)
+    }
+
+    /// Returns the ID and entry of the key with the given fingerprint, if any
+    pub fn get_by_fingerprint(
+        &self,
+        fingerprint: &Fingerprint,
+    ) -> Option<(&[u8], &KeyringEntry<Kem>)> {
+        // Keyrings are small, so a linear scan is fine here
+        self.iter()
+            .find(|(_, entry)| &entry.fingerprint() == fingerprint)
+    }
+
+    /// Iterates over the IDs and entries of this keyring, in order of key ID
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &KeyringEntry<Kem>)> {
+        self.entries
+            .iter()
+            .map(|(id, entry)| (id.as_slice(), entry))
+    }
+
+    /// Does a single-shot open with every private key in this keyring until one works. This is
+    /// for when the sender didn't say which key they encrypted to. On success, returns the ID of
+    /// the key that worked and leaves the plaintext in `ciphertext`. If no key works, returns
+    /// `Err(HpkeError::InvalidTag)` and leaves `ciphertext` unmodified.
+    ///
+    /// This costs one decapsulation per private key, and which key worked is observable through
+    /// timing. If the sender can say which key it used, look the key up with [`Keyring::get`]
+    /// instead.
+    pub fn single_shot_open_any<A, Kdf>(
+        &self,
+        mode: &OpModeR<Kem::Kex>,
+        encapped_key: &EncappedKey<Kem>,
+        info: &[u8],
+        ciphertext: &mut [u8],
+        aad: &[u8],
+        tag: &AeadTag<A>,
+    ) -> Result<&[u8], HpkeError>
+    where
+        A: Aead,
+        Kdf: KdfTrait,
+    {
+        // A failed open leaves its buffer in an undefined state, so every attempt works on a copy
+        let mut buf = Vec::with_capacity(ciphertext.len());
+        for (key_id, entry) in self.iter() {
+            let sk = match entry.private_key() {
+                Some(sk) => sk,
+                None => continue,
+            };
+
+            buf.clear();
+            buf.extend_from_slice(ciphertext);
+            let res =
+                single_shot_open::<A, Kdf, Kem>(mode, sk, encapped_key, info, &mut buf, aad, tag);
+            if res.is_ok() {
+                ciphertext.copy_from_slice(&buf);
+                return Ok(key_id);
+            }
+        }
+
+        Err(HpkeError::InvalidTag)
+    }
+
+    /// Serializes this keyring. The format is
+    /// `I2OSP(kem_id, 2) || entry || entry || ...`, where each entry is
+    /// `I2OSP(len(key_id), 2) || key_id || kind || key`. `kind` is 1 for a keypair, in which case
+    /// `key` is the serialized private key, and 0 for a public key, in which case `key` is the
+    /// serialized public key. If the entry has a validity window, `kind` also has the 2 bit set,
+    /// and `key` is followed by `I2OSP(not_before, 8) || I2OSP(not_after, 8)`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&Kem::KEM_ID.to_be_bytes());
+
+        for (key_id, entry) in self.iter() {
+            out.extend_from_slice(&(key_id.len() as u16).to_be_bytes());
+            out.extend_from_slice(key_id);
+            let validity_flag = match entry.validity {
+                Some(_) => ENTRY_HAS_VALIDITY,
+                None => 0,
+            };
+            match entry.private_key() {
+                Some(sk) => {
+                    out.push(ENTRY_PRIVATE | validity_flag);
+                    out.extend_from_slice(&sk.to_bytes());
+                }
+                None => {
+                    out.push(ENTRY_PUBLIC | validity_flag);
+                    out.extend_from_slice(&entry.public_key().to_bytes());
+                }
+            }
+            if let Some(validity) = entry.validity {
+                out.extend_from_slice(&validity.not_before.to_be_bytes());
+                out.extend_from_slice(&validity.not_after.to_be_bytes());
+            }
+        }
+
+        out
+    }
+
+    /// Deserializes a keyring serialized with [`Keyring::to_bytes`]. Returns
+    /// `Err(HpkeError::InvalidEncoding)` if the encoding is malformed, is for a different KEM, or
+    /// has a key ID appear twice.
+    pub fn from_bytes(encoded: &[u8]) -> Result<Self, HpkeError> {
+        let (kem_id, mut rest) = split_at_checked(encoded, 2)?;
+        if read_u16(kem_id) != Kem::KEM_ID {
+            return Err(HpkeError::InvalidEncoding);
+        }
+
+        let mut keyring = Keyring::new();
+        while !rest.is_empty() {
+            let (id_len, r) = split_at_checked(rest, 2)?;
+            let (key_id, r) = split_at_checked(r, read_u16(id_len) as usize)?;
+            let (kind, r) = split_at_checked(r, 1)?;
+
+            let (mut entry, r) = match kind[0] & !ENTRY_HAS_VALIDITY {
+                ENTRY_PRIVATE => {
+                    let (sk_bytes, r) = split_at_checked(r, PrivateKey::<Kem>::size())?;
+                    let sk = PrivateKey::<Kem>::from_bytes(sk_bytes)?;
+                    let pk = <Kem::Kex as KeyExchange>::sk_to_pk(&sk);
+                    let entry = KeyringEntry {
+                        private_key: Some(sk),
+                        public_key: pk,
+                        validity: None,
+                    };
+                    (entry, r)
+                }
+                ENTRY_PUBLIC => {
+                    let (pk_bytes, r) = split_at_checked(r, PublicKey::<Kem>::size())?;
+                    let entry = KeyringEntry {
+                        private_key: None,
+                        public_key: PublicKey::<Kem>::from_bytes(pk_bytes)?,
+                        validity: None,
+                    };
+                    (entry, r)
+                }
+                _ => return Err(HpkeError::InvalidEncoding),
+            };
+            let r = if kind[0] & ENTRY_HAS_VALIDITY != 0 {
+                let (not_before, r) = split_at_checked(r, 8)?;
+                let (not_after, r) = split_at_checked(r, 8)?;
+                entry.validity = Some(ValidityWindow {
+                    not_before: read_u64(not_before),
+                    not_after: read_u64(not_after),
+                });
+                r
+            } else {
+                r
+            };
+
+            if keyring.insert_entry(key_id, entry).is_some() {
+                return Err(HpkeError::InvalidEncoding);
+            }
+            rest = r;
+        }
+
+        Ok(keyring)
+    }
+}
+
+// Splits off the first `len` bytes of `buf`, or errors if `buf` is too short
+fn split_at_checked(buf: &[u8], len: usize) -> Result<(&[u8], &[u8]), HpkeError> {
+    if buf.len() < len {
+        Err(HpkeError::InvalidEncoding)
+    } else {
+        Ok(buf.split_at(len))
+    }
+}
+
+// Reads a big-endian u16 off the front of `buf`, which is at least 2 bytes long
+fn read_u16(buf: &[u8]) -> u16 {
+    u16::from_be_bytes([buf[0], buf[1]])
+}
+
+// Reads a big-endian u64 off the front of `buf`, which is at least 8 bytes long
+fn read_u64(buf: &[u8]) -> u64 {
+    let mut arr = [0u8; 8];
+    arr.copy_from_slice(&buf[..8]);
+    u64::from_be_bytes(arr)
+}
+
+#[cfg(test)]
+mod test {
+    use super::Keyring;
+    use crate::{
+        aead::ChaCha20Poly1305, kdf::HkdfSha256, kem::Kem as KemTrait, op_mode::OpModeR,
+        op_mode::OpModeS, psk::ValidityWindow, single_shot::single_shot_seal, Fingerprintable,
+        HpkeError, Serializable,
+    };
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Tests inserting, looking up, and removing keys
+    macro_rules! test_keyring_lookup {
+        ($test_name:ident, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type Kem = $kem;
+                let mut csprng = StdRng::from_entropy();
+
+                let (sk1, pk1) = Kem::gen_keypair(&mut csprng);
+                let (_, pk2) = Kem::gen_keypair(&mut csprng);
+
+                let mut keyring = Keyring::<Kem>::new();
+                assert!(keyring.insert_private_key(b"one", sk1).is_none());
+                assert!(keyring.insert_public_key(b"two", pk2.clone()).is_none());
+                assert_eq!(keyring.len(), 2);
+
+                // Lookups by ID and by fingerprint should find the same thing. The public key of
+                // a keypair entry is computed from its private key.
+                let entry = keyring.get(b"one").unwrap();
+                assert_eq!(entry.public_key().to_bytes(), pk1.to_bytes());
+                assert!(entry.private_key().is_some());
+                let (id, entry) = keyring.get_by_fingerprint(&pk2.fingerprint()).unwrap();
+                assert_eq!(id, b"two");
+                assert!(entry.private_key().is_none());
+
+                // Inserting under an existing ID replaces the old entry
+                let old = keyring.insert_public_key(b"one", pk2.clone()).unwrap();
+                assert_eq!(old.public_key().to_bytes(), pk1.to_bytes());
+                assert!(keyring.get_by_fingerprint(&pk1.fingerprint()).is_none());
+
+                assert!(keyring.remove(b"one").is_some());
+                assert!(keyring.remove(b"one").is_none());
+                assert!(keyring.get(b"one").is_none());
+                assert_eq!(keyring.len(), 1);
+            }
+        };
+    }
+
+    /// Tests that a keyring survives serialization, and that malformed serializations are
+    /// rejected
+    macro_rules! test_keyring_serialize {
+        ($test_name:ident, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type Kem = $kem;
+                let mut csprng = StdRng::from_entropy();
+
+                let (sk1, pk1) = Kem::gen_keypair(&mut csprng);
+                let (_, pk2) = Kem::gen_keypair(&mut csprng);
+
+                let mut keyring = Keyring::<Kem>::new();
+                keyring.insert_private_key(b"", sk1);
+                keyring.insert_public_key(b"a longer key ID", pk2.clone());
+
+                let encoded = keyring.to_bytes();
+                let decoded = Keyring::<Kem>::from_bytes(&encoded).unwrap();
+                assert_eq!(decoded.len(), 2);
+                let entry = decoded.get(b"").unwrap();
+                assert_eq!(entry.fingerprint(), pk1.fingerprint());
+                assert!(entry.private_key().is_some());
+                let entry = decoded.get(b"a longer key ID").unwrap();
+                assert_eq!(entry.fingerprint(), pk2.fingerprint());
+                assert!(entry.private_key().is_none());
+
+                // An empty keyring is just the KEM ID
+                let empty = Keyring::<Kem>::new().to_bytes();
+                assert_eq!(empty, Kem::KEM_ID.to_be_bytes());
+                assert!(Keyring::<Kem>::from_bytes(&empty).unwrap().is_empty());
+
+                // Truncations, a wrong KEM ID, and duplicate IDs are all errors
+                assert!(Keyring::<Kem>::from_bytes(&encoded[..encoded.len() - 1]).is_err());
+                assert!(Keyring::<Kem>::from_bytes(&[]).is_err());
+                let mut bad_kem = encoded.clone();
+                bad_kem[1] ^= 0xff;
+                assert!(Keyring::<Kem>::from_bytes(&bad_kem).is_err());
+                let mut duped = encoded.clone();
+                duped.extend_from_slice(&encoded[2..]);
+                assert!(Keyring::<Kem>::from_bytes(&duped).is_err());
+            }
+        };
+    }
+
+    /// Tests that validity windows survive serialization, and that expired keys get removed
+    macro_rules! test_keyring_validity {
+        ($test_name:ident, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type Kem = $kem;
+                let mut csprng = StdRng::from_entropy();
+
+                let (sk1, _) = Kem::gen_keypair(&mut csprng);
+                let (_, pk2) = Kem::gen_keypair(&mut csprng);
+                let (_, pk3) = Kem::gen_keypair(&mut csprng);
+                let window = ValidityWindow {
+                    not_before: 100,
+                    not_after: 200,
+                };
+
+                let mut keyring = Keyring::<Kem>::new();
+                keyring.insert_private_key(b"old", sk1);
+                keyring.insert_public_key(b"new", pk2);
+                keyring.insert_public_key(b"forever", pk3);
+                assert!(keyring.set_validity(b"old", window));
+                assert!(keyring.set_validity(
+                    b"new",
+                    ValidityWindow {
+                        not_before: 300,
+                        not_after: 400,
+                    }
+                ));
+                assert!(!keyring.set_validity(b"missing", window));
+
+                let entry = keyring.get(b"old").unwrap();
+                assert_eq!(entry.validity(), Some(window));
+                assert!(!entry.is_valid_at(99));
+                assert!(entry.is_valid_at(150));
+                assert!(!entry.is_valid_at(201));
+                assert!(keyring.get(b"forever").unwrap().is_valid_at(0));
+
+                let decoded = Keyring::<Kem>::from_bytes(&keyring.to_bytes()).unwrap();
+                assert_eq!(decoded.get(b"old").unwrap().validity(), Some(window));
+                assert!(decoded.get(b"old").unwrap().private_key().is_some());
+                assert_eq!(decoded.get(b"forever").unwrap().validity(), None);
+                assert_eq!(decoded.to_bytes(), keyring.to_bytes());
+
+                // Only the key whose window has passed is removed
+                keyring.remove_expired(250);
+                assert!(keyring.get(b"old").is_none());
+                assert!(keyring.get(b"new").is_some());
+                assert!(keyring.get(b"forever").is_some());
+            }
+        };
+    }
+
+    /// Tests that single_shot_open_any finds the right key, and leaves the ciphertext alone when
+    /// no key works
+    macro_rules! test_keyring_open_any {
+        ($test_name:ident, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type A = ChaCha20Poly1305;
+                type Kdf = HkdfSha256;
+                type Kem = $kem;
+                let mut csprng = StdRng::from_entropy();
+
+                let mut keyring = Keyring::<Kem>::new();
+                let (sk1, _) = Kem::gen_keypair(&mut csprng);
+                let (sk2, pk2) = Kem::gen_keypair(&mut csprng);
+                let (_, pk3) = Kem::gen_keypair(&mut csprng);
+                keyring.insert_private_key(b"1", sk1);
+                keyring.insert_private_key(b"2", sk2);
+                keyring.insert_public_key(b"3", pk3.clone());
+
+                let msg = b"the owl flies at midnight";
+                let aad = b"over the hedge";
+                let info = b"keyring test";
+
+                let mut buf = *msg;
+                let (encapped_key, tag) = single_shot_seal::<A, Kdf, Kem, _>(
+                    &OpModeS::Base,
+                    &pk2,
+                    info,
+                    &mut buf,
+                    aad,
+                    &mut csprng,
+                )
+                .unwrap();
+
+                let mut ciphertext = buf;
+                let key_id = keyring
+                    .single_shot_open_any::<A, Kdf>(
+                        &OpModeR::Base,
+                        &encapped_key,
+                        info,
+                        &mut ciphertext,
+                        aad,
+                        &tag,
+                    )
+                    .unwrap();
+                assert_eq!(key_id, b"2");
+                assert_eq!(&ciphertext, msg);
+
+                // Without the right key, nothing opens and the ciphertext is untouched
+                keyring.remove(b"2");
+                let mut ciphertext = buf;
+                let res = keyring.single_shot_open_any::<A, Kdf>(
+                    &OpModeR::Base,
+                    &encapped_key,
+                    info,
+                    &mut ciphertext,
+                    aad,
+                    &tag,
+                );
+                assert!(matches!(res, Err(HpkeError::InvalidTag)));
+                assert_eq!(ciphertext, buf);
+            }
+        };
+    }
+
+    #[cfg(feature = "x25519-dalek")]
+    test_keyring_lookup!(test_keyring_lookup_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_keyring_lookup!(test_keyring_lookup_p256, crate::kem::DhP256HkdfSha256);
+
+    #[cfg(feature = "x25519-dalek")]
+    test_keyring_serialize!(test_keyring_serialize_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_keyring_serialize!(test_keyring_serialize_p256, crate::kem::DhP256HkdfSha256);
+
+    #[cfg(feature = "x25519-dalek")]
+    test_keyring_open_any!(test_keyring_open_any_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_keyring_open_any!(test_keyring_open_any_p256, crate::kem::DhP256HkdfSha256);
+
+    #[cfg(feature = "x25519-dalek")]
+    test_keyring_validity!(test_keyring_validity_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_keyring_validity!(test_keyring_validity_p256, crate::kem::DhP256HkdfSha256);
+}
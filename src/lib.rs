@@ -68,32 +68,57 @@
 
 //-------- no_std stuff --------//
 #![no_std]
+// There is no unsafe code in this crate, and there won't be. FFI layers, which can't avoid it, go
+// in their own crates under bindings/.
+#![forbid(unsafe_code)]
 
 #[cfg(feature = "std")]
 #[allow(unused_imports)]
 #[macro_use]
 extern crate std;
 
-#[cfg(not(feature = "std"))]
-#[allow(unused_imports)]
-#[macro_use]
+// Some optional features need heap-allocated buffers. The macros only get imported if std isn't
+// there to provide them.
+#[cfg_attr(not(feature = "std"), allow(unused_imports), macro_use)]
 extern crate alloc;
 
 //-------- Testing stuff --------//
 
 // kat_tests tests all the implemented ciphersuites, and thus needs all the dependencies. It also
 // needs std for file IO.
-#[cfg(all(test, feature = "std", feature = "x25519", feature = "p256"))]
+#[cfg(all(
+    test,
+    feature = "std",
+    feature = "x25519",
+    feature = "p256",
+    feature = "aes-gcm",
+    feature = "chacha20poly1305",
+    feature = "hkdf-sha384",
+    feature = "hkdf-sha512"
+))]
 mod kat_tests;
 
 // kat_tests requires serde
-#[cfg(all(test, feature = "std", feature = "x25519", feature = "p256"))]
+#[cfg(all(
+    test,
+    feature = "std",
+    feature = "x25519",
+    feature = "p256",
+    feature = "aes-gcm",
+    feature = "chacha20poly1305",
+    feature = "hkdf-sha384",
+    feature = "hkdf-sha512"
+))]
 #[macro_use]
 extern crate serde_derive;
 
 #[cfg(test)]
 mod test_util;
 
+// Cross-cutting tests for nonce reuse. These need an X25519 KEM and ChaCha20Poly1305.
+#[cfg(all(test, feature = "x25519", feature = "chacha20poly1305"))]
+mod nonce_reuse_tests;
+
 //-------- Modules and exports--------//
 
 // Re-export this version of generic_array, since that's what's used everywhere in this crate
@@ -102,29 +127,131 @@ pub use generic_array;
 #[macro_use]
 mod util;
 
+pub mod aad;
 pub mod aead;
+#[cfg(feature = "age")]
+pub mod age;
+pub mod backend;
+pub mod build_info;
+pub mod channel;
+pub mod config;
+pub mod core_api;
+#[cfg(feature = "danger")]
+pub mod danger;
+pub mod datagram;
+#[cfg(feature = "events")]
+pub mod events;
+pub mod fingerprint;
+#[cfg(any(test, feature = "fixtures"))]
+pub mod fixtures;
+pub mod header;
+pub mod info;
 pub mod kdf;
 pub mod kem;
 pub mod kex;
+pub mod key_config;
+pub mod key_transport;
+pub mod keyring;
+pub mod migrate;
 pub mod op_mode;
+pub mod psk;
+pub mod replay;
+pub mod sealed;
+pub mod seq_guard;
 pub mod setup;
+#[cfg(feature = "signcryption")]
+pub mod signcryption;
 pub mod single_shot;
+#[cfg(feature = "threshold")]
+pub mod threshold;
+pub mod token_binding;
+#[cfg(feature = "transcript")]
+pub mod transcript;
 
+#[cfg(feature = "jwk")]
+pub mod jwk;
+#[cfg(feature = "pkcs8")]
+pub mod pkcs8;
 #[cfg(feature = "serde_impls")]
 mod serde_impls;
 
+// Everything a protocol built on this crate needs is re-exported here: the algorithm traits, the
+// ciphersuites, the setup and single-shot functions, and the context and error types. These
+// re-exports are the stable API, and are checked by tests/public_api.rs. Items marked
+// #[doc(hidden)] are implementation details that only exist because trait impls need them, and
+// can change in any release.
+
+#[doc(inline)]
+pub use crate::aad::AadBuilder;
+#[cfg(feature = "chacha20poly1305")]
+#[doc(inline)]
+pub use crate::aead::ChaCha20Poly1305;
+#[doc(inline)]
+pub use crate::aead::{
+    Aead, AeadCtxR, AeadCtxS, AeadTag, ConfirmationTag, ContextProbe, ExportNamespace, Stats,
+};
+#[cfg(feature = "aes-gcm")]
+#[doc(inline)]
+pub use crate::aead::{AesGcm128, AesGcm256};
+#[doc(inline)]
+pub use build_info::build_info;
+#[doc(inline)]
+pub use channel::{HpkeChannel, PaddingProfile};
+#[doc(inline)]
+pub use config::{ReceiverConfig, Recipient};
+#[doc(inline)]
+pub use datagram::{DatagramReceiver, DatagramSender};
+#[doc(inline)]
+pub use fingerprint::{Fingerprint, Fingerprintable};
+#[doc(inline)]
+pub use header::{single_shot_open_with_header, single_shot_seal_with_header, MessageHeader};
 #[doc(inline)]
-pub use crate::aead::{AeadCtxR, AeadCtxS};
+pub use info::InfoBuilder;
+#[cfg(feature = "hkdf-sha256")]
+#[doc(inline)]
+pub use kdf::HkdfSha256;
+#[cfg(feature = "hkdf-sha384")]
+#[doc(inline)]
+pub use kdf::HkdfSha384;
+#[cfg(feature = "hkdf-sha512")]
+#[doc(inline)]
+pub use kdf::HkdfSha512;
+#[doc(inline)]
+pub use kdf::Kdf;
+#[cfg(feature = "k256")]
+#[doc(inline)]
+pub use kem::DhK256HkdfSha256;
+#[cfg(feature = "p256")]
+#[doc(inline)]
+pub use kem::DhP256HkdfSha256;
+#[cfg(feature = "x25519-dalek")]
+#[doc(inline)]
+pub use kem::X25519HkdfSha256;
 #[doc(inline)]
 pub use kem::{EncappedKey, Kem};
 #[doc(inline)]
-pub use kex::{Deserializable, Serializable};
+pub use kex::{Deserializable, KeyExchange, Serializable};
+#[doc(inline)]
+pub use key_config::KeyConfig;
+#[doc(inline)]
+pub use keyring::Keyring;
+#[doc(inline)]
+pub use migrate::Migration;
 #[doc(inline)]
 pub use op_mode::{OpModeR, OpModeS, PskBundle};
 #[doc(inline)]
-pub use setup::{setup_receiver, setup_sender};
+pub use replay::ReplayCache;
+#[doc(inline)]
+pub use sealed::{SealedMessage, Suite};
+#[doc(inline)]
+pub use seq_guard::SeqGuard;
+#[doc(inline)]
+pub use setup::{setup_receiver, setup_receiver_from_bytes, setup_sender};
 #[doc(inline)]
 pub use single_shot::{single_shot_open, single_shot_seal};
+#[cfg(feature = "heapless")]
+#[doc(inline)]
+pub use single_shot::{single_shot_open_heapless, single_shot_seal_heapless};
 
 //-------- Top-level types --------//
 
@@ -143,6 +270,31 @@ pub enum HpkeError {
     InvalidKdfLength,
     /// The deserializer was given a bad encoding
     InvalidEncoding,
+    /// The recipient has no private key with the given key ID
+    UnknownKeyId,
+    /// A signature failed to verify
+    InvalidSignature,
+    /// The encapsulated key was already seen by a [`ReplayCache`]
+    ReplayedKey,
+    /// The peer's key confirmation tag didn't match, i.e., it derived a different context
+    ConfirmationFailed,
+    /// The deserializer was given a well-formed encoding that isn't canonical
+    NonCanonicalEncoding,
+    /// The deserializer was given a canonical encoding of an invalid public key, e.g., a point
+    /// that isn't on the curve
+    InvalidPoint,
+    /// The output doesn't fit in the fixed-capacity buffer it was asked to go in
+    BufferTooSmall,
+    /// The KEM can't do the requested mode, i.e., an auth mode with a KEM whose
+    /// [`SUPPORTS_AUTH`](Kem::SUPPORTS_AUTH) is false
+    UnsupportedMode,
+    /// A self-describing message names a different ciphersuite than the one it's being opened
+    /// with
+    SuiteMismatch,
+    /// A key was used outside of its validity period
+    KeyExpired,
+    /// The sequence number was already used under this context, according to a [`SeqGuard`]
+    ReusedSeq,
 }
 
 impl core::fmt::Display for HpkeError {
@@ -154,6 +306,19 @@ impl core::fmt::Display for HpkeError {
             HpkeError::InvalidKeyExchange => "Key exchange validation error",
             HpkeError::InvalidKdfLength => "Too many bytes requested from KDF",
             HpkeError::InvalidEncoding => "Cannot deserialize byte sequence: invalid encoding",
+            HpkeError::UnknownKeyId => "No private key with the given key ID",
+            HpkeError::InvalidSignature => "Invalid signature",
+            HpkeError::ReplayedKey => "Replayed encapsulated key",
+            HpkeError::ConfirmationFailed => "Key confirmation failed",
+            HpkeError::NonCanonicalEncoding => {
+                "Cannot deserialize byte sequence: non-canonical encoding"
+            }
+            HpkeError::InvalidPoint => "Cannot deserialize byte sequence: invalid public key",
+            HpkeError::BufferTooSmall => "Output buffer is too small",
+            HpkeError::UnsupportedMode => "Mode not supported by this KEM",
+            HpkeError::SuiteMismatch => "Message was sealed under a different ciphersuite",
+            HpkeError::KeyExpired => "Key is outside its validity period",
+            HpkeError::ReusedSeq => "Sequence number was already used",
         };
         f.write_str(kind)
     }
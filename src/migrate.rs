@@ -0,0 +1,199 @@
+//! This module defines [`Migration`], which moves stored [`SealedMessage`]s from one recipient
+//! key and suite to another, e.g., from a P-256 key to an X25519 key, or from AES-GCM to
+//! ChaCha20Poly1305. Each message is opened with a [`ReceiverConfig`] for the old key and sealed
+//! again with a [`Recipient`] for the new one, under the same AAD.
+//!
+//! [`Migration::migrate_all`] does this lazily over an iterator of messages, so a store of any
+//! size can be migrated while only one plaintext is in memory at a time. Each plaintext is zeroed
+//! once it's been sealed again.
+
+use crate::{
+    aead::Aead,
+    config::{ReceiverConfig, Recipient},
+    kdf::Kdf as KdfTrait,
+    kem::Kem as KemTrait,
+    sealed::SealedMessage,
+    HpkeError,
+};
+
+use alloc::vec::Vec;
+use rand::{CryptoRng, RngCore};
+use zeroize::Zeroize;
+
+/// Re-encrypts messages sealed to the key of `from` so that they're sealed to `to` instead.
+/// `OldKem` is the KEM of the old key. The new suite is `NewA`, `NewKdf`, and `NewKem`.
+pub struct Migration<'c, 'a, OldKem, NewA, NewKdf, NewKem>
+where
+    OldKem: KemTrait,
+    NewA: Aead,
+    NewKdf: KdfTrait,
+    NewKem: KemTrait,
+{
+    from: &'c ReceiverConfig<'a, OldKem>,
+    to: &'c Recipient<'a, NewA, NewKdf, NewKem>,
+}
+
+impl<'c, 'a, OldKem, NewA, NewKdf, NewKem> Migration<'c, 'a, OldKem, NewA, NewKdf, NewKem>
+where
+    OldKem: KemTrait,
+    NewA: Aead,
+    NewKdf: KdfTrait,
+    NewKem: KemTrait,
+{
+    /// Makes a migration that opens with `from` and seals with `to`
+    pub fn new(
+        from: &'c ReceiverConfig<'a, OldKem>,
+        to: &'c Recipient<'a, NewA, NewKdf, NewKem>,
+    ) -> Self {
+        Migration { from, to }
+    }
+
+    /// Re-encrypts one serialized message, which was sealed under the suite `OldA`, `OldKdf`, and
+    /// `OldKem` with the AAD `aad`. `now` is the current time.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns the serialization of the new message on success. If `envelope` was sealed under
+    /// another suite, returns `Err(HpkeError::SuiteMismatch)`, so a store that mixes suites can
+    /// try each of them in turn. Otherwise, fails the same way as [`SealedMessage::from_bytes`],
+    /// [`ReceiverConfig::open`], and [`Recipient::seal`].
+    pub fn migrate<OldA, OldKdf, R>(
+        &self,
+        envelope: &[u8],
+        aad: &[u8],
+        now: u64,
+        csprng: &mut R,
+    ) -> Result<Vec<u8>, HpkeError>
+    where
+        OldA: Aead,
+        OldKdf: KdfTrait,
+        R: CryptoRng + RngCore,
+    {
+        let msg = SealedMessage::<(OldA, OldKdf, OldKem)>::from_bytes(envelope)?;
+        let mut plaintext = self.from.open(&msg, aad, now)?;
+        let res = self.to.seal(&plaintext, aad, now, csprng);
+        plaintext.zeroize();
+
+        Ok(res?.to_bytes())
+    }
+
+    /// Re-encrypts each `(envelope, aad)` pair that `envelopes` yields, as in
+    /// [`migrate`](Self::migrate). Nothing is done until the returned iterator is advanced, and
+    /// each item is dropped before the next is taken.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns an iterator over the results of [`migrate`](Self::migrate), in the same order as
+    /// `envelopes`. A failure doesn't stop the iteration.
+    pub fn migrate_all<'m, OldA, OldKdf, I, E, D, R>(
+        &'m self,
+        envelopes: I,
+        now: u64,
+        csprng: &'m mut R,
+    ) -> impl Iterator<Item = Result<Vec<u8>, HpkeError>> + 'm
+    where
+        OldA: Aead,
+        OldKdf: KdfTrait,
+        I: IntoIterator<Item = (E, D)>,
+        I::IntoIter: 'm,
+        E: AsRef<[u8]>,
+        D: AsRef<[u8]>,
+        R: CryptoRng + RngCore,
+    {
+        envelopes.into_iter().map(move |(envelope, aad)| {
+            self.migrate::<OldA, OldKdf, R>(envelope.as_ref(), aad.as_ref(), now, csprng)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Migration;
+    use crate::{
+        aead::ChaCha20Poly1305,
+        config::{ReceiverConfig, Recipient},
+        kdf::HkdfSha256,
+        kem::Kem as KemTrait,
+        sealed::SealedMessage,
+        HpkeError,
+    };
+
+    use alloc::vec::Vec;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Tests that messages move from the old key to the new one, keeping their plaintext and AAD,
+    /// and that only the new key can open the results
+    macro_rules! test_migrate {
+        ($test_name:ident, $old_kem:ty, $new_kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type A = ChaCha20Poly1305;
+                type Kdf = HkdfSha256;
+                type OldKem = $old_kem;
+                type NewKem = $new_kem;
+                let mut csprng = StdRng::from_entropy();
+
+                let (old_sk, old_pk) = OldKem::gen_keypair(&mut csprng);
+                let (new_sk, new_pk) = NewKem::gen_keypair(&mut csprng);
+                let old_sender = Recipient::<A, Kdf, OldKem>::new(old_pk, b"old info");
+                let old_config =
+                    ReceiverConfig::<OldKem>::new(old_sk, b"old info").suite::<Kdf, A>();
+                let new_recipient = Recipient::<A, Kdf, NewKem>::new(new_pk, b"new info");
+                let new_config =
+                    ReceiverConfig::<NewKem>::new(new_sk, b"new info").suite::<Kdf, A>();
+
+                // A store of a few messages, each with its own AAD
+                let store: Vec<(Vec<u8>, Vec<u8>)> = (0..4u8)
+                    .map(|i| {
+                        let aad = alloc::vec![b'a', i];
+                        let msg = old_sender.seal(&[i; 20], &aad, 0, &mut csprng).unwrap();
+                        (msg.to_bytes(), aad)
+                    })
+                    .collect();
+
+                let migration = Migration::new(&old_config, &new_recipient);
+                let migrated: Vec<Vec<u8>> = migration
+                    .migrate_all::<A, Kdf, _, _, _, _>(store.iter().cloned(), 0, &mut csprng)
+                    .collect::<Result<_, _>>()
+                    .unwrap();
+
+                for (i, (envelope, (_, aad))) in migrated.iter().zip(&store).enumerate() {
+                    let msg = SealedMessage::<(A, Kdf, NewKem)>::from_bytes(envelope).unwrap();
+                    assert_eq!(new_config.open(&msg, aad, 0).unwrap(), [i as u8; 20]);
+                    // The AAD still has to match
+                    assert!(new_config.open(&msg, b"other aad", 0).is_err());
+                }
+
+                // The old suite is checked, and a bad AAD fails the migration
+                let (envelope, aad) = &store[0];
+                #[cfg(feature = "hkdf-sha384")]
+                {
+                    type OtherKdf = crate::kdf::HkdfSha384;
+                    let res = migration.migrate::<A, OtherKdf, _>(envelope, aad, 0, &mut csprng);
+                    assert!(matches!(res, Err(HpkeError::SuiteMismatch)));
+                }
+                let res = migration.migrate::<A, Kdf, _>(envelope, b"wrong", 0, &mut csprng);
+                assert!(matches!(res, Err(HpkeError::InvalidTag)));
+            }
+        };
+    }
+
+    #[cfg(feature = "x25519-dalek")]
+    test_migrate!(
+        test_migrate_x25519,
+        crate::kem::X25519HkdfSha256,
+        crate::kem::X25519HkdfSha256
+    );
+    #[cfg(feature = "p256")]
+    test_migrate!(
+        test_migrate_p256,
+        crate::kem::DhP256HkdfSha256,
+        crate::kem::DhP256HkdfSha256
+    );
+    #[cfg(all(feature = "p256", feature = "x25519-dalek"))]
+    test_migrate!(
+        test_migrate_p256_to_x25519,
+        crate::kem::DhP256HkdfSha256,
+        crate::kem::X25519HkdfSha256
+    );
+}
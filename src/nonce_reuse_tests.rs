@@ -0,0 +1,293 @@
+//! Regression tests for nonce reuse across everything that seals. The context API, chunked
+//! sealing, external nonces, channel key updates, datagrams, and rewrapping each pick their own
+//! keys and sequence numbers, and a bug in how any two of them interact would show up as a
+//! (key, nonce) pair used twice. These tests seal through an [`Offload`] AEAD whose backend logs
+//! the key and nonce of every seal, run arbitrary sequences of operations, and check that no pair
+//! in the log repeats.
+
+#[cfg(feature = "danger")]
+use crate::seq_guard::SeqGuard;
+use crate::{
+    aead::{AeadBackend, AeadKey, AeadNonce, AeadTag, ChaCha20Poly1305, Offload, LARGE_CHUNK_SIZE},
+    channel::HpkeChannel,
+    datagram::{DatagramReceiver, DatagramSender},
+    kdf::HkdfSha256,
+    kem::{Kem as KemTrait, X25519HkdfSha256},
+    op_mode::{OpModeR, OpModeS},
+    sealed::SealedMessage,
+    setup::{setup_receiver, setup_sender},
+    test_util::gen_ctx_simple_pair,
+    Deserializable, HpkeError, Serializable,
+};
+
+extern crate std;
+use std::{cell::RefCell, collections::BTreeSet, thread_local, vec::Vec};
+
+use proptest::prelude::*;
+use rand::{rngs::StdRng, SeedableRng};
+
+type Kem = X25519HkdfSha256;
+type Kdf = HkdfSha256;
+// The AEAD under test. Its output is the same as ChaCha20Poly1305's.
+type A = Offload<RecordingBackend>;
+
+// A (key, nonce) pair that was sealed under
+type Use = (Vec<u8>, Vec<u8>);
+
+thread_local! {
+    // Every (key, nonce) pair sealed under on this thread, in order
+    static LOG: RefCell<Vec<Use>> = const { RefCell::new(Vec::new()) };
+}
+
+// Logs a (key, nonce) pair
+fn record(key: &[u8], nonce: &[u8]) {
+    LOG.with(|log| log.borrow_mut().push((key.to_vec(), nonce.to_vec())));
+}
+
+// Empties the log and returns what was in it
+fn take_log() -> Vec<Use> {
+    LOG.with(|log| log.replace(Vec::new()))
+}
+
+// Empties the log and checks that no pair in it repeats. Returns the number of distinct keys.
+fn check_log() -> Result<usize, TestCaseError> {
+    let log = take_log();
+    let mut seen = BTreeSet::new();
+    for (key, nonce) in log.iter() {
+        prop_assert!(
+            seen.insert((key, nonce)),
+            "nonce {:02x?} was used twice under one key",
+            nonce
+        );
+    }
+    let keys: BTreeSet<&Vec<u8>> = log.iter().map(|(key, _)| key).collect();
+    Ok(keys.len())
+}
+
+/// A backend that runs ChaCha20Poly1305 in software and logs the key and nonce of every seal
+#[derive(Clone)]
+struct RecordingBackend {
+    cipher: <ChaCha20Poly1305 as crate::aead::Aead>::AeadImpl,
+    key: Vec<u8>,
+}
+
+impl AeadBackend for RecordingBackend {
+    type Alg = ChaCha20Poly1305;
+
+    fn new(key: &AeadKey<ChaCha20Poly1305>) -> Self {
+        use aead::NewAead;
+        RecordingBackend {
+            cipher: NewAead::new(key),
+            key: key.to_vec(),
+        }
+    }
+
+    fn seal_in_place(
+        &self,
+        nonce: &AeadNonce<ChaCha20Poly1305>,
+        aad: &[u8],
+        buf: &mut [u8],
+    ) -> Result<AeadTag<ChaCha20Poly1305>, HpkeError> {
+        use aead::AeadInPlace;
+        record(&self.key, nonce);
+        self.cipher
+            .encrypt_in_place_detached(nonce, aad, buf)
+            .map_err(|_| HpkeError::Encryption)
+            .and_then(|tag| AeadTag::from_bytes(&tag))
+    }
+
+    fn open_in_place(
+        &self,
+        nonce: &AeadNonce<ChaCha20Poly1305>,
+        aad: &[u8],
+        buf: &mut [u8],
+        tag: &AeadTag<ChaCha20Poly1305>,
+    ) -> Result<(), HpkeError> {
+        use aead::AeadInPlace;
+        self.cipher
+            .decrypt_in_place_detached(nonce, aad, buf, &tag.to_bytes())
+            .map_err(|_| HpkeError::InvalidTag)
+    }
+}
+
+/// Tests that the log catches a repeat, so that the properties below aren't vacuous
+#[test]
+fn test_log_catches_reuse() {
+    take_log();
+    record(b"key", b"nonce one");
+    record(b"key", b"nonce two");
+    record(b"other key", b"nonce one");
+    assert_eq!(check_log().unwrap(), 2);
+
+    record(b"key", b"nonce one");
+    record(b"key", b"nonce one");
+    assert!(check_log().is_err());
+}
+
+proptest! {
+    /// Tests that, over an arbitrary mix of `seal`, `seal_large`, and, with the `danger` feature,
+    /// `next_nonce_dangerous` on one context, every nonce is new. The ops are
+    /// `(kind, chunks, extra)`, and a `seal_large` is of `chunks` full chunks plus `extra` bytes.
+    #[test]
+    fn prop_ctx_ops(ops in proptest::collection::vec((0u8..3, 0usize..2, 0usize..2), 1..12)) {
+        take_log();
+        let (mut sender_ctx, _) = gen_ctx_simple_pair::<A, Kdf, Kem>();
+        // External nonces are used with the key the context hands out. That's only available
+        // before the first seal.
+        #[cfg(feature = "danger")]
+        let key = sender_ctx.key_dangerous().unwrap().to_vec();
+
+        let mut expected_uses = 0;
+        for (kind, chunks, extra) in ops {
+            match kind {
+                0 => {
+                    sender_ctx.seal(&mut [0u8; 16], b"").unwrap();
+                    expected_uses += 1;
+                }
+                1 => {
+                    let mut msg = vec![0u8; chunks * LARGE_CHUNK_SIZE + extra];
+                    let tags = sender_ctx.seal_large(&mut msg, b"").unwrap();
+                    expected_uses += tags.len();
+                }
+                #[cfg(feature = "danger")]
+                _ => {
+                    let nonce = sender_ctx.next_nonce_dangerous().unwrap();
+                    record(&key, &nonce);
+                    expected_uses += 1;
+                }
+                #[cfg(not(feature = "danger"))]
+                _ => (),
+            }
+        }
+
+        prop_assert_eq!(LOG.with(|log| log.borrow().len()), expected_uses);
+        // Everything was under the one key, so all the nonces differ
+        prop_assert!(check_log()? <= 1);
+    }
+
+    /// Tests that the nonces `compute_nonce_checked` hands out are all different, however the
+    /// sequence numbers asked for repeat
+    #[cfg(feature = "danger")]
+    #[test]
+    fn prop_checked_nonces(
+        windowed in any::<bool>(),
+        seqs in proptest::collection::vec(0u64..100, 1..64),
+    ) {
+        let (sender_ctx, _) = gen_ctx_simple_pair::<A, Kdf, Kem>();
+        let mut guard = if windowed { SeqGuard::windowed() } else { SeqGuard::monotonic() };
+
+        let mut nonces = BTreeSet::new();
+        for seq in seqs {
+            if let Ok(nonce) = sender_ctx.compute_nonce_checked(seq, &mut guard) {
+                prop_assert!(nonces.insert(nonce.to_vec()));
+            }
+        }
+    }
+
+    /// Tests that, over an arbitrary run of sends and key updates from either end of a channel,
+    /// no nonce is used twice under one key, and that every epoch of each direction has its own
+    /// key. The ops are `(kind, len)`. Kinds 0 and 1 send `len` bytes from the initiator and the
+    /// responder, and kinds 2 and 3 update their sending keys.
+    #[test]
+    fn prop_channel_ops(ops in proptest::collection::vec((0u8..4, 0usize..40), 1..32)) {
+        let mut csprng = StdRng::from_entropy();
+        let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+        let info = b"nonce reuse test";
+        take_log();
+
+        let (encapped_key, mut initiator) =
+            HpkeChannel::<A, Kdf, Kem>::initiate(&OpModeS::Base, &pk_recip, info, &mut csprng)
+                .unwrap();
+        let mut responder =
+            HpkeChannel::<A, Kdf, Kem>::accept(&OpModeR::Base, &sk_recip, &encapped_key, info)
+                .unwrap();
+
+        // The (direction, epoch) of every frame sent
+        let mut epochs_used = BTreeSet::new();
+        for (kind, len) in ops {
+            let (from, to) = if kind % 2 == 0 {
+                (&mut initiator, &mut responder)
+            } else {
+                (&mut responder, &mut initiator)
+            };
+            epochs_used.insert((kind % 2, from.send_epoch()));
+
+            let msg = vec![kind; len];
+            let frame = if kind < 2 {
+                from.send(&msg).unwrap()
+            } else {
+                from.update_send_key().unwrap()
+            };
+            let received = to.recv(&frame).unwrap();
+            if kind < 2 {
+                prop_assert_eq!(received, msg);
+            }
+        }
+
+        prop_assert_eq!(check_log()?, epochs_used.len());
+    }
+
+    /// Tests that the fragments of arbitrary messages sent over datagrams all use new nonces,
+    /// and that the messages arrive intact when each one's fragments come in reverse order
+    #[test]
+    fn prop_datagram_ops(lens in proptest::collection::vec(0usize..200, 1..16)) {
+        let mut csprng = StdRng::from_entropy();
+        let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+        let info = b"nonce reuse test";
+        take_log();
+
+        let (encapped_key, sender_ctx) =
+            setup_sender::<A, Kdf, Kem, _>(&OpModeS::Base, &pk_recip, info, &mut csprng).unwrap();
+        let receiver_ctx =
+            setup_receiver::<A, Kdf, Kem>(&OpModeR::Base, &sk_recip, &encapped_key, info).unwrap();
+        let mut sender = DatagramSender::new(sender_ctx, 64);
+        let mut receiver = DatagramReceiver::new(receiver_ctx, 4);
+
+        let mut num_datagrams = 0;
+        for (i, len) in lens.into_iter().enumerate() {
+            let msg = vec![i as u8; len];
+            let datagrams = sender.send(&msg).unwrap();
+            num_datagrams += datagrams.len();
+
+            let mut delivered = None;
+            for datagram in datagrams.iter().rev() {
+                delivered = receiver.recv(datagram).unwrap();
+            }
+            prop_assert_eq!(delivered, Some(msg));
+        }
+
+        prop_assert_eq!(LOG.with(|log| log.borrow().len()), num_datagrams);
+        prop_assert_eq!(check_log()?, 1);
+    }
+
+    /// Tests that rewrapping a message back and forth between two keys seals it under a new key
+    /// each time
+    #[test]
+    fn prop_rewrap_chain(hops in 1usize..8) {
+        type Suite = (A, Kdf, Kem);
+        let mut csprng = StdRng::from_entropy();
+        let keypairs = [Kem::gen_keypair(&mut csprng), Kem::gen_keypair(&mut csprng)];
+        let (info, aad) = (b"nonce reuse test", b"aad");
+        take_log();
+
+        let mut wrapped = SealedMessage::<Suite>::seal(
+            &OpModeS::Base,
+            &keypairs[0].1,
+            info,
+            b"a data encryption key",
+            aad,
+            &mut csprng,
+        )
+        .unwrap();
+        for hop in 0..hops {
+            let (sk_old, _) = &keypairs[hop % 2];
+            let (_, pk_new) = &keypairs[(hop + 1) % 2];
+            wrapped = wrapped.rewrap(sk_old, pk_new, info, aad, &mut csprng).unwrap();
+        }
+
+        let (sk_recip, _) = &keypairs[hops % 2];
+        let plaintext = wrapped.open(&OpModeR::Base, sk_recip, info, aad).unwrap();
+        prop_assert_eq!(&plaintext[..], b"a data encryption key");
+        prop_assert_eq!(check_log()?, hops + 1);
+    }
+}
@@ -0,0 +1,310 @@
+//! This module defines [`PskManager`], which keeps track of a rotating preshared key. It holds the
+//! current PSK and the one before it, each with a window of time in which it's valid. Senders use
+//! the newest valid PSK, and receivers accept any valid PSK, so that messages sealed just before a
+//! rotation still open just after it.
+//!
+//! Times are whatever the caller says they are, usually seconds since the UNIX epoch. This crate
+//! doesn't read the clock.
+//!
+//! It also defines [`HybridPsk`], which turns a secret agreed on some other way, e.g., by a
+//! post-quantum KEM, into a PSK.
+
+use crate::op_mode::PskBundle;
+
+use alloc::vec::Vec;
+
+use digest::Digest;
+use hkdf::Hkdf;
+use sha2::Sha256;
+use zeroize::Zeroize;
+
+// PSK IDs are bound to this string, so that they can't collide with any other hash of the PSK
+const PSK_ID_DOMAIN: &[u8] = b"rust-hpke psk id";
+
+// The HKDF salt that hybrid PSKs are extracted with
+const HYBRID_PSK_DOMAIN: &[u8] = b"rust-hpke hybrid psk";
+
+/// Derives a PSK ID from a PSK. This is `SHA-256(domain || psk)`, so the ID is a commitment to the
+/// PSK and needn't be tracked separately. Since the ID goes over the wire, the PSK must have high
+/// entropy, which the spec already requires.
+pub fn derive_psk_id(psk: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(PSK_ID_DOMAIN);
+    hasher.update(psk);
+
+    let mut buf = [0u8; 32];
+    buf.copy_from_slice(&hasher.finalize());
+    buf
+}
+
+/// The times between which something is valid, inclusive on both ends
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ValidityWindow {
+    pub not_before: u64,
+    pub not_after: u64,
+}
+
+impl ValidityWindow {
+    /// Returns whether `now` falls in this window
+    pub fn contains(&self, now: u64) -> bool {
+        self.not_before <= now && now <= self.not_after
+    }
+}
+
+// A PSK, its ID, and when it's valid
+#[derive(Clone)]
+struct PskEntry {
+    psk: Vec<u8>,
+    psk_id: [u8; 32],
+    validity: ValidityWindow,
+}
+
+impl PskEntry {
+    fn bundle(&self) -> PskBundle<'_> {
+        PskBundle {
+            psk: &self.psk,
+            psk_id: &self.psk_id,
+        }
+    }
+}
+
+/// Holds the current and previous PSK of a PSK-mode deployment. See the [module docs](self).
+#[derive(Clone, Default)]
+pub struct PskManager {
+    current: Option<PskEntry>,
+    previous: Option<PskEntry>,
+}
+
+impl PskManager {
+    /// Makes a manager with no PSKs
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes `psk` the current PSK, valid in the given window. The old current PSK becomes the
+    /// previous PSK, and the old previous PSK is forgotten. Returns the ID of the new PSK, as
+    /// computed by [`derive_psk_id`].
+    pub fn rotate(&mut self, psk: &[u8], validity: ValidityWindow) -> [u8; 32] {
+        let psk_id = derive_psk_id(psk);
+        let entry = PskEntry {
+            psk: psk.to_vec(),
+            psk_id,
+            validity,
+        };
+        self.previous = self.current.replace(entry);
+        psk_id
+    }
+
+    /// Forgets every PSK whose validity window ended before `now`
+    pub fn remove_expired(&mut self, now: u64) {
+        if matches!(&self.current, Some(e) if e.validity.not_after < now) {
+            self.current = None;
+        }
+        if matches!(&self.previous, Some(e) if e.validity.not_after < now) {
+            self.previous = None;
+        }
+    }
+
+    /// Returns the PSK a sender should use at time `now`. This is the current PSK if it's valid,
+    /// and otherwise the previous PSK if that's valid. This fallback covers a new PSK that was
+    /// installed ahead of its start time.
+    pub fn sender_bundle(&self, now: u64) -> Option<PskBundle<'_>> {
+        self.valid_entries(now).next().map(PskEntry::bundle)
+    }
+
+    /// Returns the PSK with the given ID, if it's valid at time `now`. This is what a receiver
+    /// uses to look up the PSK that a sender said it used.
+    pub fn receiver_bundle(&self, psk_id: &[u8], now: u64) -> Option<PskBundle<'_>> {
+        self.valid_entries(now)
+            .find(|e| &e.psk_id[..] == psk_id)
+            .map(PskEntry::bundle)
+    }
+
+    // Iterates over the PSKs that are valid at time `now`, newest first
+    fn valid_entries(&self, now: u64) -> impl Iterator<Item = &PskEntry> {
+        self.current
+            .iter()
+            .chain(self.previous.iter())
+            .filter(move |e| e.validity.contains(now))
+    }
+}
+
+/// A PSK derived from a secret that both sides got out of band, e.g., the shared secret of a
+/// post-quantum KEM run next to HPKE. Using it in PSK mode mixes that secret into the key
+/// schedule, so the context stays confidential as long as either the DH or the out-of-band
+/// secret is unbroken. This is a stopgap for deployments that can't use a post-quantum KEM in
+/// HPKE itself yet.
+///
+/// The PSK is `HKDF-SHA256(salt = domain, ikm = secret, info = context)`, so it's 32 bytes with
+/// full entropy whatever the length of the secret. The `context` should name the protocol and
+/// the exchange the secret came from, so that one secret can't yield the same PSK in two places.
+/// The PSK ID is [`derive_psk_id`] of the PSK. It goes over the wire, and it reveals nothing
+/// about the secret besides telling apart different secrets.
+///
+/// The secret has to be fresh and have at least 256 bits of entropy, like any PSK. As in any PSK
+/// mode, everyone who knows the secret can make contexts that the receiver will accept, so this
+/// doesn't authenticate the sender by itself. Use `OpModeS::AuthPsk` for that.
+pub struct HybridPsk {
+    psk: [u8; 32],
+    psk_id: [u8; 32],
+}
+
+impl HybridPsk {
+    /// Derives the PSK and its ID from the out-of-band `secret` and a `context` string. Both ends
+    /// call this with the same inputs.
+    pub fn new(secret: &[u8], context: &[u8]) -> Self {
+        let mut psk = [0u8; 32];
+        // This unwrap is fine. 32 bytes is far less than the 255 * 32 bytes HKDF-SHA256 can output
+        Hkdf::<Sha256>::new(Some(HYBRID_PSK_DOMAIN), secret)
+            .expand(context, &mut psk)
+            .unwrap();
+        let psk_id = derive_psk_id(&psk);
+
+        HybridPsk { psk, psk_id }
+    }
+
+    /// Returns the ID of this PSK
+    pub fn psk_id(&self) -> &[u8; 32] {
+        &self.psk_id
+    }
+
+    /// Returns the PSK and its ID, to go in an `OpModeS` or `OpModeR`
+    pub fn bundle(&self) -> PskBundle<'_> {
+        PskBundle {
+            psk: &self.psk,
+            psk_id: &self.psk_id,
+        }
+    }
+}
+
+impl Drop for HybridPsk {
+    fn drop(&mut self) {
+        self.psk.zeroize();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{derive_psk_id, HybridPsk, PskManager, ValidityWindow};
+
+    /// Tests the sender and receiver views of a PSK across a rotation
+    #[test]
+    fn test_psk_rotation() {
+        let (psk1, psk2, psk3) = ([1u8; 32], [2u8; 32], [3u8; 32]);
+        let mut mgr = PskManager::new();
+        assert!(mgr.sender_bundle(0).is_none());
+
+        let id1 = mgr.rotate(
+            &psk1,
+            ValidityWindow {
+                not_before: 0,
+                not_after: 200,
+            },
+        );
+        assert_eq!(id1, derive_psk_id(&psk1));
+
+        // Install the next PSK ahead of time. Senders keep using the old one until it starts.
+        let id2 = mgr.rotate(
+            &psk2,
+            ValidityWindow {
+                not_before: 100,
+                not_after: 300,
+            },
+        );
+        assert_ne!(id1, id2);
+        assert_eq!(mgr.sender_bundle(50).unwrap().psk, &psk1);
+        assert_eq!(mgr.sender_bundle(150).unwrap().psk, &psk2);
+
+        // During the overlap, receivers accept both
+        assert_eq!(mgr.receiver_bundle(&id1, 150).unwrap().psk, &psk1);
+        assert_eq!(mgr.receiver_bundle(&id2, 150).unwrap().psk_id, &id2);
+        assert!(mgr.receiver_bundle(&id2, 50).is_none());
+
+        // After the old PSK expires, it's rejected, and removing it leaves the new one
+        assert!(mgr.receiver_bundle(&id1, 250).is_none());
+        mgr.remove_expired(250);
+        assert!(mgr.previous.is_none());
+        assert_eq!(mgr.sender_bundle(250).unwrap().psk, &psk2);
+
+        // A third rotation forgets the first PSK entirely
+        let id3 = mgr.rotate(
+            &psk3,
+            ValidityWindow {
+                not_before: 300,
+                not_after: 400,
+            },
+        );
+        assert_eq!(mgr.receiver_bundle(&id3, 300).unwrap().psk, &psk3);
+        assert_eq!(mgr.receiver_bundle(&id2, 300).unwrap().psk, &psk2);
+        assert!(mgr.receiver_bundle(&id1, 0).is_none());
+        assert!(mgr.sender_bundle(401).is_none());
+    }
+
+    /// Tests that both ends derive the same hybrid PSK, that the context and the secret both
+    /// change it, and that a receiver with a different secret can't open what the sender sealed
+    #[cfg(feature = "x25519-dalek")]
+    #[test]
+    fn test_hybrid_psk() {
+        use crate::{
+            aead::ChaCha20Poly1305,
+            kdf::HkdfSha256,
+            kem::{Kem as KemTrait, X25519HkdfSha256},
+            op_mode::{OpModeR, OpModeS},
+            setup::{setup_receiver, setup_sender},
+        };
+        use rand::{rngs::StdRng, SeedableRng};
+
+        type A = ChaCha20Poly1305;
+        type Kdf = HkdfSha256;
+        type Kem = X25519HkdfSha256;
+
+        // Stand-ins for the shared secrets of a PQ KEM
+        let secret = [7u8; 32];
+        let other_secret = [8u8; 32];
+
+        let sender_psk = HybridPsk::new(&secret, b"test protocol v1");
+        let receiver_psk = HybridPsk::new(&secret, b"test protocol v1");
+        assert_eq!(sender_psk.bundle().psk, receiver_psk.bundle().psk);
+        assert_eq!(sender_psk.psk_id(), &derive_psk_id(sender_psk.bundle().psk));
+        assert_ne!(sender_psk.bundle().psk, &secret[..]);
+
+        let other_context = HybridPsk::new(&secret, b"test protocol v2");
+        assert_ne!(other_context.psk_id(), sender_psk.psk_id());
+        let wrong_psk = HybridPsk::new(&other_secret, b"test protocol v1");
+        assert_ne!(wrong_psk.psk_id(), sender_psk.psk_id());
+
+        let mut csprng = StdRng::from_entropy();
+        let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+        let (encapped_key, mut sender_ctx) = setup_sender::<A, Kdf, Kem, _>(
+            &OpModeS::Psk(sender_psk.bundle()),
+            &pk_recip,
+            b"info",
+            &mut csprng,
+        )
+        .unwrap();
+
+        let mut msg = *b"hybrid";
+        let tag = sender_ctx.seal(&mut msg, b"").unwrap();
+
+        // The wrong secret gives a context that can't open the message
+        let mut wrong_ctx = setup_receiver::<A, Kdf, Kem>(
+            &OpModeR::Psk(wrong_psk.bundle()),
+            &sk_recip,
+            &encapped_key,
+            b"info",
+        )
+        .unwrap();
+        let mut ciphertext = msg;
+        assert!(wrong_ctx.open(&mut ciphertext, b"", &tag).is_err());
+
+        let mut receiver_ctx = setup_receiver::<A, Kdf, Kem>(
+            &OpModeR::Psk(receiver_psk.bundle()),
+            &sk_recip,
+            &encapped_key,
+            b"info",
+        )
+        .unwrap();
+        receiver_ctx.open(&mut msg, b"", &tag).unwrap();
+        assert_eq!(&msg, b"hybrid");
+    }
+}
@@ -0,0 +1,293 @@
+//! This module defines [`ReplayCache`], which remembers the encapsulated keys a receiver has seen
+//! recently and rejects any that come around again. Every single-shot message has a fresh,
+//! random encapsulated key, so a repeat means the message was replayed.
+//!
+//! The cache only remembers a key for a fixed window of time after it was first seen. For
+//! at-most-once processing, the receiver also has to drop messages older than that window, e.g.,
+//! by putting a timestamp in the AAD. Otherwise an attacker can wait for a key to be forgotten and
+//! replay it then.
+//!
+//! Times are whatever the caller says they are, usually seconds since the UNIX epoch. This crate
+//! doesn't read the clock.
+
+use crate::{
+    aead::{Aead, AeadTag},
+    kdf::Kdf as KdfTrait,
+    kem::{EncappedKey, Kem as KemTrait},
+    kex::{KeyExchange, Serializable},
+    op_mode::OpModeR,
+    single_shot::single_shot_open,
+    HpkeError,
+};
+
+use alloc::collections::BTreeMap;
+
+use digest::Digest;
+use sha2::Sha256;
+
+// The cache is indexed by the hash of an encapsulated key, so that every entry is the same size
+// regardless of the KEM
+type EncDigest = [u8; 32];
+
+fn enc_digest(enc: &[u8]) -> EncDigest {
+    let mut buf = [0u8; 32];
+    buf.copy_from_slice(&Sha256::digest(enc));
+    buf
+}
+
+/// A cache of recently seen encapsulated keys. A key is remembered from the time it's inserted
+/// until `window` later, inclusive.
+#[derive(Clone, Debug)]
+pub struct ReplayCache {
+    window: u64,
+    // Maps the digest of each encapsulated key to the time it was first seen
+    seen: BTreeMap<EncDigest, u64>,
+}
+
+impl ReplayCache {
+    /// Makes an empty cache that remembers each encapsulated key for `window` units of time
+    pub fn new(window: u64) -> Self {
+        ReplayCache {
+            window,
+            seen: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the number of encapsulated keys in the cache, including expired ones that haven't
+    /// been removed yet
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    /// Returns whether the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+
+    /// Returns whether the given encapsulated key was seen within the window before `now`
+    pub fn contains(&self, enc: &[u8], now: u64) -> bool {
+        match self.seen.get(&enc_digest(enc)) {
+            Some(&seen_at) => now <= seen_at.saturating_add(self.window),
+            None => false,
+        }
+    }
+
+    /// Records the given encapsulated key as seen at time `now`. Expired keys are removed first.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::ReplayedKey)` if the key was already seen within the window, and
+    /// leaves the cache unchanged. Otherwise returns `Ok(())`.
+    pub fn check_and_insert(&mut self, enc: &[u8], now: u64) -> Result<(), HpkeError> {
+        self.remove_expired(now);
+        if self.contains(enc, now) {
+            return Err(HpkeError::ReplayedKey);
+        }
+        self.seen.insert(enc_digest(enc), now);
+        Ok(())
+    }
+
+    /// Forgets every encapsulated key whose window ended before `now`
+    pub fn remove_expired(&mut self, now: u64) {
+        let window = self.window;
+        self.seen
+            .retain(|_, seen_at| now <= seen_at.saturating_add(window));
+    }
+
+    /// Does a [`single_shot_open`], rejecting encapsulated keys that were seen within the window
+    /// before `now`. The key is only recorded if the message opens, so that an attacker can't
+    /// block a message by sending its encapsulated key with a bad ciphertext first.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::ReplayedKey)` if the key was already seen, without decrypting
+    /// anything. Otherwise, succeeds and fails the same way as [`single_shot_open`].
+    #[allow(clippy::too_many_arguments)]
+    pub fn single_shot_open<A, Kdf, Kem>(
+        &mut self,
+        mode: &OpModeR<Kem::Kex>,
+        sk_recip: &<Kem::Kex as KeyExchange>::PrivateKey,
+        encapped_key: &EncappedKey<Kem>,
+        info: &[u8],
+        ciphertext: &mut [u8],
+        aad: &[u8],
+        tag: &AeadTag<A>,
+        now: u64,
+    ) -> Result<(), HpkeError>
+    where
+        A: Aead,
+        Kdf: KdfTrait,
+        Kem: KemTrait,
+    {
+        let enc = encapped_key.to_bytes();
+        self.remove_expired(now);
+        if self.contains(&enc, now) {
+            return Err(HpkeError::ReplayedKey);
+        }
+
+        single_shot_open::<A, Kdf, Kem>(mode, sk_recip, encapped_key, info, ciphertext, aad, tag)?;
+        self.seen.insert(enc_digest(&enc), now);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ReplayCache;
+    use crate::{
+        aead::ChaCha20Poly1305,
+        kdf::HkdfSha256,
+        kem::Kem as KemTrait,
+        op_mode::{OpModeR, OpModeS},
+        single_shot::single_shot_seal,
+        HpkeError,
+    };
+
+    use alloc::collections::BTreeMap;
+
+    use proptest::prelude::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Tests that keys are rejected within the window and forgotten after it
+    #[test]
+    fn test_window() {
+        let mut cache = ReplayCache::new(10);
+
+        cache.check_and_insert(b"enc one", 100).unwrap();
+        cache.check_and_insert(b"enc two", 105).unwrap();
+        assert_eq!(cache.len(), 2);
+
+        // Both ends of the window are inclusive
+        assert!(matches!(
+            cache.check_and_insert(b"enc one", 100),
+            Err(HpkeError::ReplayedKey)
+        ));
+        assert!(matches!(
+            cache.check_and_insert(b"enc one", 110),
+            Err(HpkeError::ReplayedKey)
+        ));
+
+        // Once the window is over, the key is forgotten and can be inserted again
+        assert!(!cache.contains(b"enc one", 111));
+        cache.check_and_insert(b"enc one", 111).unwrap();
+        assert!(cache.contains(b"enc one", 121));
+
+        cache.remove_expired(116);
+        assert_eq!(cache.len(), 1);
+        cache.remove_expired(122);
+        assert!(cache.is_empty());
+    }
+
+    proptest! {
+        /// Tests that, over an arbitrary run of keys and non-decreasing times, the cache never
+        /// accepts a key twice within the window, and accepts it again once the window is over
+        #[test]
+        fn prop_no_duplicates_in_window(
+            window in 0u64..20,
+            ops in proptest::collection::vec((0u8..4, 0u64..8), 1..64),
+        ) {
+            let mut cache = ReplayCache::new(window);
+            // The last time each key was accepted
+            let mut accepted_at: BTreeMap<u8, u64> = BTreeMap::new();
+
+            let mut now = 0u64;
+            for (key, step) in ops {
+                now += step;
+                let enc = [key; 32];
+                let replayed = matches!(accepted_at.get(&key), Some(&t) if now <= t + window);
+
+                match cache.check_and_insert(&enc, now) {
+                    Ok(()) => {
+                        prop_assert!(!replayed);
+                        accepted_at.insert(key, now);
+                    }
+                    Err(HpkeError::ReplayedKey) => prop_assert!(replayed),
+                    Err(e) => panic!("unexpected error {:?}", e),
+                }
+            }
+        }
+    }
+
+    /// Tests that a replayed message is rejected, and that a failed open doesn't record the key
+    macro_rules! test_replay_open {
+        ($test_name:ident, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type A = ChaCha20Poly1305;
+                type Kdf = HkdfSha256;
+                type Kem = $kem;
+                let mut csprng = StdRng::from_entropy();
+
+                let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+                let info = b"replay test";
+                let aad = b"aad";
+                let msg = b"at most once";
+
+                let mut ciphertext = msg.to_vec();
+                let (encapped_key, tag) = single_shot_seal::<A, Kdf, Kem, _>(
+                    &OpModeS::Base,
+                    &pk_recip,
+                    info,
+                    &mut ciphertext,
+                    aad,
+                    &mut csprng,
+                )
+                .unwrap();
+
+                let mut cache = ReplayCache::new(60);
+
+                // A forgery with the same encapsulated key fails and isn't remembered
+                let mut forged = ciphertext.clone();
+                forged[0] ^= 1;
+                let res = cache.single_shot_open::<A, Kdf, Kem>(
+                    &OpModeR::Base,
+                    &sk_recip,
+                    &encapped_key,
+                    info,
+                    &mut forged,
+                    aad,
+                    &tag,
+                    0,
+                );
+                assert!(matches!(res, Err(HpkeError::InvalidTag)));
+                assert!(cache.is_empty());
+
+                // The real message opens once
+                let mut plaintext = ciphertext.clone();
+                cache
+                    .single_shot_open::<A, Kdf, Kem>(
+                        &OpModeR::Base,
+                        &sk_recip,
+                        &encapped_key,
+                        info,
+                        &mut plaintext,
+                        aad,
+                        &tag,
+                        0,
+                    )
+                    .unwrap();
+                assert_eq!(&plaintext[..], &msg[..]);
+
+                // And is rejected the second time
+                let mut plaintext = ciphertext.clone();
+                let res = cache.single_shot_open::<A, Kdf, Kem>(
+                    &OpModeR::Base,
+                    &sk_recip,
+                    &encapped_key,
+                    info,
+                    &mut plaintext,
+                    aad,
+                    &tag,
+                    30,
+                );
+                assert!(matches!(res, Err(HpkeError::ReplayedKey)));
+                assert_eq!(plaintext, ciphertext);
+            }
+        };
+    }
+
+    #[cfg(feature = "x25519-dalek")]
+    test_replay_open!(test_replay_open_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_replay_open!(test_replay_open_p256, crate::kem::DhP256HkdfSha256);
+}
@@ -0,0 +1,532 @@
+//! This module defines [`SealedMessage`], a single-shot message that carries its ciphersuite in its
+//! type. The usual single-shot API takes the AEAD, KDF, and KEM as three separate type
+//! parameters at each call, so nothing stops a sender and receiver from disagreeing on one of
+//! them. That only shows up as a failed tag check. A [`SealedMessage<S>`] is sealed and opened
+//! with the same [`Suite`] `S`, and its serialization names the suite, so parsing a message from
+//! another suite fails right away.
+//!
+//! Wire format
+//! ===========
+//! A serialized message is `version || suite_id || enc || ciphertext || tag`, where `version` is
+//! the one byte [`SEALED_MESSAGE_VERSION`], and `suite_id` is the 10-byte
+//! `"HPKE" || kem_id || kdf_id || aead_id` from the spec. The header `version || suite_id` is
+//! prepended to the caller's AAD when sealing and opening, so a message whose header was stripped
+//! or rewritten doesn't open, even under a suite that parses it.
+//!
+//! Key rotation
+//! ============
+//! A store that encrypts each object under its own data encryption key (DEK) can keep the DEK
+//! as a `SealedMessage` next to the object's ciphertext. Moving the object to a new recipient key
+//! is then a [`SealedMessage::rewrap`] of the DEK, which never reads or rewrites the object
+//! itself. To change suites as well, see [`Migration`](crate::migrate::Migration).
+
+use crate::{
+    aead::{Aead, AeadTag},
+    kdf::Kdf as KdfTrait,
+    kem::{EncappedKey, Kem as KemTrait},
+    kex::{Deserializable, KeyExchange, Serializable},
+    op_mode::{OpModeR, OpModeS},
+    single_shot::{single_shot_open, single_shot_seal},
+    util::{full_suite_id, FullSuiteId},
+    HpkeError,
+};
+
+use alloc::vec::Vec;
+use rand::{CryptoRng, RngCore};
+use zeroize::Zeroize;
+
+/// A full HPKE ciphersuite: an AEAD, a KDF, and a KEM. This is implemented for every tuple
+/// `(A, Kdf, Kem)`, so a suite can be named as, e.g.,
+/// `(ChaCha20Poly1305, HkdfSha256, X25519HkdfSha256)`.
+pub trait Suite {
+    type Aead: Aead;
+    type Kdf: KdfTrait;
+    type Kem: KemTrait;
+
+    /// Returns the number of bytes that sealing adds to a plaintext, i.e., the size of the AEAD's
+    /// tag
+    fn overhead() -> usize {
+        AeadTag::<Self::Aead>::size()
+    }
+
+    /// Returns the length of the ciphertext and tag of a plaintext of length `pt_len`. This
+    /// doesn't count the encapsulated key, which is [`EncappedKey::size`] bytes.
+    fn ciphertext_len(pt_len: usize) -> usize {
+        pt_len + Self::overhead()
+    }
+}
+
+impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> Suite for (A, Kdf, Kem) {
+    type Aead = A;
+    type Kdf = Kdf;
+    type Kem = Kem;
+}
+
+// Shorthands for the pieces of a suite
+type SuiteAead<S> = <S as Suite>::Aead;
+type SuiteKdf<S> = <S as Suite>::Kdf;
+type SuiteKem<S> = <S as Suite>::Kem;
+type SuiteKex<S> = <SuiteKem<S> as KemTrait>::Kex;
+
+/// The version of the wire format of [`SealedMessage`], which is its first byte
+pub const SEALED_MESSAGE_VERSION: u8 = 1;
+
+// The size of the header of a serialized message: the version and the suite ID
+const HEADER_SIZE: usize = 1 + core::mem::size_of::<FullSuiteId>();
+
+// Returns the header of a message of the given suite, i.e., `version || suite_id`
+fn header<S: Suite>() -> [u8; HEADER_SIZE] {
+    let mut header = [0u8; HEADER_SIZE];
+    header[0] = SEALED_MESSAGE_VERSION;
+    header[1..].copy_from_slice(&full_suite_id::<SuiteAead<S>, SuiteKdf<S>, SuiteKem<S>>());
+    header
+}
+
+// Returns the AAD that a message of the given suite is actually sealed with, i.e.,
+// `header || aad`. The header is a fixed size, so this is unambiguous.
+fn bound_aad<S: Suite>(aad: &[u8]) -> Vec<u8> {
+    let mut bound = Vec::with_capacity(HEADER_SIZE + aad.len());
+    bound.extend_from_slice(&header::<S>());
+    bound.extend_from_slice(aad);
+    bound
+}
+
+/// A single-shot message sealed under the suite `S`
+pub struct SealedMessage<S: Suite> {
+    encapped_key: EncappedKey<SuiteKem<S>>,
+    ciphertext: Vec<u8>,
+    tag: AeadTag<SuiteAead<S>>,
+}
+
+impl<S: Suite> SealedMessage<S> {
+    /// Does a [`single_shot_seal`] of `plaintext` under the suite `S`. The message header is
+    /// prepended to `aad`, as described in the [module docs](self).
+    ///
+    /// Return Value
+    /// ============
+    /// Fails the same way as [`single_shot_seal`].
+    pub fn seal<R: CryptoRng + RngCore>(
+        mode: &OpModeS<SuiteKex<S>>,
+        pk_recip: &<SuiteKex<S> as KeyExchange>::PublicKey,
+        info: &[u8],
+        plaintext: &[u8],
+        aad: &[u8],
+        csprng: &mut R,
+    ) -> Result<Self, HpkeError> {
+        let mut ciphertext = plaintext.to_vec();
+        let (encapped_key, tag) = single_shot_seal::<SuiteAead<S>, SuiteKdf<S>, SuiteKem<S>, R>(
+            mode,
+            pk_recip,
+            info,
+            &mut ciphertext,
+            &bound_aad::<S>(aad),
+            csprng,
+        )?;
+
+        Ok(SealedMessage {
+            encapped_key,
+            ciphertext,
+            tag,
+        })
+    }
+
+    /// Does a [`single_shot_open`] of this message under the suite `S`, and returns the plaintext
+    ///
+    /// Return Value
+    /// ============
+    /// Fails the same way as [`single_shot_open`].
+    pub fn open(
+        &self,
+        mode: &OpModeR<SuiteKex<S>>,
+        sk_recip: &<SuiteKex<S> as KeyExchange>::PrivateKey,
+        info: &[u8],
+        aad: &[u8],
+    ) -> Result<Vec<u8>, HpkeError> {
+        let mut plaintext = self.ciphertext.clone();
+        single_shot_open::<SuiteAead<S>, SuiteKdf<S>, SuiteKem<S>>(
+            mode,
+            sk_recip,
+            &self.encapped_key,
+            info,
+            &mut plaintext,
+            &bound_aad::<S>(aad),
+            &self.tag,
+        )?;
+        Ok(plaintext)
+    }
+
+    /// Opens this base mode message with `sk_old` and seals its plaintext again to `pk_new`, with
+    /// the same `info` and `aad`. The plaintext is zeroed afterwards. See
+    /// [Key rotation](self#key-rotation).
+    ///
+    /// Return Value
+    /// ============
+    /// Returns the message sealed to `pk_new` on success. Otherwise, fails the same way as
+    /// [`open`](Self::open) and [`seal`](Self::seal).
+    pub fn rewrap<R: CryptoRng + RngCore>(
+        &self,
+        sk_old: &<SuiteKex<S> as KeyExchange>::PrivateKey,
+        pk_new: &<SuiteKex<S> as KeyExchange>::PublicKey,
+        info: &[u8],
+        aad: &[u8],
+        csprng: &mut R,
+    ) -> Result<Self, HpkeError> {
+        let mut plaintext = self.open(&OpModeR::Base, sk_old, info, aad)?;
+        let res = Self::seal(&OpModeS::Base, pk_new, info, &plaintext, aad, csprng);
+        plaintext.zeroize();
+        res
+    }
+
+    /// Like [`open`](Self::open), but returns the plaintext in a [`heapless::Vec`] with capacity
+    /// `N`
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::BufferTooSmall)` if the plaintext is longer than `N`. Otherwise,
+    /// fails the same way as [`single_shot_open`].
+    #[cfg(feature = "heapless")]
+    pub fn open_heapless<const N: usize>(
+        &self,
+        mode: &OpModeR<SuiteKex<S>>,
+        sk_recip: &<SuiteKex<S> as KeyExchange>::PrivateKey,
+        info: &[u8],
+        aad: &[u8],
+    ) -> Result<heapless::Vec<u8, N>, HpkeError> {
+        crate::single_shot::single_shot_open_heapless::<SuiteAead<S>, SuiteKdf<S>, SuiteKem<S>, N>(
+            mode,
+            sk_recip,
+            &self.encapped_key,
+            info,
+            &self.ciphertext,
+            &bound_aad::<S>(aad),
+            &self.tag,
+        )
+    }
+
+    /// Returns the encapsulated key of this message
+    pub fn encapped_key(&self) -> &EncappedKey<SuiteKem<S>> {
+        &self.encapped_key
+    }
+
+    /// Returns the ciphertext of this message, without the tag
+    pub fn ciphertext(&self) -> &[u8] {
+        &self.ciphertext
+    }
+
+    /// Returns the length of the serialization of a message whose plaintext is `pt_len` bytes
+    pub fn encoded_len(pt_len: usize) -> usize {
+        HEADER_SIZE + EncappedKey::<SuiteKem<S>>::size() + S::ciphertext_len(pt_len)
+    }
+
+    /// Serializes this message as `version || suite_id || enc || ciphertext || tag`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(Self::encoded_len(self.ciphertext.len()));
+        out.extend_from_slice(&header::<S>());
+        out.extend_from_slice(&self.encapped_key.to_bytes());
+        out.extend_from_slice(&self.ciphertext);
+        out.extend_from_slice(&self.tag.to_bytes());
+        out
+    }
+
+    /// Like [`to_bytes`](Self::to_bytes), but serializes into a [`heapless::Vec`] with capacity
+    /// `N`
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::BufferTooSmall)` if the serialized message is longer than `N`.
+    #[cfg(feature = "heapless")]
+    pub fn to_heapless<const N: usize>(&self) -> Result<heapless::Vec<u8, N>, HpkeError> {
+        let mut out = heapless::Vec::new();
+        let parts: [&[u8]; 4] = [
+            &header::<S>(),
+            &self.encapped_key.to_bytes(),
+            &self.ciphertext,
+            &self.tag.to_bytes(),
+        ];
+        for part in parts.iter() {
+            out.extend_from_slice(part)
+                .map_err(|_| HpkeError::BufferTooSmall)?;
+        }
+        Ok(out)
+    }
+
+    /// Deserializes a message that was serialized with [`SealedMessage::to_bytes`]
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::SuiteMismatch)` if the suite ID in `encoded` isn't that of `S`, i.e.,
+    /// the message was sealed under a different suite than the one it's being parsed as. Returns
+    /// `Err(HpkeError::InvalidEncoding)` if `encoded` is too short, has a version other than
+    /// [`SEALED_MESSAGE_VERSION`], or if the encapsulated key is malformed.
+    pub fn from_bytes(encoded: &[u8]) -> Result<Self, HpkeError> {
+        let header = header::<S>();
+        let enc_size = EncappedKey::<SuiteKem<S>>::size();
+        let tag_size = AeadTag::<SuiteAead<S>>::size();
+        if encoded.len() < header.len() {
+            return Err(HpkeError::InvalidEncoding);
+        }
+
+        // Check the version and suite before the length, since the length depends on the suite
+        let (encoded_header, rest) = encoded.split_at(header.len());
+        if encoded_header[0] != SEALED_MESSAGE_VERSION {
+            return Err(HpkeError::InvalidEncoding);
+        }
+        if encoded_header[1..] != header[1..] {
+            return Err(HpkeError::SuiteMismatch);
+        }
+        if rest.len() < enc_size + tag_size {
+            return Err(HpkeError::InvalidEncoding);
+        }
+        let (enc, rest) = rest.split_at(enc_size);
+        let (ciphertext, tag) = rest.split_at(rest.len() - tag_size);
+
+        Ok(SealedMessage {
+            encapped_key: EncappedKey::from_bytes(enc)?,
+            ciphertext: ciphertext.to_vec(),
+            tag: AeadTag::from_bytes(tag)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{header, SealedMessage, HEADER_SIZE, SEALED_MESSAGE_VERSION};
+    use crate::{
+        aead::ChaCha20Poly1305,
+        kdf::HkdfSha256,
+        kem::Kem as KemTrait,
+        op_mode::{OpModeR, OpModeS},
+        single_shot::single_shot_open,
+        HpkeError,
+    };
+
+    #[cfg(feature = "aes-gcm")]
+    use crate::aead::AesGcm128;
+    #[cfg(feature = "hkdf-sha384")]
+    use crate::kdf::HkdfSha384;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Tests that a message round-trips through serialization and opens, and that a message from
+    /// another suite is refused with a suite mismatch
+    macro_rules! test_sealed_message {
+        ($test_name:ident, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type Kem = $kem;
+                type Suite = (ChaCha20Poly1305, HkdfSha256, Kem);
+                let mut csprng = StdRng::from_entropy();
+
+                let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+                let info = b"sealed message test";
+                let aad = b"aad";
+                let msg = b"typed all the way down";
+
+                let sealed = SealedMessage::<Suite>::seal(
+                    &OpModeS::Base,
+                    &pk_recip,
+                    info,
+                    msg,
+                    aad,
+                    &mut csprng,
+                )
+                .unwrap();
+                let encoded = sealed.to_bytes();
+
+                // The length helpers agree with what was actually sealed
+                assert_eq!(
+                    encoded.len(),
+                    SealedMessage::<Suite>::encoded_len(msg.len())
+                );
+                assert_eq!(<Suite as super::Suite>::overhead(), 16);
+                assert_eq!(
+                    <Suite as super::Suite>::ciphertext_len(msg.len()),
+                    msg.len() + 16
+                );
+
+                let parsed = SealedMessage::<Suite>::from_bytes(&encoded).unwrap();
+                let plaintext = parsed.open(&OpModeR::Base, &sk_recip, info, aad).unwrap();
+                assert_eq!(&plaintext[..], &msg[..]);
+
+                // A different AEAD or KDF is a different suite, and is refused before decryption
+                #[cfg(feature = "aes-gcm")]
+                {
+                    let res = SealedMessage::<(AesGcm128, HkdfSha256, Kem)>::from_bytes(&encoded);
+                    assert!(matches!(res, Err(HpkeError::SuiteMismatch)));
+                }
+                #[cfg(feature = "hkdf-sha384")]
+                {
+                    let res =
+                        SealedMessage::<(ChaCha20Poly1305, HkdfSha384, Kem)>::from_bytes(&encoded);
+                    assert!(matches!(res, Err(HpkeError::SuiteMismatch)));
+                }
+
+                // The heapless variants give the same bytes, as long as there's room for them
+                #[cfg(feature = "heapless")]
+                {
+                    let heapless_encoded = sealed.to_heapless::<128>().unwrap();
+                    assert_eq!(&heapless_encoded[..], &encoded[..]);
+                    let res = sealed.to_heapless::<16>();
+                    assert!(matches!(res, Err(HpkeError::BufferTooSmall)));
+
+                    let plaintext = parsed
+                        .open_heapless::<32>(&OpModeR::Base, &sk_recip, info, aad)
+                        .unwrap();
+                    assert_eq!(&plaintext[..], &msg[..]);
+                    let res = parsed.open_heapless::<4>(&OpModeR::Base, &sk_recip, info, aad);
+                    assert!(matches!(res, Err(HpkeError::BufferTooSmall)));
+                }
+
+                // A message too short to hold a tag doesn't parse
+                let min_len = encoded.len() - msg.len();
+                let res = SealedMessage::<Suite>::from_bytes(&encoded[..min_len - 1]);
+                assert!(matches!(res, Err(HpkeError::InvalidEncoding)));
+            }
+        };
+    }
+
+    /// Tests that a message whose header was tampered with doesn't parse or doesn't open, and
+    /// that the header is bound into the AAD
+    macro_rules! test_tampered_header {
+        ($test_name:ident, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type Kem = $kem;
+                type Suite = (ChaCha20Poly1305, HkdfSha256, Kem);
+                let mut csprng = StdRng::from_entropy();
+
+                let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+                let info = b"tampered header test";
+                let aad = b"aad";
+                let sealed = SealedMessage::<Suite>::seal(
+                    &OpModeS::Base,
+                    &pk_recip,
+                    info,
+                    b"don't touch the header",
+                    aad,
+                    &mut csprng,
+                )
+                .unwrap();
+                let encoded = sealed.to_bytes();
+                assert_eq!(&encoded[..HEADER_SIZE], &header::<Suite>()[..]);
+
+                // Another version doesn't parse
+                let mut tampered = encoded.clone();
+                tampered[0] = SEALED_MESSAGE_VERSION + 1;
+                let res = SealedMessage::<Suite>::from_bytes(&tampered);
+                assert!(matches!(res, Err(HpkeError::InvalidEncoding)));
+
+                // Neither does a message without its header
+                let res = SealedMessage::<Suite>::from_bytes(&encoded[HEADER_SIZE..]);
+                assert!(res.is_err());
+
+                // Rewriting the header to that of another suite of the same sizes makes it parse
+                // as that suite, but it doesn't open
+                #[cfg(feature = "hkdf-sha384")]
+                {
+                    type OtherSuite = (ChaCha20Poly1305, HkdfSha384, Kem);
+                    let mut tampered = encoded.clone();
+                    tampered[..HEADER_SIZE].copy_from_slice(&header::<OtherSuite>());
+                    let parsed = SealedMessage::<OtherSuite>::from_bytes(&tampered).unwrap();
+                    let res = parsed.open(&OpModeR::Base, &sk_recip, info, aad);
+                    assert!(matches!(res, Err(HpkeError::InvalidTag)));
+                }
+
+                // The message doesn't open with the caller's AAD alone
+                let mut plaintext = sealed.ciphertext().to_vec();
+                let res = single_shot_open::<ChaCha20Poly1305, HkdfSha256, Kem>(
+                    &OpModeR::Base,
+                    &sk_recip,
+                    sealed.encapped_key(),
+                    info,
+                    &mut plaintext,
+                    aad,
+                    &sealed.tag,
+                );
+                assert!(matches!(res, Err(HpkeError::InvalidTag)));
+            }
+        };
+    }
+
+    /// Tests that a rewrapped message opens under the new key and not the old one, and that
+    /// rewrapping needs the old key
+    macro_rules! test_rewrap {
+        ($test_name:ident, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type Kem = $kem;
+                type Suite = (ChaCha20Poly1305, HkdfSha256, Kem);
+                let mut csprng = StdRng::from_entropy();
+
+                let (sk_old, pk_old) = Kem::gen_keypair(&mut csprng);
+                let (sk_new, pk_new) = Kem::gen_keypair(&mut csprng);
+                let info = b"rewrap test";
+                let aad = b"object 42";
+                let dek = [7u8; 32];
+
+                let wrapped = SealedMessage::<Suite>::seal(
+                    &OpModeS::Base,
+                    &pk_old,
+                    info,
+                    &dek,
+                    aad,
+                    &mut csprng,
+                )
+                .unwrap();
+                let rewrapped = wrapped
+                    .rewrap(&sk_old, &pk_new, info, aad, &mut csprng)
+                    .unwrap();
+
+                let opened = rewrapped.open(&OpModeR::Base, &sk_new, info, aad).unwrap();
+                assert_eq!(&opened[..], &dek[..]);
+                assert!(rewrapped.open(&OpModeR::Base, &sk_old, info, aad).is_err());
+
+                // Only the holder of the old key can rewrap, and only with the right AAD
+                let res = wrapped.rewrap(&sk_new, &pk_new, info, aad, &mut csprng);
+                assert!(matches!(res, Err(HpkeError::InvalidTag)));
+                let res = wrapped.rewrap(&sk_old, &pk_new, info, b"object 43", &mut csprng);
+                assert!(matches!(res, Err(HpkeError::InvalidTag)));
+            }
+        };
+    }
+
+    #[cfg(feature = "x25519-dalek")]
+    test_sealed_message!(test_sealed_message_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_sealed_message!(test_sealed_message_p256, crate::kem::DhP256HkdfSha256);
+
+    #[cfg(feature = "x25519-dalek")]
+    test_tampered_header!(test_tampered_header_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_tampered_header!(test_tampered_header_p256, crate::kem::DhP256HkdfSha256);
+
+    #[cfg(feature = "x25519-dalek")]
+    test_rewrap!(test_rewrap_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_rewrap!(test_rewrap_p256, crate::kem::DhP256HkdfSha256);
+
+    /// Tests that a message from another KEM is a suite mismatch, even though its encapsulated key
+    /// is a different size than the one being parsed
+    #[cfg(all(feature = "x25519-dalek", feature = "p256"))]
+    #[test]
+    fn test_kem_mismatch() {
+        use crate::kem::{DhP256HkdfSha256, X25519HkdfSha256};
+
+        let mut csprng = StdRng::from_entropy();
+        let (_, pk_recip) = X25519HkdfSha256::gen_keypair(&mut csprng);
+        let encoded = SealedMessage::<(ChaCha20Poly1305, HkdfSha256, X25519HkdfSha256)>::seal(
+            &OpModeS::Base,
+            &pk_recip,
+            b"info",
+            b"",
+            b"",
+            &mut csprng,
+        )
+        .unwrap()
+        .to_bytes();
+
+        let res =
+            SealedMessage::<(ChaCha20Poly1305, HkdfSha256, DhP256HkdfSha256)>::from_bytes(&encoded);
+        assert!(matches!(res, Err(HpkeError::SuiteMismatch)));
+    }
+}
@@ -0,0 +1,248 @@
+//! This module defines [`SeqGuard`], which remembers the sequence numbers a sender has used and
+//! rejects any that come around again. It's for applications that pick sequence numbers
+//! themselves instead of letting the context count, e.g., via `AeadCtxS::compute_nonce_checked`
+//! from the `danger` feature, or [`DatagramReceiver`](crate::datagram::DatagramReceiver).
+//! Sealing two messages under the same sequence number reuses a nonce, which breaks the
+//! confidentiality and integrity of both.
+//!
+//! A guard has one of two policies:
+//!
+//! * [`SeqGuard::monotonic`] only accepts sequence numbers greater than every one before it. It
+//!   fits senders that count up, but may skip numbers.
+//! * [`SeqGuard::windowed`] also accepts sequence numbers up to 63 below the greatest one so far,
+//!   as long as they haven't been used. It fits senders that hand out numbers in order but seal
+//!   out of order, e.g., from a pool of workers. Anything further back is rejected, since the
+//!   guard no longer knows whether it was used.
+//!
+//! Each guard belongs to exactly one sender context. A fresh context needs a fresh guard.
+
+use crate::HpkeError;
+
+// The number of sequence numbers below the greatest one that a windowed guard keeps track of,
+// i.e., the number of bits in the bitmap
+const WINDOW_SIZE: u64 = 64;
+
+/// Rejects sequence numbers that were already used under one sender context
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SeqGuard {
+    windowed: bool,
+    // The greatest sequence number accepted so far
+    highest: Option<u64>,
+    // Bit i is set iff `highest - i` was accepted. Only used by windowed guards.
+    bitmap: u64,
+}
+
+impl SeqGuard {
+    /// Makes a guard that only accepts strictly increasing sequence numbers
+    pub fn monotonic() -> Self {
+        SeqGuard {
+            windowed: false,
+            highest: None,
+            bitmap: 0,
+        }
+    }
+
+    /// Makes a guard that accepts any unused sequence number within 63 of the greatest one so far
+    pub fn windowed() -> Self {
+        SeqGuard {
+            windowed: true,
+            highest: None,
+            bitmap: 0,
+        }
+    }
+
+    /// Returns the greatest sequence number accepted so far, if any
+    pub fn highest(&self) -> Option<u64> {
+        self.highest
+    }
+
+    /// Returns whether `seq` would be rejected by [`check_and_insert`](Self::check_and_insert),
+    /// i.e., whether it was used, or is too far back for the guard to tell
+    pub fn is_rejected(&self, seq: u64) -> bool {
+        let highest = match self.highest {
+            Some(h) => h,
+            None => return false,
+        };
+        if seq > highest {
+            return false;
+        }
+        if !self.windowed {
+            return true;
+        }
+
+        let offset = highest - seq;
+        offset >= WINDOW_SIZE || self.bitmap & (1 << offset) != 0
+    }
+
+    /// Records `seq` as used. A rejected sequence number isn't recorded.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::ReusedSeq)` if `seq` was already used, or is too far back for the
+    /// guard to tell.
+    pub fn check_and_insert(&mut self, seq: u64) -> Result<(), HpkeError> {
+        if self.is_rejected(seq) {
+            return Err(HpkeError::ReusedSeq);
+        }
+
+        match self.highest {
+            Some(highest) if seq <= highest => {
+                // Only reachable for windowed guards, within the window
+                self.bitmap |= 1 << (highest - seq);
+            }
+            Some(highest) => {
+                // Slide the window up to the new greatest number
+                let shift = seq - highest;
+                self.bitmap = if shift >= WINDOW_SIZE {
+                    0
+                } else {
+                    self.bitmap << shift
+                };
+                self.bitmap |= 1;
+                self.highest = Some(seq);
+            }
+            None => {
+                self.bitmap = 1;
+                self.highest = Some(seq);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SeqGuard;
+    use crate::HpkeError;
+    #[cfg(feature = "danger")]
+    use crate::{aead::ChaCha20Poly1305, kdf::HkdfSha256, test_util::gen_ctx_simple_pair};
+
+    use alloc::collections::BTreeSet;
+
+    use proptest::prelude::*;
+
+    /// Tests that a monotonic guard rejects anything that isn't greater than what came before
+    #[test]
+    fn test_monotonic() {
+        let mut guard = SeqGuard::monotonic();
+        assert_eq!(guard.highest(), None);
+
+        guard.check_and_insert(0).unwrap();
+        guard.check_and_insert(5).unwrap();
+        assert_eq!(guard.highest(), Some(5));
+
+        // Repeats and skipped-over numbers are both rejected
+        assert!(matches!(
+            guard.check_and_insert(5),
+            Err(HpkeError::ReusedSeq)
+        ));
+        assert!(matches!(
+            guard.check_and_insert(3),
+            Err(HpkeError::ReusedSeq)
+        ));
+        guard.check_and_insert(u64::MAX).unwrap();
+        assert!(guard.is_rejected(u64::MAX));
+    }
+
+    /// Tests that a windowed guard accepts unused numbers in the window and nothing before it
+    #[test]
+    fn test_windowed() {
+        let mut guard = SeqGuard::windowed();
+
+        guard.check_and_insert(10).unwrap();
+        guard.check_and_insert(7).unwrap();
+        assert!(matches!(
+            guard.check_and_insert(7),
+            Err(HpkeError::ReusedSeq)
+        ));
+        assert!(matches!(
+            guard.check_and_insert(10),
+            Err(HpkeError::ReusedSeq)
+        ));
+
+        // Moving the window up keeps what's still in it
+        guard.check_and_insert(70).unwrap();
+        assert!(guard.is_rejected(10));
+        assert!(!guard.is_rejected(8));
+        // 6 is unused, but it's outside the window now
+        assert!(guard.is_rejected(6));
+
+        // A big jump forgets everything below the new window
+        guard.check_and_insert(1000).unwrap();
+        assert!(guard.is_rejected(70));
+        assert!(!guard.is_rejected(999));
+        assert!(!guard.is_rejected(1000 - 63));
+        assert!(guard.is_rejected(1000 - 64));
+    }
+
+    proptest! {
+        /// Tests that, over an arbitrary run of sequence numbers, neither kind of guard ever
+        /// accepts a number twice, and that a windowed guard accepts every unused number in its
+        /// window
+        #[test]
+        fn prop_no_reuse(
+            windowed in any::<bool>(),
+            seqs in proptest::collection::vec(0u64..200, 1..128),
+        ) {
+            let mut guard = if windowed { SeqGuard::windowed() } else { SeqGuard::monotonic() };
+            let mut used = BTreeSet::new();
+
+            for seq in seqs {
+                let was_used = used.contains(&seq);
+                let highest = used.iter().next_back().copied();
+                match guard.check_and_insert(seq) {
+                    Ok(()) => {
+                        prop_assert!(!was_used);
+                        used.insert(seq);
+                    }
+                    Err(HpkeError::ReusedSeq) => {
+                        let too_old = match highest {
+                            Some(h) if windowed => seq + 64 <= h,
+                            Some(h) => seq <= h,
+                            None => false,
+                        };
+                        prop_assert!(was_used || too_old);
+                    }
+                    Err(e) => panic!("unexpected error {:?}", e),
+                }
+            }
+        }
+    }
+
+    /// Tests that compute_nonce_checked gives the same nonces as compute_nonce, and refuses to give
+    /// one twice
+    #[cfg(feature = "danger")]
+    macro_rules! test_compute_nonce_checked {
+        ($test_name:ident, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type A = ChaCha20Poly1305;
+                type Kdf = HkdfSha256;
+                type Kem = $kem;
+
+                let (sender_ctx, _) = gen_ctx_simple_pair::<A, Kdf, Kem>();
+                let mut guard = SeqGuard::windowed();
+
+                let nonce = sender_ctx.compute_nonce_checked(3, &mut guard).unwrap();
+                assert_eq!(nonce, sender_ctx.compute_nonce(3));
+                sender_ctx.compute_nonce_checked(1, &mut guard).unwrap();
+                assert!(matches!(
+                    sender_ctx.compute_nonce_checked(3, &mut guard),
+                    Err(HpkeError::ReusedSeq)
+                ));
+            }
+        };
+    }
+
+    #[cfg(all(feature = "danger", feature = "x25519-dalek"))]
+    test_compute_nonce_checked!(
+        test_compute_nonce_checked_x25519,
+        crate::kem::X25519HkdfSha256
+    );
+    #[cfg(all(feature = "danger", feature = "p256"))]
+    test_compute_nonce_checked!(
+        test_compute_nonce_checked_p256,
+        crate::kem::DhP256HkdfSha256
+    );
+}
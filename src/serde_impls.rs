@@ -3,8 +3,7 @@
 
 use crate::{
     aead::{Aead, AeadTag},
-    kex::{self, KeyExchange},
-    Deserializable, EncappedKey, Serializable,
+    kex, Deserializable, EncappedKey, Kem, Serializable,
 };
 
 use digest::generic_array::GenericArray;
@@ -46,8 +45,8 @@ macro_rules! impl_serde_withparam {
     };
 }
 
-// Implement Serialize/Deserialize for EncappedKey<P: KeyExchange> and AeadTag<P: Aead>
-impl_serde_withparam!(EncappedKey, KeyExchange);
+// Implement Serialize/Deserialize for EncappedKey<P: Kem> and AeadTag<P: Aead>
+impl_serde_withparam!(EncappedKey, Kem);
 impl_serde_withparam!(AeadTag, Aead);
 
 // Implements serde::{Serialize, Deserialize} over a plain type t. This is almost identical to above.
@@ -92,11 +91,19 @@ impl_serde_noparam!(kex::x25519::PrivateKey);
 #[cfg(feature = "x25519")]
 impl_serde_noparam!(kex::x25519::PublicKey);
 
+#[cfg(feature = "k256")]
+impl_serde_noparam!(kex::secp256k1::PrivateKey);
+#[cfg(feature = "k256")]
+impl_serde_noparam!(kex::secp256k1::PublicKey);
 #[cfg(feature = "p256")]
 impl_serde_noparam!(kex::ecdh_nistp::PrivateKey);
 #[cfg(feature = "p256")]
 impl_serde_noparam!(kex::ecdh_nistp::PublicKey);
 
+// Context probes and confirmation tags get sent over the wire too
+impl_serde_noparam!(crate::aead::ContextProbe);
+impl_serde_noparam!(crate::aead::ConfirmationTag);
+
 #[cfg(test)]
 mod test {
     use crate::{
@@ -147,8 +154,7 @@ mod test {
                 // this gives us a pubkey, secret key, and encapped key to test serde on
                 let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
                 let (psk, psk_id) = (gen_rand_buf(), gen_rand_buf());
-                let (sender_mode, _) =
-                    new_op_mode_pair::<Kex, Kdf>(OpModeKind::Base, &psk, &psk_id);
+                let (sender_mode, _) = new_op_mode_pair::<Kex>(OpModeKind::Base, &psk, &psk_id);
                 let (encapped_key, mut aead_ctx) =
                     setup_sender::<A, Kdf, Kem, _>(&sender_mode, &pk_recip, &info[..], &mut csprng)
                         .unwrap();
@@ -168,4 +174,7 @@ mod test {
 
     #[cfg(feature = "p256")]
     test_serde_roundtrip!(test_serde_roundtrip_p256, crate::kem::DhP256HkdfSha256);
+
+    #[cfg(feature = "k256")]
+    test_serde_roundtrip!(test_serde_roundtrip_k256, crate::kem::DhK256HkdfSha256);
 }
@@ -8,17 +8,23 @@ use crate::{
     HpkeError,
 };
 
+#[cfg(feature = "events")]
+use crate::events::{self, emit_result, Event, Operation, Role};
+
 use digest::Digest;
 use generic_array::GenericArray;
 use rand::{CryptoRng, RngCore};
+use zeroize::Zeroize;
 
-/// Secret generated in `derive_enc_ctx` and stored in `AeadCtx`
+/// Secret generated in `derive_enc_ctx` and stored in `AeadCtx`. Its length is the KDF's `Nh`,
+/// which is unrelated to the length of the KEM's shared secret.
 pub(crate) type ExporterSecret<K> =
     GenericArray<u8, <<K as KdfTrait>::HashImpl as Digest>::OutputSize>;
 
-/// get receiver context with shared secretly directly provided
-/// WARNING: temporary measure
-pub fn derive_receiver_ctx<A, Kdf, Kem>(
+// Runs the key schedule on a shared secret that came from outside this crate. Exposed as
+// danger::derive_receiver_ctx, and used by threshold decap.
+#[cfg(any(feature = "danger", feature = "threshold"))]
+pub(crate) fn derive_receiver_ctx<A, Kdf, Kem>(
     mode: &OpModeR<Kem::Kex>,
     shared_secret: SharedSecret<Kem>,
     info: &[u8],
@@ -60,13 +66,10 @@ where
             labeled_extract::<Kdf>(&[], &suite_id, b"psk_id_hash", mode.get_psk_id());
         let (info_hash, _) = labeled_extract::<Kdf>(&[], &suite_id, b"info_hash", info);
 
-        // Yes it's overkill to bound the first input by MAX_DIGEST_SIZE, since it's only 1 byte.
-        // But whatever, this is pretty clean.
         concat_with_known_maxlen!(
-            MAX_DIGEST_SIZE,
-            &[mode.mode_id()],
-            &psk_id_hash.as_slice(),
-            &info_hash.as_slice()
+            &[mode.mode_id()] => 1,
+            &psk_id_hash => MAX_DIGEST_SIZE,
+            &info_hash => MAX_DIGEST_SIZE
         )
     };
     let sched_context = &sched_context_buf[..sched_context_size];
@@ -77,12 +80,16 @@ where
     //   key = LabeledExpand(secret, "key", key_schedule_context, Nk)
     //   nonce = LabeledExpand(secret, "nonce", key_schedule_context, Nn)
     //   exporter_secret = LabeledExpand(secret, "exp", key_schedule_context, Nh)
-    let (extracted_psk, _) =
+    let (mut extracted_psk, _) =
         labeled_extract::<Kdf>(&[], &suite_id, b"psk_hash", mode.get_psk_bytes());
     // Instead of `secret` we derive an HKDF context which we run .expand() on to derive the
     // key-nonce pair.
-    let (_, secret_ctx) =
-        labeled_extract::<Kdf>(&extracted_psk, &suite_id, b"secret", &shared_secret);
+    let (mut secret, secret_ctx) = labeled_extract::<Kdf>(
+        &extracted_psk,
+        &suite_id,
+        b"secret",
+        shared_secret.as_bytes(),
+    );
 
     // Empty fixed-size buffers
     let mut key = crate::aead::AeadKey::<A>::default();
@@ -107,7 +114,45 @@ where
         )
         .expect("exporter secret len is way too big");
 
-    AeadCtx::new(&key, nonce, exporter_secret)
+    // The context zeroes its own copies of the key and exporter secret. Zero ours, and the other
+    // key schedule secrets, here. The HKDF state in secret_ctx can't be zeroed, since hkdf doesn't
+    // support it, so it's just dropped.
+    #[allow(unused_mut)]
+    let mut ctx = AeadCtx::new(key.clone(), nonce, exporter_secret.clone());
+    key.as_mut_slice().zeroize();
+    exporter_secret.as_mut_slice().zeroize();
+    extracted_psk.as_mut_slice().zeroize();
+    secret.as_mut_slice().zeroize();
+    #[cfg(feature = "transcript")]
+    {
+        ctx.setup_transcript = crate::transcript::SetupTranscript {
+            mode_id: mode.mode_id(),
+            info_len: info.len(),
+            psk_id_len: mode.get_psk_id().len(),
+            key_schedule_context: sched_context.to_vec(),
+        };
+    }
+    ctx
+}
+
+// Reports the outcome of a setup to the event sink
+#[cfg(feature = "events")]
+fn report_setup<A, Kdf, Kem, O, T>(res: &Result<T, HpkeError>, role: Role, mode: &O, info: &[u8])
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    O: OpMode<Kem::Kex>,
+{
+    emit_result::<A, Kdf, Kem, _, _>(res, role, Operation::Setup, |suite| {
+        Some(Event::Setup {
+            role,
+            suite,
+            mode_id: mode.mode_id(),
+            psk_id_hash: events::hash(mode.get_psk_id()),
+            info_hash: events::hash(info),
+        })
+    });
 }
 
 // def SetupAuthPSKI(pkR, info, psk, psk_id, skI):
@@ -119,13 +164,22 @@ where
 /// ============
 /// On success, returns an encapsulated public key (intended to be sent to the recipient), and an
 /// encryption context. If an error happened during key exchange, returns
-/// `Err(HpkeError::InvalidKeyExchange)`. This is the only possible error.
+/// `Err(HpkeError::InvalidKeyExchange)`. If `mode` is an auth mode and the KEM doesn't support
+/// auth, returns `Err(HpkeError::UnsupportedMode)`.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(kem_id = Kem::KEM_ID, kdf_id = Kdf::KDF_ID, aead_id = A::AEAD_ID, mode_id = mode.mode_id())
+    )
+)]
 pub fn setup_sender<A, Kdf, Kem, R>(
     mode: &OpModeS<Kem::Kex>,
     pk_recip: &<Kem::Kex as KeyExchange>::PublicKey,
     info: &[u8],
     csprng: &mut R,
-) -> Result<(EncappedKey<Kem::Kex>, AeadCtxS<A, Kdf, Kem>), HpkeError>
+) -> Result<(EncappedKey<Kem>, AeadCtxS<A, Kdf, Kem>), HpkeError>
 where
     A: Aead,
     Kdf: KdfTrait,
@@ -135,7 +189,11 @@ where
     // If the identity key is set, use it
     let sender_id_keypair = mode.get_sender_id_keypair();
     // Do the encapsulation
-    let (shared_secret, encapped_key) = kem::encap::<Kem, _>(pk_recip, sender_id_keypair, csprng)?;
+    let encap_res = kem::encap::<Kem, _>(pk_recip, sender_id_keypair, csprng);
+    #[cfg(feature = "events")]
+    report_setup::<A, Kdf, Kem, _, _>(&encap_res, Role::Sender, mode, info);
+    let (shared_secret, encapped_key) = encap_res?;
+
     // Use everything to derive an encryption context
     let enc_ctx = derive_enc_ctx::<_, _, Kem, _>(mode, shared_secret, info);
 
@@ -151,11 +209,20 @@ where
 /// Return Value
 /// ============
 /// On success, returns a decryption context. If an error happened during key exchange, returns
-/// `Err(HpkeError::InvalidKeyExchange)`. This is the only possible error.
+/// `Err(HpkeError::InvalidKeyExchange)`. If `mode` is an auth mode and the KEM doesn't support
+/// auth, returns `Err(HpkeError::UnsupportedMode)`.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(kem_id = Kem::KEM_ID, kdf_id = Kdf::KDF_ID, aead_id = A::AEAD_ID, mode_id = mode.mode_id())
+    )
+)]
 pub fn setup_receiver<A, Kdf, Kem>(
     mode: &OpModeR<Kem::Kex>,
     sk_recip: &<Kem::Kex as KeyExchange>::PrivateKey,
-    encapped_key: &EncappedKey<Kem::Kex>,
+    encapped_key: &EncappedKey<Kem>,
     info: &[u8],
 ) -> Result<AeadCtxR<A, Kdf, Kem>, HpkeError>
 where
@@ -166,18 +233,67 @@ where
     // If the identity key is set, use it
     let pk_sender_id: Option<&<Kem::Kex as KeyExchange>::PublicKey> = mode.get_pk_sender_id();
     // Do the decapsulation
-    let shared_secret = kem::decap::<Kem>(sk_recip, pk_sender_id, encapped_key)?;
+    let decap_res = kem::decap::<Kem>(sk_recip, pk_sender_id, encapped_key);
+    #[cfg(feature = "events")]
+    report_setup::<A, Kdf, Kem, _, _>(&decap_res, Role::Receiver, mode, info);
+    let shared_secret = decap_res?;
 
     // Use everything to derive an encryption context
     let enc_ctx = derive_enc_ctx::<_, _, Kem, _>(mode, shared_secret, info);
     Ok(enc_ctx.into())
 }
 
+/// Like [`setup_receiver`], but takes the encapsulated key as it came over the wire. This saves
+/// making an [`EncappedKey`] and serializing it again, which servers that set up a context per
+/// request would otherwise do.
+///
+/// Return Value
+/// ============
+/// On success, returns a decryption context. If `encapped_key` is malformed, returns
+/// `Err(HpkeError::InvalidEncoding)`. If an error happened during key exchange, returns
+/// `Err(HpkeError::InvalidKeyExchange)`. If `mode` is an auth mode and the KEM doesn't support
+/// auth, returns `Err(HpkeError::UnsupportedMode)`.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(kem_id = Kem::KEM_ID, kdf_id = Kdf::KDF_ID, aead_id = A::AEAD_ID, mode_id = mode.mode_id())
+    )
+)]
+pub fn setup_receiver_from_bytes<A, Kdf, Kem>(
+    mode: &OpModeR<Kem::Kex>,
+    sk_recip: &<Kem::Kex as KeyExchange>::PrivateKey,
+    encapped_key: &[u8],
+    info: &[u8],
+) -> Result<AeadCtxR<A, Kdf, Kem>, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    let pk_sender_id: Option<&<Kem::Kex as KeyExchange>::PublicKey> = mode.get_pk_sender_id();
+    let decap_res = kem::decap_from_bytes::<Kem>(sk_recip, pk_sender_id, encapped_key);
+    #[cfg(feature = "events")]
+    report_setup::<A, Kdf, Kem, _, _>(&decap_res, Role::Receiver, mode, info);
+    let shared_secret = decap_res?;
+
+    let enc_ctx = derive_enc_ctx::<_, _, Kem, _>(mode, shared_secret, info);
+    Ok(enc_ctx.into())
+}
+
 #[cfg(test)]
 mod test {
-    use super::{setup_receiver, setup_sender};
+    use super::{setup_receiver, setup_receiver_from_bytes, setup_sender};
     use crate::test_util::{aead_ctx_eq, gen_rand_buf, new_op_mode_pair, OpModeKind};
-    use crate::{aead::ChaCha20Poly1305, kdf::HkdfSha256, kem::Kem as KemTrait};
+    use crate::{
+        aead::{ChaCha20Poly1305, ConfirmationTag, ContextProbe},
+        kdf::HkdfSha256,
+        kem::Kem as KemTrait,
+        Deserializable, HpkeError, Serializable,
+    };
+    #[cfg(all(feature = "tracing", feature = "std", feature = "x25519-dalek"))]
+    use crate::{OpModeR, OpModeS};
 
     use rand::{rngs::StdRng, SeedableRng};
 
@@ -209,7 +325,7 @@ mod test {
                     // Generate a mutually agreeing op mode pair
                     let (psk, psk_id) = (gen_rand_buf(), gen_rand_buf());
                     let (sender_mode, receiver_mode) =
-                        new_op_mode_pair::<Kex, Kdf>(*op_mode_kind, &psk, &psk_id);
+                        new_op_mode_pair::<Kex>(*op_mode_kind, &psk, &psk_id);
 
                     // Construct the sender's encryption context, and get an encapped key
                     let (encapped_key, mut aead_ctx1) = setup_sender::<A, Kdf, Kem, _>(
@@ -256,7 +372,7 @@ mod test {
                 // Generate a mutually agreeing op mode pair
                 let (psk, psk_id) = (gen_rand_buf(), gen_rand_buf());
                 let (sender_mode, receiver_mode) =
-                    new_op_mode_pair::<Kex, Kdf>(OpModeKind::Base, &psk, &psk_id);
+                    new_op_mode_pair::<Kex>(OpModeKind::Base, &psk, &psk_id);
 
                 // Construct the sender's encryption context normally
                 let (encapped_key, sender_ctx) =
@@ -312,6 +428,243 @@ mod test {
         };
     }
 
+    /// Tests that `setup_receiver_from_bytes` derives the same context as `setup_receiver` does
+    /// from the parsed encapped key, and that it rejects malformed encapped key bytes
+    macro_rules! test_setup_receiver_from_bytes {
+        ($test_name:ident, $aead_ty:ty, $kdf_ty:ty, $kem_ty:ty) => {
+            #[test]
+            fn $test_name() {
+                type A = $aead_ty;
+                type Kdf = $kdf_ty;
+                type Kem = $kem_ty;
+                type Kex = <Kem as KemTrait>::Kex;
+
+                let mut csprng = StdRng::from_entropy();
+
+                let info = b"test_setup_receiver_from_bytes";
+
+                // Generate the receiver's long-term keypair
+                let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+
+                for op_mode_kind in &[
+                    OpModeKind::Base,
+                    OpModeKind::Auth,
+                    OpModeKind::Psk,
+                    OpModeKind::AuthPsk,
+                ] {
+                    let (psk, psk_id) = (gen_rand_buf(), gen_rand_buf());
+                    let (sender_mode, receiver_mode) =
+                        new_op_mode_pair::<Kex>(*op_mode_kind, &psk, &psk_id);
+
+                    let (encapped_key, mut sender_ctx) = setup_sender::<A, Kdf, Kem, _>(
+                        &sender_mode,
+                        &pk_recip,
+                        &info[..],
+                        &mut csprng,
+                    )
+                    .unwrap();
+
+                    // Setting up from the encapped key bytes gives the sender's context
+                    let encapped_key_bytes = encapped_key.to_bytes();
+                    let mut receiver_ctx = setup_receiver_from_bytes::<A, Kdf, Kem>(
+                        &receiver_mode,
+                        &sk_recip,
+                        &encapped_key_bytes,
+                        &info[..],
+                    )
+                    .unwrap();
+                    assert!(aead_ctx_eq(&mut sender_ctx, &mut receiver_ctx));
+
+                    // Truncated and overlong bytes are rejected
+                    let mut overlong = encapped_key_bytes.to_vec();
+                    overlong.push(0);
+                    for bad_bytes in [&encapped_key_bytes[1..], &overlong[..]].iter() {
+                        assert!(matches!(
+                            setup_receiver_from_bytes::<A, Kdf, Kem>(
+                                &receiver_mode,
+                                &sk_recip,
+                                bad_bytes,
+                                &info[..],
+                            ),
+                            Err(HpkeError::InvalidEncoding)
+                        ));
+                    }
+                }
+            }
+        };
+    }
+
+    /// Tests that both ends' context probes match when they derive the same context, survive a
+    /// round trip through bytes, and differ when the ends disagree on the info string or keys
+    macro_rules! test_context_probe {
+        ($test_name:ident, $aead_ty:ty, $kdf_ty:ty, $kem_ty:ty) => {
+            #[test]
+            fn $test_name() {
+                type A = $aead_ty;
+                type Kdf = $kdf_ty;
+                type Kem = $kem_ty;
+                type Kex = <Kem as KemTrait>::Kex;
+
+                let mut csprng = StdRng::from_entropy();
+
+                let info = b"test_context_probe";
+
+                // Generate the receiver's long-term keypair
+                let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+
+                let (psk, psk_id) = (gen_rand_buf(), gen_rand_buf());
+                let (sender_mode, receiver_mode) =
+                    new_op_mode_pair::<Kex>(OpModeKind::Base, &psk, &psk_id);
+
+                let (encapped_key, sender_ctx) =
+                    setup_sender::<A, Kdf, Kem, _>(&sender_mode, &pk_recip, &info[..], &mut csprng)
+                        .unwrap();
+
+                // The probes of two matching contexts are equal, including over the wire
+                let receiver_ctx = setup_receiver::<A, Kdf, Kem>(
+                    &receiver_mode,
+                    &sk_recip,
+                    &encapped_key,
+                    &info[..],
+                )
+                .unwrap();
+                assert_eq!(sender_ctx.probe(), receiver_ctx.probe());
+                let probe_bytes = sender_ctx.probe().to_bytes();
+                assert_eq!(
+                    ContextProbe::from_bytes(&probe_bytes).unwrap(),
+                    receiver_ctx.probe()
+                );
+
+                // A probe of the wrong length is rejected
+                assert!(matches!(
+                    ContextProbe::from_bytes(&probe_bytes[1..]),
+                    Err(HpkeError::InvalidEncoding)
+                ));
+
+                // A receiver with the wrong info string has a different probe
+                let receiver_ctx = setup_receiver::<A, Kdf, Kem>(
+                    &receiver_mode,
+                    &sk_recip,
+                    &encapped_key,
+                    b"something else",
+                )
+                .unwrap();
+                assert_ne!(sender_ctx.probe(), receiver_ctx.probe());
+
+                // So does a receiver with the wrong secret key
+                let (bad_sk, _) = Kem::gen_keypair(&mut csprng);
+                let receiver_ctx = setup_receiver::<A, Kdf, Kem>(
+                    &receiver_mode,
+                    &bad_sk,
+                    &encapped_key,
+                    &info[..],
+                )
+                .unwrap();
+                assert_ne!(sender_ctx.probe(), receiver_ctx.probe());
+            }
+        };
+    }
+
+    /// Tests that a receiver accepts the confirmation tag of a sender with the same context, and
+    /// refuses a tag from a sender whose context differs or a tag that was tampered with
+    macro_rules! test_confirmation_tag {
+        ($test_name:ident, $aead_ty:ty, $kdf_ty:ty, $kem_ty:ty) => {
+            #[test]
+            fn $test_name() {
+                type A = $aead_ty;
+                type Kdf = $kdf_ty;
+                type Kem = $kem_ty;
+                type Kex = <Kem as KemTrait>::Kex;
+
+                let mut csprng = StdRng::from_entropy();
+
+                let info = b"test_confirmation_tag";
+
+                // Generate the receiver's long-term keypair
+                let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+
+                // Use a PSK mode, so that a mismatched PSK can be tried too
+                let (psk, psk_id) = (gen_rand_buf(), gen_rand_buf());
+                let (sender_mode, receiver_mode) =
+                    new_op_mode_pair::<Kex>(OpModeKind::Psk, &psk, &psk_id);
+
+                let (encapped_key, sender_ctx) =
+                    setup_sender::<A, Kdf, Kem, _>(&sender_mode, &pk_recip, &info[..], &mut csprng)
+                        .unwrap();
+                let receiver_ctx = setup_receiver::<A, Kdf, Kem>(
+                    &receiver_mode,
+                    &sk_recip,
+                    &encapped_key,
+                    &info[..],
+                )
+                .unwrap();
+
+                // Key confirmation succeeds, including over the wire
+                let tag_bytes = sender_ctx.confirmation_tag().to_bytes();
+                let tag = ConfirmationTag::from_bytes(&tag_bytes).unwrap();
+                receiver_ctx.verify_confirmation(&tag).unwrap();
+
+                // A tag of the wrong length is rejected
+                assert!(matches!(
+                    ConfirmationTag::from_bytes(&tag_bytes[1..]),
+                    Err(HpkeError::InvalidEncoding)
+                ));
+
+                // A tampered tag fails confirmation
+                let mut bad_tag_bytes = tag_bytes;
+                bad_tag_bytes[0] ^= 1;
+                let bad_tag = ConfirmationTag::from_bytes(&bad_tag_bytes).unwrap();
+                assert!(matches!(
+                    receiver_ctx.verify_confirmation(&bad_tag),
+                    Err(HpkeError::ConfirmationFailed)
+                ));
+
+                // So does the tag of a sender that used a different info string
+                let (encapped_key, other_sender_ctx) = setup_sender::<A, Kdf, Kem, _>(
+                    &sender_mode,
+                    &pk_recip,
+                    b"something else",
+                    &mut csprng,
+                )
+                .unwrap();
+                let receiver_ctx = setup_receiver::<A, Kdf, Kem>(
+                    &receiver_mode,
+                    &sk_recip,
+                    &encapped_key,
+                    &info[..],
+                )
+                .unwrap();
+                assert!(matches!(
+                    receiver_ctx.verify_confirmation(&other_sender_ctx.confirmation_tag()),
+                    Err(HpkeError::ConfirmationFailed)
+                ));
+
+                // And the tag of a sender that used a different PSK
+                let other_psk = gen_rand_buf();
+                let (other_sender_mode, _) =
+                    new_op_mode_pair::<Kex>(OpModeKind::Psk, &other_psk, &psk_id);
+                let (encapped_key, other_sender_ctx) = setup_sender::<A, Kdf, Kem, _>(
+                    &other_sender_mode,
+                    &pk_recip,
+                    &info[..],
+                    &mut csprng,
+                )
+                .unwrap();
+                let receiver_ctx = setup_receiver::<A, Kdf, Kem>(
+                    &receiver_mode,
+                    &sk_recip,
+                    &encapped_key,
+                    &info[..],
+                )
+                .unwrap();
+                assert!(matches!(
+                    receiver_ctx.verify_confirmation(&other_sender_ctx.confirmation_tag()),
+                    Err(HpkeError::ConfirmationFailed)
+                ));
+            }
+        };
+    }
+
     #[cfg(feature = "x25519-dalek")]
     test_setup_correctness!(
         test_setup_correctness_x25519,
@@ -326,6 +679,13 @@ mod test {
         HkdfSha256,
         crate::kem::DhP256HkdfSha256
     );
+    #[cfg(feature = "k256")]
+    test_setup_correctness!(
+        test_setup_correctness_k256,
+        ChaCha20Poly1305,
+        HkdfSha256,
+        crate::kem::DhK256HkdfSha256
+    );
 
     #[cfg(feature = "x25519-dalek")]
     test_setup_soundness!(
@@ -341,4 +701,301 @@ mod test {
         HkdfSha256,
         crate::kem::DhP256HkdfSha256
     );
+    #[cfg(feature = "k256")]
+    test_setup_soundness!(
+        test_setup_soundness_k256,
+        ChaCha20Poly1305,
+        HkdfSha256,
+        crate::kem::DhK256HkdfSha256
+    );
+
+    #[cfg(feature = "x25519-dalek")]
+    test_setup_receiver_from_bytes!(
+        test_setup_receiver_from_bytes_x25519,
+        ChaCha20Poly1305,
+        HkdfSha256,
+        crate::kem::X25519HkdfSha256
+    );
+    #[cfg(feature = "p256")]
+    test_setup_receiver_from_bytes!(
+        test_setup_receiver_from_bytes_p256,
+        ChaCha20Poly1305,
+        HkdfSha256,
+        crate::kem::DhP256HkdfSha256
+    );
+    #[cfg(feature = "k256")]
+    test_setup_receiver_from_bytes!(
+        test_setup_receiver_from_bytes_k256,
+        ChaCha20Poly1305,
+        HkdfSha256,
+        crate::kem::DhK256HkdfSha256
+    );
+
+    #[cfg(feature = "x25519-dalek")]
+    test_context_probe!(
+        test_context_probe_x25519,
+        ChaCha20Poly1305,
+        HkdfSha256,
+        crate::kem::X25519HkdfSha256
+    );
+    #[cfg(feature = "p256")]
+    test_context_probe!(
+        test_context_probe_p256,
+        ChaCha20Poly1305,
+        HkdfSha256,
+        crate::kem::DhP256HkdfSha256
+    );
+    #[cfg(feature = "k256")]
+    test_context_probe!(
+        test_context_probe_k256,
+        ChaCha20Poly1305,
+        HkdfSha256,
+        crate::kem::DhK256HkdfSha256
+    );
+
+    #[cfg(feature = "x25519-dalek")]
+    test_confirmation_tag!(
+        test_confirmation_tag_x25519,
+        ChaCha20Poly1305,
+        HkdfSha256,
+        crate::kem::X25519HkdfSha256
+    );
+    #[cfg(feature = "p256")]
+    test_confirmation_tag!(
+        test_confirmation_tag_p256,
+        ChaCha20Poly1305,
+        HkdfSha256,
+        crate::kem::DhP256HkdfSha256
+    );
+    #[cfg(feature = "k256")]
+    test_confirmation_tag!(
+        test_confirmation_tag_k256,
+        ChaCha20Poly1305,
+        HkdfSha256,
+        crate::kem::DhK256HkdfSha256
+    );
+
+    /// Tests that the auth modes are refused, on both ends, with a KEM that doesn't support them,
+    /// and that the other modes still work
+    #[cfg(feature = "x25519-dalek")]
+    #[test]
+    fn test_unsupported_auth() {
+        use crate::{OpModeR, OpModeS, PskBundle};
+        use generic_array::typenum;
+
+        // X25519 with auth turned off
+        struct NoAuthKem;
+        impl KemTrait for NoAuthKem {
+            type Kex = crate::kex::X25519;
+            type Kdf = HkdfSha256;
+            type SharedSecretSize = typenum::U32;
+            const KEM_ID: u16 = 0x0020;
+            const SUPPORTS_AUTH: bool = false;
+        }
+        type A = ChaCha20Poly1305;
+        type Kem = NoAuthKem;
+
+        let mut csprng = StdRng::from_entropy();
+        let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+        let (sk_sender, pk_sender) = Kem::gen_keypair(&mut csprng);
+        let psk = PskBundle {
+            psk: b"a psk",
+            psk_id: b"psk id",
+        };
+
+        for mode in [
+            OpModeS::Auth((sk_sender.clone(), pk_sender.clone())),
+            OpModeS::AuthPsk((sk_sender, pk_sender.clone()), psk),
+        ]
+        .iter()
+        {
+            let res = setup_sender::<A, HkdfSha256, Kem, _>(mode, &pk_recip, b"", &mut csprng);
+            assert!(matches!(res, Err(HpkeError::UnsupportedMode)));
+        }
+
+        let (encapped_key, _) =
+            setup_sender::<A, HkdfSha256, Kem, _>(&OpModeS::Psk(psk), &pk_recip, b"", &mut csprng)
+                .unwrap();
+        assert!(setup_receiver::<A, HkdfSha256, Kem>(
+            &OpModeR::Psk(psk),
+            &sk_recip,
+            &encapped_key,
+            b""
+        )
+        .is_ok());
+        for mode in [
+            OpModeR::Auth(pk_sender.clone()),
+            OpModeR::AuthPsk(pk_sender, psk),
+        ]
+        .iter()
+        {
+            let res = setup_receiver::<A, HkdfSha256, Kem>(mode, &sk_recip, &encapped_key, b"");
+            assert!(matches!(res, Err(HpkeError::UnsupportedMode)));
+        }
+    }
+
+    /// Tests that setup, encap/decap, seal, and open each get a span, and that no span records a
+    /// field that isn't on the allowlist of non-secret fields
+    #[cfg(all(feature = "tracing", feature = "std", feature = "x25519-dalek"))]
+    #[test]
+    fn test_tracing_spans() {
+        use std::{sync::Mutex, vec::Vec};
+        use tracing::{
+            field::{Field, Visit},
+            span, Event, Metadata, Subscriber,
+        };
+
+        // Records the names and fields of all the spans it sees
+        #[derive(Default)]
+        struct RecordingSubscriber {
+            spans: Mutex<Vec<(&'static str, Vec<&'static str>)>>,
+        }
+
+        struct FieldNames(Vec<&'static str>);
+
+        impl Visit for FieldNames {
+            fn record_debug(&mut self, field: &Field, _: &dyn core::fmt::Debug) {
+                self.0.push(field.name());
+            }
+        }
+
+        impl Subscriber for RecordingSubscriber {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+            fn new_span(&self, attrs: &span::Attributes<'_>) -> span::Id {
+                let mut fields = FieldNames(Vec::new());
+                attrs.record(&mut fields);
+                let mut spans = self.spans.lock().unwrap();
+                spans.push((attrs.metadata().name(), fields.0));
+                span::Id::from_u64(spans.len() as u64)
+            }
+            fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+            fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+            fn event(&self, _: &Event<'_>) {}
+            fn enter(&self, _: &span::Id) {}
+            fn exit(&self, _: &span::Id) {}
+        }
+
+        type A = ChaCha20Poly1305;
+        type Kdf = HkdfSha256;
+        type Kem = crate::kem::X25519HkdfSha256;
+
+        let subscriber = std::sync::Arc::new(RecordingSubscriber::default());
+        tracing::subscriber::with_default(subscriber.clone(), || {
+            let mut csprng = StdRng::from_entropy();
+            let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+            let info = b"test_tracing_spans";
+
+            let (encapped_key, mut sender_ctx) =
+                setup_sender::<A, Kdf, Kem, _>(&OpModeS::Base, &pk_recip, info, &mut csprng)
+                    .unwrap();
+            let mut receiver_ctx =
+                setup_receiver::<A, Kdf, Kem>(&OpModeR::Base, &sk_recip, &encapped_key, info)
+                    .unwrap();
+            let mut msg = *b"spans only";
+            let tag = sender_ctx.seal(&mut msg, b"").unwrap();
+            receiver_ctx.open(&mut msg, b"", &tag).unwrap();
+        });
+
+        let allowed_fields = ["kem_id", "kdf_id", "aead_id", "mode_id", "len"];
+        let spans = subscriber.spans.lock().unwrap();
+        let names: Vec<_> = spans.iter().map(|(name, _)| *name).collect();
+        assert_eq!(
+            names,
+            [
+                "setup_sender",
+                "encap",
+                "setup_receiver",
+                "decap",
+                "seal",
+                "open"
+            ]
+        );
+        for (_, fields) in spans.iter() {
+            assert!(fields.iter().all(|f| allowed_fields.contains(f)));
+        }
+    }
+
+    /// Tests that an AuthPsk setup, seal, open, and export fit in the given stack size, in bytes.
+    /// This runs them on a thread with exactly that much stack, so going over is a stack overflow.
+    /// The budgets are the measurements in the README's "Stack usage" section, plus some room. Debug
+    /// builds use far more stack, so they all get the same loose budget.
+    #[cfg(feature = "std")]
+    macro_rules! test_stack_usage {
+        ($test_name:ident, $aead_ty:ty, $kdf_ty:ty, $kem_ty:ty, $release_budget:expr) => {
+            #[test]
+            fn $test_name() {
+                type A = $aead_ty;
+                type Kdf = $kdf_ty;
+                type Kem = $kem_ty;
+                type Kex = <Kem as KemTrait>::Kex;
+
+                let budget = if cfg!(debug_assertions) {
+                    128 * 1024
+                } else {
+                    $release_budget
+                };
+
+                let thread = std::thread::Builder::new().stack_size(budget);
+                let handle = thread
+                    .spawn(|| {
+                        let mut csprng = StdRng::from_entropy();
+                        let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+                        let (psk, psk_id) = (gen_rand_buf(), gen_rand_buf());
+                        let (sender_mode, receiver_mode) =
+                            new_op_mode_pair::<Kex>(OpModeKind::AuthPsk, &psk, &psk_id);
+                        let info = b"test_stack_usage";
+
+                        let (encapped_key, mut sender_ctx) = setup_sender::<A, Kdf, Kem, _>(
+                            &sender_mode,
+                            &pk_recip,
+                            info,
+                            &mut csprng,
+                        )
+                        .unwrap();
+                        let mut receiver_ctx = setup_receiver::<A, Kdf, Kem>(
+                            &receiver_mode,
+                            &sk_recip,
+                            &encapped_key,
+                            info,
+                        )
+                        .unwrap();
+
+                        let mut msg = [0u8; 64];
+                        let tag = sender_ctx.seal(&mut msg, b"").unwrap();
+                        receiver_ctx.open(&mut msg, b"", &tag).unwrap();
+                        let mut secret = [0u8; 64];
+                        receiver_ctx.export(b"", &mut secret).unwrap();
+                    })
+                    .unwrap();
+                handle.join().unwrap();
+            }
+        };
+    }
+
+    #[cfg(all(feature = "std", feature = "x25519-dalek"))]
+    test_stack_usage!(
+        test_stack_usage_chacha_x25519,
+        ChaCha20Poly1305,
+        crate::kdf::HkdfSha512,
+        crate::kem::X25519HkdfSha256,
+        16 * 1024
+    );
+    #[cfg(all(feature = "std", feature = "p256"))]
+    test_stack_usage!(
+        test_stack_usage_chacha_p256,
+        ChaCha20Poly1305,
+        crate::kdf::HkdfSha512,
+        crate::kem::DhP256HkdfSha256,
+        16 * 1024
+    );
+    #[cfg(all(feature = "std", feature = "p256"))]
+    test_stack_usage!(
+        test_stack_usage_aes256_p256,
+        crate::aead::AesGcm256,
+        crate::kdf::HkdfSha512,
+        crate::kem::DhP256HkdfSha256,
+        48 * 1024
+    );
 }
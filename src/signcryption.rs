@@ -0,0 +1,446 @@
+//! This module defines sign-then-encrypt helpers. HPKE's Auth mode proves to the recipient that
+//! the sender knew a private key, but the recipient could have made the same ciphertext itself, so
+//! it can't prove anything to a third party. When the recipient needs a non-repudiable proof of
+//! who sent a message, the sender has to sign it.
+//!
+//! [`signcrypt_seal`] signs the plaintext along with the info string, the AAD, and the
+//! encapsulated key, then encrypts the plaintext and signature together in Base mode. So the
+//! signature is hidden from everyone but the recipient, and can't be moved to another message.
+//! [`signcrypt_open`] reverses this. Any signature scheme that implements the traits of the
+//! `signature` crate works, e.g., Ed25519 or ECDSA.
+//!
+//! For deployments whose sender identities are signature keys rather than DH keys,
+//! [`setup_sender_auth_sig`] and [`setup_receiver_auth_sig`] stand in for Auth mode. The sender
+//! does a Base mode setup and signs the encapsulated key along with a value exported from the new
+//! context. The receiver checks the signature before using the context. The exported value
+//! depends on the whole key schedule, so the signature can't be moved to another context, even
+//! one with the same encapsulated key.
+//!
+//! Wire format
+//! ===========
+//! The sealed message is `ciphertext || tag`, where the plaintext of `ciphertext` is
+//! `sig_len || sig || plaintext`, and `sig_len` is the length of `sig` as a big-endian u16. The
+//! signed message is
+//! `"rust-hpke signcryption" || len(info) || info || len(aad) || aad || enc || plaintext`, where
+//! the lengths are big-endian u64s.
+//!
+//! In the auth-with-signature setup, the signed message is `"rust-hpke auth sig" || enc || binding`,
+//! where `binding` is 32 bytes exported from the context under the exporter context
+//! `"rust-hpke auth sig binding"`.
+
+use crate::{
+    aead::{Aead, AeadCtxR, AeadCtxS, AeadTag},
+    kdf::Kdf as KdfTrait,
+    kem::{EncappedKey, Kem as KemTrait},
+    kex::{Deserializable, KeyExchange, Serializable},
+    op_mode::{OpModeR, OpModeS},
+    setup::{setup_receiver, setup_sender},
+    HpkeError,
+};
+
+use alloc::vec::Vec;
+use rand::{CryptoRng, RngCore};
+use signature::{Signature, Signer, Verifier};
+
+// Signatures are bound to this string, so that they can't be confused with any other signature by
+// the same key
+const SIGNCRYPTION_DOMAIN: &[u8] = b"rust-hpke signcryption";
+
+// The domain string and exporter context of the auth-with-signature setup, and the size of the
+// exported binding value
+const AUTH_SIG_DOMAIN: &[u8] = b"rust-hpke auth sig";
+const AUTH_SIG_BINDING_LABEL: &[u8] = b"rust-hpke auth sig binding";
+const AUTH_SIG_BINDING_SIZE: usize = 32;
+
+// The output of setup_sender_auth_sig
+type AuthSigSenderOutput<A, Kdf, Kem, Sig> = (EncappedKey<Kem>, Sig, AeadCtxS<A, Kdf, Kem>);
+
+// Returns the message that's signed for the given inputs. See the module docs.
+fn signed_message(info: &[u8], aad: &[u8], enc: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(
+        SIGNCRYPTION_DOMAIN.len() + 16 + info.len() + aad.len() + enc.len() + plaintext.len(),
+    );
+    msg.extend_from_slice(SIGNCRYPTION_DOMAIN);
+    msg.extend_from_slice(&(info.len() as u64).to_be_bytes());
+    msg.extend_from_slice(info);
+    msg.extend_from_slice(&(aad.len() as u64).to_be_bytes());
+    msg.extend_from_slice(aad);
+    msg.extend_from_slice(enc);
+    msg.extend_from_slice(plaintext);
+    msg
+}
+
+/// Signs `plaintext` with `signer`, then encrypts the plaintext and signature to the given
+/// recipient in Base mode. See the [module docs](self) for what's signed.
+///
+/// Return Value
+/// ============
+/// Returns `Ok((encapped_key, sealed))` on success, where `sealed` is `ciphertext || tag`. If
+/// signing fails, or the signature is longer than 65535 bytes, returns
+/// `Err(HpkeError::Encryption)`. Otherwise, fails the same way as
+/// [`single_shot_seal`](crate::single_shot_seal).
+pub fn signcrypt_seal<A, Kdf, Kem, Sig, S, R>(
+    pk_recip: &<Kem::Kex as KeyExchange>::PublicKey,
+    info: &[u8],
+    plaintext: &[u8],
+    aad: &[u8],
+    signer: &S,
+    csprng: &mut R,
+) -> Result<(EncappedKey<Kem>, Vec<u8>), HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    Sig: Signature,
+    S: Signer<Sig>,
+    R: CryptoRng + RngCore,
+{
+    let (encapped_key, mut aead_ctx) =
+        setup_sender::<A, Kdf, Kem, R>(&OpModeS::Base, pk_recip, info, csprng)?;
+
+    // Sign everything, including the encapsulated key, which binds the signature to this message
+    let msg = signed_message(info, aad, &encapped_key.to_bytes(), plaintext);
+    let sig = signer.try_sign(&msg).map_err(|_| HpkeError::Encryption)?;
+    let sig_bytes = sig.as_bytes();
+    if sig_bytes.len() > u16::MAX as usize {
+        return Err(HpkeError::Encryption);
+    }
+
+    // sealed = Seal(sig_len || sig || plaintext) || tag
+    let mut sealed =
+        Vec::with_capacity(2 + sig_bytes.len() + plaintext.len() + AeadTag::<A>::size());
+    sealed.extend_from_slice(&(sig_bytes.len() as u16).to_be_bytes());
+    sealed.extend_from_slice(sig_bytes);
+    sealed.extend_from_slice(plaintext);
+    let tag = aead_ctx.seal(&mut sealed, aad)?;
+    sealed.extend_from_slice(&tag.to_bytes());
+
+    Ok((encapped_key, sealed))
+}
+
+/// Decrypts a message made by [`signcrypt_seal`] and checks its signature with `verifier`
+///
+/// Return Value
+/// ============
+/// Returns the plaintext on success. If the signature doesn't verify, returns
+/// `Err(HpkeError::InvalidSignature)`. If `sealed` is too short, or the decrypted contents are
+/// malformed, returns `Err(HpkeError::InvalidEncoding)`. Otherwise, fails the same way as
+/// [`single_shot_open`](crate::single_shot_open).
+pub fn signcrypt_open<A, Kdf, Kem, Sig, V>(
+    sk_recip: &<Kem::Kex as KeyExchange>::PrivateKey,
+    encapped_key: &EncappedKey<Kem>,
+    info: &[u8],
+    sealed: &[u8],
+    aad: &[u8],
+    verifier: &V,
+) -> Result<Vec<u8>, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    Sig: Signature,
+    V: Verifier<Sig>,
+{
+    let tag_size = AeadTag::<A>::size();
+    if sealed.len() < tag_size {
+        return Err(HpkeError::InvalidEncoding);
+    }
+    let (ciphertext, tag_bytes) = sealed.split_at(sealed.len() - tag_size);
+    let tag = AeadTag::<A>::from_bytes(tag_bytes)?;
+
+    let mut aead_ctx = setup_receiver::<A, Kdf, Kem>(&OpModeR::Base, sk_recip, encapped_key, info)?;
+    let mut contents = ciphertext.to_vec();
+    aead_ctx.open(&mut contents, aad, &tag)?;
+
+    // contents = sig_len || sig || plaintext
+    if contents.len() < 2 {
+        return Err(HpkeError::InvalidEncoding);
+    }
+    let (sig_len, rest) = contents.split_at(2);
+    let sig_len = u16::from_be_bytes([sig_len[0], sig_len[1]]) as usize;
+    if rest.len() < sig_len {
+        return Err(HpkeError::InvalidEncoding);
+    }
+    let (sig_bytes, plaintext) = rest.split_at(sig_len);
+    let sig = Sig::from_bytes(sig_bytes).map_err(|_| HpkeError::InvalidSignature)?;
+
+    let msg = signed_message(info, aad, &encapped_key.to_bytes(), plaintext);
+    verifier
+        .verify(&msg, &sig)
+        .map_err(|_| HpkeError::InvalidSignature)?;
+
+    Ok(plaintext.to_vec())
+}
+
+// Returns the message that's signed in the auth-with-signature setup. `export` is the export
+// function of either end of the context.
+fn auth_sig_message<F>(enc: &[u8], export: F) -> Result<Vec<u8>, HpkeError>
+where
+    F: Fn(&[u8], &mut [u8]) -> Result<(), HpkeError>,
+{
+    let mut binding = [0u8; AUTH_SIG_BINDING_SIZE];
+    export(AUTH_SIG_BINDING_LABEL, &mut binding)?;
+
+    let mut msg = Vec::with_capacity(AUTH_SIG_DOMAIN.len() + enc.len() + AUTH_SIG_BINDING_SIZE);
+    msg.extend_from_slice(AUTH_SIG_DOMAIN);
+    msg.extend_from_slice(enc);
+    msg.extend_from_slice(&binding);
+    Ok(msg)
+}
+
+/// Does a Base mode [`setup_sender`] and signs the result with the sender's long-term signature
+/// key. See the [module docs](self).
+///
+/// Return Value
+/// ============
+/// On success, returns the encapsulated key and the signature, which must both be sent to the
+/// recipient, and an encryption context. If signing fails, returns `Err(HpkeError::Encryption)`.
+/// Otherwise, fails the same way as [`setup_sender`].
+pub fn setup_sender_auth_sig<A, Kdf, Kem, Sig, S, R>(
+    pk_recip: &<Kem::Kex as KeyExchange>::PublicKey,
+    info: &[u8],
+    signer: &S,
+    csprng: &mut R,
+) -> Result<AuthSigSenderOutput<A, Kdf, Kem, Sig>, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    Sig: Signature,
+    S: Signer<Sig>,
+    R: CryptoRng + RngCore,
+{
+    let (encapped_key, aead_ctx) =
+        setup_sender::<A, Kdf, Kem, R>(&OpModeS::Base, pk_recip, info, csprng)?;
+
+    let msg = auth_sig_message(&encapped_key.to_bytes(), |label, out| {
+        aead_ctx.export(label, out)
+    })?;
+    let sig = signer.try_sign(&msg).map_err(|_| HpkeError::Encryption)?;
+
+    Ok((encapped_key, sig, aead_ctx))
+}
+
+/// Does a Base mode [`setup_receiver`] and checks the sender's signature on the result with
+/// `verifier`. See the [module docs](self).
+///
+/// Return Value
+/// ============
+/// On success, returns a decryption context. If the signature doesn't verify, returns
+/// `Err(HpkeError::InvalidSignature)`. Otherwise, fails the same way as [`setup_receiver`].
+pub fn setup_receiver_auth_sig<A, Kdf, Kem, Sig, V>(
+    sk_recip: &<Kem::Kex as KeyExchange>::PrivateKey,
+    encapped_key: &EncappedKey<Kem>,
+    sig: &Sig,
+    info: &[u8],
+    verifier: &V,
+) -> Result<AeadCtxR<A, Kdf, Kem>, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    Sig: Signature,
+    V: Verifier<Sig>,
+{
+    let aead_ctx = setup_receiver::<A, Kdf, Kem>(&OpModeR::Base, sk_recip, encapped_key, info)?;
+
+    let msg = auth_sig_message(&encapped_key.to_bytes(), |label, out| {
+        aead_ctx.export(label, out)
+    })?;
+    verifier
+        .verify(&msg, sig)
+        .map_err(|_| HpkeError::InvalidSignature)?;
+
+    Ok(aead_ctx)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{setup_receiver_auth_sig, setup_sender_auth_sig, signcrypt_open, signcrypt_seal};
+    use crate::{
+        aead::ChaCha20Poly1305, kdf::HkdfSha256, kem::Kem as KemTrait, test_util::aead_ctx_eq,
+        HpkeError,
+    };
+
+    use p256_ecdsa::{
+        ecdsa::{self, signature::RandomizedSigner},
+        PublicKey, SecretKey,
+    };
+    use rand::{rngs::StdRng, RngCore, SeedableRng};
+
+    // ECDSA signers in this version of the ecdsa crate are only randomized signers. Wrap one so it
+    // can be used as a plain signer.
+    struct EcdsaSigner(ecdsa::Signer);
+
+    impl ecdsa::signature::Signer<ecdsa::Signature> for EcdsaSigner {
+        fn try_sign(&self, msg: &[u8]) -> Result<ecdsa::Signature, ecdsa::Error> {
+            self.0.try_sign_with_rng(rand::rngs::OsRng, msg)
+        }
+    }
+
+    // Makes a P-256 ECDSA signer and its verifier
+    fn gen_ecdsa_keypair(csprng: &mut StdRng) -> (EcdsaSigner, ecdsa::Verifier) {
+        let mut sk_bytes = [0u8; 32];
+        csprng.fill_bytes(&mut sk_bytes);
+        let sk = SecretKey::from_bytes(sk_bytes).unwrap();
+        let pk = PublicKey::from_secret_key(&sk, false).unwrap();
+        (
+            EcdsaSigner(ecdsa::Signer::new(&sk).unwrap()),
+            ecdsa::Verifier::new(&pk).unwrap(),
+        )
+    }
+
+    /// Tests that signcrypted messages open, and that the wrong verifier, the wrong AAD, or a
+    /// truncated message makes them fail
+    macro_rules! test_signcryption {
+        ($test_name:ident, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type A = ChaCha20Poly1305;
+                type Kdf = HkdfSha256;
+                type Kem = $kem;
+                let mut csprng = StdRng::from_entropy();
+
+                let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+                let (signer, verifier) = gen_ecdsa_keypair(&mut csprng);
+                let (_, other_verifier) = gen_ecdsa_keypair(&mut csprng);
+                let (info, aad, msg) = (b"signcryption test", b"aad", b"signed and sealed");
+
+                let (encapped_key, sealed) = signcrypt_seal::<A, Kdf, Kem, _, _, _>(
+                    &pk_recip,
+                    info,
+                    msg,
+                    aad,
+                    &signer,
+                    &mut csprng,
+                )
+                .unwrap();
+                let plaintext = signcrypt_open::<A, Kdf, Kem, _, _>(
+                    &sk_recip,
+                    &encapped_key,
+                    info,
+                    &sealed,
+                    aad,
+                    &verifier,
+                )
+                .unwrap();
+                assert_eq!(plaintext, msg);
+
+                // The wrong verifier rejects it
+                let res = signcrypt_open::<A, Kdf, Kem, _, _>(
+                    &sk_recip,
+                    &encapped_key,
+                    info,
+                    &sealed,
+                    aad,
+                    &other_verifier,
+                );
+                assert!(matches!(res, Err(HpkeError::InvalidSignature)));
+
+                // The wrong AAD fails to decrypt
+                let res = signcrypt_open::<A, Kdf, Kem, _, _>(
+                    &sk_recip,
+                    &encapped_key,
+                    info,
+                    &sealed,
+                    b"other aad",
+                    &verifier,
+                );
+                assert!(matches!(res, Err(HpkeError::InvalidTag)));
+
+                // Truncated input is rejected
+                let res = signcrypt_open::<A, Kdf, Kem, _, _>(
+                    &sk_recip,
+                    &encapped_key,
+                    info,
+                    &sealed[..15],
+                    aad,
+                    &verifier,
+                );
+                assert!(matches!(res, Err(HpkeError::InvalidEncoding)));
+            }
+        };
+    }
+
+    /// Tests that the auth-with-signature setup gives matching contexts, and that the wrong
+    /// verifier, the wrong info string, or a signature from another session is rejected
+    macro_rules! test_auth_sig {
+        ($test_name:ident, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type A = ChaCha20Poly1305;
+                type Kdf = HkdfSha256;
+                type Kem = $kem;
+                let mut csprng = StdRng::from_entropy();
+
+                let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+                let (signer, verifier) = gen_ecdsa_keypair(&mut csprng);
+                let (_, other_verifier) = gen_ecdsa_keypair(&mut csprng);
+                let info = b"auth sig test";
+
+                let (encapped_key, sig, mut sender_ctx) =
+                    setup_sender_auth_sig::<A, Kdf, Kem, _, _, _>(
+                        &pk_recip,
+                        info,
+                        &signer,
+                        &mut csprng,
+                    )
+                    .unwrap();
+                let mut receiver_ctx = setup_receiver_auth_sig::<A, Kdf, Kem, _, _>(
+                    &sk_recip,
+                    &encapped_key,
+                    &sig,
+                    info,
+                    &verifier,
+                )
+                .unwrap();
+                assert!(aead_ctx_eq(&mut sender_ctx, &mut receiver_ctx));
+
+                // The wrong verifier or info string rejects it
+                let res = setup_receiver_auth_sig::<A, Kdf, Kem, _, _>(
+                    &sk_recip,
+                    &encapped_key,
+                    &sig,
+                    info,
+                    &other_verifier,
+                );
+                assert!(matches!(res, Err(HpkeError::InvalidSignature)));
+                let res = setup_receiver_auth_sig::<A, Kdf, Kem, _, _>(
+                    &sk_recip,
+                    &encapped_key,
+                    &sig,
+                    b"other info",
+                    &verifier,
+                );
+                assert!(matches!(res, Err(HpkeError::InvalidSignature)));
+
+                // A signature from another session doesn't carry over
+                let (other_encapped_key, _, _) = setup_sender_auth_sig::<A, Kdf, Kem, _, _, _>(
+                    &pk_recip,
+                    info,
+                    &signer,
+                    &mut csprng,
+                )
+                .unwrap();
+                let res = setup_receiver_auth_sig::<A, Kdf, Kem, _, _>(
+                    &sk_recip,
+                    &other_encapped_key,
+                    &sig,
+                    info,
+                    &verifier,
+                );
+                assert!(matches!(res, Err(HpkeError::InvalidSignature)));
+            }
+        };
+    }
+
+    #[cfg(feature = "x25519-dalek")]
+    test_signcryption!(test_signcryption_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_signcryption!(test_signcryption_p256, crate::kem::DhP256HkdfSha256);
+
+    #[cfg(feature = "x25519-dalek")]
+    test_auth_sig!(test_auth_sig_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_auth_sig!(test_auth_sig_p256, crate::kem::DhP256HkdfSha256);
+}
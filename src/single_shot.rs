@@ -31,7 +31,7 @@ pub fn single_shot_seal<A, Kdf, Kem, R>(
     plaintext: &mut [u8],
     aad: &[u8],
     csprng: &mut R,
-) -> Result<(EncappedKey<Kem::Kex>, AeadTag<A>), HpkeError>
+) -> Result<(EncappedKey<Kem>, AeadTag<A>), HpkeError>
 where
     A: Aead,
     Kdf: KdfTrait,
@@ -62,7 +62,7 @@ where
 pub fn single_shot_open<A, Kdf, Kem>(
     mode: &OpModeR<Kem::Kex>,
     sk_recip: &<Kem::Kex as KeyExchange>::PrivateKey,
-    encapped_key: &EncappedKey<Kem::Kex>,
+    encapped_key: &EncappedKey<Kem>,
     info: &[u8],
     ciphertext: &mut [u8],
     aad: &[u8],
@@ -79,6 +79,66 @@ where
     aead_ctx.open(ciphertext, aad, tag)
 }
 
+/// Like [`single_shot_seal`], but takes the plaintext by reference and returns the ciphertext in
+/// a [`heapless::Vec`] with capacity `N`, rather than encrypting in place. This is for callers who
+/// don't have a heap.
+///
+/// Return Value
+/// ============
+/// Returns `Ok((encapped_key, ciphertext, auth_tag))` on success. If the plaintext is longer than
+/// `N`, returns `Err(HpkeError::BufferTooSmall)`. Otherwise, fails the same way as
+/// [`single_shot_seal`].
+#[cfg(feature = "heapless")]
+pub fn single_shot_seal_heapless<A, Kdf, Kem, R, const N: usize>(
+    mode: &OpModeS<Kem::Kex>,
+    pk_recip: &<Kem::Kex as KeyExchange>::PublicKey,
+    info: &[u8],
+    plaintext: &[u8],
+    aad: &[u8],
+    csprng: &mut R,
+) -> Result<(EncappedKey<Kem>, heapless::Vec<u8, N>, AeadTag<A>), HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    R: CryptoRng + RngCore,
+{
+    let mut ciphertext =
+        heapless::Vec::<u8, N>::from_slice(plaintext).map_err(|_| HpkeError::BufferTooSmall)?;
+    let (encapped_key, tag) =
+        single_shot_seal::<A, Kdf, Kem, R>(mode, pk_recip, info, &mut ciphertext, aad, csprng)?;
+    Ok((encapped_key, ciphertext, tag))
+}
+
+/// Like [`single_shot_open`], but takes the ciphertext by reference and returns the plaintext in
+/// a [`heapless::Vec`] with capacity `N`, rather than decrypting in place. This is for callers who
+/// don't have a heap.
+///
+/// Return Value
+/// ============
+/// Returns the plaintext on success. If the ciphertext is longer than `N`, returns
+/// `Err(HpkeError::BufferTooSmall)`. Otherwise, fails the same way as [`single_shot_open`].
+#[cfg(feature = "heapless")]
+pub fn single_shot_open_heapless<A, Kdf, Kem, const N: usize>(
+    mode: &OpModeR<Kem::Kex>,
+    sk_recip: &<Kem::Kex as KeyExchange>::PrivateKey,
+    encapped_key: &EncappedKey<Kem>,
+    info: &[u8],
+    ciphertext: &[u8],
+    aad: &[u8],
+    tag: &AeadTag<A>,
+) -> Result<heapless::Vec<u8, N>, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    let mut plaintext =
+        heapless::Vec::<u8, N>::from_slice(ciphertext).map_err(|_| HpkeError::BufferTooSmall)?;
+    single_shot_open::<A, Kdf, Kem>(mode, sk_recip, encapped_key, info, &mut plaintext, aad, tag)?;
+    Ok(plaintext)
+}
+
 #[cfg(test)]
 mod test {
     use super::{single_shot_open, single_shot_seal};
@@ -163,6 +223,78 @@ mod test {
         };
     }
 
+    /// Tests that the heapless variants round-trip, and reject messages over their capacity
+    #[cfg(feature = "heapless")]
+    macro_rules! test_single_shot_heapless {
+        ($test_name:ident, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                use super::{single_shot_open_heapless, single_shot_seal_heapless};
+                use crate::HpkeError;
+
+                type A = ChaCha20Poly1305;
+                type Kdf = HkdfSha256;
+                type Kem = $kem;
+
+                let mut csprng = StdRng::from_entropy();
+                let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+                let msg = b"no heap here";
+                let info = b"heapless test";
+
+                let (encapped_key, ciphertext, tag) =
+                    single_shot_seal_heapless::<A, Kdf, Kem, _, 16>(
+                        &OpModeS::Base,
+                        &pk_recip,
+                        info,
+                        msg,
+                        b"",
+                        &mut csprng,
+                    )
+                    .unwrap();
+                let plaintext = single_shot_open_heapless::<A, Kdf, Kem, 16>(
+                    &OpModeR::Base,
+                    &sk_recip,
+                    &encapped_key,
+                    info,
+                    &ciphertext,
+                    b"",
+                    &tag,
+                )
+                .unwrap();
+                assert_eq!(&plaintext[..], &msg[..]);
+
+                // Neither fits in 8 bytes
+                let res = single_shot_seal_heapless::<A, Kdf, Kem, _, 8>(
+                    &OpModeS::Base,
+                    &pk_recip,
+                    info,
+                    msg,
+                    b"",
+                    &mut csprng,
+                );
+                assert!(matches!(res, Err(HpkeError::BufferTooSmall)));
+                let res = single_shot_open_heapless::<A, Kdf, Kem, 8>(
+                    &OpModeR::Base,
+                    &sk_recip,
+                    &encapped_key,
+                    info,
+                    &ciphertext,
+                    b"",
+                    &tag,
+                );
+                assert!(matches!(res, Err(HpkeError::BufferTooSmall)));
+            }
+        };
+    }
+
+    #[cfg(all(feature = "heapless", feature = "x25519-dalek"))]
+    test_single_shot_heapless!(
+        test_single_shot_heapless_x25519,
+        crate::kem::X25519HkdfSha256
+    );
+    #[cfg(all(feature = "heapless", feature = "p256"))]
+    test_single_shot_heapless!(test_single_shot_heapless_p256, crate::kem::DhP256HkdfSha256);
+
     #[cfg(feature = "x25519-dalek")]
     test_single_shot_correctness!(
         test_single_shot_correctness_x25519,
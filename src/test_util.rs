@@ -1,14 +1,18 @@
+//! The random counterparts of the fixtures in [`crate::fixtures`], plus helpers that only this
+//! crate's tests need
+
 use crate::{
-    aead::{Aead, AeadCtx, AeadCtxR, AeadCtxS, AeadKey, AeadNonce},
+    aead::{Aead, AeadCtxR, AeadCtxS},
+    fixtures,
     kdf::Kdf as KdfTrait,
     kem::Kem as KemTrait,
-    kex::{KeyExchange, Serializable},
-    op_mode::{OpModeR, OpModeS, PskBundle},
-    setup::ExporterSecret,
+    kex::KeyExchange,
+    op_mode::{OpModeR, OpModeS},
 };
 
-use generic_array::GenericArray;
-use rand::{rngs::StdRng, CryptoRng, Rng, RngCore, SeedableRng};
+use rand::{rngs::StdRng, Rng, RngCore, SeedableRng};
+
+pub(crate) use crate::fixtures::{kex_gen_keypair, OpModeKind};
 
 /// Returns a random 32-byte buffer
 pub(crate) fn gen_rand_buf() -> [u8; 32] {
@@ -18,17 +22,9 @@ pub(crate) fn gen_rand_buf() -> [u8; 32] {
     buf
 }
 
-/// Generates a keypair without the need of a KEM
-pub(crate) fn kex_gen_keypair<Kex: KeyExchange, R: CryptoRng + RngCore>(
-    csprng: &mut R,
-) -> (Kex::PrivateKey, Kex::PublicKey) {
-    // Make some keying material that's the size of a private key
-    let mut ikm: GenericArray<u8, <Kex::PrivateKey as Serializable>::OutputSize> =
-        GenericArray::default();
-    // Fill it with randomness
-    csprng.fill_bytes(&mut ikm);
-    // Run derive_keypair with a nonsense ciphersuite. We use SHA-512 to satisfy any security level
-    Kex::derive_keypair::<crate::kdf::HkdfSha512>(b"31337", &ikm)
+/// Returns a random seed for the fixtures, so that every test run covers new values
+fn random_seed() -> u64 {
+    StdRng::from_entropy().next_u64()
 }
 
 /// Creates a pair of `AeadCtx`s without doing a key exchange
@@ -38,71 +34,16 @@ where
     Kdf: KdfTrait,
     Kem: KemTrait,
 {
-    let mut csprng = StdRng::from_entropy();
-
-    // Initialize the key and nonce
-    let key = {
-        let mut buf = AeadKey::<A>::default();
-        csprng.fill_bytes(buf.as_mut_slice());
-        buf
-    };
-    let nonce = {
-        let mut buf = AeadNonce::<A>::default();
-        csprng.fill_bytes(buf.as_mut_slice());
-        buf
-    };
-    let exporter_secret = {
-        let mut buf = ExporterSecret::<Kdf>::default();
-        csprng.fill_bytes(buf.as_mut_slice());
-        buf
-    };
-
-    let ctx1 = AeadCtx::new(&key, nonce.clone(), exporter_secret.clone());
-    let ctx2 = AeadCtx::new(&key, nonce.clone(), exporter_secret.clone());
-
-    (ctx1.into(), ctx2.into())
-}
-
-#[derive(Clone, Copy)]
-pub(crate) enum OpModeKind {
-    Base,
-    Auth,
-    Psk,
-    AuthPsk,
+    fixtures::gen_ctx_simple_pair(random_seed())
 }
 
 /// Makes an agreeing pair of `OpMode`s of the specified variant
-pub(crate) fn new_op_mode_pair<'a, Kex: KeyExchange, Kdf: KdfTrait>(
+pub(crate) fn new_op_mode_pair<'a, Kex: KeyExchange>(
     kind: OpModeKind,
     psk: &'a [u8],
     psk_id: &'a [u8],
 ) -> (OpModeS<'a, Kex>, OpModeR<'a, Kex>) {
-    let mut csprng = StdRng::from_entropy();
-    let (sk_sender, pk_sender) = kex_gen_keypair::<Kex, _>(&mut csprng);
-    let psk_bundle = PskBundle { psk, psk_id };
-
-    match kind {
-        OpModeKind::Base => {
-            let sender_mode = OpModeS::Base;
-            let receiver_mode = OpModeR::Base;
-            (sender_mode, receiver_mode)
-        }
-        OpModeKind::Psk => {
-            let sender_mode = OpModeS::Psk(psk_bundle);
-            let receiver_mode = OpModeR::Psk(psk_bundle);
-            (sender_mode, receiver_mode)
-        }
-        OpModeKind::Auth => {
-            let sender_mode = OpModeS::Auth((sk_sender, pk_sender.clone()));
-            let receiver_mode = OpModeR::Auth(pk_sender);
-            (sender_mode, receiver_mode)
-        }
-        OpModeKind::AuthPsk => {
-            let sender_mode = OpModeS::AuthPsk((sk_sender, pk_sender.clone()), psk_bundle);
-            let receiver_mode = OpModeR::AuthPsk(pk_sender, psk_bundle);
-            (sender_mode, receiver_mode)
-        }
-    }
+    fixtures::new_op_mode_pair(kind, psk, psk_id, random_seed())
 }
 
 /// Evaluates the equivalence of two encryption contexts by doing some encryption-decryption
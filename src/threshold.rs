@@ -0,0 +1,252 @@
+//! This module lets a recipient decapsulate without any one party holding its private key. The
+//! key is split into shares, any `threshold` of which are enough to decap. Each share-holder does
+//! its part of the DH with the encapsulated key, and hands back a [`PartialDh`]. The recipient
+//! combines enough partials into the DH result of the whole key, and finishes the decap and key
+//! schedule with [`setup_receiver_threshold`]. No share-holder learns the private key, or the
+//! shared secret. This is gated under the `threshold` feature.
+//!
+//! A share-holder is anything that implements [`PartialDecapProvider`], e.g., a client for a
+//! remote escrow service. A [`KeyShare`] held in this process is one too.
+//!
+//! Splitting and combining is up to the key exchange, through [`ThresholdKex`]. Shares are Shamir
+//! shares of the private key, and partials are combined with Lagrange coefficients at 0. This
+//! needs the DH to be a scalar multiplication in a group where points can be added, so it's only
+//! implemented for [`DhP256`](crate::kex::DhP256). X25519 only works with the u-coordinates of
+//! points, which can't be added.
+//!
+//! The shares are made by [`ThresholdKex::split_private_key`] from a whole private key, which
+//! should be destroyed once the shares are handed out. Generating the shares without ever having
+//! the whole key, i.e., a distributed key generation, is out of scope.
+//!
+//! Only the Base and Psk modes are supported. The auth modes need a second DH with the sender's
+//! key, which the share-holders don't do.
+
+use crate::{
+    aead::{Aead, AeadCtxR},
+    kdf::Kdf as KdfTrait,
+    kem::{self, EncappedKey, Kem as KemTrait},
+    kex::{Deserializable, KeyExchange, Serializable, ToPubkeyBytes},
+    op_mode::OpModeR,
+    setup, HpkeError,
+};
+
+use alloc::vec::Vec;
+use rand::{CryptoRng, RngCore};
+
+/// One share of a private key. `index` is the share's x-coordinate in the sharing, which is
+/// nonzero and unique among the shares of a key. `share` is stored and serialized like any other
+/// private key.
+pub struct KeyShare<Kex: KeyExchange> {
+    pub index: u32,
+    pub share: Kex::PrivateKey,
+}
+
+/// One share-holder's part of a DH: its share times the encapsulated key. `value` is a group
+/// element, so it's stored and serialized like a public key. `index` is the index of the share
+/// that made it.
+pub struct PartialDh<Kex: KeyExchange> {
+    pub index: u32,
+    pub value: Kex::PublicKey,
+}
+
+/// A key exchange whose private keys can be split into shares, and whose DH results can be put
+/// back together from the partial DH results of the shares. See the [module docs](self).
+pub trait ThresholdKex: KeyExchange {
+    /// Splits `sk` into `num_shares` shares with indices 1 to `num_shares`, any `threshold` of
+    /// which are enough to decap
+    ///
+    /// Panics
+    /// ======
+    /// Panics if `threshold` is 0 or greater than `num_shares`.
+    fn split_private_key<R: CryptoRng + RngCore>(
+        sk: &Self::PrivateKey,
+        threshold: u32,
+        num_shares: u32,
+        csprng: &mut R,
+    ) -> Vec<KeyShare<Self>>
+    where
+        Self: Sized;
+
+    /// Combines the partials of at least `threshold` distinct shares into the DH result of the
+    /// whole key. Fewer partials give a wrong result, which shows up as a failure to open.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Err(HpkeError::InvalidKeyExchange)` if `partials` is empty, if an index is 0 or
+    /// repeated, or if the result is the identity.
+    fn combine_partials(partials: &[PartialDh<Self>]) -> Result<Self::KexResult, HpkeError>
+    where
+        Self: Sized;
+}
+
+/// Something that does one share's part of a decap, e.g., a remote share-holder
+pub trait PartialDecapProvider<Kem: KemTrait> {
+    /// Returns this share's partial DH with the encapsulated key
+    fn partial_decap(
+        &self,
+        encapped_key: &EncappedKey<Kem>,
+    ) -> Result<PartialDh<Kem::Kex>, HpkeError>;
+}
+
+impl<Kem: KemTrait> PartialDecapProvider<Kem> for KeyShare<Kem::Kex> {
+    fn partial_decap(
+        &self,
+        encapped_key: &EncappedKey<Kem>,
+    ) -> Result<PartialDh<Kem::Kex>, HpkeError> {
+        let dh = <Kem::Kex as KeyExchange>::kex(&self.share, encapped_key.pubkey())?;
+        let value = <Kem::Kex as KeyExchange>::PublicKey::from_bytes(&dh.to_pubkey_bytes())?;
+        Ok(PartialDh {
+            index: self.index,
+            value,
+        })
+    }
+}
+
+/// Sets up a receiver context from the partial DH results of enough shares of the private key of
+/// `pk_recip`, which were computed with `encapped_key`. This is [`setup_receiver`] with the DH
+/// done by [`ThresholdKex::combine_partials`].
+///
+/// [`setup_receiver`]: crate::setup_receiver
+///
+/// Return Value
+/// ============
+/// Returns `Err(HpkeError::UnsupportedMode)` if `mode` is an auth mode. Otherwise, fails the same
+/// way as [`ThresholdKex::combine_partials`].
+pub fn setup_receiver_threshold<A, Kdf, Kem>(
+    mode: &OpModeR<Kem::Kex>,
+    partials: &[PartialDh<Kem::Kex>],
+    pk_recip: &<Kem::Kex as KeyExchange>::PublicKey,
+    encapped_key: &EncappedKey<Kem>,
+    info: &[u8],
+) -> Result<AeadCtxR<A, Kdf, Kem>, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    Kem::Kex: ThresholdKex,
+{
+    if let OpModeR::Auth(..) | OpModeR::AuthPsk(..) = mode {
+        return Err(HpkeError::UnsupportedMode);
+    }
+
+    let dh = <Kem::Kex as ThresholdKex>::combine_partials(partials)?;
+    let shared_secret = kem::decap_from_dh::<Kem>(&dh.to_bytes(), pk_recip, encapped_key)?;
+    Ok(setup::derive_receiver_ctx::<A, Kdf, Kem>(
+        mode,
+        shared_secret,
+        info,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        setup_receiver_threshold, KeyShare, PartialDecapProvider, PartialDh, ThresholdKex,
+    };
+    use crate::{
+        aead::ChaCha20Poly1305,
+        kdf::HkdfSha256,
+        kem::{DhP256HkdfSha256, Kem as KemTrait},
+        kex::{DhP256, KeyExchange, Serializable},
+        op_mode::{OpModeR, OpModeS, PskBundle},
+        setup::setup_sender,
+        HpkeError,
+    };
+
+    use alloc::vec::Vec;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    type Kem = DhP256HkdfSha256;
+
+    /// Tests that any 2 of 3 shares decap, that 1 share doesn't, and that the combined DH is the
+    /// DH of the whole key
+    #[test]
+    fn test_threshold_decap() {
+        type A = ChaCha20Poly1305;
+        type Kdf = HkdfSha256;
+        let mut csprng = StdRng::from_entropy();
+
+        let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+        let shares = DhP256::split_private_key(&sk_recip, 2, 3, &mut csprng);
+        assert_eq!(
+            shares.iter().map(|s| s.index).collect::<Vec<_>>(),
+            [1, 2, 3]
+        );
+
+        let info = b"threshold test";
+        let (encapped_key, mut sender_ctx) =
+            setup_sender::<A, Kdf, Kem, _>(&OpModeS::Base, &pk_recip, info, &mut csprng).unwrap();
+        let partial = |share: &KeyShare<DhP256>| share.partial_decap(&encapped_key).unwrap();
+
+        let mut msg = *b"no one holds the key";
+        let tag = sender_ctx.seal(&mut msg, b"").unwrap();
+        for pair in [[0, 1], [0, 2], [2, 1]].iter() {
+            let partials = [partial(&shares[pair[0]]), partial(&shares[pair[1]])];
+            let mut receiver_ctx = setup_receiver_threshold::<A, Kdf, Kem>(
+                &OpModeR::Base,
+                &partials,
+                &pk_recip,
+                &encapped_key,
+                info,
+            )
+            .unwrap();
+            let mut plaintext = msg;
+            receiver_ctx.open(&mut plaintext, b"", &tag).unwrap();
+            assert_eq!(&plaintext, b"no one holds the key");
+        }
+
+        // Any number of partials above the threshold gives the real DH
+        let all: Vec<PartialDh<DhP256>> = shares.iter().map(partial).collect();
+        let whole = DhP256::kex(&sk_recip, encapped_key.pubkey()).unwrap();
+        let combined = DhP256::combine_partials(&all).unwrap();
+        assert_eq!(combined.to_bytes(), whole.to_bytes());
+
+        // One share isn't enough
+        let mut receiver_ctx = setup_receiver_threshold::<A, Kdf, Kem>(
+            &OpModeR::Base,
+            &all[..1],
+            &pk_recip,
+            &encapped_key,
+            info,
+        )
+        .unwrap();
+        let mut plaintext = msg;
+        assert!(receiver_ctx.open(&mut plaintext, b"", &tag).is_err());
+    }
+
+    /// Tests that bad sets of partials and the auth modes are refused
+    #[test]
+    fn test_threshold_errors() {
+        type A = ChaCha20Poly1305;
+        type Kdf = HkdfSha256;
+        let mut csprng = StdRng::from_entropy();
+
+        let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+        let shares = DhP256::split_private_key(&sk_recip, 2, 2, &mut csprng);
+        let (encapped_key, _) =
+            setup_sender::<A, Kdf, Kem, _>(&OpModeS::Base, &pk_recip, b"", &mut csprng).unwrap();
+        let partial = |share: &KeyShare<DhP256>| share.partial_decap(&encapped_key).unwrap();
+
+        let repeated = [partial(&shares[0]), partial(&shares[0])];
+        let mut zero = partial(&shares[1]);
+        zero.index = 0;
+        for partials in [&repeated[..], &[], &[zero]].iter() {
+            let res = DhP256::combine_partials(partials);
+            assert!(matches!(res, Err(HpkeError::InvalidKeyExchange)));
+        }
+
+        let psk = PskBundle {
+            psk: b"a psk with plenty of entropy",
+            psk_id: b"psk id",
+        };
+        let partials = [partial(&shares[0]), partial(&shares[1])];
+        let res = setup_receiver_threshold::<A, Kdf, Kem>(
+            &OpModeR::AuthPsk(pk_recip.clone(), psk),
+            &partials,
+            &pk_recip,
+            &encapped_key,
+            b"",
+        );
+        assert!(matches!(res, Err(HpkeError::UnsupportedMode)));
+    }
+}
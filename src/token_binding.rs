@@ -0,0 +1,204 @@
+//! This module defines [`TokenBinding`], a pair of secrets for binding Privacy Pass style tokens
+//! to the HPKE context that carried the token request. When token issuance runs over Oblivious
+//! HTTP, every request has its own context, and the client and the gateway can both export from
+//! it. The blinding secret is for the client to blind its token request with, and the
+//! verification secret is for the issuer to check that a request came over the context it
+//! arrived on. Both are fresh per request and known only to the two ends of the context.
+//!
+//! The secrets are exports of the context under two labels in an [`ExportNamespace`]. The default
+//! [`TokenBindingLabels`] use the namespace `"privacy pass token binding"` and the labels
+//! `"blinding"` and `"verification"`. Deployments that fix their own labels can set them with
+//! [`TokenBindingLabels::new`], as long as both ends agree.
+
+use crate::{
+    aead::{Aead, AeadCtxR, AeadCtxS, ExportNamespace},
+    kdf::Kdf as KdfTrait,
+    kem::Kem as KemTrait,
+    HpkeError,
+};
+
+use alloc::vec::Vec;
+
+use subtle::ConstantTimeEq;
+use zeroize::Zeroize;
+
+// The defaults for TokenBindingLabels
+const DEFAULT_NAMESPACE: &[u8] = b"privacy pass token binding";
+const DEFAULT_BLINDING_LABEL: &[u8] = b"blinding";
+const DEFAULT_VERIFICATION_LABEL: &[u8] = b"verification";
+
+/// The namespace and labels that the secrets of a [`TokenBinding`] are exported under
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TokenBindingLabels {
+    namespace: ExportNamespace,
+    blinding_label: Vec<u8>,
+    verification_label: Vec<u8>,
+}
+
+impl Default for TokenBindingLabels {
+    fn default() -> Self {
+        TokenBindingLabels::new(
+            ExportNamespace::new(DEFAULT_NAMESPACE),
+            DEFAULT_BLINDING_LABEL,
+            DEFAULT_VERIFICATION_LABEL,
+        )
+    }
+}
+
+impl TokenBindingLabels {
+    /// Makes a set of labels for exporting the blinding and verification secrets in `namespace`
+    ///
+    /// Panics
+    /// ======
+    /// Panics if the two labels are the same, since the two secrets would then be the same.
+    pub fn new(
+        namespace: ExportNamespace,
+        blinding_label: &[u8],
+        verification_label: &[u8],
+    ) -> Self {
+        assert_ne!(
+            blinding_label, verification_label,
+            "blinding and verification labels must differ"
+        );
+        TokenBindingLabels {
+            namespace,
+            blinding_label: blinding_label.to_vec(),
+            verification_label: verification_label.to_vec(),
+        }
+    }
+
+    /// Returns the namespace the secrets are exported in
+    pub fn namespace(&self) -> &ExportNamespace {
+        &self.namespace
+    }
+
+    /// Returns the label of the blinding secret
+    pub fn blinding_label(&self) -> &[u8] {
+        &self.blinding_label
+    }
+
+    /// Returns the label of the verification secret
+    pub fn verification_label(&self) -> &[u8] {
+        &self.verification_label
+    }
+}
+
+/// The blinding and verification secrets of a token request. Both are 32 bytes, and both are
+/// zeroed when this is dropped.
+pub struct TokenBinding {
+    blinding_secret: [u8; 32],
+    verification_secret: [u8; 32],
+}
+
+impl TokenBinding {
+    /// Derives the secrets of the request sent over the given sender context
+    pub fn from_sender_ctx<A: Aead, Kdf: KdfTrait, Kem: KemTrait>(
+        ctx: &AeadCtxS<A, Kdf, Kem>,
+        labels: &TokenBindingLabels,
+    ) -> Self {
+        Self::derive(labels, |label, out| {
+            ctx.export_in(&labels.namespace, label, out)
+        })
+    }
+
+    /// Derives the secrets of the request received over the given receiver context
+    pub fn from_receiver_ctx<A: Aead, Kdf: KdfTrait, Kem: KemTrait>(
+        ctx: &AeadCtxR<A, Kdf, Kem>,
+        labels: &TokenBindingLabels,
+    ) -> Self {
+        Self::derive(labels, |label, out| {
+            ctx.export_in(&labels.namespace, label, out)
+        })
+    }
+
+    // Exports both secrets with the given exporter. 32 bytes is well under the export limit of
+    // any KDF, so this can't fail.
+    fn derive(
+        labels: &TokenBindingLabels,
+        export: impl Fn(&[u8], &mut [u8]) -> Result<(), HpkeError>,
+    ) -> Self {
+        let mut binding = TokenBinding {
+            blinding_secret: [0u8; 32],
+            verification_secret: [0u8; 32],
+        };
+        export(&labels.blinding_label, &mut binding.blinding_secret)
+            .expect("blinding secret is way too big");
+        export(&labels.verification_label, &mut binding.verification_secret)
+            .expect("verification secret is way too big");
+        binding
+    }
+
+    /// Returns the secret the client blinds its token request with
+    pub fn blinding_secret(&self) -> &[u8; 32] {
+        &self.blinding_secret
+    }
+
+    /// Returns the secret the issuer checks the request against
+    pub fn verification_secret(&self) -> &[u8; 32] {
+        &self.verification_secret
+    }
+
+    /// Returns whether `secret` is this binding's verification secret. The comparison is constant
+    /// time.
+    pub fn verify(&self, secret: &[u8]) -> bool {
+        self.verification_secret.ct_eq(secret).into()
+    }
+}
+
+impl Drop for TokenBinding {
+    fn drop(&mut self) {
+        self.blinding_secret.zeroize();
+        self.verification_secret.zeroize();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{TokenBinding, TokenBindingLabels};
+    use crate::{aead::ExportNamespace, test_util::gen_ctx_simple_pair};
+
+    /// Tests that both ends of a context derive the same secrets, that other contexts and other
+    /// labels derive different ones, and that verification only accepts the right secret
+    macro_rules! test_token_binding {
+        ($test_name:ident, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type A = crate::aead::ChaCha20Poly1305;
+                type Kdf = crate::kdf::HkdfSha256;
+                type Kem = $kem;
+
+                let labels = TokenBindingLabels::default();
+                let (sender_ctx, receiver_ctx) = gen_ctx_simple_pair::<A, Kdf, Kem>();
+                let client = TokenBinding::from_sender_ctx(&sender_ctx, &labels);
+                let gateway = TokenBinding::from_receiver_ctx(&receiver_ctx, &labels);
+                assert_eq!(client.blinding_secret(), gateway.blinding_secret());
+                assert_eq!(client.verification_secret(), gateway.verification_secret());
+                assert_ne!(client.blinding_secret(), client.verification_secret());
+                assert!(gateway.verify(client.verification_secret()));
+                assert!(!gateway.verify(client.blinding_secret()));
+                assert!(!gateway.verify(&client.verification_secret()[..31]));
+
+                // Every request gets its own secrets
+                let (other_ctx, _) = gen_ctx_simple_pair::<A, Kdf, Kem>();
+                let other = TokenBinding::from_sender_ctx(&other_ctx, &labels);
+                assert_ne!(other.blinding_secret(), client.blinding_secret());
+                assert!(!gateway.verify(other.verification_secret()));
+
+                // So does every deployment that picks its own labels
+                let custom = TokenBindingLabels::new(
+                    ExportNamespace::new(b"my issuer"),
+                    labels.blinding_label(),
+                    labels.verification_label(),
+                );
+                let custom = TokenBinding::from_sender_ctx(&sender_ctx, &custom);
+                assert_ne!(custom.blinding_secret(), client.blinding_secret());
+                assert_ne!(custom.verification_secret(), client.verification_secret());
+            }
+        };
+    }
+
+    #[cfg(feature = "x25519-dalek")]
+    test_token_binding!(test_token_binding_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_token_binding!(test_token_binding_p256, crate::kem::DhP256HkdfSha256);
+}
@@ -0,0 +1,169 @@
+//! This module defines [`Transcript`], a record of the non-secret inputs and state of an
+//! encryption context. When this crate and another HPKE implementation can't talk to each other,
+//! comparing the transcripts of the two ends shows the first value they disagree on: the suite,
+//! the mode, the key schedule context (which covers the PSK ID and info), the base nonce, or the
+//! sequence number.
+//!
+//! A transcript holds no keys. It does hold the base nonce, which is derived from the shared
+//! secret. Nonces aren't secret, but the base nonce is the same for every message of a context,
+//! so a transcript can tell an observer which messages belong together. This module is only
+//! meant for debugging, and needs the `transcript` feature.
+
+use alloc::vec::Vec;
+use core::fmt;
+
+/// The parts of a transcript that are fixed at setup
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct SetupTranscript {
+    pub(crate) mode_id: u8,
+    pub(crate) info_len: usize,
+    pub(crate) psk_id_len: usize,
+    pub(crate) key_schedule_context: Vec<u8>,
+}
+
+/// The non-secret inputs and state of a context. Its `Display` impl prints one field per line,
+/// with byte strings in lowercase hex, so two transcripts can be diffed line by line.
+///
+/// A context made by an [`HpkeChannel`](crate::HpkeChannel) key update doesn't come from the key
+/// schedule, so its mode is 0 and its lengths and key schedule context are empty.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Transcript {
+    /// The 10-byte `"HPKE" || kem_id || kdf_id || aead_id`
+    pub suite_id: [u8; 10],
+    /// The mode ID, e.g., 0 for base mode or 3 for auth-PSK mode
+    pub mode_id: u8,
+    /// The length of the info string
+    pub info_len: usize,
+    /// The length of the PSK ID, or 0 outside of PSK modes
+    pub psk_id_len: usize,
+    /// `mode || psk_id_hash || info_hash`, as fed to the key schedule
+    pub key_schedule_context: Vec<u8>,
+    /// The base nonce that sequence numbers are XORed into
+    pub base_nonce: Vec<u8>,
+    /// The sequence number of the next message
+    pub seq: u64,
+}
+
+impl Transcript {
+    pub(crate) fn new(
+        suite_id: [u8; 10],
+        setup: &SetupTranscript,
+        base_nonce: &[u8],
+        seq: u64,
+    ) -> Transcript {
+        Transcript {
+            suite_id,
+            mode_id: setup.mode_id,
+            info_len: setup.info_len,
+            psk_id_len: setup.psk_id_len,
+            key_schedule_context: setup.key_schedule_context.clone(),
+            base_nonce: base_nonce.to_vec(),
+            seq,
+        }
+    }
+}
+
+// Writes the given bytes as lowercase hex
+fn write_hex(f: &mut fmt::Formatter<'_>, bytes: &[u8]) -> fmt::Result {
+    for b in bytes {
+        write!(f, "{:02x}", b)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Transcript {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("suite_id: ")?;
+        write_hex(f, &self.suite_id)?;
+        writeln!(f)?;
+        writeln!(f, "mode_id: {}", self.mode_id)?;
+        writeln!(f, "info_len: {}", self.info_len)?;
+        writeln!(f, "psk_id_len: {}", self.psk_id_len)?;
+        f.write_str("key_schedule_context: ")?;
+        write_hex(f, &self.key_schedule_context)?;
+        writeln!(f)?;
+        f.write_str("base_nonce: ")?;
+        write_hex(f, &self.base_nonce)?;
+        writeln!(f)?;
+        write!(f, "seq: {}", self.seq)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        aead::ChaCha20Poly1305,
+        kdf::HkdfSha256,
+        kem::Kem as KemTrait,
+        op_mode::{OpModeR, OpModeS, PskBundle},
+        setup::{setup_receiver, setup_sender},
+    };
+
+    use alloc::string::ToString;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Tests that both ends of a context have the same transcript, that it tracks the sequence
+    /// number, and that it shows where two setups diverged
+    macro_rules! test_transcript {
+        ($test_name:ident, $kem:ty) => {
+            #[test]
+            fn $test_name() {
+                type A = ChaCha20Poly1305;
+                type Kdf = HkdfSha256;
+                type Kem = $kem;
+                let mut csprng = StdRng::from_entropy();
+
+                let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+                let psk = PskBundle {
+                    psk: b"a psk",
+                    psk_id: b"psk id",
+                };
+
+                let (encapped_key, mut sender_ctx) = setup_sender::<A, Kdf, Kem, _>(
+                    &OpModeS::Psk(psk),
+                    &pk_recip,
+                    b"info",
+                    &mut csprng,
+                )
+                .unwrap();
+                let receiver_ctx = setup_receiver::<A, Kdf, Kem>(
+                    &OpModeR::Psk(psk),
+                    &sk_recip,
+                    &encapped_key,
+                    b"info",
+                )
+                .unwrap();
+
+                let transcript = sender_ctx.transcript();
+                assert_eq!(transcript, receiver_ctx.transcript());
+                assert_eq!(&transcript.suite_id[..4], b"HPKE");
+                assert_eq!(transcript.mode_id, 0x01);
+                assert_eq!(transcript.info_len, 4);
+                assert_eq!(transcript.psk_id_len, 6);
+                assert_eq!(transcript.seq, 0);
+                assert!(transcript.to_string().contains("mode_id: 1\n"));
+
+                sender_ctx.seal(&mut [], b"").unwrap();
+                assert_eq!(sender_ctx.transcript().seq, 1);
+
+                // A receiver with a different info of the same length differs first in the key
+                // schedule context
+                let other_ctx = setup_receiver::<A, Kdf, Kem>(
+                    &OpModeR::Psk(psk),
+                    &sk_recip,
+                    &encapped_key,
+                    b"ofni",
+                )
+                .unwrap();
+                let other = other_ctx.transcript();
+                assert_eq!(other.info_len, transcript.info_len);
+                assert_ne!(other.key_schedule_context, transcript.key_schedule_context);
+            }
+        };
+    }
+
+    #[cfg(feature = "x25519-dalek")]
+    test_transcript!(test_transcript_x25519, crate::kem::X25519HkdfSha256);
+    #[cfg(feature = "p256")]
+    test_transcript!(test_transcript_p256, crate::kem::DhP256HkdfSha256);
+}
@@ -1,7 +1,5 @@
 use crate::{aead::Aead, kdf::Kdf as KdfTrait, kem::Kem as KemTrait};
 
-use byteorder::{BigEndian, ByteOrder};
-
 /// Represents a ciphersuite context. That's "KEMXX", where `XX` is the KEM ID
 pub(crate) type KemSuiteId = [u8; 5];
 
@@ -21,9 +19,9 @@ where
     let mut suite_id = *b"HPKEXXYYZZ";
 
     // Write the ciphersuite identifiers to the buffer. Forgive the explicit indexing.
-    BigEndian::write_u16(&mut suite_id[4..6], Kem::KEM_ID);
-    BigEndian::write_u16(&mut suite_id[6..8], Kdf::KDF_ID);
-    BigEndian::write_u16(&mut suite_id[8..10], A::AEAD_ID);
+    suite_id[4..6].copy_from_slice(&Kem::KEM_ID.to_be_bytes());
+    suite_id[6..8].copy_from_slice(&Kdf::KDF_ID.to_be_bytes());
+    suite_id[8..10].copy_from_slice(&A::AEAD_ID.to_be_bytes());
 
     suite_id
 }
@@ -35,33 +33,31 @@ pub(crate) fn kem_suite_id<Kem: KemTrait>() -> KemSuiteId {
     let mut suite_id = *b"KEMXX";
 
     // Write the KEM ID to the buffer. Forgive the explicit indexing.
-    BigEndian::write_u16(&mut suite_id[3..5], Kem::KEM_ID);
+    suite_id[3..5].copy_from_slice(&Kem::KEM_ID.to_be_bytes());
 
     suite_id
 }
 
-/// Returns a const expression that evaluates to the number of arguments it received
-macro_rules! count {
-    () => (0usize);
-    ( $x:tt $($xs:tt)* ) => (1usize + count!($($xs)*));
-}
-
-/// Given a length L and a sequence of n bytestrings with length at most L, this does a
-/// non-allocating concatentation of the bytestrings. It constructs a big buffer of n*L many bytes
-/// writes everything into there, and keeps track of how many bytes it wrote. The macro returns
-/// `(buf, num_bytes_written)`.
+/// Given a sequence of bytestrings, each paired with an upper bound on its length, this does a
+/// non-allocating concatentation of the bytestrings. It constructs a buffer whose size is the sum
+/// of the bounds, writes everything into there, and keeps track of how many bytes it wrote. The
+/// macro returns `(buf, num_bytes_written)`.
+///
+/// The buffer lives on the stack, so the bounds should be as tight as possible. They're checked in
+/// debug builds.
 macro_rules! concat_with_known_maxlen {
-    ( $maxlen:expr, $( $slice:expr ),* ) => {{
-        // The length of the big buffer is the number of items we're concatting times the max
-        // length of the items. count! tells us how many items we're concatting.
-        const BUFLEN: usize = count!($($slice)*) * $maxlen;
+    ( $( $slice:expr => $maxlen:expr ),* ) => {{
+        // The length of the buffer is the sum of the max lengths of the items
+        const BUFLEN: usize = 0 $( + $maxlen )*;
 
-        // Make the big buffer and iteratively write each slice to the remaining unused space.
-        // This panics if if we ever run out of space.
+        // Make the buffer and iteratively write each slice to the remaining unused space. This
+        // panics if if we ever run out of space.
         let mut buf = [0u8; BUFLEN];
         let mut unused_space = &mut buf[..];
         $(
-            unused_space = crate::util::write_to_buf(unused_space, $slice);
+            let slice: &[u8] = $slice;
+            debug_assert!(slice.len() <= $maxlen);
+            unused_space = crate::util::write_to_buf(unused_space, slice);
         )*
 
         let num_bytes_written = BUFLEN - unused_space.len();
@@ -69,6 +65,21 @@ macro_rules! concat_with_known_maxlen {
     }};
 }
 
+/// Returns the largest of `sizes`, or 0 if there are none. This is for sizing buffers at compile
+/// time.
+#[cfg(feature = "tight-buffers")]
+pub(crate) const fn max_of(sizes: &[usize]) -> usize {
+    let mut max = 0;
+    let mut i = 0;
+    while i < sizes.len() {
+        if sizes[i] > max {
+            max = sizes[i];
+        }
+        i += 1;
+    }
+    max
+}
+
 /// A helper function that writes to a buffer and returns a slice containing the unwritten portion.
 /// If this crate were allowed to use std, we'd just use std::io::Write instead.
 pub(crate) fn write_to_buf<'a>(buf: &'a mut [u8], to_write: &[u8]) -> &'a mut [u8] {
@@ -0,0 +1,87 @@
+//! Checks that the stanzas and key encodings of the `age` module interoperate with the `age` crate,
+//! by encrypting whole files with one implementation and decrypting them with the other
+
+#![cfg(feature = "age")]
+
+use hpke::{
+    age::{
+        identity_from_str, identity_to_string, recipient_from_str, recipient_to_string,
+        X25519Stanza, X25519_STANZA_TAG,
+    },
+    HpkeError, Kem, KeyExchange, X25519HkdfSha256,
+};
+
+use age::secrecy::ExposeSecret;
+use age_core::format::{FileKey, Stanza};
+use rand::{rngs::StdRng, SeedableRng};
+use std::{collections::HashSet, str::FromStr};
+
+type PublicKey = <<X25519HkdfSha256 as Kem>::Kex as KeyExchange>::PublicKey;
+type PrivateKey = <<X25519HkdfSha256 as Kem>::Kex as KeyExchange>::PrivateKey;
+
+// An age recipient that wraps file keys with X25519Stanza
+struct HpkeRecipient(PublicKey);
+
+impl age::Recipient for HpkeRecipient {
+    fn wrap_file_key(
+        &self,
+        file_key: &FileKey,
+    ) -> Result<(Vec<Stanza>, HashSet<String>), age::EncryptError> {
+        let mut csprng = StdRng::from_entropy();
+        let stanza = X25519Stanza::wrap(file_key.expose_secret(), &self.0, &mut csprng)
+            .expect("couldn't wrap file key");
+        let stanza = Stanza {
+            tag: X25519_STANZA_TAG.into(),
+            args: vec![stanza.arg()],
+            body: stanza.body().to_vec(),
+        };
+        Ok((vec![stanza], HashSet::new()))
+    }
+}
+
+// An age identity that unwraps file keys with X25519Stanza. Like age's own X25519 identity, it
+// skips stanzas that are for someone else.
+struct HpkeIdentity(PrivateKey);
+
+impl age::Identity for HpkeIdentity {
+    fn unwrap_stanza(&self, stanza: &Stanza) -> Option<Result<FileKey, age::DecryptError>> {
+        match X25519Stanza::from_parts(&stanza.tag, &stanza.args, &stanza.body) {
+            Ok(stanza) => match stanza.unwrap(&self.0) {
+                Ok(file_key) => Some(Ok(FileKey::new(Box::new(file_key)))),
+                Err(_) => None,
+            },
+            Err(HpkeError::SuiteMismatch) => None,
+            Err(_) => Some(Err(age::DecryptError::InvalidHeader)),
+        }
+    }
+}
+
+/// Tests that a file this crate wraps the key of can be decrypted by age
+#[test]
+fn test_wrap_for_age() {
+    let identity = age::x25519::Identity::generate();
+    let recipient = recipient_from_str(&identity.to_public().to_string()).unwrap();
+
+    let msg = b"from rust-hpke to age";
+    let encrypted = age::encrypt(&HpkeRecipient(recipient), msg).unwrap();
+    assert_eq!(age::decrypt(&identity, &encrypted).unwrap(), msg);
+}
+
+/// Tests that this crate can unwrap the key of a file that age encrypted, and that it skips files
+/// for other recipients
+#[test]
+fn test_unwrap_from_age() {
+    let mut csprng = StdRng::from_entropy();
+    let (sk, pk) = X25519HkdfSha256::gen_keypair(&mut csprng);
+    let recipient = age::x25519::Recipient::from_str(&recipient_to_string(&pk)).unwrap();
+    // age can read the identity too
+    age::x25519::Identity::from_str(&identity_to_string(&sk)).unwrap();
+
+    let msg = b"from age to rust-hpke";
+    let encrypted = age::encrypt(&recipient, msg).unwrap();
+    let identity = HpkeIdentity(identity_from_str(&identity_to_string(&sk)).unwrap());
+    assert_eq!(age::decrypt(&identity, &encrypted).unwrap(), msg);
+
+    let (other_sk, _) = X25519HkdfSha256::gen_keypair(&mut csprng);
+    assert!(age::decrypt(&HpkeIdentity(other_sk), &encrypted).is_err());
+}
@@ -0,0 +1,186 @@
+//! A snapshot of the crate-root API. Protocol crates import these items by these paths, and call
+//! these functions with these signatures, so this file failing to compile means a release breaks
+//! semver. If a change here is intentional, it needs a major version bump.
+
+#![cfg(all(
+    feature = "x25519",
+    feature = "p256",
+    feature = "aes-gcm",
+    feature = "chacha20poly1305",
+    feature = "hkdf-sha256",
+    feature = "hkdf-sha384",
+    feature = "hkdf-sha512",
+))]
+// Spelling out the full signatures is the point
+#![allow(clippy::type_complexity)]
+
+use hpke::{
+    setup_receiver, setup_receiver_from_bytes, setup_sender, single_shot_open, single_shot_seal,
+    Aead, AeadCtxR, AeadCtxS, AeadTag, AesGcm128, AesGcm256, ChaCha20Poly1305, Deserializable,
+    DhP256HkdfSha256, EncappedKey, HkdfSha256, HkdfSha384, HkdfSha512, HpkeError, Kdf, Kem,
+    KeyExchange, OpModeR, OpModeS, PskBundle, Serializable, X25519HkdfSha256,
+};
+
+use rand::rngs::StdRng;
+
+type A = ChaCha20Poly1305;
+type Kd = HkdfSha256;
+type Km = X25519HkdfSha256;
+type Kex = <Km as Kem>::Kex;
+type PublicKey = <Kex as KeyExchange>::PublicKey;
+type PrivateKey = <Kex as KeyExchange>::PrivateKey;
+
+// Every ciphersuite at the root implements its trait
+fn assert_aead<T: Aead>() {}
+fn assert_kdf<T: Kdf>() {}
+fn assert_kem<T: Kem>() {}
+fn assert_serializable<T: Serializable + Deserializable>() {}
+fn assert_send_sync<T: Send + Sync>() {}
+
+/// Tests that the root re-exports every algorithm under its trait
+#[test]
+fn test_suites() {
+    assert_aead::<AesGcm128>();
+    assert_aead::<AesGcm256>();
+    assert_aead::<ChaCha20Poly1305>();
+    assert_kdf::<HkdfSha256>();
+    assert_kdf::<HkdfSha384>();
+    assert_kdf::<HkdfSha512>();
+    assert_kem::<X25519HkdfSha256>();
+    assert_kem::<DhP256HkdfSha256>();
+}
+
+/// Tests the signatures of the setup and single-shot functions
+#[test]
+fn test_function_signatures() {
+    let _: fn(
+        &OpModeS<Kex>,
+        &PublicKey,
+        &[u8],
+        &mut StdRng,
+    ) -> Result<(EncappedKey<Km>, AeadCtxS<A, Kd, Km>), HpkeError> =
+        setup_sender::<A, Kd, Km, StdRng>;
+    let _: fn(
+        &OpModeR<Kex>,
+        &PrivateKey,
+        &EncappedKey<Km>,
+        &[u8],
+    ) -> Result<AeadCtxR<A, Kd, Km>, HpkeError> = setup_receiver::<A, Kd, Km>;
+    let _: fn(&OpModeR<Kex>, &PrivateKey, &[u8], &[u8]) -> Result<AeadCtxR<A, Kd, Km>, HpkeError> =
+        setup_receiver_from_bytes::<A, Kd, Km>;
+    let _: fn(
+        &OpModeS<Kex>,
+        &PublicKey,
+        &[u8],
+        &mut [u8],
+        &[u8],
+        &mut StdRng,
+    ) -> Result<(EncappedKey<Km>, AeadTag<A>), HpkeError> = single_shot_seal::<A, Kd, Km, StdRng>;
+    let _: fn(
+        &OpModeR<Kex>,
+        &PrivateKey,
+        &EncappedKey<Km>,
+        &[u8],
+        &mut [u8],
+        &[u8],
+        &AeadTag<A>,
+    ) -> Result<(), HpkeError> = single_shot_open::<A, Kd, Km>;
+}
+
+// Asserts that the contexts of the suite (A, Kdf, Kem) can cross threads
+macro_rules! assert_ctxs_send_sync {
+    ($kem:ty; $($aead:ty),*; $kdfs:tt) => {
+        $(assert_ctxs_send_sync!(@kdf $kem, $aead, $kdfs);)*
+    };
+    (@kdf $kem:ty, $aead:ty, [$($kdf:ty),*]) => {
+        $(
+            assert_send_sync::<AeadCtxS<$aead, $kdf, $kem>>();
+            assert_send_sync::<AeadCtxR<$aead, $kdf, $kem>>();
+        )*
+    };
+}
+
+/// Tests that the contexts of every suite are `Send` and `Sync`, so async code can hold them
+/// across an `.await` or move them into a spawned task
+#[test]
+fn test_ctx_send_sync() {
+    assert_ctxs_send_sync!(
+        X25519HkdfSha256;
+        AesGcm128, AesGcm256, ChaCha20Poly1305;
+        [HkdfSha256, HkdfSha384, HkdfSha512]
+    );
+    assert_ctxs_send_sync!(
+        DhP256HkdfSha256;
+        AesGcm128, AesGcm256, ChaCha20Poly1305;
+        [HkdfSha256, HkdfSha384, HkdfSha512]
+    );
+
+    // Seal on one thread and open on another
+    let mut csprng = <StdRng as rand::SeedableRng>::from_entropy();
+    let (sk_recip, pk_recip) = Km::gen_keypair(&mut csprng);
+    let (encapped_key, mut sender_ctx) =
+        setup_sender::<A, Kd, Km, _>(&OpModeS::Base, &pk_recip, b"", &mut csprng).unwrap();
+    let receiver_ctx =
+        setup_receiver::<A, Kd, Km>(&OpModeR::Base, &sk_recip, &encapped_key, b"").unwrap();
+
+    let (mut msg, tag) = std::thread::spawn(move || {
+        let mut msg = *b"over the wall";
+        let tag = sender_ctx.seal(&mut msg, b"").unwrap();
+        (msg, tag)
+    })
+    .join()
+    .unwrap();
+    std::thread::spawn(move || {
+        let mut receiver_ctx = receiver_ctx;
+        receiver_ctx.open(&mut msg, b"", &tag).unwrap();
+        assert_eq!(&msg, b"over the wall");
+    })
+    .join()
+    .unwrap();
+}
+
+/// Tests the context and serialization methods that protocols call
+#[test]
+fn test_methods() {
+    let _: fn(&mut AeadCtxS<A, Kd, Km>, &mut [u8], &[u8]) -> Result<AeadTag<A>, HpkeError> =
+        AeadCtxS::seal;
+    let _: fn(&mut AeadCtxR<A, Kd, Km>, &mut [u8], &[u8], &AeadTag<A>) -> Result<(), HpkeError> =
+        AeadCtxR::open;
+    let _: fn(&AeadCtxS<A, Kd, Km>, &[u8], &mut [u8]) -> Result<(), HpkeError> = AeadCtxS::export;
+    let _: fn(&AeadCtxR<A, Kd, Km>, &[u8], &mut [u8]) -> Result<(), HpkeError> = AeadCtxR::export;
+    let _: fn(&[u8]) -> Result<EncappedKey<Km>, HpkeError> = EncappedKey::<Km>::from_bytes;
+    let _: fn(&[u8]) -> Result<AeadTag<A>, HpkeError> = AeadTag::<A>::from_bytes;
+    let _: fn(&[u8]) -> (PrivateKey, PublicKey) = Km::derive_keypair;
+
+    assert_serializable::<EncappedKey<Km>>();
+    assert_serializable::<AeadTag<A>>();
+    assert_serializable::<PublicKey>();
+    assert_serializable::<PrivateKey>();
+
+    // Serialized sizes are constants
+    let _: [u8; EncappedKey::<Km>::SIZE] = [0u8; 32];
+    let _: [u8; AeadTag::<A>::SIZE] = [0u8; 16];
+    let _: [u8; Km::N_ENC] = [0u8; 32];
+    let _: [u8; Km::N_PK] = [0u8; 32];
+}
+
+/// Tests that the error enum still has the variants protocols match on, and that `PskBundle` can
+/// still be built from its fields
+#[test]
+fn test_plain_types() {
+    let describe = |e: HpkeError| match e {
+        HpkeError::SeqOverflow => "seq",
+        HpkeError::InvalidTag => "tag",
+        HpkeError::Encryption => "encryption",
+        HpkeError::InvalidKeyExchange => "kex",
+        HpkeError::InvalidKdfLength => "kdf",
+        HpkeError::InvalidEncoding => "encoding",
+        _ => "other",
+    };
+    assert_eq!(describe(HpkeError::InvalidTag), "tag");
+
+    let _ = PskBundle {
+        psk: b"psk",
+        psk_id: b"psk id",
+    };
+}